@@ -0,0 +1,109 @@
+//! Register state recorded in core dumps.
+
+use crate::arch::idt::InterruptStackFrame;
+use crate::arch::syscall::syscall::SyscallFrame;
+
+/// General purpose registers in the layout of Linux's `struct user_regs_struct`, as found
+/// in `NT_PRSTATUS` notes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserRegs {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub orig_rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub ds: u64,
+    pub es: u64,
+    pub fs: u64,
+    pub gs: u64,
+}
+
+impl UserRegs {
+    /// Registers of a thread about to return from a system call.
+    ///
+    /// The syscall entry path does not save the callee-saved `rbx` and `r12`-`r15`, so
+    /// they read as zero.
+    pub fn from_syscall_frame(frame: &SyscallFrame, fs_base: u64, gs_base: u64) -> Self {
+        Self {
+            r11: frame.r11,
+            r10: frame.r10,
+            r9: frame.r9,
+            r8: frame.r8,
+            rax: frame.rax,
+            rcx: frame.rcx,
+            rdx: frame.rdx,
+            rsi: frame.rsi,
+            rdi: frame.rdi,
+            rbp: frame.rbp,
+            orig_rax: !0,
+            rip: frame.rip,
+            cs: frame.cs,
+            rflags: frame.rflags,
+            rsp: frame.rsp,
+            ss: frame.ss,
+            fs_base,
+            gs_base,
+            ..Default::default()
+        }
+    }
+
+    /// Registers of a thread that raised a CPU exception in user mode.
+    ///
+    /// Exception handlers only see the hardware frame, so the general purpose registers
+    /// read as zero; `rip` and `rsp` are enough for a debugger to unwind.
+    pub fn from_interrupt_frame(frame: &InterruptStackFrame, fs_base: u64, gs_base: u64) -> Self {
+        Self {
+            orig_rax: !0,
+            rip: frame.instruction_pointer,
+            cs: frame.code_segment,
+            rflags: frame.cpu_flags,
+            rsp: frame.stack_pointer,
+            ss: frame.stack_segment,
+            fs_base,
+            gs_base,
+            ..Default::default()
+        }
+    }
+}
+
+/// x87/SSE state in the `FXSAVE` layout of `NT_FPREGSET` notes.
+#[repr(C, align(16))]
+pub struct FpuState(pub [u8; 512]);
+
+impl FpuState {
+    /// Save the FPU state of the calling CPU.
+    ///
+    /// The kernel does not use the FPU and does not switch its state between threads, so
+    /// this is the state the current thread left behind when it entered the kernel.
+    pub fn save() -> Self {
+        let mut state = Self([0; 512]);
+        // SAFETY: The area is 512 bytes and 16-byte aligned, as FXSAVE requires.
+        unsafe {
+            core::arch::asm!(
+                "fxsave64 [{}]",
+                in(reg) state.0.as_mut_ptr(),
+                options(nostack, preserves_flags)
+            );
+        }
+        state
+    }
+}
@@ -0,0 +1,41 @@
+//! Kernel backtraces through the frame pointer chain.
+//!
+//! Each frame starts with the caller's saved `rbp` followed by the return address. The
+//! chain is only complete when the kernel is built with `-C force-frame-pointers=yes`;
+//! without it the walk stops at the first frame that does not look like one.
+
+use core::arch::asm;
+
+/// Lowest address of the kernel half, where kernel stacks live.
+const KERNEL_HALF: usize = 0xFFFF_8000_0000_0000;
+
+/// Largest distance between two frames on the same stack the walk follows.
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Fill `frames` with the return addresses of the calling function's callers, innermost
+/// first, and return how many were found.
+#[inline(never)]
+pub fn capture(frames: &mut [usize]) -> usize {
+    let mut rbp: usize;
+    // SAFETY: Only reads the frame pointer register.
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+    let mut len = 0;
+    while len < frames.len() && rbp >= KERNEL_HALF && rbp % 8 == 0 {
+        // SAFETY: `rbp` points into a kernel stack frame: the saved frame pointer and
+        // the return address above it are mapped as long as the chain is intact, which
+        // the checks on each link keep the walk to.
+        let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+        frames[len] = ret;
+        len += 1;
+        // Frames of callers sit higher up the same stack.
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+    len
+}
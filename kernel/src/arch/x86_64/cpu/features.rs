@@ -0,0 +1,72 @@
+//! CPUID feature detection.
+
+use core::arch::x86_64::{CpuidResult, __cpuid, __cpuid_count};
+
+/// Structured extended feature flags (CPUID leaf 7, subleaf 0), if the CPU reports them.
+fn leaf7() -> Option<CpuidResult> {
+    if __cpuid(0).eax >= 7 {
+        Some(__cpuid_count(7, 0))
+    } else {
+        None
+    }
+}
+
+/// Supervisor Mode Execution Prevention.
+pub fn has_smep() -> bool {
+    leaf7().is_some_and(|r| r.ebx & (1 << 7) != 0)
+}
+
+/// Supervisor Mode Access Prevention (and the `stac`/`clac` instructions).
+pub fn has_smap() -> bool {
+    leaf7().is_some_and(|r| r.ebx & (1 << 20) != 0)
+}
+
+/// User Mode Instruction Prevention.
+pub fn has_umip() -> bool {
+    leaf7().is_some_and(|r| r.ecx & (1 << 2) != 0)
+}
+
+/// Process-context identifiers (CR4.PCIDE).
+pub fn has_pcid() -> bool {
+    __cpuid(1).ecx & (1 << 17) != 0
+}
+
+/// The `rdrand` hardware random number instruction.
+pub fn has_rdrand() -> bool {
+    __cpuid(1).ecx & (1 << 30) != 0
+}
+
+/// The `rdtscp` instruction, which also returns `IA32_TSC_AUX`.
+pub fn has_rdtscp() -> bool {
+    __cpuid(0x8000_0000).eax >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 27) != 0
+}
+
+/// A TSC that ticks at a constant rate in every frequency and power state.
+pub fn has_invariant_tsc() -> bool {
+    __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// The TSC-deadline mode of the LAPIC timer (`IA32_TSC_DEADLINE`).
+pub fn has_tsc_deadline() -> bool {
+    __cpuid(1).ecx & (1 << 24) != 0
+}
+
+/// Feature bits reported to user space as `AT_HWCAP` (CPUID leaf 1 EDX, as on Linux).
+pub fn hwcap() -> u64 {
+    __cpuid(1).edx as u64
+}
+
+/// Feature bits reported to user space as `AT_HWCAP2`.
+///
+/// Only `HWCAP2_FSGSBASE` is defined for the kernel to set, and only when it has
+/// enabled `CR4.FSGSBASE` for user space.
+pub fn hwcap2() -> u64 {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    const HWCAP2_FSGSBASE: u64 = 1 << 1;
+    if Cr4::read().contains(Cr4Flags::FSGSBASE) {
+        HWCAP2_FSGSBASE
+    } else {
+        0
+    }
+}
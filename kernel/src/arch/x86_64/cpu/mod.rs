@@ -1,4 +1,7 @@
+#[cfg(feature = "lockdep")]
+pub mod backtrace;
 pub mod context;
+pub mod features;
 pub mod gdt;
 pub mod msr;
 pub mod ports;
@@ -6,13 +9,13 @@ pub mod rdtsc;
 pub mod smp;
 pub mod stack;
 pub mod tss;
+pub mod uaccess;
 pub mod userspace;
 
-use x86_64::registers::control::{Cr0, Cr0Flags, Cr2, Cr3, Cr3Flags, Cr4, Cr4Flags};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr2, Cr3, Cr4, Cr4Flags};
 use x86_64::registers::model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star};
 use x86_64::registers::rflags::RFlags;
-use x86_64::structures::paging::PhysFrame;
-use x86_64::{PhysAddr, VirtAddr};
+use x86_64::VirtAddr;
 
 /// Sets the active page table physical root address (CR3).
 ///
@@ -20,9 +23,9 @@ use x86_64::{PhysAddr, VirtAddr};
 /// The caller must ensure `root` points to a valid root page table (PML4) physical address.
 #[inline(always)]
 pub unsafe fn set_address_space_root(root: u64) {
-    let frame = PhysFrame::containing_address(PhysAddr::new(root));
+    // SAFETY: Forwarded from the caller.
     unsafe {
-        Cr3::write(frame, Cr3Flags::empty());
+        crate::arch::paging::tlb::load_root(root);
     }
 }
 
@@ -60,6 +63,31 @@ pub unsafe fn enable_sse() {
     }
 }
 
+/// Enable the supervisor protection features the CPU supports: SMEP, SMAP and UMIP.
+///
+/// With SMAP set, kernel accesses to user pages fault unless made through the `uaccess`
+/// helpers, so `uaccess::init()` must have run first.
+pub unsafe fn enable_protection() -> Cr4Flags {
+    let mut wanted = Cr4Flags::empty();
+    if features::has_smep() {
+        wanted |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if features::has_smap() {
+        wanted |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    if features::has_umip() {
+        wanted |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION;
+    }
+
+    // SAFETY: Each bit is only set after CPUID reported support for it.
+    unsafe {
+        let mut cr4 = Cr4::read();
+        cr4.insert(wanted);
+        Cr4::write(cr4);
+    }
+    wanted
+}
+
 /// Enable and configure the fast system call (SYSCALL / SYSRET) MSRs for a specific CPU core.
 pub unsafe fn enable_syscall_for_cpu(cpu_id: usize) {
     // SAFETY: IA32 MSRs configuration for enabling x86_64 fast syscall handling.
@@ -97,6 +125,12 @@ pub unsafe fn enable_syscall_for_cpu(cpu_id: usize) {
             let cpu_local_ptr = core::ptr::addr_of_mut!((*locals)[cpu_id]) as u64;
             KernelGsBase::write(VirtAddr::new(cpu_local_ptr));
         }
+
+        // 6. Program IA32_TSC_AUX with the CPU number so the vDSO `getcpu` can read it
+        // through `rdtscp` (bits 11:0 CPU, bits 31:12 NUMA node, always 0 here).
+        if features::has_rdtscp() {
+            msr::wrmsr(msr::IA32_TSC_AUX, (cpu_id & 0xFFF) as u64);
+        }
     }
 }
 
@@ -111,10 +145,20 @@ pub unsafe fn enable_syscall() {
 
 pub fn init() {
     gdt::init();
+    uaccess::init();
+    rdtsc::init();
+    stack::init();
 
-    // SAFETY: Initializing SSE and SYSCALL MSRs for the BSP.
-    unsafe {
+    // SAFETY: Initializing SSE, SYSCALL MSRs and CR4 protections for the BSP.
+    let protections = unsafe {
         enable_sse();
         enable_syscall();
-    }
+        enable_protection()
+    };
+    log::info!(
+        "CPU protections: SMEP={} SMAP={} UMIP={}",
+        protections.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+        protections.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        protections.contains(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION),
+    );
 }
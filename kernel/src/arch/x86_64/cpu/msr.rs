@@ -10,6 +10,8 @@ pub const IA32_FMASK: u32 = 0xC000_0084;
 pub const IA32_FS_BASE: u32 = 0xC000_0100;
 pub const IA32_GS_BASE: u32 = 0xC000_0101;
 pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+pub const IA32_TSC_AUX: u32 = 0xC000_0103;
+pub const IA32_TSC_DEADLINE: u32 = 0x6E0;
 
 /// Read a 64-bit value from an MSR.
 ///
@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 /// Read the current CPU Time-Stamp Counter (TSC).
 #[inline(always)]
@@ -9,6 +9,58 @@ pub fn rdtsc() -> u64 {
 
 static RANDOM_STATE: AtomicU64 = AtomicU64::new(0x853c_49e6_748f_ea9b);
 
+/// Set once the CPU is known to implement `rdrand`.
+#[unsafe(link_section = ".data.ro_after_init")]
+static RDRAND_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Probe for `rdrand` and seed the fallback generator from it, or from the boot time.
+pub fn init() {
+    RDRAND_SUPPORTED.store(super::features::has_rdrand(), Ordering::Relaxed);
+
+    let seed = rdrand_u64().unwrap_or_else(|| {
+        let boot_time = crate::limine::BOOT_TIME
+            .get_response()
+            .map_or(0, |resp| resp.timestamp().as_secs());
+        boot_time.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ rdtsc()
+    });
+    RANDOM_STATE.fetch_xor(seed | 1, Ordering::Relaxed);
+}
+
+/// Read a hardware random number, retrying a few times if the DRNG is momentarily empty.
+pub fn rdrand_u64() -> Option<u64> {
+    if !RDRAND_SUPPORTED.load(Ordering::Relaxed) {
+        return None;
+    }
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        // SAFETY: `rdrand` is supported (checked above) and only writes its operands.
+        unsafe {
+            core::arch::asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Random value for security-sensitive uses such as address space randomization.
+///
+/// Draws from `rdrand` when the CPU has it, falling back to the TSC-mixed generator.
+pub fn get_random_u64() -> u64 {
+    match rdrand_u64() {
+        Some(value) => value ^ next_random_u64(),
+        None => next_random_u64(),
+    }
+}
+
 /// Generates a pseudo-random 64-bit unsigned integer with TSC entropy mixing.
 pub fn next_random_u64() -> u64 {
     let tsc = rdtsc();
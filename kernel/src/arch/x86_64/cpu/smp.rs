@@ -7,7 +7,7 @@
 use crate::arch::enable_interrupts;
 use crate::arch::idle;
 use crate::arch::tss::*;
-use crate::arch::{gdt, interrupts, lapic, lapic_timer};
+use crate::arch::{gdt, interrupts, lapic};
 use crate::mm::map_mmio;
 use core::sync::atomic::{AtomicU32, Ordering};
 
@@ -31,6 +31,7 @@ unsafe extern "C" fn ap_entry(cpu: &limine::mp::Cpu) -> ! {
     unsafe {
         CPU_TSS_POINTERS[lapic_id] = tss_addr;
         super::enable_sse();
+        super::enable_protection();
     }
 
     // Load the shared IDT so exception/interrupt handlers are available.
@@ -48,15 +49,17 @@ unsafe extern "C" fn ap_entry(cpu: &limine::mp::Cpu) -> ! {
     let local_apic = lapic::LocalApic::new(lapic_phys);
     local_apic.enable();
 
+    // Take part in TLB shootdowns from here on.
+    crate::arch::paging::tlb::init_cpu();
+
     // Enable fast syscalls with explicit LAPIC ID now that hardware is ready.
     // SAFETY: MSR configuration for fast system calls on this AP core.
     unsafe {
         super::enable_syscall_for_cpu(lapic_id);
     }
 
-    // Calibrate and start the LAPIC timer for this AP.
-    let timer = lapic_timer::LapicTimer::calibrate_or_get(&local_apic);
-    timer.start_periodic(&local_apic, 100);
+    // Set up the LAPIC timer of this AP and start its tick.
+    crate::arch::timer::init_cpu(&local_apic);
 
     log::info!(
         "SMP: AP online (processor_id={}, lapic_id={}).",
@@ -67,10 +70,12 @@ unsafe extern "C" fn ap_entry(cpu: &limine::mp::Cpu) -> ! {
     // Signal to the BSP that this AP is ready.
     APS_ONLINE.fetch_add(1, Ordering::Release);
 
-    // Enable interrupts on this AP.
+    // Hand this AP to the scheduler, which switches to its idle thread or to queued work.
+    crate::sched::init_cpu(lapic_id as u32);
     crate::arch::enable_interrupts();
+    crate::sched::schedule(false);
 
-    // Park this AP in a low-power halt loop.
+    // Only reached if the CPU could not be registered with the scheduler.
     idle()
 }
 
@@ -1,6 +1,11 @@
 // ── Stack Frame Layout ────────────────────────────────────────────────────────
 use super::context::thread_bootstrapper;
+use crate::arch::paging::ArchPageTable;
 use crate::arch::syscall::SyscallFrame;
+use crate::mm::{PMM, PageTable};
+use crate::sync::spinlock::Spinlock;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
 
 /// The layout of the context saved on the thread's stack during a context switch on x86_64.
 #[derive(Debug, Clone, Copy)]
@@ -15,28 +20,136 @@ pub struct StackFrame {
     pub rip: u64,
 }
 
-/// A 16-byte aligned kernel stack allocated dynamically.
-#[repr(C, align(16))]
+// ── Kernel Stack Area ─────────────────────────────────────────────────────────
+
+/// Base of the virtual region kernel stacks are mapped in (PML4 slot 508).
+const KSTACK_AREA_BASE: u64 = 0xFFFF_FE00_0000_0000;
+/// Size of the kernel stack region: one full PML4 entry (512 GiB).
+const KSTACK_AREA_SIZE: u64 = 512 * 1024 * 1024 * 1024;
+/// Virtual span reserved per stack. The pages below the mapped stack are left unmapped,
+/// so the lowest page of every slot is always a guard page.
+const KSTACK_SLOT_SIZE: u64 = 64 * 1024;
+/// Largest stack that fits in a slot while keeping the guard page.
+pub const KSTACK_MAX_SIZE: usize = (KSTACK_SLOT_SIZE - 4096) as usize;
+
+/// Slot allocator for the kernel stack region.
+struct StackArea {
+    /// Next never-used slot.
+    next: u64,
+    /// Slots released by dropped stacks.
+    free: alloc::vec::Vec<u64>,
+}
+
+static STACK_AREA: Spinlock<StackArea> = Spinlock::new(StackArea {
+    next: KSTACK_AREA_BASE,
+    free: alloc::vec::Vec::new(),
+});
+
+/// Reserve the kernel stack region's top-level page table entry.
+///
+/// Must run before the first user address space is created, since those copy the kernel
+/// half of the PML4 at creation time.
+pub fn init() {
+    crate::mm::preallocate_kernel_pml4_entry(VirtAddr::new(KSTACK_AREA_BASE))
+        .expect("Failed to reserve the kernel stack area");
+}
+
+/// Returns true if `addr` lies in the kernel stack region but outside any mapped stack,
+/// which is where a kernel stack overflow faults.
+pub fn is_stack_guard(addr: u64) -> bool {
+    (KSTACK_AREA_BASE..KSTACK_AREA_BASE + KSTACK_AREA_SIZE).contains(&addr)
+        && kernel_page_table().translate(VirtAddr::new(addr)).is_none()
+}
+
+fn kernel_page_table() -> ArchPageTable {
+    // SAFETY: The kernel root stays valid for the lifetime of the kernel.
+    unsafe { ArchPageTable::from_root(PhysAddr::new(crate::mm::kernel_root())) }
+}
+
+/// A kernel stack mapped in the kernel stack region with an unmapped guard page below it.
 pub struct KernelStack {
-    buffer: alloc::vec::Vec<u8>,
+    /// Lowest mapped address of the stack.
+    base: u64,
+    size: usize,
 }
 
 impl KernelStack {
     /// Create a new kernel stack with the specified size in bytes.
     pub fn new(size: usize) -> Self {
-        let buffer = alloc::vec![0u8; size];
-        Self { buffer }
+        let size = (size + 4095) & !4095;
+        assert!(
+            size > 0 && size <= KSTACK_MAX_SIZE,
+            "Kernel stack size {:#x} out of range",
+            size
+        );
+
+        crate::arch::without_interrupts(|| {
+            let mut area = STACK_AREA.lock();
+            let slot = match area.free.pop() {
+                Some(slot) => slot,
+                None => {
+                    let slot = area.next;
+                    assert!(
+                        slot + KSTACK_SLOT_SIZE <= KSTACK_AREA_BASE + KSTACK_AREA_SIZE,
+                        "Kernel stack area exhausted"
+                    );
+                    area.next += KSTACK_SLOT_SIZE;
+                    slot
+                }
+            };
+
+            // Map the stack at the top of the slot; everything below it stays unmapped.
+            let base = slot + KSTACK_SLOT_SIZE - size as u64;
+            let hhdm = crate::mm::hhdm_offset();
+            let mut table = kernel_page_table();
+            let flags = PageTableFlags::PRESENT
+                | PageTableFlags::WRITABLE
+                | PageTableFlags::GLOBAL
+                | PageTableFlags::NO_EXECUTE;
+            for page in (base..base + size as u64).step_by(4096) {
+                let frame = PMM.alloc_page().expect("Out of memory for kernel stack");
+                // SAFETY: The frame was just allocated and is reachable through the HHDM.
+                unsafe { core::ptr::write_bytes((frame.as_u64() + hhdm) as *mut u8, 0, 4096) };
+                table
+                    .map(VirtAddr::new(page), frame, flags)
+                    .expect("Failed to map kernel stack page");
+            }
+
+            Self { base, size }
+        })
     }
 
     /// Returns the 16-byte aligned top virtual address of the stack.
     pub fn top(&self) -> u64 {
-        let top = self.buffer.as_ptr() as u64 + self.buffer.len() as u64;
-        top & !15
+        (self.base + self.size as u64) & !15
     }
 
     /// Access the underlying stack buffer slice.
     pub fn as_slice_mut(&mut self) -> &mut [u8] {
-        &mut self.buffer
+        // SAFETY: `base .. base + size` is mapped writable for as long as `self` lives.
+        unsafe { core::slice::from_raw_parts_mut(self.base as *mut u8, self.size) }
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        crate::arch::without_interrupts(|| {
+            let mut area = STACK_AREA.lock();
+            let mut table = kernel_page_table();
+            let mut frames = alloc::vec::Vec::new();
+            for page in (self.base..self.base + self.size as u64).step_by(4096) {
+                if let Ok(frame) = table.unmap(VirtAddr::new(page)) {
+                    frames.push(frame);
+                }
+            }
+            // Other CPUs may have cached the stack; only then can its frames be reused.
+            table.flush_tlb();
+            for frame in frames {
+                PMM.free_page(frame);
+            }
+            let slot = (self.base + self.size as u64) - KSTACK_SLOT_SIZE;
+            area.free.push(slot);
+        });
     }
 }
 
@@ -0,0 +1,97 @@
+//! Fault-tolerant access to user memory.
+//!
+//! The copy routines live in `Uaccess.S` and register their user-touching instructions in
+//! the `__ex_table` section. When one of them faults on an unmapped user address, the page
+//! fault handler redirects execution to the matching fixup through `fixup_exception`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+core::arch::global_asm!(include_str!("Uaccess.S"));
+
+unsafe extern "C" {
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn __strncpy_from_user(dst: *mut u8, src: *const u8, max: usize) -> isize;
+}
+
+/// One `__ex_table` entry: a faulting instruction and where to resume after a fault on it.
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: u64,
+    fixup: u64,
+}
+
+#[allow(improper_ctypes)]
+unsafe extern "C" {
+    static __ex_table_start: ExceptionTableEntry;
+    static __ex_table_end: ExceptionTableEntry;
+}
+
+/// Set once the CPU is known to implement SMAP (and thus `stac`/`clac`).
+#[unsafe(link_section = ".data.ro_after_init")]
+static SMAP_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Probe SMAP support so the copy routines know whether `stac`/`clac` may be executed.
+///
+/// Must run before CR4.SMAP is set.
+pub fn init() {
+    SMAP_SUPPORTED.store(super::features::has_smap(), Ordering::Relaxed);
+}
+
+/// Temporarily allows supervisor access to user pages (`stac`) while alive.
+struct UserAccessGuard;
+
+impl UserAccessGuard {
+    #[inline(always)]
+    fn new() -> Self {
+        if SMAP_SUPPORTED.load(Ordering::Relaxed) {
+            // SAFETY: Setting RFLAGS.AC only relaxes SMAP checks until the guard is dropped.
+            unsafe { core::arch::asm!("stac", options(nomem, nostack)) };
+        }
+        UserAccessGuard
+    }
+}
+
+impl Drop for UserAccessGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if SMAP_SUPPORTED.load(Ordering::Relaxed) {
+            // SAFETY: Clearing RFLAGS.AC re-enables SMAP checks.
+            unsafe { core::arch::asm!("clac", options(nomem, nostack)) };
+        }
+    }
+}
+
+/// Copy `len` bytes between user and kernel memory, returning the number of bytes not copied.
+///
+/// # Safety
+/// The kernel side of the copy must be valid for `len` bytes; the user side must lie
+/// entirely below the user address space limit.
+pub unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    let _guard = UserAccessGuard::new();
+    // SAFETY: Faults on the user side are caught by the exception table.
+    unsafe { __copy_user(dst, src, len) }
+}
+
+/// Copy a NUL-terminated user string into `dst`.
+///
+/// Returns the string length (or `dst.len()` if no terminator fits), or `None` on a fault.
+///
+/// # Safety
+/// `src .. src + dst.len()` must lie entirely below the user address space limit.
+pub unsafe fn strncpy_from_user(dst: &mut [u8], src: *const u8) -> Option<usize> {
+    let _guard = UserAccessGuard::new();
+    // SAFETY: Faults on the user side are caught by the exception table.
+    let len = unsafe { __strncpy_from_user(dst.as_mut_ptr(), src, dst.len()) };
+    usize::try_from(len).ok()
+}
+
+/// Look up the fixup address for a fault at `rip`, if the instruction is allowed to fault.
+pub fn fixup_exception(rip: u64) -> Option<u64> {
+    // SAFETY: The linker script brackets `__ex_table` with these symbols.
+    let table = unsafe {
+        let start = core::ptr::addr_of!(__ex_table_start);
+        let end = core::ptr::addr_of!(__ex_table_end);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    table.iter().find(|entry| entry.insn == rip).map(|entry| entry.fixup)
+}
@@ -5,10 +5,6 @@ pub const USER_CS: u64 = 0x1B;
 /// User Data Segment Selector with RPL = 3
 pub const USER_DS: u64 = 0x23;
 
-/// Default virtual memory base address for user process executable code
-pub const USER_CODE_VBASE: u64 = 0x0000_0000_0040_0000;
-/// Default top address for user process stack
-pub const USER_STACK_VTOP: u64 = 0x0000_0000_7000_0000;
 /// Default virtual memory base address for user process heap (brk)
 pub const USER_HEAP_VBASE: u64 = 0x0000_0000_1000_0000;
 /// Default virtual memory base address for user mmap region
@@ -1,13 +1,29 @@
 use crate::arch::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use crate::arch::lapic_timer;
 use crate::arch::{halt, read_cr2, without_interrupts};
-use crate::ipc::signal::SIGSEGV;
+use crate::ipc::signal::{SIGKILL, SIGSEGV};
+use crate::mm::PageFaultError;
 use crate::sched::SCHEDULER;
+use crate::sync::rcu;
+use crate::work::softirq;
 use x86_64::VirtAddr;
 use x86_64::structures::idt::PageFaultErrorCode;
 
 pub const KEYBOARD_VECTOR: u8 = 33;
 
+/// CMOS RTC interrupt (ISA IRQ 8).
+pub const RTC_VECTOR: u8 = 40;
+
+/// Inter-processor interrupt asking a CPU to look for a thread to run.
+pub const RESCHEDULE_VECTOR: u8 = 0xF0;
+
+/// Inter-processor interrupt asking a CPU to invalidate TLB entries.
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xF1;
+
+/// Interrupt enable flag in RFLAGS.
+const RFLAGS_IF: u64 = 1 << 9;
+
+#[unsafe(link_section = ".data.ro_after_init")]
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
 /// Load the shared IDT on the calling CPU.
@@ -56,6 +72,17 @@ pub fn init() {
         // Keyboard interrupt (vector 33, ISA IRQ 1)
         IDT.entries[KEYBOARD_VECTOR as usize].set_handler_fn(keyboard_handler as *const () as u64);
 
+        // CMOS RTC interrupt (vector 40, ISA IRQ 8)
+        IDT.entries[RTC_VECTOR as usize].set_handler_fn(rtc_handler as *const () as u64);
+
+        // Reschedule IPI (vector 0xF0)
+        IDT.entries[RESCHEDULE_VECTOR as usize]
+            .set_handler_fn(reschedule_handler as *const () as u64);
+
+        // TLB shootdown IPI (vector 0xF1)
+        IDT.entries[TLB_SHOOTDOWN_VECTOR as usize]
+            .set_handler_fn(tlb_shootdown_handler as *const () as u64);
+
         // Spurious interrupt (vector 0xFF)
         IDT.entries[0xFF].set_handler_fn(spurious_interrupt_handler as *const () as u64);
 
@@ -89,10 +116,46 @@ extern "x86-interrupt" fn bound_range_handler(stack_frame: &mut InterruptStackFr
     log::error!("EXCEPTION: BOUND RANGE EXCEEDED (#BR)\n{}", stack_frame);
 }
 
-fn kill_user_process(sig: u8) -> ! {
+/// Kill the current process with `sig` after a fault in `stack_frame`.
+///
+/// Signals whose default action dumps core do so if the fault was raised in user mode.
+fn kill_user_process(sig: u8, stack_frame: &InterruptStackFrame) -> ! {
+    let crashed = crate::proc::current_thread()
+        .filter(|_| {
+            (stack_frame.code_segment & 3) == 3
+                && crate::ipc::signal::default_action(sig)
+                    == crate::ipc::signal::SignalDefaultAction::CoreDump
+        })
+        .map(|thread_arc| {
+            let t = thread_arc.lock();
+            crate::proc::coredump::CrashedThread {
+                tid: t.tid,
+                regs: crate::arch::coredump::UserRegs::from_interrupt_frame(
+                    stack_frame,
+                    t.context.fs_base,
+                    t.context.gs_base,
+                ),
+                sig_pending: t.pending_signals.mask,
+                sig_blocked: t.sig_mask,
+            }
+        });
+
     let ppid_opt = if let Some(proc_arc) = crate::proc::current_process() {
+        let core = crashed.and_then(|mut crashed| {
+            let proc = proc_arc.lock();
+            crashed.sig_pending |= proc.pending_signals.mask;
+            Some((crate::proc::coredump::CoreProcess::capture(&proc)?, crashed))
+        });
+        // Writing the file may sleep: do it with no lock held and, as the fault came from
+        // user mode, with interrupts enabled.
+        let core_dumped = core.is_some_and(|(core, crashed)| {
+            crate::arch::enable_interrupts();
+            let dumped = crate::proc::coredump::dump_core(&core, &crashed, sig);
+            crate::arch::disable_interrupts();
+            dumped
+        });
         let mut proc = proc_arc.lock();
-        proc.exit(128 + sig as i32);
+        proc.exit_by_signal(sig, core_dumped);
         proc.ppid
     } else {
         crate::proc::ProcessId(0)
@@ -122,7 +185,7 @@ extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: &mut InterruptStac
             "User process invalid opcode (#UD) at RIP {:#x}",
             stack_frame.instruction_pointer
         );
-        kill_user_process(crate::ipc::signal::SIGILL);
+        kill_user_process(crate::ipc::signal::SIGILL, stack_frame);
     }
     log::error!("EXCEPTION: INVALID OPCODE (#UD)\n{}", stack_frame);
     halt();
@@ -137,6 +200,10 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    let fault_addr = read_cr2();
+    if crate::arch::cpu::stack::is_stack_guard(fault_addr) {
+        log::error!("KERNEL STACK OVERFLOW: guard page hit at {:#x}", fault_addr);
+    }
     log::error!(
         "EXCEPTION: DOUBLE FAULT (#DF, Error Code: {:#x})\n{}",
         error_code,
@@ -191,7 +258,7 @@ extern "x86-interrupt" fn general_protection_fault_handler(
             error_code,
             stack_frame.instruction_pointer
         );
-        kill_user_process(crate::ipc::signal::SIGSEGV);
+        kill_user_process(crate::ipc::signal::SIGSEGV, stack_frame);
     }
     log::error!(
         "EXCEPTION: GENERAL PROTECTION FAULT (#GP, Error Code: {:#x})\n{}",
@@ -257,6 +324,16 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     let fault_virt = VirtAddr::new(read_cr2());
     let fault_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let kernel_mode = (stack_frame.code_segment & 3) == 0;
+
+    if kernel_mode && crate::arch::cpu::stack::is_stack_guard(fault_virt.as_u64()) {
+        log::error!(
+            "KERNEL STACK OVERFLOW: guard page hit at {:#x}\n{}",
+            fault_virt.as_u64(),
+            stack_frame
+        );
+        halt();
+    }
 
     let cpu_id = unsafe { super::lapic::get_lapic().id() };
 
@@ -265,17 +342,65 @@ extern "x86-interrupt" fn page_fault_handler(
         sched.current_threads[cpu_id as usize].clone()
     });
 
+    // The gate cleared IF. Restore it if the faulting context could be interrupted, so a
+    // preempted holder of the address space lock gets to run while this CPU waits on it.
+    if stack_frame.cpu_flags & RFLAGS_IF != 0 {
+        crate::arch::enable_interrupts();
+    }
+
     if let Some(thread_arc) = current_thread {
-        let thread = thread_arc.lock();
-        if let Some(proc_arc) = thread.process.upgrade() {
-            let proc = proc_arc.lock();
-            let mut addr_space = proc.address_space.lock();
-            if addr_space.handle_page_fault(fault_virt, fault_code).is_ok() {
-                return;
+        let mut oom_retries = 0;
+        loop {
+            let result = {
+                let thread = thread_arc.lock();
+                let proc_arc = match thread.process.upgrade() {
+                    Some(p) => p,
+                    None => break,
+                };
+                let proc = proc_arc.lock();
+                let mut addr_space = proc.address_space.lock();
+                addr_space.handle_page_fault(fault_virt, fault_code)
+            };
+
+            match result {
+                Ok(()) => return,
+                Err(PageFaultError::FrameAllocationFailed) => {
+                    // All locks are dropped: let the OOM killer reclaim memory and retry,
+                    // a few times at most in case the memory freed keeps being taken.
+                    let self_pid = crate::proc::current_process().map(|p| p.lock().pid);
+                    oom_retries += 1;
+                    let victim = if oom_retries <= crate::mm::oom::OOM_MAX_RETRIES {
+                        crate::mm::oom::out_of_memory(0)
+                    } else {
+                        None
+                    };
+                    match victim {
+                        Some(victim) if Some(victim) != self_pid => continue,
+                        _ => kill_user_process(SIGKILL, stack_frame),
+                    }
+                }
+                Err(_) => break,
             }
         }
     }
 
+    // A fault inside a user-copy helper returns an error to its caller instead.
+    if kernel_mode {
+        if let Some(fixup) = crate::arch::uaccess::fixup_exception(stack_frame.instruction_pointer) {
+            // Volatile so the store to the hardware-restored frame is not optimized away.
+            // SAFETY: `fixup` is a landing pad registered for the faulting instruction.
+            unsafe { core::ptr::write_volatile(&mut stack_frame.instruction_pointer, fixup) };
+            return;
+        }
+        if fault_virt.as_u64() <= crate::syscalls::USER_SPACE_MAX_ADDR {
+            log::error!(
+                "Kernel access to user address {:#x} outside the uaccess helpers at RIP {:#x}",
+                fault_virt.as_u64(),
+                stack_frame.instruction_pointer
+            );
+        }
+    }
+
     if (stack_frame.code_segment & 3) == 3 || fault_virt.as_u64() <= crate::syscalls::USER_SPACE_MAX_ADDR {
         log::warn!(
             "User process page fault (SIGSEGV) at {:#x}, Error Code: {:#x} [{:?}]",
@@ -283,7 +408,7 @@ extern "x86-interrupt" fn page_fault_handler(
             error_code,
             fault_code
         );
-        kill_user_process(SIGSEGV);
+        kill_user_process(SIGSEGV, stack_frame);
     }
 
     log::error!(
@@ -296,15 +421,52 @@ extern "x86-interrupt" fn page_fault_handler(
     halt();
 }
 
-extern "x86-interrupt" fn timer_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "x86-interrupt" fn timer_handler(stack_frame: &mut InterruptStackFrame) {
     let cpu_id = unsafe { super::lapic::get_lapic().id() };
+    softirq::irq_enter(cpu_id);
 
     unsafe {
         super::lapic::get_lapic().end_of_interrupt();
     }
 
-    crate::sched::SCHEDULER.lock().tick(cpu_id, 10_000_000);
-    crate::sched::schedule(true);
+    let user_mode = (stack_frame.code_segment & 3) == 3;
+    let resched = crate::time::tick::handle_interrupt(cpu_id, user_mode);
+    if softirq::irq_exit(cpu_id) && resched && !rcu::defer_preemption(cpu_id) {
+        crate::sched::schedule(true);
+    }
+}
+
+/// Make the CPU with LAPIC ID `cpu_id` reschedule, e.g. after queueing a thread on it.
+pub fn send_reschedule(cpu_id: u32) {
+    // SAFETY: The LAPIC is not modified after initialisation.
+    if let Some(lapic) = unsafe { super::lapic::try_get_lapic() } {
+        lapic.send_ipi(cpu_id, RESCHEDULE_VECTOR);
+    }
+}
+
+extern "x86-interrupt" fn reschedule_handler(_stack_frame: &mut InterruptStackFrame) {
+    let cpu_id = crate::arch::cpu_id();
+    softirq::irq_enter(cpu_id);
+
+    unsafe {
+        super::lapic::get_lapic().end_of_interrupt();
+    }
+
+    // Threads are only switched between, not in the middle of softirqs or RCU readers.
+    if softirq::irq_exit(cpu_id) && !rcu::defer_preemption(cpu_id) {
+        crate::sched::preempt();
+    }
+}
+
+extern "x86-interrupt" fn tlb_shootdown_handler(_stack_frame: &mut InterruptStackFrame) {
+    let cpu_id = crate::arch::cpu_id();
+    softirq::irq_enter(cpu_id);
+    crate::arch::paging::tlb::handle_shootdown();
+
+    unsafe {
+        super::lapic::get_lapic().end_of_interrupt();
+    }
+    softirq::irq_exit(cpu_id);
 }
 
 extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
@@ -314,6 +476,9 @@ extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: &mut Interrup
 }
 
 extern "x86-interrupt" fn keyboard_handler(_stack_frame: &mut InterruptStackFrame) {
+    let cpu_id = crate::arch::cpu_id();
+    softirq::irq_enter(cpu_id);
+
     // Drain pending bytes from 8042 controller output buffer.
     // Bit 0 of port 0x64 (OBF): Output buffer full
     // Bit 5 of port 0x64 (AUX): 0 = Keyboard (Port 1), 1 = Mouse (Port 2)
@@ -329,7 +494,7 @@ extern "x86-interrupt" fn keyboard_handler(_stack_frame: &mut InterruptStackFram
 
         // Only process keyboard data (bit 5 clear). Mouse data (bit 5 set) is discarded.
         if (status & 0x20) == 0 {
-            crate::drivers::char::keyboard::handle_scancode(byte);
+            crate::drivers::char::keyboard::queue_scancode(byte);
         }
     }
 
@@ -337,4 +502,17 @@ extern "x86-interrupt" fn keyboard_handler(_stack_frame: &mut InterruptStackFram
     unsafe {
         super::lapic::get_lapic().end_of_interrupt();
     }
+    softirq::irq_exit(cpu_id);
+}
+
+extern "x86-interrupt" fn rtc_handler(_stack_frame: &mut InterruptStackFrame) {
+    let cpu_id = crate::arch::cpu_id();
+    softirq::irq_enter(cpu_id);
+    crate::drivers::time::cmos_rtc::handle_interrupt();
+
+    // SAFETY: LAPIC is guaranteed to be initialized and active when receiving interrupts.
+    unsafe {
+        super::lapic::get_lapic().end_of_interrupt();
+    }
+    softirq::irq_exit(cpu_id);
 }
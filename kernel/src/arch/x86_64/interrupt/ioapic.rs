@@ -308,15 +308,13 @@ pub fn mask_isa_irq(irq: u8) {
 /// Unmask a Global System Interrupt (GSI) on its controlling IOAPIC.
 pub fn unmask_gsi(gsi: u32) {
     let guard = IO_APICS.lock();
-    for ioapic_opt in guard.iter() {
-        if let Some(ioapic) = ioapic_opt {
-            let max_entries = ioapic.max_redirection_entries();
-            if gsi >= ioapic.gsi_base && gsi - ioapic.gsi_base < max_entries {
-                let irq_line = gsi - ioapic.gsi_base;
-                ioapic.unmask_irq(irq_line);
-                log::info!("IOAPIC unmasked GSI {} (IRQ line {})", gsi, irq_line);
-                return;
-            }
+    for ioapic in guard.iter().flatten() {
+        let max_entries = ioapic.max_redirection_entries();
+        if gsi >= ioapic.gsi_base && gsi - ioapic.gsi_base < max_entries {
+            let irq_line = gsi - ioapic.gsi_base;
+            ioapic.unmask_irq(irq_line);
+            log::info!("IOAPIC unmasked GSI {} (IRQ line {})", gsi, irq_line);
+            return;
         }
     }
     log::warn!("IOAPIC: No controller found for GSI {}", gsi);
@@ -325,17 +323,35 @@ pub fn unmask_gsi(gsi: u32) {
 /// Mask a Global System Interrupt (GSI) on its controlling IOAPIC.
 pub fn mask_gsi(gsi: u32) {
     let guard = IO_APICS.lock();
-    for ioapic_opt in guard.iter() {
-        if let Some(ioapic) = ioapic_opt {
-            let max_entries = ioapic.max_redirection_entries();
-            if gsi >= ioapic.gsi_base && gsi - ioapic.gsi_base < max_entries {
-                let irq_line = gsi - ioapic.gsi_base;
-                ioapic.mask_irq(irq_line);
-                log::info!("IOAPIC masked GSI {} (IRQ line {})", gsi, irq_line);
-                return;
-            }
+    for ioapic in guard.iter().flatten() {
+        let max_entries = ioapic.max_redirection_entries();
+        if gsi >= ioapic.gsi_base && gsi - ioapic.gsi_base < max_entries {
+            let irq_line = gsi - ioapic.gsi_base;
+            ioapic.mask_irq(irq_line);
+            log::info!("IOAPIC masked GSI {} (IRQ line {})", gsi, irq_line);
+            return;
         }
     }
     log::warn!("IOAPIC: No controller found for GSI {}", gsi);
 }
 
+
+/// Route a Global System Interrupt (GSI) as an edge-triggered, active-high interrupt with
+/// `vector` to the CPU with LAPIC ID `lapic_id` and unmask it.
+///
+/// Returns whether an IOAPIC controls the GSI.
+pub fn route_gsi(gsi: u32, vector: u8, lapic_id: u32) -> bool {
+    let guard = IO_APICS.lock();
+    for ioapic in guard.iter().flatten() {
+        let max_entries = ioapic.max_redirection_entries();
+        if gsi >= ioapic.gsi_base && gsi - ioapic.gsi_base < max_entries {
+            let irq_line = gsi - ioapic.gsi_base;
+            ioapic.set_irq_route(irq_line, vector, lapic_id, DeliveryMode::Fixed, false, false);
+            ioapic.unmask_irq(irq_line);
+            log::info!("IOAPIC routed GSI {} to vector {}", gsi, vector);
+            return true;
+        }
+    }
+    log::warn!("IOAPIC: No controller found for GSI {}", gsi);
+    false
+}
@@ -27,6 +27,12 @@ pub const TIMER_INITIAL_COUNT: usize = 0x380;
 pub const TIMER_CURRENT_COUNT: usize = 0x390;
 pub const TIMER_DIVIDE_CONFIG: usize = 0x3E0;
 
+/// ICR delivery status: set while the previous IPI has not been accepted yet.
+const ICR_SEND_PENDING: u32 = 1 << 12;
+
+/// ICR level bit; must be set for every delivery mode except INIT de-assert.
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
 /// The spurious interrupt vector number. Must have bits [3:0] = 1111b.
 const SPURIOUS_VECTOR_NUM: u32 = 0xFF;
 
@@ -93,6 +99,18 @@ impl LocalApic {
         self.write_register(EOI, 0);
     }
 
+    /// Send a fixed interrupt with `vector` to the CPU whose LAPIC ID is `apic_id`.
+    pub fn send_ipi(&self, apic_id: u32, vector: u8) {
+        crate::arch::without_interrupts(|| {
+            // The ICR holds one IPI at a time; wait for the previous one to go out.
+            while self.read_register(ICR_LOW) & ICR_SEND_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+            self.write_register(ICR_HIGH, apic_id << 24);
+            self.write_register(ICR_LOW, ICR_LEVEL_ASSERT | vector as u32);
+        });
+    }
+
     /// Read a 32-bit value from a LAPIC MMIO register.
     fn read_register(&self, offset: usize) -> u32 {
         // SAFETY: The LAPIC MMIO region is mapped via HHDM and each register
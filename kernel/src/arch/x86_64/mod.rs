@@ -1,15 +1,20 @@
 pub mod acpi;
+pub mod coredump;
 pub mod cpu;
 pub mod interrupt;
 pub mod paging;
 pub mod signal;
 pub mod syscall;
 pub mod timer;
+pub mod vdso;
 
+#[cfg(feature = "lockdep")]
+pub use cpu::backtrace;
 pub use cpu::gdt;
 pub use cpu::ports;
 pub use cpu::rdtsc;
 pub use cpu::tss;
+pub use cpu::uaccess;
 pub use cpu::userspace;
 pub use cpu::{active_address_space_root, read_cr2, set_address_space_root};
 pub use interrupt::idt;
@@ -33,12 +38,29 @@ pub fn halt() {
     hlt();
 }
 
+/// Enable interrupts and halt until the next one, without a window in between in which
+/// a wakeup interrupt could be missed.
+#[inline(always)]
+pub fn enable_interrupts_and_halt() {
+    x86_interrupts::enable_and_hlt();
+}
+
 pub fn idle() -> ! {
     loop {
         halt();
     }
 }
 
+/// Busy-wait hint for spin loops.
+///
+/// Also answers TLB shootdowns, so that a CPU spinning with interrupts disabled cannot
+/// stall another CPU waiting for its acknowledgement.
+#[inline(always)]
+pub fn cpu_relax() {
+    core::hint::spin_loop();
+    paging::tlb::poll();
+}
+
 /// Disable interrupts on the calling CPU and return the previous interrupt flag state.
 #[inline(always)]
 pub fn disable_interrupts() -> bool {
@@ -74,9 +96,11 @@ pub fn init_stack(stack: &mut [u8], entry: extern "C" fn(*mut u8), arg: *mut u8)
 }
 
 /// Switch CPU stack and execution context between two threads.
-pub unsafe fn switch_context(prev_rsp_ptr: *mut u64, next_rsp: u64) {
+///
+/// `prev_on_cpu` is cleared once the previous thread's stack is no longer in use.
+pub unsafe fn switch_context(prev_rsp_ptr: *mut u64, next_rsp: u64, prev_on_cpu: *mut u8) {
     unsafe {
-        cpu::context::switch_context(prev_rsp_ptr, next_rsp);
+        cpu::context::switch_context(prev_rsp_ptr, next_rsp, prev_on_cpu);
     }
 }
 
@@ -90,6 +114,7 @@ pub unsafe fn switch_context_to(next_rsp: u64) -> ! {
 /// Main architecture hardware initialization entry point.
 pub fn init() {
     cpu::init();
+    paging::protect_kernel_image();
 
     let madt_info =
         acpi::parse_madt().expect("Failed to parse ACPI MADT — APIC initialization requires MADT");
@@ -102,6 +127,7 @@ pub fn init() {
     );
 
     interrupt::init(&madt_info);
+    paging::tlb::init_cpu();
     timer::init();
 
     enable_interrupts();
@@ -66,3 +66,24 @@ pub fn map_mmio(phys_addr: u64, size: usize) {
         }
     }
 }
+
+/// Ensure the kernel page table has a top-level (PML4) entry covering `virt`.
+///
+/// User page tables copy the kernel half of the PML4 when they are created, so a kernel
+/// region mapped on demand must have its PML4 entry in place before the first one is.
+pub fn preallocate_kernel_pml4_entry(virt: VirtAddr) -> Result<(), &'static str> {
+    use x86_64::structures::paging::{FrameAllocator, PageTable as X86PageTable};
+
+    let hhdm = hhdm_offset();
+    let root = crate::mm::kernel_root();
+    // SAFETY: The kernel root is a valid PML4 mapped through the HHDM.
+    let pml4 = unsafe { &mut *((root + hhdm) as *mut X86PageTable) };
+    let entry = &mut pml4[virt.p4_index()];
+    if entry.is_unused() {
+        let frame = super::frame::KernelFrameAllocator
+            .allocate_frame()
+            .ok_or("Out of memory for kernel PML4 entry")?;
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+    Ok(())
+}
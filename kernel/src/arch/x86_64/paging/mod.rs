@@ -1,9 +1,12 @@
 pub mod flags;
 pub mod frame;
 pub mod helpers;
+pub mod protect;
 pub mod table;
+pub mod tlb;
 
 pub use flags::enable_nxe;
 pub use frame::KernelFrameAllocator;
-pub use helpers::{active_cr3, ensure_mapped, map_mmio, read_cr2};
+pub use helpers::{active_cr3, ensure_mapped, map_mmio, preallocate_kernel_pml4_entry, read_cr2};
+pub use protect::{protect_kernel_image, seal_ro_after_init};
 pub use table::ArchPageTable;
@@ -0,0 +1,81 @@
+//! Kernel image page protections.
+//!
+//! The linker script brackets each kernel section with boundary symbols. At boot the
+//! image is remapped so that no page is both writable and executable, and once
+//! initialization is over `.data.ro_after_init` is made read-only as well.
+
+use super::table::ArchPageTable;
+use crate::mm::vmm::PageTable;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __ro_after_init_start: u8;
+    static __ro_after_init_end: u8;
+    static __data_start: u8;
+    static __bss_end: u8;
+}
+
+/// Page-aligned `[start, end)` bounds of a linker-defined section.
+fn section(start: *const u8, end: *const u8) -> (u64, u64) {
+    (start as u64 & !4095, (end as u64 + 4095) & !4095)
+}
+
+/// Rewrite the permissions of every page in `[start, end)`, keeping its other flags.
+fn set_protection(start: u64, end: u64, writable: bool, executable: bool) {
+    // SAFETY: The kernel root stays valid for the lifetime of the kernel.
+    let mut table = unsafe { ArchPageTable::from_root(PhysAddr::new(crate::mm::kernel_root())) };
+
+    for page in (start..end).step_by(4096) {
+        let page = VirtAddr::new(page);
+        let Some((_, flags)) = table.get_entry(page) else {
+            continue;
+        };
+        let mut new_flags = flags - (PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+        if writable {
+            new_flags |= PageTableFlags::WRITABLE;
+        }
+        if !executable {
+            new_flags |= PageTableFlags::NO_EXECUTE;
+        }
+        if new_flags != flags && table.remap(page, new_flags).is_err() {
+            log::warn!("Cannot change protection of kernel page {:#x}", page.as_u64());
+        }
+    }
+    table.flush_tlb();
+}
+
+/// Enforce W^X on the kernel image: text is read-only, rodata is also non-executable and
+/// the data sections are writable but non-executable.
+pub fn protect_kernel_image() {
+    let text = section(&raw const __text_start, &raw const __text_end);
+    let rodata = section(&raw const __rodata_start, &raw const __rodata_end);
+    let ro_after_init = section(&raw const __ro_after_init_start, &raw const __ro_after_init_end);
+    let data = section(&raw const __data_start, &raw const __bss_end);
+
+    set_protection(text.0, text.1, false, true);
+    set_protection(rodata.0, rodata.1, false, false);
+    set_protection(ro_after_init.0, ro_after_init.1, true, false);
+    set_protection(data.0, data.1, true, false);
+
+    log::info!(
+        "Kernel W^X: text {:#x}-{:#x} RX, rodata {:#x}-{:#x} R, data {:#x}-{:#x} RW",
+        text.0,
+        text.1,
+        rodata.0,
+        rodata.1,
+        ro_after_init.0,
+        data.1
+    );
+}
+
+/// Write-protect `.data.ro_after_init`. Called once boot-time initialization is complete.
+pub fn seal_ro_after_init() {
+    let (start, end) = section(&raw const __ro_after_init_start, &raw const __ro_after_init_end);
+    set_protection(start, end, false, false);
+    log::info!("Sealed .data.ro_after_init ({} KiB)", (end - start) / 1024);
+}
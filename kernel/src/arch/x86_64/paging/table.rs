@@ -1,5 +1,6 @@
 use super::flags::enable_nxe;
 use super::frame::KernelFrameAllocator;
+use super::tlb::TlbBatch;
 use crate::arch::{active_address_space_root, set_address_space_root};
 use crate::mm::hhdm_offset;
 use crate::mm::pmm::PMM;
@@ -7,8 +8,10 @@ use crate::mm::vmm::PageTable;
 use x86_64::structures::paging::mapper::{
     FlagUpdateError, MapToError, Mapper, Translate, TranslateResult, UnmapError,
 };
+use x86_64::structures::paging::page_table::PageTableEntry;
 use x86_64::structures::paging::{
-    OffsetPageTable, Page, PageTable as X86PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    OffsetPageTable, Page, PageTable as X86PageTable, PageTableFlags, PhysFrame, Size2MiB,
+    Size4KiB,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
@@ -16,6 +19,8 @@ use x86_64::{PhysAddr, VirtAddr};
 pub struct ArchPageTable {
     pml4_phys: PhysAddr,
     is_owned: bool,
+    /// Changed translations other CPUs have not been told about yet.
+    pending: TlbBatch,
 }
 
 unsafe impl Send for ArchPageTable {}
@@ -29,6 +34,76 @@ impl ArchPageTable {
         // SAFETY: pml4_ptr is a valid PML4 pointer mapped at HHDM offset.
         unsafe { OffsetPageTable::new(&mut *pml4_ptr, VirtAddr::new(hhdm)) }
     }
+
+    /// Walk to the page-directory entry covering `page`, if its PML4 and PDPT entries exist.
+    ///
+    /// # Safety
+    /// The returned reference aliases live page-table memory; the caller must hold the
+    /// owning address space's lock and must not keep it across a table free.
+    unsafe fn pd_entry(&self, page: VirtAddr) -> Option<&'static mut PageTableEntry> {
+        let hhdm = hhdm_offset();
+        let page = Page::<Size4KiB>::containing_address(page);
+        let pml4 = unsafe { &mut *((self.pml4_phys.as_u64() + hhdm) as *mut X86PageTable) };
+        let pml4e = &pml4[page.p4_index()];
+        if !pml4e.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        let pdpt = unsafe { &mut *((pml4e.addr().as_u64() + hhdm) as *mut X86PageTable) };
+        let pdpte = &pdpt[page.p3_index()];
+        if !pdpte.flags().contains(PageTableFlags::PRESENT)
+            || pdpte.flags().contains(PageTableFlags::HUGE_PAGE)
+        {
+            return None;
+        }
+        let pd = unsafe { &mut *((pdpte.addr().as_u64() + hhdm) as *mut X86PageTable) };
+        Some(&mut pd[page.p2_index()])
+    }
+}
+
+/// Returns true if the page table at `paddr` has no used entries.
+fn table_is_empty(paddr: PhysAddr, hhdm: u64) -> bool {
+    let table = unsafe { &*((paddr.as_u64() + hhdm) as *const X86PageTable) };
+    table.iter().all(|entry| entry.is_unused())
+}
+
+fn huge_map_err(err: MapToError<Size2MiB>) -> MapToError<Size4KiB> {
+    match err {
+        MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+        MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+        MapToError::PageAlreadyMapped(frame) => {
+            MapToError::PageAlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+        }
+    }
+}
+
+/// Count the 4 KiB pages mapped within `[start, end)` by the table at `paddr`, of
+/// `level` (4 for the PML4), whose first entry maps `base`.
+fn count_mapped_recursive(
+    paddr: PhysAddr,
+    level: usize,
+    base: u64,
+    start: u64,
+    end: u64,
+    hhdm: u64,
+) -> usize {
+    let table = unsafe { &*((paddr.as_u64() + hhdm) as *const X86PageTable) };
+    let span = 1u64 << (12 + 9 * (level - 1));
+    let mut count = 0;
+    for (i, entry) in table.iter().enumerate() {
+        let lo = base + i as u64 * span;
+        let hi = lo + span;
+        if hi <= start || lo >= end || !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        if level == 1 {
+            count += 1;
+        } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            count += ((hi.min(end) - lo.max(start)) / 4096) as usize;
+        } else {
+            count += count_mapped_recursive(entry.addr(), level - 1, lo, start, end, hhdm);
+        }
+    }
+    count
 }
 
 fn free_table_recursive(paddr: PhysAddr, level: usize, hhdm: u64) {
@@ -46,7 +121,7 @@ fn free_table_recursive(paddr: PhysAddr, level: usize, hhdm: u64) {
         if entry.flags().contains(PageTableFlags::PRESENT) {
             let child_phys = entry.addr();
             if level > 1 && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
-                PMM.free_page(child_phys);
+                PMM.free_huge_page(child_phys);
                 continue;
             }
             free_table_recursive(child_phys, level - 1, hhdm);
@@ -59,6 +134,9 @@ fn free_table_recursive(paddr: PhysAddr, level: usize, hhdm: u64) {
 impl Drop for ArchPageTable {
     fn drop(&mut self) {
         if self.is_owned {
+            // No CPU may keep translations (e.g. under a PCID) into the freed tables.
+            self.pending.add_all();
+            self.flush_tlb();
             let hhdm = hhdm_offset();
             free_table_recursive(self.pml4_phys, 4, hhdm);
         }
@@ -92,6 +170,7 @@ impl PageTable for ArchPageTable {
         Ok(Self {
             pml4_phys,
             is_owned: true,
+            pending: TlbBatch::new(),
         })
     }
 
@@ -103,6 +182,7 @@ impl PageTable for ArchPageTable {
         Self {
             pml4_phys: root,
             is_owned: false,
+            pending: TlbBatch::new(),
         }
     }
 
@@ -180,6 +260,7 @@ impl PageTable for ArchPageTable {
         let (frame, flush) = mapper.unmap(target_page)?;
 
         flush.flush();
+        self.pending.add(page);
         Ok(frame.start_address())
     }
 
@@ -204,6 +285,7 @@ impl PageTable for ArchPageTable {
         let flush = unsafe { mapper.update_flags(target_page, flags)? };
 
         flush.flush();
+        self.pending.add(page);
         Ok(())
     }
 
@@ -220,6 +302,122 @@ impl PageTable for ArchPageTable {
         Ok(())
     }
 
+    fn map_huge(
+        &mut self,
+        page: VirtAddr,
+        frame: PhysAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let target_page = Page::<Size2MiB>::from_start_address(page)
+            .map_err(|_| MapToError::FrameAllocationFailed)?;
+        let target_frame = PhysFrame::<Size2MiB>::from_start_address(frame)
+            .map_err(|_| MapToError::FrameAllocationFailed)?;
+
+        // A page table left behind by earlier 4 KiB mappings that have since been
+        // unmapped would block the huge entry; reclaim it.
+        let hhdm = hhdm_offset();
+        if let Some(entry) = unsafe { self.pd_entry(page) } {
+            let entry_flags = entry.flags();
+            if entry_flags.contains(PageTableFlags::PRESENT)
+                && !entry_flags.contains(PageTableFlags::HUGE_PAGE)
+                && table_is_empty(entry.addr(), hhdm)
+            {
+                let pt_phys = entry.addr();
+                entry.set_unused();
+                x86_64::instructions::tlb::flush(page);
+                // Other CPUs may still walk through the table; flush before freeing it.
+                self.pending.add(page);
+                self.flush_tlb();
+                PMM.free_page(pt_phys);
+            }
+        }
+
+        let mut mapper = unsafe { self.get_offset_page_table() };
+        let mut frame_allocator = KernelFrameAllocator;
+        let parent_table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE;
+
+        // SAFETY: Installing a huge leaf entry using the standard OffsetPageTable mapper.
+        unsafe {
+            mapper
+                .map_to_with_table_flags(
+                    target_page,
+                    target_frame,
+                    flags,
+                    parent_table_flags,
+                    &mut frame_allocator,
+                )
+                .map_err(huge_map_err)?
+                .flush();
+        }
+
+        Ok(())
+    }
+
+    fn unmap_huge(&mut self, page: VirtAddr) -> Result<PhysAddr, UnmapError> {
+        let target_page = Page::<Size2MiB>::from_start_address(page)
+            .map_err(|_| UnmapError::InvalidFrameAddress(PhysAddr::zero()))?;
+
+        let mut mapper = unsafe { self.get_offset_page_table() };
+        let (frame, flush) = mapper.unmap(target_page)?;
+
+        flush.flush();
+        self.pending.add(page);
+        Ok(frame.start_address())
+    }
+
+    fn remap_huge(&mut self, page: VirtAddr, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+        let target_page = Page::<Size2MiB>::from_start_address(page)
+            .map_err(|_| FlagUpdateError::PageNotMapped)?;
+
+        let mut mapper = unsafe { self.get_offset_page_table() };
+        // SAFETY: Updating flags of an existing huge leaf entry.
+        let flush = unsafe { mapper.update_flags(target_page, flags)? };
+
+        flush.flush();
+        self.pending.add(page);
+        Ok(())
+    }
+
+    fn split_huge(&mut self, page: VirtAddr) -> Result<(), MapToError<Size4KiB>> {
+        let base = page.align_down(crate::mm::vmm::HUGE_PAGE_SIZE);
+        let entry = match unsafe { self.pd_entry(base) } {
+            Some(entry) if entry.flags().contains(PageTableFlags::HUGE_PAGE) => entry,
+            _ => return Ok(()),
+        };
+
+        let huge_flags = entry.flags();
+        let frame_base = entry.addr();
+        let leaf_flags = huge_flags & !PageTableFlags::HUGE_PAGE;
+
+        let pt_phys = PMM.alloc_page().ok_or(MapToError::FrameAllocationFailed)?;
+        let hhdm = hhdm_offset();
+        let pt = unsafe { &mut *((pt_phys.as_u64() + hhdm) as *mut X86PageTable) };
+        for (i, pte) in pt.iter_mut().enumerate() {
+            pte.set_addr(frame_base + (i as u64 * 4096), leaf_flags);
+        }
+
+        entry.set_addr(
+            pt_phys,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        );
+        x86_64::instructions::tlb::flush(base);
+        self.pending.add(base);
+        Ok(())
+    }
+
+    fn huge_slot_vacant(&self, page: VirtAddr) -> bool {
+        match unsafe { self.pd_entry(page) } {
+            None => true,
+            Some(entry) => {
+                entry.is_unused()
+                    || (!entry.flags().contains(PageTableFlags::HUGE_PAGE)
+                        && table_is_empty(entry.addr(), hhdm_offset()))
+            }
+        }
+    }
+
     fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
         let mapper = unsafe { self.get_offset_page_table() };
         match mapper.translate(virt) {
@@ -242,6 +440,15 @@ impl PageTable for ArchPageTable {
         }
     }
 
+    fn count_mapped(&self, start: VirtAddr, end: VirtAddr) -> usize {
+        let (start, end) = (start.as_u64(), end.as_u64());
+        count_mapped_recursive(self.pml4_phys, 4, 0, start, end, hhdm_offset())
+    }
+
+    fn flush_tlb(&mut self) {
+        super::tlb::flush(self.pml4_phys.as_u64(), &mut self.pending);
+    }
+
     unsafe fn activate(&self) {
         unsafe {
             set_address_space_root(self.pml4_phys.as_u64());
@@ -0,0 +1,352 @@
+//! TLB maintenance across CPUs.
+//!
+//! Page table updates invalidate the local TLB right away and are recorded in a
+//! [`TlbBatch`]. Flushing the batch sends one shootdown IPI to every other CPU that may
+//! cache translations of the address space and waits until all of them have acted on it,
+//! so frames may be freed afterwards.
+//!
+//! With PCID, each CPU keeps translations of its last few address spaces tagged in the
+//! TLB and switches between them without flushing. The CPUs an address space is active
+//! on, or still cached on, are recorded per CPU and gathered into a mask when flushing.
+//! Cached but inactive address spaces are flushed lazily on their next activation.
+
+use crate::arch::lapic;
+use crate::arch::tss::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::VirtAddr;
+
+/// Pages invalidated one by one before a batch falls back to a full flush.
+pub const MAX_BATCH_PAGES: usize = 32;
+
+/// PCIDs handed out per CPU; PCID 0 is used while PCID is off.
+const NR_PCIDS: usize = 6;
+
+/// CR3 bit 63: keep the TLB entries of the PCID being loaded.
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+/// Pending TLB invalidations of one page table.
+pub struct TlbBatch {
+    pages: [u64; MAX_BATCH_PAGES],
+    count: usize,
+    full: bool,
+}
+
+impl TlbBatch {
+    pub const fn new() -> Self {
+        Self {
+            pages: [0; MAX_BATCH_PAGES],
+            count: 0,
+            full: false,
+        }
+    }
+
+    /// Record that the translation of the page (of any size) at `page` changed.
+    pub fn add(&mut self, page: VirtAddr) {
+        if self.full {
+            return;
+        }
+        if self.count == MAX_BATCH_PAGES {
+            self.full = true;
+            return;
+        }
+        self.pages[self.count] = page.as_u64();
+        self.count += 1;
+    }
+
+    /// Record that every translation of the address space must go.
+    pub fn add_all(&mut self) {
+        self.full = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0 && !self.full
+    }
+
+    fn clear(&mut self) {
+        self.count = 0;
+        self.full = false;
+    }
+}
+
+/// TLB bookkeeping of one CPU. Only the owning CPU writes it; others read it to find
+/// shootdown targets.
+struct CpuTlb {
+    /// Whether the CPU takes part in shootdowns.
+    online: AtomicBool,
+    /// Whether CR4.PCIDE is set on the CPU.
+    pcid: AtomicBool,
+    /// Page table root loaded in CR3.
+    active_root: AtomicU64,
+    /// Roots tagged with PCID `slot + 1`, 0 for an unused slot.
+    pcid_roots: [AtomicU64; NR_PCIDS],
+    /// Slots whose TLB entries are out of date and must be flushed on next use.
+    stale: AtomicU32,
+    /// Slot to reuse when a root without a PCID is loaded.
+    next_victim: AtomicUsize,
+}
+
+impl CpuTlb {
+    const fn new() -> Self {
+        Self {
+            online: AtomicBool::new(false),
+            pcid: AtomicBool::new(false),
+            active_root: AtomicU64::new(0),
+            pcid_roots: [const { AtomicU64::new(0) }; NR_PCIDS],
+            stale: AtomicU32::new(0),
+            next_victim: AtomicUsize::new(0),
+        }
+    }
+
+    fn pcid_slot(&self, root: u64) -> Option<usize> {
+        self.pcid_roots
+            .iter()
+            .position(|r| r.load(Ordering::SeqCst) == root)
+    }
+
+    /// Whether the CPU may hold translations of `root`.
+    fn caches(&self, root: u64) -> bool {
+        self.online.load(Ordering::SeqCst)
+            && (self.active_root.load(Ordering::SeqCst) == root || self.pcid_slot(root).is_some())
+    }
+}
+
+static CPU_TLB: [CpuTlb; MAX_CPUS] = [const { CpuTlb::new() }; MAX_CPUS];
+
+/// A shootdown broadcast to other CPUs.
+#[derive(Clone, Copy)]
+struct Request {
+    root: u64,
+    kernel: bool,
+    full: bool,
+    count: usize,
+    pages: [u64; MAX_BATCH_PAGES],
+}
+
+struct RequestSlot(UnsafeCell<Request>);
+
+// SAFETY: Only the holder of `INITIATOR` writes the request, and only before publishing it
+// through `PENDING`; targets read it until they clear their bit.
+unsafe impl Sync for RequestSlot {}
+
+static REQUEST: RequestSlot = RequestSlot(UnsafeCell::new(Request {
+    root: 0,
+    kernel: false,
+    full: false,
+    count: 0,
+    pages: [0; MAX_BATCH_PAGES],
+}));
+
+/// CPUs that have not yet acted on `REQUEST`.
+static PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// Serializes shootdowns; one request is in flight at a time.
+static INITIATOR: Spinlock<()> = Spinlock::new(());
+
+/// Prepare the calling CPU for shootdowns and enable PCID if the CPU supports it.
+pub fn init_cpu() {
+    let cpu = crate::arch::cpu_id() as usize;
+    if cpu >= MAX_CPUS {
+        return;
+    }
+    let tlb = &CPU_TLB[cpu];
+    tlb.active_root
+        .store(crate::arch::active_address_space_root(), Ordering::SeqCst);
+
+    if crate::arch::cpu::features::has_pcid() {
+        // SAFETY: CPUID reports PCID and the loaded CR3 has PCID 0, as CR4.PCIDE requires.
+        unsafe {
+            let mut cr4 = Cr4::read();
+            cr4.insert(Cr4Flags::PCID);
+            Cr4::write(cr4);
+        }
+        tlb.pcid.store(true, Ordering::SeqCst);
+    }
+    tlb.online.store(true, Ordering::SeqCst);
+
+    // Kernel mappings may have changed before shootdowns reached this CPU.
+    flush_everything();
+}
+
+/// Load `root` into CR3, reusing the TLB entries cached for it under its PCID if they are
+/// still valid.
+///
+/// # Safety
+/// `root` must be the physical address of a valid PML4.
+pub unsafe fn load_root(root: u64) {
+    let cpu = crate::arch::cpu_id() as usize;
+    let Some(tlb) = CPU_TLB.get(cpu).filter(|t| t.online.load(Ordering::Relaxed)) else {
+        // SAFETY: Forwarded from the caller.
+        unsafe { write_cr3(root) };
+        return;
+    };
+
+    crate::arch::without_interrupts(|| {
+        // Publish the new root before loading it, so that a concurrent shootdown either
+        // targets this CPU or completed its page table update before the load.
+        tlb.active_root.store(root, Ordering::SeqCst);
+        if !tlb.pcid.load(Ordering::Relaxed) {
+            // SAFETY: Forwarded from the caller.
+            unsafe { write_cr3(root) };
+            return;
+        }
+
+        let (slot, keep) = match tlb.pcid_slot(root) {
+            Some(slot) => {
+                let stale = tlb.stale.fetch_and(!(1 << slot), Ordering::SeqCst) & (1 << slot);
+                (slot, stale == 0)
+            }
+            None => {
+                let slot = tlb.next_victim.load(Ordering::Relaxed);
+                tlb.next_victim.store((slot + 1) % NR_PCIDS, Ordering::Relaxed);
+                tlb.pcid_roots[slot].store(root, Ordering::SeqCst);
+                tlb.stale.fetch_and(!(1 << slot), Ordering::SeqCst);
+                (slot, false)
+            }
+        };
+
+        let mut value = root | (slot as u64 + 1);
+        if keep {
+            value |= CR3_NOFLUSH;
+        }
+        // SAFETY: PCIDE is set on this CPU, so the low bits select the PCID.
+        unsafe { write_cr3(value) };
+    });
+}
+
+unsafe fn write_cr3(value: u64) {
+    // SAFETY: The caller passes a valid PML4 address with valid PCID bits.
+    unsafe {
+        core::arch::asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+/// Drop every TLB entry of the calling CPU, global ones and all PCIDs included.
+fn flush_everything() {
+    // SAFETY: Toggling CR4.PGE flushes the TLB and changes nothing else.
+    unsafe {
+        let cr4 = Cr4::read();
+        Cr4::write(cr4 ^ Cr4Flags::PAGE_GLOBAL);
+        Cr4::write(cr4);
+    }
+}
+
+/// Carry out `req` on the calling CPU.
+fn apply(req: &Request, cpu: usize) {
+    if req.kernel {
+        flush_everything();
+        return;
+    }
+    let Some(tlb) = CPU_TLB.get(cpu) else {
+        return;
+    };
+
+    if tlb.active_root.load(Ordering::SeqCst) == req.root {
+        if req.full {
+            let slot = tlb.pcid_slot(req.root).filter(|_| tlb.pcid.load(Ordering::Relaxed));
+            let pcid = slot.map_or(0, |s| s as u64 + 1);
+            // SAFETY: Reloading the active root without NOFLUSH flushes its PCID.
+            unsafe { write_cr3(req.root | pcid) };
+        } else {
+            for &page in &req.pages[..req.count] {
+                x86_64::instructions::tlb::flush(VirtAddr::new(page));
+            }
+        }
+    } else if let Some(slot) = tlb.pcid_slot(req.root) {
+        // Not loaded here: INVLPG would hit the wrong PCID, so flush it on next use.
+        tlb.stale.fetch_or(1 << slot, Ordering::SeqCst);
+    }
+}
+
+/// Act on a shootdown addressed to the calling CPU, if there is one.
+pub fn handle_shootdown() {
+    let cpu = crate::arch::cpu_id() as usize;
+    if cpu >= MAX_CPUS || PENDING.load(Ordering::Acquire) & (1 << cpu) == 0 {
+        return;
+    }
+    // SAFETY: Our bit in PENDING keeps the initiator from touching the request.
+    let req = unsafe { *REQUEST.0.get() };
+    apply(&req, cpu);
+    PENDING.fetch_and(!(1 << cpu), Ordering::Release);
+}
+
+/// Answer a pending shootdown from a busy-wait loop.
+#[inline(always)]
+pub fn poll() {
+    if PENDING.load(Ordering::Relaxed) != 0 {
+        handle_shootdown();
+    }
+}
+
+/// Invalidate the translations in `batch` on every CPU that may cache them and wait for
+/// all of them to finish. The batch is emptied.
+///
+/// `root` identifies the page table; changes to the kernel's own table affect every CPU.
+pub fn flush(root: u64, batch: &mut TlbBatch) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let mut req = Request {
+        root,
+        kernel: root == crate::mm::kernel_root(),
+        full: batch.full,
+        count: batch.count,
+        pages: batch.pages,
+    };
+    if req.full {
+        req.count = 0;
+    }
+    batch.clear();
+
+    let _guard = INITIATOR.lock();
+    let this_cpu = crate::arch::cpu_id() as usize;
+
+    // Order the page table writes before reading where the address space is cached.
+    core::sync::atomic::fence(Ordering::SeqCst);
+    let targets = if req.kernel {
+        online_mask()
+    } else {
+        cpu_mask(root)
+    } & !(1 << this_cpu);
+
+    apply(&req, this_cpu);
+    if targets == 0 {
+        return;
+    }
+
+    // SAFETY: PENDING is clear, so no CPU reads the request while it is rewritten.
+    unsafe { *REQUEST.0.get() = req };
+    PENDING.store(targets, Ordering::Release);
+
+    // SAFETY: The LAPIC is not modified after initialisation.
+    if let Some(apic) = unsafe { lapic::try_get_lapic() } {
+        for cpu in (0..MAX_CPUS).filter(|cpu| targets & (1 << cpu) != 0) {
+            apic.send_ipi(cpu as u32, crate::arch::interrupts::TLB_SHOOTDOWN_VECTOR);
+        }
+    }
+
+    while PENDING.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// CPUs that may hold translations of the page table at `root`.
+pub fn cpu_mask(root: u64) -> u32 {
+    mask_of(|tlb| tlb.caches(root))
+}
+
+/// CPUs taking part in shootdowns.
+fn online_mask() -> u32 {
+    mask_of(|tlb| tlb.online.load(Ordering::SeqCst))
+}
+
+fn mask_of(pred: impl Fn(&CpuTlb) -> bool) -> u32 {
+    CPU_TLB
+        .iter()
+        .enumerate()
+        .filter(|(_, tlb)| pred(tlb))
+        .fold(0, |mask, (cpu, _)| mask | (1 << cpu))
+}
@@ -1,5 +1,6 @@
 use crate::arch::syscall::syscall::SyscallFrame;
 use crate::ipc::signal::{SigAction, SigSet};
+use crate::syscalls::uaccess::UserPtr;
 use core::mem::size_of;
 
 /// Saved signal execution context on the user stack (matches x86_64 POSIX layout).
@@ -39,6 +40,10 @@ pub struct SigFrame {
     pub uc: SigContext,
 }
 
+/// Stack space a handler needs for the frame pushed by `setup_signal_frame`, including
+/// alignment slack. Reported to user space as `AT_MINSIGSTKSZ`.
+pub const MINSIGSTKSZ: u64 = size_of::<SigFrame>() as u64 + 16;
+
 /// Setup user stack frame for invoking a signal handler.
 ///
 /// # Safety
@@ -88,15 +93,9 @@ pub unsafe fn setup_signal_frame(
         uc: sig_context,
     };
 
-    if !crate::syscalls::is_user_ptr_valid(user_rsp, size_of::<SigFrame>()) {
-        return Err("Invalid user stack pointer for signal frame");
-    }
-
-    // SAFETY: Copy SigFrame directly to user stack space
-    let frame_ptr = user_rsp as *mut SigFrame;
-    unsafe {
-        core::ptr::write_volatile(frame_ptr, sig_frame);
-    }
+    UserPtr::<SigFrame>::new(user_rsp)
+        .write(sig_frame)
+        .map_err(|_| "Invalid user stack pointer for signal frame")?;
 
     // 2. Redirect execution context to signal handler
     frame.rsp = user_rsp;
@@ -114,13 +113,9 @@ pub unsafe fn setup_signal_frame(
 /// Reads `SigFrame` from user stack pointer in `SyscallFrame`.
 pub unsafe fn restore_signal_frame(frame: &mut SyscallFrame) -> Result<SigSet, &'static str> {
     let user_rsp = frame.rsp;
-    if !crate::syscalls::is_user_ptr_valid(user_rsp, size_of::<SigFrame>()) {
-        return Err("Invalid user stack pointer for sigreturn");
-    }
-    let frame_ptr = user_rsp as *const SigFrame;
-
-    // SAFETY: Read SigFrame from current user stack pointer
-    let sig_frame = unsafe { core::ptr::read_volatile(frame_ptr) };
+    let sig_frame = UserPtr::<SigFrame>::new(user_rsp)
+        .read()
+        .map_err(|_| "Invalid user stack pointer for sigreturn")?;
     let uc = sig_frame.uc;
 
     frame.r8 = uc.r8;
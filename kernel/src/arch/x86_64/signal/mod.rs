@@ -1,3 +1,3 @@
 pub mod frame;
 
-pub use frame::{restore_signal_frame, setup_signal_frame};
+pub use frame::{MINSIGSTKSZ, restore_signal_frame, setup_signal_frame};
@@ -1,9 +1,10 @@
 //! Architecture-specific system call numbering and dispatch table for x86_64 Linux ABI.
 
 use crate::define_syscall_table;
-use crate::syscalls::{arch_prctl, fs, ioctl, mm, proc, signals, sync, sys_info, time};
+use crate::syscalls::{arch_prctl, fs, ioctl, mm, proc, sched, signals, sync, sys_info, time};
 
-// Entries in SYSCALL_TABLE must be kept sorted by system call number for binary search.
+// Entries in SYSCALL_TABLE must be kept sorted by system call number for binary search;
+// the build fails otherwise.
 define_syscall_table! {
     SYS_READ           = 0   => ("read",           fs::sys_read),
     SYS_WRITE          = 1   => ("write",          fs::sys_write),
@@ -23,6 +24,7 @@ define_syscall_table! {
     SYS_ACCESS         = 21  => ("access",         fs::sys_access),
     SYS_PIPE           = 22  => ("pipe",           fs::sys_pipe),
     SYS_YIELD          = 24  => ("yield",          proc::sys_yield),
+    SYS_MADVISE        = 28  => ("madvise",        mm::sys_madvise),
     SYS_DUP            = 32  => ("dup",            fs::sys_dup),
     SYS_DUP2           = 33  => ("dup2",           fs::sys_dup2),
     SYS_NANOSLEEP      = 35  => ("nanosleep",      time::sys_nanosleep),
@@ -40,6 +42,7 @@ define_syscall_table! {
     SYS_UMASK          = 95  => ("umask",          fs::sys_umask),
     SYS_GETTIMEOFDAY   = 96  => ("gettimeofday",   time::sys_gettimeofday),
     SYS_GETRLIMIT      = 97  => ("getrlimit",      proc::sys_getrlimit),
+    SYS_SYSINFO        = 99  => ("sysinfo",        sys_info::sys_sysinfo),
     SYS_TIMES          = 100 => ("times",          time::sys_times),
     SYS_GETUID         = 102 => ("getuid",         proc::sys_getuid),
     SYS_GETGID         = 104 => ("getgid",         proc::sys_getgid),
@@ -52,12 +55,29 @@ define_syscall_table! {
     SYS_GETPGRP        = 111 => ("getpgrp",        proc::sys_getpgrp),
     SYS_SETSID         = 112 => ("setsid",         proc::sys_setsid),
     SYS_GETGROUPS      = 115 => ("getgroups",      proc::sys_getgroups),
+    SYS_PERSONALITY    = 135 => ("personality",    proc::sys_personality),
+    SYS_GETPRIORITY    = 140 => ("getpriority",    sched::sys_getpriority),
+    SYS_SETPRIORITY    = 141 => ("setpriority",    sched::sys_setpriority),
+    SYS_SCHED_SETPARAM = 142 => ("sched_setparam", sched::sys_sched_setparam),
+    SYS_SCHED_GETPARAM = 143 => ("sched_getparam", sched::sys_sched_getparam),
+    SYS_SCHED_SETSCHEDULER = 144 => ("sched_setscheduler", sched::sys_sched_setscheduler),
+    SYS_SCHED_GETSCHEDULER = 145 => ("sched_getscheduler", sched::sys_sched_getscheduler),
+    SYS_SCHED_GET_PRIORITY_MAX = 146 => ("sched_get_priority_max", sched::sys_sched_get_priority_max),
+    SYS_SCHED_GET_PRIORITY_MIN = 147 => ("sched_get_priority_min", sched::sys_sched_get_priority_min),
+    SYS_SCHED_RR_GET_INTERVAL = 148 => ("sched_rr_get_interval", sched::sys_sched_rr_get_interval),
     SYS_ARCH_PRCTL     = 158 => ("arch_prctl",     arch_prctl::sys_arch_prctl),
+    SYS_ADJTIMEX       = 159 => ("adjtimex",       time::sys_adjtimex),
     SYS_SETRLIMIT      = 160 => ("setrlimit",      proc::sys_setrlimit),
+    SYS_SETTIMEOFDAY   = 164 => ("settimeofday",   time::sys_settimeofday),
+    SYS_TIME           = 201 => ("time",           time::sys_time),
     SYS_FUTEX          = 202 => ("futex",          sync::sys_futex),
+    SYS_SCHED_SETAFFINITY = 203 => ("sched_setaffinity", sched::sys_sched_setaffinity),
+    SYS_SCHED_GETAFFINITY = 204 => ("sched_getaffinity", sched::sys_sched_getaffinity),
     SYS_ISATTY         = 215 => ("isatty",         ioctl::sys_isatty),
     SYS_GETDENTS64     = 217 => ("getdents64",     fs::sys_getdents64),
+    SYS_CLOCK_SETTIME  = 227 => ("clock_settime",  time::sys_clock_settime),
     SYS_CLOCK_GETTIME  = 228 => ("clock_gettime",  time::sys_clock_gettime),
+    SYS_CLOCK_GETRES   = 229 => ("clock_getres",   time::sys_clock_getres),
     SYS_EXIT_GROUP     = 231 => ("exit_group",     proc::sys_exit_group),
     SYS_OPENAT         = 257 => ("openat",         fs::sys_openat),
     SYS_NEWFSTATAT     = 262 => ("newfstatat",     fs::sys_newfstatat),
@@ -65,4 +85,9 @@ define_syscall_table! {
     SYS_DUP3           = 292 => ("dup3",           fs::sys_dup3),
     SYS_PIPE2          = 293 => ("pipe2",          fs::sys_pipe2),
     SYS_PRLIMIT64      = 302 => ("prlimit64",      proc::sys_prlimit64),
+    SYS_CLOCK_ADJTIME  = 305 => ("clock_adjtime",  time::sys_clock_adjtime),
+    SYS_GETCPU         = 309 => ("getcpu",         sched::sys_getcpu),
+    SYS_SCHED_SETATTR  = 314 => ("sched_setattr",  sched::sys_sched_setattr),
+    SYS_SCHED_GETATTR  = 315 => ("sched_getattr",  sched::sys_sched_getattr),
+    SYS_EXECVEAT       = 322 => ("execveat",       proc::sys_execveat),
 }
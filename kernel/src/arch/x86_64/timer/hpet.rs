@@ -1,10 +1,18 @@
 //! High Precision Event Timer (HPET) Driver for x86_64
 //!
 //! Provides high-resolution timing, elapsed time measurements, and microsecond/millisecond busy-wait delays.
+//! The main counter is also a clock source, and comparator 0 doubles as a clock event device for
+//! the boot CPU, used where the LAPIC timer is not available.
 
 use crate::arch::acpi::{Rsdp, Sdt};
+use crate::arch::interrupt::ioapic;
+use super::lapic_timer::TIMER_VECTOR;
 use crate::mm::{ensure_mapped, map_mmio};
 use crate::sync::spinlock::Spinlock;
+use crate::arch::vdso::CLOCK_MODE_HPET;
+use crate::time::clockevent::ClockEventDevice;
+use crate::time::clocksource::{ClockSource, MULT_SHIFT};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 pub const HPET_DEFAULT_PHYS_BASE: u64 = 0xFED0_0000;
 
@@ -13,11 +21,29 @@ const REG_CAP_ID: usize = 0x00; // General Capabilities and ID (64-bit)
 const REG_CONFIG: usize = 0x10; // General Configuration (64-bit)
 const REG_INT_STATUS: usize = 0x20; // General Interrupt Status (64-bit)
 const REG_COUNTER: usize = 0xF0; // Main Counter Value (64-bit)
+const REG_TIMER0_CONFIG: usize = 0x100; // Timer 0 Configuration and Capabilities (64-bit)
+const REG_TIMER0_COMPARATOR: usize = 0x108; // Timer 0 Comparator Value (64-bit)
 
 // Configuration register flags
 const CONFIG_ENABLE: u64 = 1 << 0; // Overall Enable (starts main counter)
 const CONFIG_LEG_RT: u64 = 1 << 1; // Legacy Replacement Route
 
+// Timer configuration flags
+const TIMER_INT_LEVEL: u64 = 1 << 1; // Level-triggered interrupt (edge when clear)
+const TIMER_INT_ENABLE: u64 = 1 << 2; // Interrupt Enable
+const TIMER_PERIODIC: u64 = 1 << 3; // Periodic mode (one-shot when clear)
+const TIMER_INT_ROUTE_SHIFT: u64 = 9; // I/O APIC input the interrupt is routed to (bits 9-13)
+const TIMER_INT_ROUTE_MASK: u64 = 0x1F << TIMER_INT_ROUTE_SHIFT;
+
+/// Virtual address of the registers, for reading the counter without taking `HPET`.
+static HPET_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Counter period in femtoseconds, alongside `HPET_BASE`.
+static HPET_PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+
+/// Set if the main counter is only 32 bits wide.
+static HPET_32BIT: AtomicBool = AtomicBool::new(false);
+
 /// High Precision Event Timer Hardware Abstraction.
 #[derive(Debug)]
 pub struct Hpet {
@@ -49,31 +75,22 @@ impl Hpet {
         unsafe { core::ptr::write_volatile(ptr, val) };
     }
 
-    /// Read current value of the HPET main counter.
-    #[inline]
-    pub fn read_counter(&self) -> u64 {
-        unsafe { self.read_reg(REG_COUNTER) }
-    }
-
     /// Returns clock tick period in femtoseconds (10^-15 s).
     #[inline]
     pub fn period_fs(&self) -> u64 {
         self.counter_clk_period_fs
     }
 
-    /// Convert tick count into elapsed nanoseconds (1 ns = 1,000,000 fs).
+    /// Physical address of the HPET register block.
     #[inline]
-    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
-        (ticks as u128 * self.counter_clk_period_fs as u128 / 1_000_000) as u64
+    pub fn phys_base(&self) -> u64 {
+        self.phys_base
     }
 
-    /// Convert nanoseconds into required HPET counter ticks.
+    /// Returns true if the main counter is 64 bits wide.
     #[inline]
-    pub fn ns_to_ticks(&self, ns: u64) -> u64 {
-        if self.counter_clk_period_fs == 0 {
-            return 0;
-        }
-        (ns as u128 * 1_000_000 / self.counter_clk_period_fs as u128) as u64
+    pub fn is_64bit(&self) -> bool {
+        self.is_64bit
     }
 }
 
@@ -164,35 +181,192 @@ pub fn init() {
         is_64bit
     );
 
+    HPET_PERIOD_FS.store(period_fs, Ordering::Relaxed);
+    HPET_32BIT.store(!is_64bit, Ordering::Relaxed);
+    HPET_BASE.store(virt_base as u64, Ordering::Release);
     *HPET.lock() = Some(hpet);
 }
 
 /// Read current main counter value.
+///
+/// Lock-free, so that it can be used from interrupt handlers.
 pub fn read_counter() -> u64 {
-    HPET.lock().as_ref().map(|h| h.read_counter()).unwrap_or(0)
+    match HPET_BASE.load(Ordering::Acquire) {
+        0 => 0,
+        // SAFETY: The register block was mapped by `init` before its address was published.
+        base => unsafe { core::ptr::read_volatile((base as usize + REG_COUNTER) as *const u64) },
+    }
 }
 
 /// Read elapsed nanoseconds since boot from HPET.
 pub fn elapsed_ns() -> u64 {
-    let guard = HPET.lock();
-    if let Some(ref hpet) = *guard {
-        hpet.ticks_to_ns(hpet.read_counter())
-    } else {
-        0
+    let period_fs = HPET_PERIOD_FS.load(Ordering::Relaxed);
+    (read_counter() as u128 * period_fs as u128 / 1_000_000) as u64
+}
+
+/// Convert nanoseconds into counter ticks without taking `HPET`.
+fn ns_to_counter_ticks(ns: u64) -> u64 {
+    match HPET_PERIOD_FS.load(Ordering::Relaxed) {
+        0 => 0,
+        period_fs => (ns as u128 * 1_000_000 / period_fs as u128).min(u64::MAX as u128) as u64,
     }
 }
 
+/// Comparator 0 in one-shot mode, routed through the I/O APIC to the boot CPU.
+pub struct HpetComparator;
+
+pub static HPET_COMPARATOR: HpetComparator = HpetComparator;
+
+impl HpetComparator {
+    /// Route comparator 0 to the timer vector of the CPU with LAPIC ID `lapic_id`.
+    ///
+    /// Returns whether the comparator can be used, which needs an I/O APIC input beyond
+    /// the ISA range that the comparator can be routed to.
+    pub fn setup(&self, lapic_id: u32) -> bool {
+        let base = HPET_BASE.load(Ordering::Acquire);
+        if base == 0 {
+            return false;
+        }
+        // SAFETY: The register block was mapped by `init` before its address was published.
+        let config = unsafe { read_reg(base, REG_TIMER0_CONFIG) };
+        let route_cap = (config >> 32) as u32;
+        let Some(gsi) = (16..32).find(|&gsi| route_cap & (1 << gsi) != 0) else {
+            log::warn!("HPET: comparator 0 cannot be routed above the ISA IRQs.");
+            return false;
+        };
+
+        let config = config & !(TIMER_INT_LEVEL | TIMER_INT_ENABLE | TIMER_PERIODIC);
+        let config = (config & !TIMER_INT_ROUTE_MASK) | ((gsi as u64) << TIMER_INT_ROUTE_SHIFT);
+        // SAFETY: As above.
+        unsafe { write_reg(base, REG_TIMER0_CONFIG, config) };
+        ioapic::route_gsi(gsi, TIMER_VECTOR, lapic_id)
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        let base = HPET_BASE.load(Ordering::Acquire);
+        // SAFETY: `setup` succeeded, so the registers are mapped.
+        unsafe {
+            let config = read_reg(base, REG_TIMER0_CONFIG);
+            let config = if enabled {
+                config | TIMER_INT_ENABLE
+            } else {
+                config & !TIMER_INT_ENABLE
+            };
+            write_reg(base, REG_TIMER0_CONFIG, config);
+        }
+    }
+}
+
+impl ClockEventDevice for HpetComparator {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn rating(&self) -> u32 {
+        50
+    }
+
+    fn min_delta_ns(&self) -> u64 {
+        10_000
+    }
+
+    fn max_delta_ns(&self) -> u64 {
+        let period_fs = HPET_PERIOD_FS.load(Ordering::Relaxed);
+        ((i32::MAX as u128 * period_fs as u128) / 1_000_000) as u64
+    }
+
+    fn enable(&self) {
+        self.set_enabled(false);
+    }
+
+    fn set_next_event(&self, delta_ns: u64) {
+        let base = HPET_BASE.load(Ordering::Acquire);
+        // A 32-bit counter compares only the low half, so compare differences in it.
+        let shift = if HPET_32BIT.load(Ordering::Relaxed) { 32 } else { 0 };
+        let mut ticks = ns_to_counter_ticks(delta_ns).max(1);
+        loop {
+            let target = read_counter().wrapping_add(ticks);
+            // SAFETY: `setup` succeeded, so the registers are mapped.
+            unsafe { write_reg(base, REG_TIMER0_COMPARATOR, target) };
+            // The interrupt is edge-triggered: a comparator the counter has already
+            // passed never fires, so check and retry further ahead.
+            if (target.wrapping_sub(read_counter()) << shift) as i64 > 0 {
+                break;
+            }
+            ticks = ticks.saturating_mul(2);
+        }
+        self.set_enabled(true);
+    }
+
+    fn shutdown(&self) {
+        self.set_enabled(false);
+    }
+}
+
+/// The main counter as a clock source.
+pub struct HpetClockSource;
+
+pub static HPET_CLOCKSOURCE: HpetClockSource = HpetClockSource;
+
+impl HpetClockSource {
+    /// Whether the HPET was found and runs.
+    pub fn is_usable(&self) -> bool {
+        HPET_BASE.load(Ordering::Acquire) != 0 && HPET_PERIOD_FS.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl ClockSource for HpetClockSource {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn rating(&self) -> u32 {
+        250
+    }
+
+    fn read(&self) -> u64 {
+        read_counter()
+    }
+
+    fn mask(&self) -> u64 {
+        if HPET_32BIT.load(Ordering::Relaxed) {
+            u32::MAX as u64
+        } else {
+            u64::MAX
+        }
+    }
+
+    fn mult(&self) -> u64 {
+        let period_fs = HPET_PERIOD_FS.load(Ordering::Relaxed);
+        (((period_fs as u128) << MULT_SHIFT) / 1_000_000) as u64
+    }
+
+    fn vdso_mode(&self) -> u32 {
+        CLOCK_MODE_HPET
+    }
+}
+
+/// Read a register of the HPET mapped at `base`.
+unsafe fn read_reg(base: u64, offset: usize) -> u64 {
+    unsafe { core::ptr::read_volatile((base as usize + offset) as *const u64) }
+}
+
+/// Write a register of the HPET mapped at `base`.
+unsafe fn write_reg(base: u64, offset: usize, val: u64) {
+    unsafe { core::ptr::write_volatile((base as usize + offset) as *mut u64, val) };
+}
+
 /// High-precision busy-wait sleep for `ns` nanoseconds using HPET counter.
 pub fn sleep_ns(ns: u64) {
-    let guard = HPET.lock();
-    if let Some(ref hpet) = *guard {
-        let start_counter = hpet.read_counter();
-        let target_ticks = hpet.ns_to_ticks(ns);
-        drop(guard);
-
-        while read_counter().wrapping_sub(start_counter) < target_ticks {
-            core::hint::spin_loop();
-        }
+    let period_fs = HPET_PERIOD_FS.load(Ordering::Relaxed);
+    if period_fs == 0 {
+        return;
+    }
+    let target_ticks = (ns as u128 * 1_000_000 / period_fs as u128) as u64;
+    let start_counter = read_counter();
+
+    while read_counter().wrapping_sub(start_counter) < target_ticks {
+        core::hint::spin_loop();
     }
 }
 
@@ -1,20 +1,26 @@
 //! LAPIC Timer driver with PIT-based calibration.
 //!
 //! The LAPIC timer is a per-CPU timer integrated into each Local APIC.
-//! It can operate in periodic or one-shot mode and is commonly used
-//! as the scheduler tick source.
+//! It serves as the clock event device of its CPU, either counting down
+//! in one-shot mode or, where the CPU supports it, firing when the TSC
+//! reaches a deadline.
 //!
 //! Calibration is performed using the PIT (Programmable Interval Timer)
 //! channel 2 as a reference clock to determine the LAPIC timer frequency.
+//! TSC-deadline mode relies on the TSC calibration of [`super::tsc`].
 
-use crate::arch::lapic::LocalApic;
+use crate::arch::cpu::msr::{wrmsr, IA32_TSC_DEADLINE};
+use crate::arch::cpu::rdtsc::rdtsc;
+use crate::arch::lapic::{self, LocalApic};
 use crate::arch::ports::Ports;
+use crate::time::clockevent::ClockEventDevice;
+use core::sync::atomic::Ordering;
 
 /// The IDT vector number used for LAPIC timer interrupts.
 pub const TIMER_VECTOR: u8 = 48;
 
-/// LVT Timer mode bits.
-const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// LVT Timer mode bits (one-shot mode is 0).
+const LVT_TIMER_TSC_DEADLINE: u32 = 1 << 18;
 const LVT_TIMER_MASKED: u32 = 1 << 16;
 
 /// Timer divide configuration values.
@@ -36,21 +42,22 @@ const CALIBRATION_MS: u32 = 10;
 static CALIBRATED_TICKS_PER_MS: core::sync::atomic::AtomicU32 =
     core::sync::atomic::AtomicU32::new(0);
 
+/// Shortest delay programmed into either timer mode.
+const MIN_DELTA_NS: u64 = 1_000;
+
 /// Represents the LAPIC timer associated with a Local APIC.
 ///
 /// The timer must be calibrated before use to determine the correct
-/// tick count for the desired interrupt frequency.
-pub struct LapicTimer {
-    /// Calibrated ticks per millisecond (after divider).
-    ticks_per_ms: u32,
-}
+/// tick count for the desired interrupt frequency. The result is kept in
+/// `CALIBRATED_TICKS_PER_MS`, shared by all CPUs.
+pub struct LapicTimer;
 
 impl LapicTimer {
     /// Returns a `LapicTimer` using the cached calibration if available, or performs calibration.
     pub fn calibrate_or_get(lapic: &LocalApic) -> Self {
         let cached = CALIBRATED_TICKS_PER_MS.load(core::sync::atomic::Ordering::Relaxed);
         if cached != 0 {
-            Self { ticks_per_ms: cached }
+            Self
         } else {
             Self::calibrate(lapic)
         }
@@ -119,43 +126,115 @@ impl LapicTimer {
             CALIBRATION_MS
         );
 
-        Self { ticks_per_ms }
+        Self
     }
 
-    /// Start the LAPIC timer in periodic mode at the given frequency.
-    ///
-    /// # Arguments
-    /// * `lapic` — Reference to the Local APIC owning this timer
-    /// * `frequency_hz` — Desired interrupt frequency in Hz
-    pub fn start_periodic(&self, lapic: &LocalApic, frequency_hz: u32) {
-        if frequency_hz == 0 || self.ticks_per_ms == 0 {
-            log::warn!("LAPIC timer: invalid frequency or uncalibrated timer.");
-            return;
-        }
+    /// Stop the LAPIC timer by masking its LVT entry and zeroing the count.
+    pub fn stop(&self, lapic: &LocalApic) {
+        lapic.write_lvt_timer(LVT_TIMER_MASKED);
+        lapic.write_timer_initial_count(0);
+    }
+}
 
-        // Calculate initial count: ticks_per_ms * 1000 / frequency_hz
-        let ticks_per_second = self.ticks_per_ms as u64 * 1000;
-        let initial_count = (ticks_per_second / frequency_hz as u64) as u32;
+/// The LAPIC timer counting down in one-shot mode.
+pub struct LapicOneShot;
 
-        // Set divide configuration
-        lapic.write_timer_divide_config(TIMER_DIVIDE_BY_16);
+/// The LAPIC timer firing when the TSC reaches a deadline.
+pub struct LapicTscDeadline;
 
-        // Configure LVT Timer: periodic mode, unmasked, with our vector
-        lapic.write_lvt_timer(LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+pub static LAPIC_ONESHOT: LapicOneShot = LapicOneShot;
+pub static LAPIC_TSC_DEADLINE: LapicTscDeadline = LapicTscDeadline;
 
-        // Set the initial count to start the timer
-        lapic.write_timer_initial_count(initial_count);
+fn local_apic() -> &'static LocalApic {
+    // SAFETY: Clock events are only programmed once the LAPIC has been initialized.
+    unsafe { lapic::get_lapic() }
+}
 
-        log::info!(
-            "LAPIC timer started: periodic mode at {}Hz (initial count: {}).",
-            frequency_hz,
-            initial_count
-        );
+fn ticks_per_ms() -> u64 {
+    CALIBRATED_TICKS_PER_MS.load(Ordering::Relaxed).max(1) as u64
+}
+
+impl ClockEventDevice for LapicOneShot {
+    fn name(&self) -> &'static str {
+        "lapic"
     }
 
-    /// Stop the LAPIC timer by masking its LVT entry and zeroing the count.
-    pub fn stop(&self, lapic: &LocalApic) {
-        lapic.write_lvt_timer(LVT_TIMER_MASKED);
+    fn rating(&self) -> u32 {
+        100
+    }
+
+    fn min_delta_ns(&self) -> u64 {
+        MIN_DELTA_NS
+    }
+
+    fn max_delta_ns(&self) -> u64 {
+        u32::MAX as u64 * 1_000_000 / ticks_per_ms()
+    }
+
+    fn enable(&self) {
+        let lapic = local_apic();
+        lapic.write_timer_divide_config(TIMER_DIVIDE_BY_16);
         lapic.write_timer_initial_count(0);
+        lapic.write_lvt_timer(TIMER_VECTOR as u32);
+    }
+
+    fn set_next_event(&self, delta_ns: u64) {
+        let count = (delta_ns as u128 * ticks_per_ms() as u128 / 1_000_000).max(1);
+        local_apic().write_timer_initial_count(count.min(u32::MAX as u128) as u32);
+    }
+
+    fn shutdown(&self) {
+        local_apic().write_timer_initial_count(0);
+    }
+}
+
+impl LapicOneShot {
+    /// Whether the LAPIC timer has been calibrated.
+    pub fn is_usable(&self) -> bool {
+        CALIBRATED_TICKS_PER_MS.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl LapicTscDeadline {
+    /// Whether the CPU supports TSC-deadline mode and the TSC has been calibrated.
+    pub fn is_usable(&self) -> bool {
+        crate::arch::cpu::features::has_tsc_deadline()
+            && super::tsc::khz() != 0
+    }
+}
+
+impl ClockEventDevice for LapicTscDeadline {
+    fn name(&self) -> &'static str {
+        "lapic-tsc-deadline"
+    }
+
+    fn rating(&self) -> u32 {
+        150
+    }
+
+    fn min_delta_ns(&self) -> u64 {
+        MIN_DELTA_NS
+    }
+
+    fn max_delta_ns(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn enable(&self) {
+        local_apic().write_lvt_timer(LVT_TIMER_TSC_DEADLINE | TIMER_VECTOR as u32);
+        // The LVT write must be visible before the first deadline is written.
+        core::sync::atomic::fence(Ordering::SeqCst);
+    }
+
+    fn set_next_event(&self, delta_ns: u64) {
+        let tsc_per_ms = super::tsc::khz() as u128;
+        let ticks = (delta_ns as u128 * tsc_per_ms / 1_000_000).min(u64::MAX as u128) as u64;
+        // SAFETY: IA32_TSC_DEADLINE exists, as TSC-deadline mode is supported.
+        unsafe { wrmsr(IA32_TSC_DEADLINE, rdtsc().saturating_add(ticks.max(1))) };
+    }
+
+    fn shutdown(&self) {
+        // SAFETY: As above; a deadline of zero disarms the timer.
+        unsafe { wrmsr(IA32_TSC_DEADLINE, 0) };
     }
 }
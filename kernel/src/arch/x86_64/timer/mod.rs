@@ -1,12 +1,39 @@
 pub mod hpet;
 pub mod lapic_timer;
+pub mod tsc;
 
-use crate::arch::interrupt::lapic;
+use crate::arch::interrupt::lapic::{self, LocalApic};
+use crate::time::{clockevent, timekeeping};
 
 pub fn init() {
+    hpet::init();
+    if hpet::HPET_CLOCKSOURCE.is_usable() {
+        timekeeping::register(&hpet::HPET_CLOCKSOURCE);
+    }
+    // Before the LAPIC timer, whose TSC-deadline mode needs the TSC frequency.
+    tsc::calibrate();
+    if tsc::TSC_CLOCKSOURCE.is_usable() {
+        timekeeping::register(&tsc::TSC_CLOCKSOURCE);
+    }
+
     let local_apic = unsafe { lapic::get_lapic() };
-    let timer = lapic_timer::LapicTimer::calibrate(local_apic);
-    timer.start_periodic(local_apic, 100);
+    // The HPET comparator only serves the boot CPU, as a fallback for the LAPIC timer.
+    if hpet::HPET_COMPARATOR.setup(local_apic.id()) {
+        clockevent::register(local_apic.id(), &hpet::HPET_COMPARATOR);
+    }
+    init_cpu(local_apic);
+}
 
-    hpet::init();
+/// Calibrate the LAPIC timer if needed, register the clock event devices of the calling
+/// CPU and start its tick.
+pub fn init_cpu(local_apic: &LocalApic) {
+    let cpu = local_apic.id();
+    lapic_timer::LapicTimer::calibrate_or_get(local_apic);
+    if lapic_timer::LAPIC_ONESHOT.is_usable() {
+        clockevent::register(cpu, &lapic_timer::LAPIC_ONESHOT);
+    }
+    if lapic_timer::LAPIC_TSC_DEADLINE.is_usable() {
+        clockevent::register(cpu, &lapic_timer::LAPIC_TSC_DEADLINE);
+    }
+    crate::time::tick::start(cpu);
 }
@@ -0,0 +1,83 @@
+//! Time-Stamp Counter (TSC) calibration and clock source.
+//!
+//! The TSC frequency is measured against the HPET at boot. Where the CPU reports an
+//! invariant TSC, one that ticks at a constant rate through frequency and power state
+//! changes, it is the cheapest clock there is and becomes the clock source. Otherwise it
+//! only serves the TSC-deadline mode of the LAPIC timer.
+
+use super::hpet;
+use crate::arch::cpu::rdtsc::rdtsc;
+use crate::arch::vdso::CLOCK_MODE_TSC;
+use crate::time::clocksource::{ClockSource, MULT_SHIFT};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Length of the calibration against the HPET.
+const CALIBRATION_NS: u64 = 10_000_000;
+
+/// TSC ticks per millisecond, zero until calibrated.
+static TSC_KHZ: AtomicU64 = AtomicU64::new(0);
+
+/// TSC ticks per millisecond, or zero if the TSC could not be calibrated.
+pub fn khz() -> u64 {
+    TSC_KHZ.load(Ordering::Relaxed)
+}
+
+/// Measure the TSC frequency against the HPET main counter.
+pub fn calibrate() {
+    let hpet_start = hpet::elapsed_ns();
+    let tsc_start = rdtsc();
+    let mut hpet_now = hpet_start;
+    while hpet_now.wrapping_sub(hpet_start) < CALIBRATION_NS {
+        core::hint::spin_loop();
+        hpet_now = hpet::elapsed_ns();
+    }
+    let tsc_elapsed = rdtsc().wrapping_sub(tsc_start);
+    let hpet_elapsed = hpet_now.wrapping_sub(hpet_start);
+    if hpet_start == 0 || hpet_elapsed == 0 {
+        log::warn!("TSC: no HPET to calibrate against.");
+        return;
+    }
+
+    let khz = (tsc_elapsed as u128 * 1_000_000 / hpet_elapsed as u128) as u64;
+    TSC_KHZ.store(khz, Ordering::Relaxed);
+    log::info!(
+        "TSC calibrated: {}.{:03} MHz, invariant={}.",
+        khz / 1000,
+        khz % 1000,
+        crate::arch::cpu::features::has_invariant_tsc()
+    );
+}
+
+/// The TSC as a clock source.
+pub struct TscClockSource;
+
+pub static TSC_CLOCKSOURCE: TscClockSource = TscClockSource;
+
+impl TscClockSource {
+    /// Whether the TSC is calibrated and runs at a constant rate.
+    pub fn is_usable(&self) -> bool {
+        khz() != 0 && crate::arch::cpu::features::has_invariant_tsc()
+    }
+}
+
+impl ClockSource for TscClockSource {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn rating(&self) -> u32 {
+        300
+    }
+
+    fn read(&self) -> u64 {
+        rdtsc()
+    }
+
+    fn mult(&self) -> u64 {
+        ((1_000_000u128 << MULT_SHIFT) / khz().max(1) as u128) as u64
+    }
+
+    fn vdso_mode(&self) -> u32 {
+        CLOCK_MODE_TSC
+    }
+}
@@ -0,0 +1,195 @@
+//! Virtual dynamic shared object (vDSO).
+//!
+//! `Vdso.S` assembles a small ELF shared object exporting `clock_gettime`, `gettimeofday`,
+//! `time` and `getcpu`. At boot the image is copied into a page of its own. Each exec maps
+//! that page behind the shared [`VdsoData`] page, and behind the HPET registers when the
+//! clock can be read from user space, and reports it in `AT_SYSINFO_EHDR`.
+//!
+//! Timekeeping publishes the conversion from the clock source to `CLOCK_MONOTONIC` in the
+//! data page, so the vDSO computes the same time as the system calls from the same counter.
+
+use crate::mm::{AddrSpace, ArchPageTable, VmAreaKind, VmFlags};
+use crate::sync::spinlock::Spinlock;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+core::arch::global_asm!(include_str!("Vdso.S"));
+
+unsafe extern "C" {
+    static __vdso_image_start: u8;
+    static __vdso_image_end: u8;
+}
+
+/// No user-readable clock; the vDSO time functions make the system call.
+pub const CLOCK_MODE_NONE: u32 = 0;
+/// The vDSO reads the HPET main counter mapped just below the data page.
+pub const CLOCK_MODE_HPET: u32 = 1;
+/// The vDSO reads the TSC.
+pub const CLOCK_MODE_TSC: u32 = 2;
+
+/// `rdtscp` is available and `IA32_TSC_AUX` holds the CPU number.
+const FEATURE_RDTSCP: u32 = 1 << 0;
+
+/// Timekeeping state shared read-only with user space. The layout is mirrored in `Vdso.S`.
+#[repr(C)]
+struct VdsoData {
+    /// Sequence count, odd while an update is in progress.
+    seq: AtomicU32,
+    clock_mode: AtomicU32,
+    /// Nanoseconds per counter tick, shifted left by `shift`.
+    mult: AtomicU64,
+    /// `CLOCK_REALTIME` minus `CLOCK_MONOTONIC`, in nanoseconds (wrapping).
+    wall_offset_ns: AtomicU64,
+    features: u32,
+    shift: u32,
+    /// Counter value at which `CLOCK_MONOTONIC` was `base_ns`.
+    cycle_last: AtomicU64,
+    base_ns: AtomicU64,
+}
+
+/// The clock as timekeeping last updated it, for [`update_clock`].
+pub struct VdsoClock {
+    /// `CLOCK_MODE_*` of the clock source, `CLOCK_MODE_NONE` while the time cannot be
+    /// computed from it alone.
+    pub mode: u32,
+    pub cycle_last: u64,
+    pub mult: u64,
+    pub base_ns: u64,
+    pub wall_offset_ns: u64,
+}
+
+/// Physical frames holding the image and the data page, zero until `init` has run.
+#[unsafe(link_section = ".data.ro_after_init")]
+static IMAGE_PHYS: AtomicU64 = AtomicU64::new(0);
+#[unsafe(link_section = ".data.ro_after_init")]
+static DATA_PHYS: AtomicU64 = AtomicU64::new(0);
+/// HPET register page exposed to user space, zero if the vDSO cannot read the HPET.
+#[unsafe(link_section = ".data.ro_after_init")]
+static HPET_PHYS: AtomicU64 = AtomicU64::new(0);
+
+/// Serializes writers of the data page.
+static DATA_LOCK: Spinlock<()> = Spinlock::new(());
+
+fn data() -> Option<&'static VdsoData> {
+    let phys = DATA_PHYS.load(Ordering::Relaxed);
+    if phys == 0 {
+        return None;
+    }
+    // SAFETY: The data frame is allocated once in `init` and never freed.
+    Some(unsafe { &*((phys + crate::mm::hhdm_offset()) as *const VdsoData) })
+}
+
+/// Copy the image into its own frame and set up the data page.
+fn init() -> Result<(), &'static str> {
+    let start = &raw const __vdso_image_start;
+    let end = &raw const __vdso_image_end;
+    let len = end as usize - start as usize;
+    if len > 4096 {
+        return Err("vDSO image larger than a page");
+    }
+
+    let hhdm = crate::mm::hhdm_offset();
+    let image = crate::mm::PMM.alloc_page().ok_or("Out of memory for the vDSO image")?;
+    let data = crate::mm::PMM.alloc_page().ok_or("Out of memory for the vDSO data page")?;
+    // SAFETY: Both frames were just allocated and are reachable through the HHDM.
+    unsafe {
+        let image_ptr = (image.as_u64() + hhdm) as *mut u8;
+        core::ptr::write_bytes(image_ptr, 0, 4096);
+        core::ptr::copy_nonoverlapping(start, image_ptr, len);
+        core::ptr::write_bytes((data.as_u64() + hhdm) as *mut u8, 0, 4096);
+    }
+
+    // The HPET is only usable if its counter cannot wrap and its registers own the page.
+    let hpet = crate::arch::timer::hpet::HPET
+        .lock()
+        .as_ref()
+        .filter(|hpet| hpet.is_64bit() && hpet.period_fs() != 0 && hpet.phys_base() % 4096 == 0)
+        .map(|hpet| hpet.phys_base());
+
+    // SAFETY: Nothing maps the data page yet, so this is its only reference.
+    let vdso_data = unsafe { &mut *((data.as_u64() + hhdm) as *mut VdsoData) };
+    vdso_data.shift = crate::time::clocksource::MULT_SHIFT;
+    if let Some(phys_base) = hpet {
+        HPET_PHYS.store(phys_base, Ordering::Relaxed);
+    }
+    if crate::arch::cpu::features::has_rdtscp() {
+        vdso_data.features |= FEATURE_RDTSCP;
+    }
+
+    IMAGE_PHYS.store(image.as_u64(), Ordering::Relaxed);
+    DATA_PHYS.store(data.as_u64(), Ordering::Relaxed);
+    crate::time::timekeeping::sync_vdso();
+    log::info!(
+        "vDSO: {} byte image, clock={}",
+        len,
+        match vdso_data.clock_mode.load(Ordering::Relaxed) {
+            CLOCK_MODE_HPET => "hpet",
+            CLOCK_MODE_TSC => "tsc",
+            _ => "syscall",
+        }
+    );
+    Ok(())
+}
+
+crate::arch_initcall!(init);
+
+/// Publish the state of the clock after timekeeping updated it.
+pub fn update_clock(clock: &VdsoClock) {
+    let Some(data) = data() else {
+        return;
+    };
+    // The HPET can only be read where its registers are mapped.
+    let mode = match clock.mode {
+        CLOCK_MODE_HPET if HPET_PHYS.load(Ordering::Relaxed) == 0 => CLOCK_MODE_NONE,
+        mode => mode,
+    };
+    let _guard = DATA_LOCK.lock();
+    data.seq.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    data.clock_mode.store(mode, Ordering::Relaxed);
+    data.cycle_last.store(clock.cycle_last, Ordering::Relaxed);
+    data.mult.store(clock.mult, Ordering::Relaxed);
+    data.base_ns.store(clock.base_ns, Ordering::Relaxed);
+    data.wall_offset_ns.store(clock.wall_offset_ns, Ordering::Relaxed);
+    data.seq.fetch_add(1, Ordering::Release);
+}
+
+/// Map the HPET, data and image pages into `addr_space` at the three pages from `base`.
+///
+/// Returns the address of the image's ELF header, or `None` if the vDSO is unavailable.
+pub fn map(
+    addr_space: &mut AddrSpace<ArchPageTable>,
+    base: VirtAddr,
+) -> Result<Option<VirtAddr>, &'static str> {
+    let image = IMAGE_PHYS.load(Ordering::Relaxed);
+    let data = DATA_PHYS.load(Ordering::Relaxed);
+    if image == 0 || data == 0 {
+        return Ok(None);
+    }
+
+    let read_only =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let mut map_page = |offset: u64, phys: u64, flags: PageTableFlags| {
+        addr_space
+            .map_area_with_flags(
+                base + offset,
+                4096,
+                flags,
+                VmAreaKind::Device {
+                    phys_start: PhysAddr::new(phys),
+                },
+                VmFlags::SPECIAL,
+            )
+            .map_err(|_| "Failed to map the vDSO")
+    };
+
+    let hpet = HPET_PHYS.load(Ordering::Relaxed);
+    if hpet != 0 {
+        map_page(0, hpet, read_only | PageTableFlags::NO_CACHE)?;
+    }
+    map_page(4096, data, read_only)?;
+    map_page(2 * 4096, image, PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)?;
+
+    Ok(Some(base + 2 * 4096u64))
+}
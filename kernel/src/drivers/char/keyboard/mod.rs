@@ -9,6 +9,7 @@ pub mod scancode;
 
 use crate::device::{CharDevice, Device, DeviceType, Driver, DriverError};
 use crate::sync::spinlock::Spinlock;
+use crate::work::tasklet::{Tasklet, tasklet_schedule};
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicU64, Ordering};
@@ -99,7 +100,24 @@ impl Driver for Ps2KeyboardDriver {
     }
 }
 
-/// Dispatches a raw scancode received from the interrupt handler.
+/// Scancodes read by the interrupt handler, decoded by [`KEYBOARD_TASKLET`].
+static RAW_SCANCODES: KeyBuffer<64> = KeyBuffer::new();
+
+static KEYBOARD_TASKLET: Tasklet = Tasklet::new(decode_scancodes);
+
+/// Queue a scancode read by the interrupt handler for decoding outside of it.
+pub fn queue_scancode(scancode: u8) {
+    RAW_SCANCODES.push(scancode);
+    tasklet_schedule(&KEYBOARD_TASKLET);
+}
+
+fn decode_scancodes() {
+    while let Some(scancode) = RAW_SCANCODES.pop() {
+        handle_scancode(scancode);
+    }
+}
+
+/// Decode a raw scancode into the key input buffer.
 pub fn handle_scancode(scancode: u8) {
     KEYBOARD_INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
 
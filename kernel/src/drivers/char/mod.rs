@@ -3,4 +3,4 @@
 pub mod keyboard;
 pub mod serial;
 
-pub use keyboard::{Ps2Keyboard, handle_scancode, read_char, interrupt_count};
+pub use keyboard::{Ps2Keyboard, handle_scancode, queue_scancode, read_char, interrupt_count};
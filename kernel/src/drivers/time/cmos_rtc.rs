@@ -1,33 +1,70 @@
 //! CMOS Real-Time Clock (RTC) Driver for x86_64
 //!
-//! Provides real-time clock reading, BCD decoding, 12/24 hour format normalization,
-//! Unix epoch timestamp conversion, and wall-clock time offset calculation.
+//! Provides real-time clock reading and writing, BCD encoding and decoding, 12/24 hour
+//! format normalization, Unix epoch timestamp conversion, and the alarm, update and
+//! periodic interrupts (ISA IRQ 8) behind `/dev/rtc0`.
+//!
+//! Register accesses are serialized by `CMOS_RTC` with interrupts disabled, as the
+//! interrupt handler selects registers through the same index port.
 
 use crate::arch::ports::Ports;
 use crate::device::{Device, DeviceType, Driver, DriverError};
+use crate::fs::vfs::types::VfsError;
+use crate::proc::thread::{Thread, ThreadState};
 use crate::sync::spinlock::Spinlock;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::vec::Vec;
 
 const CMOS_ADDR: u16 = 0x70;
 const CMOS_DATA: u16 = 0x71;
 
 // CMOS Register Offsets
 const REG_SECONDS: u8 = 0x00;
+const REG_SECONDS_ALARM: u8 = 0x01;
 const REG_MINUTES: u8 = 0x02;
+const REG_MINUTES_ALARM: u8 = 0x03;
 const REG_HOURS: u8 = 0x04;
+const REG_HOURS_ALARM: u8 = 0x05;
 const REG_DAY_OF_MONTH: u8 = 0x07;
 const REG_MONTH: u8 = 0x08;
 const REG_YEAR: u8 = 0x09;
 const REG_STATUS_A: u8 = 0x0A;
 const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
 const REG_CENTURY: u8 = 0x32;
 
-/// Global atomic boot epoch timestamp (seconds since Unix epoch 1970-01-01 00:00:00 UTC)
-static BOOT_EPOCH_SEC: AtomicU64 = AtomicU64::new(0);
-/// HPET elapsed nanoseconds timestamp taken when BOOT_EPOCH_SEC was recorded
-static BOOT_HPET_NS: AtomicU64 = AtomicU64::new(0);
+/// ISA IRQ line of the RTC.
+pub const RTC_IRQ: u8 = 8;
+
+// Status register A: divider bits select the 32.768 kHz time base, the low nibble the
+// periodic interrupt rate.
+const STATUS_A_DIVIDER: u8 = 0x20;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+
+// Status register B flags
+const STATUS_B_SET: u8 = 0x80;
+/// Periodic interrupt enable.
+pub const STATUS_B_PIE: u8 = 0x40;
+/// Alarm interrupt enable.
+pub const STATUS_B_AIE: u8 = 0x20;
+/// Update-ended interrupt enable.
+pub const STATUS_B_UIE: u8 = 0x10;
+const STATUS_B_BINARY: u8 = 0x04;
+const STATUS_B_24H: u8 = 0x02;
+
+// Status register C flags, reported by `read()` on `/dev/rtc0` as in Linux.
+/// Any interrupt was raised.
+pub const STATUS_C_IRQF: u8 = 0x80;
+/// Kinds of interrupts raised: periodic, alarm and update-ended.
+const STATUS_C_FLAGS: u8 = 0x70;
+
+/// Alarm register value matching any hour, minute or second.
+const ALARM_DONT_CARE: u8 = 0xC0;
+
+/// Periodic interrupt rates, from rate select 3 (8192 Hz) to 15 (2 Hz).
+pub const PERIODIC_MAX_HZ: u32 = 8192;
+pub const PERIODIC_MIN_HZ: u32 = 2;
 
 /// Date and time structure read from CMOS RTC.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -78,6 +115,48 @@ impl RtcTime {
             + (self.minute as u64) * 60
             + (self.second as u64)
     }
+
+    /// Day of the week, 0 for Sunday.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday.
+        ((self.to_epoch() / 86400 + 4) % 7) as u8
+    }
+
+    /// Day of the year, 0 for January 1st.
+    pub fn yearday(&self) -> u16 {
+        let day_offset = self.day.saturating_sub(1) as u64;
+        (Self::days_before_month(self.month, self.year) + day_offset) as u16
+    }
+
+    /// Converts Unix epoch seconds into an `RtcTime`.
+    pub fn from_epoch(epoch_sec: u64) -> Self {
+        let mut days = epoch_sec / 86400;
+        let secs = epoch_sec % 86400;
+
+        let mut year: u16 = 1970;
+        loop {
+            let year_days = if Self::is_leap_year(year) { 366 } else { 365 };
+            if days < year_days {
+                break;
+            }
+            days -= year_days;
+            year += 1;
+        }
+        let mut month: u8 = 12;
+        while month > 1 && Self::days_before_month(month, year) > days {
+            month -= 1;
+        }
+        let day = days - Self::days_before_month(month, year) + 1;
+
+        RtcTime {
+            year,
+            month,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
 }
 
 /// Hardware CMOS RTC Driver structure.
@@ -98,6 +177,15 @@ impl CmosRtc {
         }
     }
 
+    /// Write a single raw byte to a CMOS register.
+    fn write_register(reg: u8, val: u8) {
+        // SAFETY: Writing to CMOS address port 0x70 and data port 0x71 is safe hardware I/O.
+        unsafe {
+            Ports::outb(CMOS_ADDR, reg | 0x80);
+            Ports::outb(CMOS_DATA, val);
+        }
+    }
+
     /// Returns true if the RTC Update In Progress (UIP) flag is active.
     fn is_update_in_progress() -> bool {
         (Self::read_register(REG_STATUS_A) & 0x80) != 0
@@ -118,6 +206,12 @@ impl CmosRtc {
         ((val >> 4) * 10) + (val & 0x0F)
     }
 
+    /// Convert a binary byte to Binary-Coded Decimal (BCD).
+    #[inline]
+    fn bin_to_bcd(val: u8) -> u8 {
+        ((val / 10) << 4) | (val % 10)
+    }
+
     /// Read current time and date from CMOS hardware.
     pub fn read_hardware_time() -> RtcTime {
         let mut last_sec;
@@ -169,8 +263,8 @@ impl CmosRtc {
         }
 
         let status_b = Self::read_register(REG_STATUS_B);
-        let is_binary = (status_b & 0x04) != 0;
-        let is_24h = (status_b & 0x02) != 0;
+        let is_binary = (status_b & STATUS_B_BINARY) != 0;
+        let is_24h = (status_b & STATUS_B_24H) != 0;
 
         if !is_binary {
             sec = Self::bcd_to_bin(sec);
@@ -205,10 +299,154 @@ impl CmosRtc {
             second: sec,
         }
     }
+
+    /// Encode `val` in the format selected by status register B.
+    fn encode(val: u8, status_b: u8) -> u8 {
+        if (status_b & STATUS_B_BINARY) != 0 {
+            val
+        } else {
+            Self::bin_to_bcd(val)
+        }
+    }
+
+    /// Decode `val` from the format selected by status register B.
+    fn decode(val: u8, status_b: u8) -> u8 {
+        if (status_b & STATUS_B_BINARY) != 0 {
+            val
+        } else {
+            Self::bcd_to_bin(val)
+        }
+    }
+
+    /// Encode a 24-hour `hour`, flagging PM in bit 7 in 12-hour format.
+    fn encode_hour(hour: u8, status_b: u8) -> u8 {
+        if (status_b & STATUS_B_24H) != 0 {
+            return Self::encode(hour, status_b);
+        }
+        // 12-hour format: 12 AM is 12, and PM is flagged in bit 7.
+        let pm = if hour >= 12 { 0x80 } else { 0 };
+        let hour = match hour % 12 {
+            0 => 12,
+            h => h,
+        };
+        Self::encode(hour, status_b) | pm
+    }
+
+    /// Decode an hour register into a 24-hour value.
+    fn decode_hour(raw: u8, status_b: u8) -> u8 {
+        let hour = Self::decode(raw & 0x7F, status_b);
+        if (status_b & STATUS_B_24H) != 0 {
+            hour
+        } else {
+            hour % 12 + if (raw & 0x80) != 0 { 12 } else { 0 }
+        }
+    }
+
+    /// Write time and date to CMOS hardware, in the format the RTC is configured for.
+    pub fn write_hardware_time(time: &RtcTime) {
+        let status_b = Self::read_register(REG_STATUS_B);
+        let encode = |val: u8| Self::encode(val, status_b);
+
+        // Halt updates while the registers are written, so the RTC does not tick halfway.
+        Self::wait_for_update();
+        Self::write_register(REG_STATUS_B, status_b | STATUS_B_SET);
+        Self::write_register(REG_SECONDS, encode(time.second));
+        Self::write_register(REG_MINUTES, encode(time.minute));
+        Self::write_register(REG_HOURS, Self::encode_hour(time.hour, status_b));
+        Self::write_register(REG_DAY_OF_MONTH, encode(time.day));
+        Self::write_register(REG_MONTH, encode(time.month));
+        Self::write_register(REG_YEAR, encode((time.year % 100) as u8));
+        Self::write_register(REG_CENTURY, encode((time.year / 100) as u8));
+        Self::write_register(REG_STATUS_B, status_b & !STATUS_B_SET);
+    }
+
+    /// Read the daily alarm time from CMOS hardware.
+    pub fn read_hardware_alarm() -> RtcAlarm {
+        let status_b = Self::read_register(REG_STATUS_B);
+        let field = |reg: u8| {
+            let raw = Self::read_register(reg);
+            ((raw & ALARM_DONT_CARE) != ALARM_DONT_CARE).then_some(raw)
+        };
+        RtcAlarm {
+            hour: field(REG_HOURS_ALARM).map(|raw| Self::decode_hour(raw, status_b)),
+            minute: field(REG_MINUTES_ALARM).map(|raw| Self::decode(raw, status_b)),
+            second: field(REG_SECONDS_ALARM).map(|raw| Self::decode(raw, status_b)),
+        }
+    }
+
+    /// Write the daily alarm time to CMOS hardware.
+    pub fn write_hardware_alarm(alarm: &RtcAlarm) {
+        let status_b = Self::read_register(REG_STATUS_B);
+        let encode = |val: Option<u8>| val.map_or(ALARM_DONT_CARE, |v| Self::encode(v, status_b));
+        let hour = alarm
+            .hour
+            .map_or(ALARM_DONT_CARE, |h| Self::encode_hour(h, status_b));
+
+        Self::write_register(REG_SECONDS_ALARM, encode(alarm.second));
+        Self::write_register(REG_MINUTES_ALARM, encode(alarm.minute));
+        Self::write_register(REG_HOURS_ALARM, hour);
+    }
+
+    /// Enable or disable the interrupts in `mask` (`STATUS_B_PIE`, `_AIE`, `_UIE`).
+    pub fn set_interrupts(mask: u8, enable: bool) {
+        let status_b = Self::read_register(REG_STATUS_B);
+        let status_b = if enable { status_b | mask } else { status_b & !mask };
+        Self::write_register(REG_STATUS_B, status_b);
+    }
+
+    /// Program the periodic interrupt rate select, 0 to stop it or 3 to 15.
+    pub fn set_periodic_rate(rate: u8) {
+        let status_a = Self::read_register(REG_STATUS_A);
+        let status_a = (status_a & !STATUS_A_RATE_MASK) | STATUS_A_DIVIDER | rate;
+        Self::write_register(REG_STATUS_A, status_a);
+    }
+
+    /// Acknowledge pending interrupts, returning status register C. Until it is read, the
+    /// RTC raises no further interrupts.
+    pub fn acknowledge_interrupts() -> u8 {
+        Self::read_register(REG_STATUS_C)
+    }
+}
+
+/// Daily alarm time; `None` fields match any value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RtcAlarm {
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
 }
 
 pub static CMOS_RTC: Spinlock<CmosRtc> = Spinlock::new(CmosRtc::new());
 
+/// Interrupts raised since `/dev/rtc0` was last read, and the threads waiting for one.
+struct RtcIrqState {
+    count: u64,
+    flags: u8,
+    /// Periodic interrupt frequency in Hz, applied while periodic interrupts are enabled.
+    periodic_hz: u32,
+    waiters: Vec<Arc<Spinlock<Thread>>>,
+}
+
+static RTC_IRQ_STATE: Spinlock<RtcIrqState> = Spinlock::new(RtcIrqState {
+    count: 0,
+    flags: 0,
+    periodic_hz: 1024,
+    waiters: Vec::new(),
+});
+
+/// Run `f` with exclusive access to the CMOS registers.
+fn with_rtc<R>(f: impl FnOnce() -> R) -> R {
+    crate::arch::without_interrupts(|| {
+        let _rtc = CMOS_RTC.lock();
+        f()
+    })
+}
+
+/// Periodic interrupt rate select for `hz`, a power of two the RTC supports.
+fn periodic_rate(hz: u32) -> u8 {
+    (16 - hz.trailing_zeros()) as u8
+}
+
 pub struct CmosRtcDeviceRef;
 
 impl Device for CmosRtcDeviceRef {
@@ -225,14 +463,11 @@ impl Device for CmosRtcDeviceRef {
     }
 }
 
-/// Initialize kernel boot time from CMOS RTC.
+/// Initialize the kernel realtime clock from CMOS RTC.
 pub fn init_boot_time() {
-    let rtc_time = CmosRtc::read_hardware_time();
+    let rtc_time = read_time();
     let epoch_sec = rtc_time.to_epoch();
-    let hpet_ns = crate::arch::timer::hpet::elapsed_ns();
-
-    BOOT_EPOCH_SEC.store(epoch_sec, Ordering::Relaxed);
-    BOOT_HPET_NS.store(hpet_ns, Ordering::Relaxed);
+    crate::time::timekeeping::init_realtime(epoch_sec);
 
     log::info!(
         "[CMOS RTC] Initialized real-time clock: {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC (Epoch: {})",
@@ -248,28 +483,138 @@ pub fn init_boot_time() {
 
 /// Read current CMOS hardware date and time.
 pub fn read_time() -> RtcTime {
-    CmosRtc::read_hardware_time()
+    with_rtc(CmosRtc::read_hardware_time)
 }
 
-/// Returns current wall clock time as `(seconds, microseconds)` since Unix epoch.
-pub fn get_wall_time() -> (u64, u64) {
-    let boot_sec = BOOT_EPOCH_SEC.load(Ordering::Relaxed);
-    let boot_ns = BOOT_HPET_NS.load(Ordering::Relaxed);
+/// Set CMOS hardware date and time.
+pub fn set_time(time: &RtcTime) {
+    with_rtc(|| CmosRtc::write_hardware_time(time));
+}
+
+/// Read the daily alarm time.
+pub fn read_alarm() -> RtcAlarm {
+    with_rtc(CmosRtc::read_hardware_alarm)
+}
 
-    if boot_sec == 0 {
-        // If boot time hasn't been cached yet, attempt to read now.
-        let rtc_time = CmosRtc::read_hardware_time();
-        let sec = rtc_time.to_epoch();
-        return (sec, 0);
+/// Set the daily alarm time.
+pub fn set_alarm(alarm: &RtcAlarm) {
+    with_rtc(|| CmosRtc::write_hardware_alarm(alarm));
+}
+
+/// Enable or disable the interrupts in `mask` (`STATUS_B_PIE`, `_AIE`, `_UIE`).
+pub fn enable_interrupts(mask: u8, enable: bool) {
+    with_rtc(|| {
+        if (mask & STATUS_B_PIE) != 0 {
+            let rate = if enable {
+                periodic_rate(RTC_IRQ_STATE.lock().periodic_hz)
+            } else {
+                0
+            };
+            CmosRtc::set_periodic_rate(rate);
+        }
+        CmosRtc::set_interrupts(mask, enable);
+    });
+}
+
+/// Periodic interrupt frequency in Hz.
+pub fn periodic_frequency() -> u32 {
+    crate::arch::without_interrupts(|| RTC_IRQ_STATE.lock().periodic_hz)
+}
+
+/// Set the periodic interrupt frequency to `hz`, a power of two from [`PERIODIC_MIN_HZ`]
+/// to [`PERIODIC_MAX_HZ`].
+pub fn set_periodic_frequency(hz: u32) -> Result<(), DriverError> {
+    if !hz.is_power_of_two() || !(PERIODIC_MIN_HZ..=PERIODIC_MAX_HZ).contains(&hz) {
+        return Err(DriverError::Unsupported);
     }
+    with_rtc(|| {
+        RTC_IRQ_STATE.lock().periodic_hz = hz;
+        let status_a = CmosRtc::read_register(REG_STATUS_A);
+        if (status_a & STATUS_A_RATE_MASK) != 0 {
+            CmosRtc::set_periodic_rate(periodic_rate(hz));
+        }
+    });
+    Ok(())
+}
 
-    let current_hpet_ns = crate::arch::timer::hpet::elapsed_ns();
-    let elapsed_ns = current_hpet_ns.saturating_sub(boot_ns);
+/// Wait for an RTC interrupt, returning the interrupts raised since the last call in the
+/// format `read()` on `/dev/rtc0` returns: their number above the low byte, and their
+/// `STATUS_C` flags in it.
+pub fn wait_interrupt() -> Result<u64, VfsError> {
+    let thread = crate::proc::current_thread().ok_or(VfsError::NotSupported)?;
+    loop {
+        let data = crate::arch::without_interrupts(|| {
+            let mut state = RTC_IRQ_STATE.lock();
+            if state.count != 0 {
+                let data = (state.count << 8) | state.flags as u64;
+                state.count = 0;
+                state.flags = 0;
+                return Some(data);
+            }
+            thread.lock().state = ThreadState::Sleeping;
+            state.waiters.push(thread.clone());
+            None
+        });
+        if let Some(data) = data {
+            return Ok(data);
+        }
 
-    let total_sec = boot_sec + (elapsed_ns / 1_000_000_000);
-    let usec = (elapsed_ns % 1_000_000_000) / 1_000;
+        crate::sched::schedule(false);
+        crate::arch::without_interrupts(|| {
+            RTC_IRQ_STATE
+                .lock()
+                .waiters
+                .retain(|waiter| !Arc::ptr_eq(waiter, &thread));
+        });
+
+        if let Some(proc_arc) = crate::proc::current_process()
+            && proc_arc.lock().pending_signals.mask != 0
+        {
+            return Err(VfsError::Interrupted);
+        }
+    }
+}
+
+/// Handle an RTC interrupt (ISA IRQ 8): record it and wake the threads waiting for it.
+pub fn handle_interrupt() {
+    let status_c = {
+        let _rtc = CMOS_RTC.lock();
+        CmosRtc::acknowledge_interrupts()
+    };
+    if (status_c & STATUS_C_IRQF) == 0 {
+        return;
+    }
+
+    let waiters = {
+        let mut state = RTC_IRQ_STATE.lock();
+        state.count += 1;
+        state.flags |= STATUS_C_IRQF | (status_c & STATUS_C_FLAGS);
+        core::mem::take(&mut state.waiters)
+    };
+    for thread in waiters {
+        // A thread the interrupted code holds locked is woken by the next timer event.
+        let locked = thread.try_lock().is_none();
+        if locked {
+            crate::time::timer::add(crate::time::now_ns(), thread);
+        } else {
+            Thread::unblock(thread);
+        }
+    }
+}
+
+/// Quiesce the RTC interrupts and route ISA IRQ 8.
+fn init_interrupts() {
+    with_rtc(|| {
+        CmosRtc::set_interrupts(STATUS_B_PIE | STATUS_B_AIE | STATUS_B_UIE, false);
+        CmosRtc::set_periodic_rate(0);
+        CmosRtc::acknowledge_interrupts();
+    });
+    crate::arch::interrupt::ioapic::unmask_isa_irq(RTC_IRQ);
+}
 
-    (total_sec, usec)
+/// Write `epoch_sec` seconds since the Unix epoch to CMOS hardware.
+pub fn write_time(epoch_sec: u64) {
+    set_time(&RtcTime::from_epoch(epoch_sec));
 }
 
 #[derive(Default)]
@@ -290,6 +635,7 @@ impl Driver for CmosRtcDriver {
 
     fn probe(&self) -> Result<(), DriverError> {
         init_boot_time();
+        init_interrupts();
         let device_ref: Arc<Spinlock<Box<dyn Device>>> =
             Arc::new(Spinlock::new(Box::new(CmosRtcDeviceRef)));
         crate::device::DEVICE_MANAGER.write().register(device_ref);
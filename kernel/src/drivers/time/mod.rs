@@ -2,4 +2,4 @@
 
 pub mod cmos_rtc;
 
-pub use cmos_rtc::{get_wall_time, read_time, CmosRtc, RtcTime};
+pub use cmos_rtc::{read_time, write_time, CmosRtc, RtcTime};
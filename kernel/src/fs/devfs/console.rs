@@ -4,6 +4,7 @@
 //! routing operations directly to the kernel TTY subsystem and line discipline.
 
 use crate::fs::vfs::types::{FileOps, InodeOps, Stat, VfsError};
+use crate::syscalls::uaccess::UserPtr;
 use crate::tty::console::CONSOLE;
 use crate::tty::termios::{
     FIONREAD, TCFLSH, TCGETS, TCSBRK, TCSETS, TCSETSF, TCSETSW, TCXONC, TIOCGPGRP, TIOCGWINSZ,
@@ -48,48 +49,24 @@ impl FileOps for ConsoleFileOps {
 
         match cmd {
             TCGETS => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, core::mem::size_of::<Termios>())
-                {
-                    return Err(VfsError::InvalidInput);
-                }
                 let t = console.ldisc.termios;
 
-                // SAFETY: User pointer validated within user space bounds.
-                unsafe {
-                    *(arg as *mut Termios) = t;
-                }
+                UserPtr::<Termios>::new(arg as u64).write(t)?;
 
                 Ok(0)
             }
             TCSETS | TCSETSW | TCSETSF => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, core::mem::size_of::<Termios>())
-                {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: User pointer validated within user space bounds.
-                let t = unsafe { *(arg as *const Termios) };
+                let t = UserPtr::<Termios>::new(arg as u64).read()?;
                 console.ldisc.termios = t;
                 Ok(0)
             }
             TIOCGWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, core::mem::size_of::<WinSize>())
-                {
-                    return Err(VfsError::InvalidInput);
-                }
                 let ws = console.ldisc.winsize;
-                // SAFETY: User pointer validated within user space bounds.
-                unsafe {
-                    *(arg as *mut WinSize) = ws;
-                }
+                UserPtr::<WinSize>::new(arg as u64).write(ws)?;
                 Ok(0)
             }
             TIOCSWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, core::mem::size_of::<WinSize>())
-                {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: User pointer validated within user space bounds.
-                let ws = unsafe { *(arg as *const WinSize) };
+                let ws = UserPtr::<WinSize>::new(arg as u64).read()?;
                 console.ldisc.winsize = ws;
                 if console.ldisc.foreground_pgid > 0 {
                     let _ = crate::ipc::signal::send_signal_to_process_group(
@@ -100,22 +77,12 @@ impl FileOps for ConsoleFileOps {
                 Ok(0)
             }
             TIOCGPGRP => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let pgid = console.ldisc.foreground_pgid;
-                // SAFETY: User pointer validated within user space bounds.
-                unsafe {
-                    *(arg as *mut i32) = pgid;
-                }
+                UserPtr::<i32>::new(arg as u64).write(pgid)?;
                 Ok(0)
             }
             TIOCSPGRP => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: User pointer validated within user space bounds.
-                let pgid = unsafe { *(arg as *const i32) };
+                let pgid = UserPtr::<i32>::new(arg as u64).read()?;
                 console.ldisc.foreground_pgid = pgid;
                 Ok(0)
             }
@@ -128,14 +95,8 @@ impl FileOps for ConsoleFileOps {
                 Ok(0)
             }
             FIONREAD => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let len = console.available_input() as i32;
-                // SAFETY: User pointer validated within user space bounds.
-                unsafe {
-                    *(arg as *mut i32) = len;
-                }
+                UserPtr::<i32>::new(arg as u64).write(len)?;
                 Ok(0)
             }
             _ => Err(VfsError::NotSupported),
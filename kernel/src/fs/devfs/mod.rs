@@ -2,6 +2,7 @@ pub mod block;
 pub mod console;
 pub mod fb;
 pub mod null;
+pub mod rtc;
 pub mod urandom;
 pub mod zero;
 
@@ -10,6 +11,7 @@ pub use block::BlockDeviceInode;
 pub use console::ConsoleInode;
 pub use fb::FbInode;
 pub use null::NullInode;
+pub use rtc::RtcInode;
 pub use urandom::UrandomInode;
 pub use zero::ZeroInode;
 
@@ -22,7 +24,7 @@ use core::sync::atomic::AtomicU64;
 use crate::device::{DeviceType, DEVICE_MANAGER};
 use crate::fs::ramfs::RamDirFileOps;
 use crate::fs::vfs::dentry::Dentry;
-use crate::fs::vfs::mount::MOUNT_TABLE;
+use crate::fs::vfs::mount::{MOUNT_TABLE, MountFlags};
 use crate::fs::vfs::types::{
     FileOps, FileSystem, Inode, InodeOps, InodeType, Stat, SuperBlock, VfsError,
 };
@@ -86,8 +88,8 @@ pub fn register_dev_node(name: &str, inode: Arc<Inode>) {
     if let Some(root_dir) = DEV_ROOT_DIR.lock().as_ref() {
         root_dir.insert(name, inode.clone());
     }
-    let mt = MOUNT_TABLE.read();
-    if let Some((mount, _)) = mt.lookup("/dev") {
+    let dev_mount = MOUNT_TABLE.read().lookup("/dev");
+    if let Some((mount, _)) = dev_mount {
         Dentry::add_child(&mount.root_dentry, name.into(), inode);
     }
 }
@@ -97,8 +99,8 @@ pub fn register_pts_node(name: &str, inode: Arc<Inode>) {
     if let Some(pts_dir) = DEV_PTS_DIR.lock().as_ref() {
         pts_dir.insert(name, inode.clone());
     }
-    let mt = MOUNT_TABLE.read();
-    if let Some((mount, _)) = mt.lookup("/dev") {
+    let dev_mount = MOUNT_TABLE.read().lookup("/dev");
+    if let Some((mount, _)) = dev_mount {
         if let Some(pts_dentry) = mount.root_dentry.children.lock().get("pts").cloned() {
             Dentry::add_child(&pts_dentry, name.into(), inode);
         } else {
@@ -153,7 +155,7 @@ impl DevFs {
     pub fn init() -> Result<(), &'static str> {
         let mut mt = MOUNT_TABLE.write();
         let dev_mount = mt
-            .mount("/dev", &DevFs)
+            .mount_with_flags("/dev", &DevFs, MountFlags::NOEXEC)
             .map_err(|_| "Failed to mount devfs at /dev")?;
 
         let root_dir = DEV_ROOT_DIR
@@ -168,7 +170,7 @@ impl DevFs {
         };
 
         // Declarative list of static core device nodes.
-        let core_nodes: [DevNode; 8] = [
+        let core_nodes: [DevNode; 9] = [
             DevNode {
                 name: "console",
                 inode_type: InodeType::CharDevice,
@@ -209,6 +211,11 @@ impl DevFs {
                 inode_type: InodeType::CharDevice,
                 ops: Arc::new(UrandomInode),
             },
+            DevNode {
+                name: "rtc0",
+                inode_type: InodeType::CharDevice,
+                ops: Arc::new(RtcInode),
+            },
         ];
 
         for node in &core_nodes {
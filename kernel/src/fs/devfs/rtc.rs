@@ -0,0 +1,228 @@
+//! Real-Time Clock Character Device (/dev/rtc0)
+//!
+//! Exposes the CMOS RTC through the Linux rtc interface: the date and time, the daily
+//! alarm, and the alarm, update-ended and periodic interrupts. `read()` blocks until the
+//! next enabled interrupt and returns how many were raised since the last read.
+//!
+//! Like Linux, the device can only be open once at a time, and only root may arm the
+//! alarm or switch interrupts on and off.
+
+use crate::drivers::time::cmos_rtc::{
+    self, RtcAlarm, RtcTime, STATUS_B_AIE, STATUS_B_PIE, STATUS_B_UIE,
+};
+use crate::fs::vfs::types::{FileOps, InodeOps, Stat, VfsError};
+use crate::syscalls::uaccess::UserPtr;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Linux rtc ioctl commands ('p').
+pub const RTC_AIE_ON: u64 = 0x7001;
+pub const RTC_AIE_OFF: u64 = 0x7002;
+pub const RTC_UIE_ON: u64 = 0x7003;
+pub const RTC_UIE_OFF: u64 = 0x7004;
+pub const RTC_PIE_ON: u64 = 0x7005;
+pub const RTC_PIE_OFF: u64 = 0x7006;
+pub const RTC_ALM_SET: u64 = 0x4024_7007;
+pub const RTC_ALM_READ: u64 = 0x8024_7008;
+pub const RTC_RD_TIME: u64 = 0x8024_7009;
+pub const RTC_SET_TIME: u64 = 0x4024_700A;
+pub const RTC_IRQP_READ: u64 = 0x8008_700B;
+pub const RTC_IRQP_SET: u64 = 0x4008_700C;
+
+/// Highest periodic interrupt frequency unprivileged users may set, as in Linux.
+const MAX_USER_FREQ: u64 = 64;
+
+/// Whether `/dev/rtc0` is open.
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// `struct rtc_time`, laid out like `struct tm`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RtcTm {
+    pub tm_sec: i32,
+    pub tm_min: i32,
+    pub tm_hour: i32,
+    pub tm_mday: i32,
+    /// Month, 0 for January.
+    pub tm_mon: i32,
+    /// Years since 1900.
+    pub tm_year: i32,
+    pub tm_wday: i32,
+    pub tm_yday: i32,
+    pub tm_isdst: i32,
+}
+
+impl RtcTm {
+    fn from_rtc_time(time: &RtcTime) -> Self {
+        RtcTm {
+            tm_sec: time.second as i32,
+            tm_min: time.minute as i32,
+            tm_hour: time.hour as i32,
+            tm_mday: time.day as i32,
+            tm_mon: time.month as i32 - 1,
+            tm_year: time.year as i32 - 1900,
+            tm_wday: time.weekday() as i32,
+            tm_yday: time.yearday() as i32,
+            tm_isdst: 0,
+        }
+    }
+
+    /// Convert to an `RtcTime`, if this is a valid date from 1970 on.
+    fn to_rtc_time(self) -> Option<RtcTime> {
+        let field = |val: i32, max: i32| (0..max).contains(&val).then_some(val as u8);
+        let year = u16::try_from(self.tm_year.checked_add(1900)?).ok()?;
+        let time = RtcTime {
+            year,
+            month: field(self.tm_mon, 12)? + 1,
+            day: field(self.tm_mday, 32)?,
+            hour: field(self.tm_hour, 24)?,
+            minute: field(self.tm_min, 60)?,
+            second: field(self.tm_sec, 60)?,
+        };
+        // Rejects days past the end of the month, and years the RTC cannot hold.
+        ((1970..=9999).contains(&year) && RtcTime::from_epoch(time.to_epoch()) == time)
+            .then_some(time)
+    }
+
+    fn from_alarm(alarm: &RtcAlarm) -> Self {
+        let field = |val: Option<u8>| val.map_or(-1, |v| v as i32);
+        RtcTm {
+            tm_sec: field(alarm.second),
+            tm_min: field(alarm.minute),
+            tm_hour: field(alarm.hour),
+            tm_mday: -1,
+            tm_mon: -1,
+            tm_year: -1,
+            tm_wday: -1,
+            tm_yday: -1,
+            tm_isdst: -1,
+        }
+    }
+
+    /// Convert to an alarm; negative fields match any value.
+    fn to_alarm(self) -> Option<RtcAlarm> {
+        let field = |val: i32, max: i32| match val {
+            v if v < 0 => Some(None),
+            v if v < max => Some(Some(v as u8)),
+            _ => None,
+        };
+        Some(RtcAlarm {
+            hour: field(self.tm_hour, 24)?,
+            minute: field(self.tm_min, 60)?,
+            second: field(self.tm_sec, 60)?,
+        })
+    }
+}
+
+/// Fail with `PermissionDenied` unless the calling process has effective UID 0.
+fn check_root() -> Result<(), VfsError> {
+    let proc_arc = crate::proc::current_process().ok_or(VfsError::PermissionDenied)?;
+    if proc_arc.lock().euid != 0 {
+        return Err(VfsError::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// Inode for the `/dev/rtc0` device.
+pub struct RtcInode;
+
+impl InodeOps for RtcInode {
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        if OPEN.swap(true, Ordering::Acquire) {
+            return Err(VfsError::Busy);
+        }
+        Ok(Arc::new(RtcFileOps))
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: 0o020644, // S_IFCHR | 0644
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+}
+
+/// File operations for `/dev/rtc0`.
+pub struct RtcFileOps;
+
+impl FileOps for RtcFileOps {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, VfsError> {
+        if buf.len() < size_of::<u32>() {
+            return Err(VfsError::InvalidInput);
+        }
+        let data = cmos_rtc::wait_interrupt()?;
+        if buf.len() < size_of::<u64>() {
+            buf[..4].copy_from_slice(&(data as u32).to_ne_bytes());
+            Ok(4)
+        } else {
+            buf[..8].copy_from_slice(&data.to_ne_bytes());
+            Ok(8)
+        }
+    }
+
+    fn ioctl(&self, cmd: u64, arg: usize) -> Result<usize, VfsError> {
+        if matches!(
+            cmd,
+            RTC_AIE_ON
+                | RTC_AIE_OFF
+                | RTC_UIE_ON
+                | RTC_UIE_OFF
+                | RTC_PIE_ON
+                | RTC_PIE_OFF
+                | RTC_ALM_SET
+        ) {
+            check_root()?;
+        }
+        match cmd {
+            RTC_AIE_ON => cmos_rtc::enable_interrupts(STATUS_B_AIE, true),
+            RTC_AIE_OFF => cmos_rtc::enable_interrupts(STATUS_B_AIE, false),
+            RTC_UIE_ON => cmos_rtc::enable_interrupts(STATUS_B_UIE, true),
+            RTC_UIE_OFF => cmos_rtc::enable_interrupts(STATUS_B_UIE, false),
+            RTC_PIE_ON => cmos_rtc::enable_interrupts(STATUS_B_PIE, true),
+            RTC_PIE_OFF => cmos_rtc::enable_interrupts(STATUS_B_PIE, false),
+            RTC_RD_TIME => {
+                let tm = RtcTm::from_rtc_time(&cmos_rtc::read_time());
+                UserPtr::<RtcTm>::new(arg as u64).write(tm)?;
+            }
+            RTC_SET_TIME => {
+                let tm = UserPtr::<RtcTm>::new(arg as u64).read()?;
+                check_root()?;
+                let time = tm.to_rtc_time().ok_or(VfsError::InvalidInput)?;
+                cmos_rtc::set_time(&time);
+            }
+            RTC_ALM_READ => {
+                let tm = RtcTm::from_alarm(&cmos_rtc::read_alarm());
+                UserPtr::<RtcTm>::new(arg as u64).write(tm)?;
+            }
+            RTC_ALM_SET => {
+                let tm = UserPtr::<RtcTm>::new(arg as u64).read()?;
+                let alarm = tm.to_alarm().ok_or(VfsError::InvalidInput)?;
+                cmos_rtc::set_alarm(&alarm);
+            }
+            RTC_IRQP_READ => {
+                let hz = cmos_rtc::periodic_frequency() as u64;
+                UserPtr::<u64>::new(arg as u64).write(hz)?;
+            }
+            RTC_IRQP_SET => {
+                let hz = arg as u64;
+                if hz > MAX_USER_FREQ {
+                    check_root()?;
+                }
+                let hz = u32::try_from(hz).map_err(|_| VfsError::InvalidInput)?;
+                cmos_rtc::set_periodic_frequency(hz).map_err(|_| VfsError::InvalidInput)?;
+            }
+            _ => return Err(VfsError::NotSupported),
+        }
+        Ok(0)
+    }
+}
+
+impl Drop for RtcFileOps {
+    /// Stop the update and periodic interrupts when the device is closed, as Linux does;
+    /// the alarm stays armed.
+    fn drop(&mut self) {
+        cmos_rtc::enable_interrupts(STATUS_B_UIE | STATUS_B_PIE, false);
+        OPEN.store(false, Ordering::Release);
+    }
+}
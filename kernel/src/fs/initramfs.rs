@@ -31,7 +31,9 @@ pub fn mkdir_p(path: &str) -> Result<(), VfsError> {
 }
 
 /// Helper function to create a regular file and write its payload, creating parent dirs if needed.
-pub fn create_file_with_parents(path: &str, data: &[u8]) -> Result<(), VfsError> {
+///
+/// The permission bits of `mode` are applied if the filesystem supports it.
+pub fn create_file_with_parents(path: &str, data: &[u8], mode: u32) -> Result<(), VfsError> {
     if let Some(last_slash) = path.rfind('/') {
         let parent = &path[..last_slash];
         if !parent.is_empty() {
@@ -48,7 +50,10 @@ pub fn create_file_with_parents(path: &str, data: &[u8]) -> Result<(), VfsError>
     let file_ops = dentry.inode.ops.open()?;
     let _ = file_ops.truncate(0);
     file_ops.write(0, data)?;
-    Ok(())
+    match dentry.inode.ops.chmod(mode & 0o7777) {
+        Ok(()) | Err(VfsError::NotSupported) => Ok(()),
+        Err(err) => Err(err),
+    }
 }
 
 /// Helper function to create a symbolic link, creating parent dirs if needed.
@@ -86,7 +91,8 @@ pub fn extract_cpio_archive(data: &[u8]) -> Result<usize, &'static str> {
                 log::warn!("[Initramfs] Failed to mkdir '{}': {:?}", full_path, err);
             }
         } else if entry.is_regular_file() {
-            if let Err(err) = create_file_with_parents(&full_path, entry.data()) {
+            let mode = entry.header().mode;
+            if let Err(err) = create_file_with_parents(&full_path, entry.data(), mode) {
                 log::warn!("[Initramfs] Failed to create file '{}': {:?}", full_path, err);
             } else {
                 extracted_count += 1;
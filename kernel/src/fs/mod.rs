@@ -3,6 +3,7 @@ pub mod ext2;
 pub mod fd;
 pub mod initramfs;
 pub mod pipe;
+pub mod procfs;
 pub mod ramfs;
 pub mod vfs;
 
@@ -11,11 +12,11 @@ pub use initramfs::Initramfs;
 
 pub use fd::FdTable;
 pub use vfs::dentry::Dentry;
-pub use vfs::file::File;
-pub use vfs::mount::{MOUNT_TABLE, Mount};
+pub use vfs::file::{DenyWrite, File};
+pub use vfs::mount::{MOUNT_TABLE, Mount, MountFlags};
 pub use vfs::path::{
-    build_path, create_file, mkdir, normalize_path, open_file, read_file, readlink, rename,
-    resolve_path, rmdir, stat, symlink, unlink,
+    build_path, create_file, mkdir, normalize_path, open_file, read_dentry, read_file, readlink,
+    rename, resolve_path, rmdir, stat, symlink, unlink,
 };
 pub use vfs::types::{
     FileOps, FileSystem, Inode, InodeOps, InodeType, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY,
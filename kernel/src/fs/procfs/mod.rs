@@ -0,0 +1,312 @@
+pub mod pid;
+
+pub use pid::{PidDirInode, SelfLinkInode};
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::fs::ramfs::RamDirFileOps;
+use crate::fs::vfs::dcache::dcache_evict;
+use crate::fs::vfs::mount::{MOUNT_TABLE, MountFlags};
+use crate::fs::vfs::types::{
+    FileOps, FileSystem, Inode, InodeOps, InodeType, Stat, SuperBlock, VfsError,
+};
+use crate::proc::ProcessId;
+use crate::sync::spinlock::Spinlock;
+
+/// Renders the full contents of a procfs file. Called once per `open()`.
+pub type ShowFn = fn() -> String;
+
+/// Parses a write to a procfs file and applies it. The second argument is the writer's
+/// EUID.
+pub type StoreFn = fn(&str, u32) -> Result<(), VfsError>;
+
+/// Callback applied to a write on an open procfs file.
+pub type WriteHandler = Arc<dyn Fn(&str) -> Result<(), VfsError> + Send + Sync>;
+
+/// Fail with `PermissionDenied` unless `euid` is root, as system-wide tunables require.
+pub fn require_root(euid: u32) -> Result<(), VfsError> {
+    if euid == 0 { Ok(()) } else { Err(VfsError::PermissionDenied) }
+}
+
+/// Inode numbers for static entries; per-pid inodes derive theirs from the PID.
+static NEXT_INO: AtomicU64 = AtomicU64::new(2);
+
+fn alloc_ino() -> u64 {
+    NEXT_INO.fetch_add(1, Ordering::Relaxed)
+}
+
+// ===== ProcFileOps — snapshot-on-open file contents =====
+
+/// File operations for a procfs file.
+///
+/// The contents are rendered when the file is opened so that a sequence of `read()`
+/// calls observes one consistent snapshot.
+pub struct ProcFileOps {
+    data: Vec<u8>,
+    mode: u32,
+    writer: Option<WriteHandler>,
+}
+
+impl ProcFileOps {
+    pub fn new(data: String, mode: u32, writer: Option<WriteHandler>) -> Self {
+        Self {
+            data: data.into_bytes(),
+            mode,
+            writer,
+        }
+    }
+}
+
+impl FileOps for ProcFileOps {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsError> {
+        if offset >= self.data.len() {
+            return Ok(0);
+        }
+        let n = core::cmp::min(buf.len(), self.data.len() - offset);
+        buf[..n].copy_from_slice(&self.data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize, VfsError> {
+        let writer = self.writer.as_ref().ok_or(VfsError::PermissionDenied)?;
+        let text = core::str::from_utf8(buf).map_err(|_| VfsError::InvalidInput)?;
+        writer(text.trim())?;
+        Ok(buf.len())
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: self.mode,
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+}
+
+// ===== ProcFileInode — global (non-per-process) entries =====
+
+/// Inode for a global procfs file such as `/proc/meminfo`.
+pub struct ProcFileInode {
+    show: ShowFn,
+    store: Option<StoreFn>,
+}
+
+impl ProcFileInode {
+    pub fn new(show: ShowFn, store: Option<StoreFn>) -> Self {
+        Self { show, store }
+    }
+
+    fn mode(&self) -> u32 {
+        if self.store.is_some() {
+            0o100644 // S_IFREG | 0644
+        } else {
+            0o100444 // S_IFREG | 0444
+        }
+    }
+}
+
+impl InodeOps for ProcFileInode {
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        let writer = self.store.map(|store| {
+            Arc::new(move |s: &str| {
+                let euid = crate::proc::current_process().map_or(0, |p| p.lock().euid);
+                store(s, euid)
+            }) as WriteHandler
+        });
+        Ok(Arc::new(ProcFileOps::new((self.show)(), self.mode(), writer)))
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: self.mode(),
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+}
+
+// ===== ProcRootInode — `/proc` =====
+
+/// Globally registered `/proc` files and their directories, keyed by path relative to
+/// `/proc` (e.g. `sys/kernel/core_pattern`), independent of mount order.
+static PROC_ENTRIES: Spinlock<BTreeMap<String, Arc<Inode>>> = Spinlock::new(BTreeMap::new());
+
+/// Root directory of procfs.
+///
+/// Serves registered global entries and synthesizes one directory per live process.
+pub struct ProcRootInode;
+
+impl InodeOps for ProcRootInode {
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>, VfsError> {
+        if let Some(inode) = PROC_ENTRIES.lock().get(name).cloned() {
+            return Ok(inode);
+        }
+        if name == "self" {
+            return Ok(SelfLinkInode::inode());
+        }
+        let pid = name
+            .parse::<u64>()
+            .map(ProcessId)
+            .map_err(|_| VfsError::NotFound)?;
+        PidDirInode::inode(pid)
+    }
+
+    fn readdir(&self) -> Result<Vec<String>, VfsError> {
+        let mut names: Vec<String> = PROC_ENTRIES
+            .lock()
+            .keys()
+            .filter(|path| !path.contains('/'))
+            .cloned()
+            .collect();
+        names.push("self".into());
+        for proc_arc in crate::proc::all_processes() {
+            names.push(proc_arc.lock().pid.as_u64().to_string());
+        }
+        Ok(names)
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: 0o040555, // S_IFDIR | 0555
+            nlink: 2,
+            ..Default::default()
+        })
+    }
+
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        Ok(Arc::new(RamDirFileOps))
+    }
+}
+
+// ===== ProcDirInode — subdirectories of registered entries =====
+
+/// A directory such as `/proc/sys/kernel`, listing the registered entries below it.
+pub struct ProcDirInode {
+    path: String,
+}
+
+impl InodeOps for ProcDirInode {
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>, VfsError> {
+        let path = alloc::format!("{}/{}", self.path, name);
+        PROC_ENTRIES.lock().get(&path).cloned().ok_or(VfsError::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<String>, VfsError> {
+        let prefix = alloc::format!("{}/", self.path);
+        Ok(PROC_ENTRIES
+            .lock()
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(String::from)
+            .collect())
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: 0o040555, // S_IFDIR | 0555
+            nlink: 2,
+            ..Default::default()
+        })
+    }
+
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        Ok(Arc::new(RamDirFileOps))
+    }
+}
+
+// ===== Dynamic entry registration =====
+
+/// Register a global file in `/proc`.
+///
+/// `path` is relative to `/proc` and may name subdirectories, which are created as needed.
+/// `show` renders the file on every open; `store`, if present, makes the file writable.
+/// May be called before or after procfs is mounted.
+pub fn register_proc_entry(path: &str, show: ShowFn, store: Option<StoreFn>) {
+    let mut entries = PROC_ENTRIES.lock();
+    for (end, _) in path.match_indices('/') {
+        let dir = &path[..end];
+        if !entries.contains_key(dir) {
+            let inode = Arc::new(Inode {
+                ino: alloc_ino(),
+                inode_type: InodeType::Directory,
+                ops: Arc::new(ProcDirInode { path: dir.into() }),
+            });
+            entries.insert(dir.into(), inode);
+        }
+    }
+
+    let inode = Arc::new(Inode {
+        ino: alloc_ino(),
+        inode_type: InodeType::File,
+        ops: Arc::new(ProcFileInode::new(show, store)),
+    });
+    entries.insert(path.into(), inode);
+}
+
+/// Drop cached dentries for a reaped process so `/proc/[pid]` disappears with it.
+pub fn flush_pid(pid: ProcessId) {
+    let proc_mount = MOUNT_TABLE.read().lookup("/proc");
+    let mount = match proc_mount {
+        Some((mount, rest)) if rest.is_empty() && mount.mount_point == "/proc" => mount,
+        _ => return,
+    };
+
+    let name = pid.as_u64().to_string();
+    let pid_dentry = mount.root_dentry.children.lock().remove(&name);
+    if let Some(pid_dentry) = pid_dentry {
+        let children: Vec<String> = pid_dentry.children.lock().keys().cloned().collect();
+        for child in children {
+            dcache_evict(&pid_dentry, &child);
+        }
+    }
+    dcache_evict(&mount.root_dentry, &name);
+}
+
+// ===== ProcFs =====
+
+/// Process information pseudo-filesystem, mounted at `/proc`.
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn mount(&self) -> Result<SuperBlock, VfsError> {
+        let root_inode = Arc::new(Inode {
+            ino: 1,
+            inode_type: InodeType::Directory,
+            ops: Arc::new(ProcRootInode),
+        });
+
+        Ok(SuperBlock {
+            fs_name: "proc",
+            root_inode,
+            next_ino: AtomicU64::new(2),
+            read_only: false,
+        })
+    }
+}
+
+impl ProcFs {
+    /// Mount procfs at `/proc`.
+    pub fn init() -> Result<(), &'static str> {
+        MOUNT_TABLE
+            .write()
+            .mount_with_flags("/proc", &ProcFs, MountFlags::NOEXEC)
+            .map_err(|_| "Failed to mount procfs at /proc")?;
+
+        log::info!("[ProcFS] Mounted /proc successfully.");
+        Ok(())
+    }
+}
+
+crate::fs_initcall!(ProcFs::init);
+crate::MODULE_LICENSE!("BSD-2-Clause");
+crate::MODULE_AUTHOR!("Ananta98");
+crate::MODULE_DESCRIPTION!("Process Information Filesystem");
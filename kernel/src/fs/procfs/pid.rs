@@ -0,0 +1,307 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{ProcFileOps, WriteHandler};
+use crate::fs::ramfs::RamDirFileOps;
+use crate::fs::vfs::types::{FileOps, Inode, InodeOps, InodeType, Stat, VfsError};
+use crate::mm::oom::{OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN};
+use crate::proc::{Process, ProcessId, ProcessState};
+use crate::sched::CPU_MASK_ALL;
+use crate::sched::group::SchedGroup;
+use crate::sched::nice::Nice;
+
+/// Applies a write to the locked process. The last argument is the writer's EUID.
+pub type PidStore = fn(&mut Process, &str, u32) -> Result<(), VfsError>;
+
+/// A file inside `/proc/[pid]`.
+pub struct PidEntry {
+    pub name: &'static str,
+    /// Render the file from the locked process.
+    pub show: fn(&Process) -> String,
+    pub store: Option<PidStore>,
+}
+
+/// Files present in every `/proc/[pid]` directory.
+pub static PID_ENTRIES: &[PidEntry] = &[
+    PidEntry {
+        name: "status",
+        show: show_status,
+        store: None,
+    },
+    PidEntry {
+        name: "autogroup",
+        show: show_autogroup,
+        store: Some(store_autogroup),
+    },
+    PidEntry {
+        name: "oom_score",
+        show: show_oom_score,
+        store: None,
+    },
+    PidEntry {
+        name: "oom_score_adj",
+        show: show_oom_score_adj,
+        store: Some(store_oom_score_adj),
+    },
+];
+
+fn show_status(proc: &Process) -> String {
+    let name = proc.cmdline.program_name().unwrap_or("");
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let state = match proc.state {
+        ProcessState::Creating | ProcessState::Ready | ProcessState::Running => "R (running)",
+        ProcessState::Stopped => "T (stopped)",
+        ProcessState::Zombie => "Z (zombie)",
+    };
+    // Like Linux, report the affinity of the main thread.
+    let cpus_allowed = proc
+        .threads
+        .values()
+        .next()
+        .map_or(CPU_MASK_ALL, |t| t.lock().cpus_allowed);
+
+    alloc::format!(
+        "Name:\t{}\nState:\t{}\nTgid:\t{}\nPid:\t{}\nPPid:\t{}\n\
+         Uid:\t{}\t{}\t{}\t{}\nGid:\t{}\t{}\t{}\t{}\nThreads:\t{}\n\
+         Cpus_allowed:\t{:x}\nCpus_allowed_list:\t{}\n",
+        name.chars().take(15).collect::<String>(),
+        state,
+        proc.pid,
+        proc.pid,
+        proc.ppid,
+        proc.uid,
+        proc.euid,
+        proc.euid,
+        proc.euid,
+        proc.gid,
+        proc.egid,
+        proc.egid,
+        proc.egid,
+        proc.threads.len(),
+        cpus_allowed,
+        cpu_list(cpus_allowed),
+    )
+}
+
+/// Format a CPU mask as a list of ranges, e.g. `0-2,5`.
+fn cpu_list(mask: u32) -> String {
+    let mut list = String::new();
+    let mut cpu = 0;
+    while cpu < 32 {
+        if mask & (1 << cpu) == 0 {
+            cpu += 1;
+            continue;
+        }
+        let first = cpu;
+        while cpu < 32 && mask & (1 << cpu) != 0 {
+            cpu += 1;
+        }
+        if !list.is_empty() {
+            list.push(',');
+        }
+        if cpu - 1 == first {
+            list.push_str(&alloc::format!("{}", first));
+        } else {
+            list.push_str(&alloc::format!("{}-{}", first, cpu - 1));
+        }
+    }
+    list
+}
+
+/// Scheduling group of the main thread; threads of a process always share it.
+fn sched_group(proc: &Process) -> Option<Arc<SchedGroup>> {
+    let thread = proc.threads.values().next()?;
+    thread.lock().sched_group.clone()
+}
+
+/// Processes outside any autogroup are reported in group 0, the root.
+fn show_autogroup(proc: &Process) -> String {
+    match sched_group(proc) {
+        Some(group) => alloc::format!("/autogroup-{} nice {}\n", group.id, group.nice().value()),
+        None => String::from("/autogroup-0 nice 0\n"),
+    }
+}
+
+/// Only root and the owner of a process may change its settings.
+fn check_owner(proc: &Process, euid: u32) -> Result<(), VfsError> {
+    if euid == 0 || euid == proc.uid || euid == proc.euid {
+        Ok(())
+    } else {
+        Err(VfsError::PermissionDenied)
+    }
+}
+
+fn store_autogroup(proc: &mut Process, value: &str, euid: u32) -> Result<(), VfsError> {
+    check_owner(proc, euid)?;
+    let nice: i8 = value.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    let nice = Nice::new(nice).map_err(|_| VfsError::InvalidInput)?;
+    let group = sched_group(proc).ok_or(VfsError::InvalidInput)?;
+    // As with setpriority, raising the priority is a privileged operation.
+    if nice < group.nice() && euid != 0 {
+        return Err(VfsError::PermissionDenied);
+    }
+    group.set_nice(nice);
+    Ok(())
+}
+
+fn show_oom_score(proc: &Process) -> String {
+    alloc::format!("{}\n", crate::mm::oom::oom_score(proc))
+}
+
+fn show_oom_score_adj(proc: &Process) -> String {
+    alloc::format!("{}\n", proc.oom_score_adj)
+}
+
+fn store_oom_score_adj(proc: &mut Process, value: &str, euid: u32) -> Result<(), VfsError> {
+    check_owner(proc, euid)?;
+    let adj: i16 = value.parse().map_err(|_| VfsError::InvalidInput)?;
+    if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&adj) {
+        return Err(VfsError::InvalidInput);
+    }
+    // Making a process less likely to be killed is a privileged operation.
+    if adj < proc.oom_score_adj && euid != 0 {
+        return Err(VfsError::PermissionDenied);
+    }
+    proc.oom_score_adj = adj;
+    Ok(())
+}
+
+fn pid_ino(pid: ProcessId, index: usize) -> u64 {
+    (pid.as_u64() << 8) | index as u64
+}
+
+// ===== PidDirInode — `/proc/[pid]` =====
+
+/// Directory inode for a single process.
+pub struct PidDirInode {
+    pid: ProcessId,
+}
+
+impl PidDirInode {
+    /// Build the directory inode for `pid`, failing if no such process exists.
+    pub fn inode(pid: ProcessId) -> Result<Arc<Inode>, VfsError> {
+        crate::proc::find_process(pid).ok_or(VfsError::NotFound)?;
+        Ok(Arc::new(Inode {
+            ino: pid_ino(pid, 0),
+            inode_type: InodeType::Directory,
+            ops: Arc::new(PidDirInode { pid }),
+        }))
+    }
+}
+
+impl InodeOps for PidDirInode {
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>, VfsError> {
+        let (index, entry) = PID_ENTRIES
+            .iter()
+            .enumerate()
+            .find(|(_, e)| e.name == name)
+            .ok_or(VfsError::NotFound)?;
+        Ok(Arc::new(Inode {
+            ino: pid_ino(self.pid, index + 1),
+            inode_type: InodeType::File,
+            ops: Arc::new(PidFileInode {
+                pid: self.pid,
+                entry,
+            }),
+        }))
+    }
+
+    fn readdir(&self) -> Result<Vec<String>, VfsError> {
+        Ok(PID_ENTRIES.iter().map(|e| e.name.into()).collect())
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        let proc_arc = crate::proc::find_process(self.pid).ok_or(VfsError::NotFound)?;
+        let proc = proc_arc.lock();
+        Ok(Stat {
+            ino: pid_ino(self.pid, 0),
+            mode: 0o040555, // S_IFDIR | 0555
+            nlink: 2,
+            uid: proc.euid,
+            gid: proc.egid,
+            ..Default::default()
+        })
+    }
+
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        Ok(Arc::new(RamDirFileOps))
+    }
+}
+
+// ===== PidFileInode — `/proc/[pid]/<entry>` =====
+
+/// Inode for one `PID_ENTRIES` file of a specific process.
+pub struct PidFileInode {
+    pid: ProcessId,
+    entry: &'static PidEntry,
+}
+
+impl PidFileInode {
+    fn mode(&self) -> u32 {
+        if self.entry.store.is_some() {
+            0o100644 // S_IFREG | 0644
+        } else {
+            0o100444 // S_IFREG | 0444
+        }
+    }
+}
+
+impl InodeOps for PidFileInode {
+    fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
+        let proc_arc = crate::proc::find_process(self.pid).ok_or(VfsError::NotFound)?;
+        let data = (self.entry.show)(&proc_arc.lock());
+
+        let writer = self.entry.store.map(|store| {
+            let pid = self.pid;
+            Arc::new(move |value: &str| {
+                // Read the writer's credentials before locking the target, which may be itself.
+                let euid = crate::proc::current_process().map_or(0, |p| p.lock().euid);
+                let target = crate::proc::find_process(pid).ok_or(VfsError::NotFound)?;
+                let mut proc = target.lock();
+                store(&mut proc, value, euid)
+            }) as WriteHandler
+        });
+
+        Ok(Arc::new(ProcFileOps::new(data, self.mode(), writer)))
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: self.mode(),
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+}
+
+// ===== SelfLinkInode — `/proc/self` =====
+
+/// Symlink resolving to the calling process's `/proc/[pid]` directory.
+pub struct SelfLinkInode;
+
+impl SelfLinkInode {
+    pub fn inode() -> Arc<Inode> {
+        Arc::new(Inode {
+            ino: 1 << 7,
+            inode_type: InodeType::Symlink,
+            ops: Arc::new(SelfLinkInode),
+        })
+    }
+}
+
+impl InodeOps for SelfLinkInode {
+    fn readlink(&self) -> Result<String, VfsError> {
+        let proc_arc = crate::proc::current_process().ok_or(VfsError::NotFound)?;
+        let pid = proc_arc.lock().pid;
+        Ok(pid.as_u64().to_string())
+    }
+
+    fn stat(&self) -> Result<Stat, VfsError> {
+        Ok(Stat {
+            mode: 0o120777, // S_IFLNK | 0777
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+}
@@ -5,13 +5,18 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Permission bits given to newly created files.
+const DEFAULT_FILE_MODE: u32 = 0o644;
 
 // ===== RamFileOps — in-memory file I/O =====
 
 /// File I/O operations for an in-memory regular file.
 pub struct RamFileOps {
     pub content: Arc<RwLock<Vec<u8>>>,
+    /// Permission bits, shared with the owning [`RamFileInode`].
+    pub mode: Arc<AtomicU32>,
 }
 
 impl FileOps for RamFileOps {
@@ -44,7 +49,7 @@ impl FileOps for RamFileOps {
         let content = self.content.read();
         Ok(crate::fs::vfs::types::Stat {
             size: content.len() as u64,
-            mode: 0o100644,
+            mode: 0o100000 | self.mode.load(Ordering::Relaxed),
             nlink: 1,
             ..Default::default()
         })
@@ -56,12 +61,15 @@ impl FileOps for RamFileOps {
 /// Inode operations for an in-memory regular file.
 pub struct RamFileInode {
     pub content: Arc<RwLock<Vec<u8>>>,
+    /// Permission bits (`0o7777`).
+    pub mode: Arc<AtomicU32>,
 }
 
 impl RamFileInode {
     pub fn new() -> Self {
         Self {
             content: Arc::new(RwLock::new(Vec::new())),
+            mode: Arc::new(AtomicU32::new(DEFAULT_FILE_MODE)),
         }
     }
 }
@@ -70,6 +78,7 @@ impl InodeOps for RamFileInode {
     fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
         Ok(Arc::new(RamFileOps {
             content: self.content.clone(),
+            mode: self.mode.clone(),
         }))
     }
 
@@ -77,7 +86,7 @@ impl InodeOps for RamFileInode {
         let content = self.content.read();
         Ok(crate::fs::vfs::types::Stat {
             size: content.len() as u64,
-            mode: 0o100644,
+            mode: 0o100000 | self.mode.load(Ordering::Relaxed),
             nlink: 1,
             ..Default::default()
         })
@@ -87,6 +96,11 @@ impl InodeOps for RamFileInode {
         self.content.write().resize(size, 0);
         Ok(())
     }
+
+    fn chmod(&self, mode: u32) -> Result<(), VfsError> {
+        self.mode.store(mode & 0o7777, Ordering::Relaxed);
+        Ok(())
+    }
 }
 
 // ===== RamSymlinkInode — in-memory symbolic link inode =====
@@ -1,119 +1,191 @@
 use super::dentry::Dentry;
+use crate::sync::rcu::{rcu_read_lock, RcuPtr};
 use crate::sync::spinlock::Spinlock;
-use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 /// Maximum number of dentries retained in the global VFS dcache LRU table.
 pub const DCACHE_CAPACITY: usize = 2048;
 
+/// Hash buckets of the dcache.
+const DCACHE_BUCKETS: usize = 256;
+
 // SAFETY: Dentries are keyed by the raw pointer value of the parent Arc<Dentry>.
 // Since dentries are always pinned in Arc allocations that outlive dcache entries
 // (they are referenced by at least one live Arc elsewhere), the pointer value is
 // stable and unique for the lifetime of the entry, making it safe to use as a key.
 //
+// Lookups take no lock: each bucket is an RCU-protected list, replaced by a modified
+// copy on insertion and removal, and entries record their last use atomically.
+//
 // TODO: Replace the O(n) LRU eviction scan with an intrusive doubly-linked list
-//       (O(1) eviction). The current BTreeMap scan is acceptable at DCACHE_CAPACITY=2048.
+//       (O(1) eviction). The current scan is acceptable at DCACHE_CAPACITY=2048.
 
 struct DCacheEntry {
+    parent: usize,
+    name: String,
     dentry: Arc<Dentry>,
-    access_count: u64,
+    access_count: AtomicU64,
 }
 
+type Bucket = Vec<Arc<DCacheEntry>>;
+
 /// Global VFS Directory Entry Cache (DCache) with LRU eviction.
 pub struct DCache {
-    entries: BTreeMap<(usize, String), DCacheEntry>,
-    clock: u64,
+    buckets: [RcuPtr<Bucket>; DCACHE_BUCKETS],
+    /// Serialises insertions and removals, and counts the entries.
+    update: Spinlock<usize>,
+    clock: AtomicU64,
 }
 
 impl DCache {
     /// Create an empty DCache instance.
     pub const fn new() -> Self {
         Self {
-            entries: BTreeMap::new(),
-            clock: 0,
+            buckets: [const { RcuPtr::null() }; DCACHE_BUCKETS],
+            update: Spinlock::new(0),
+            clock: AtomicU64::new(0),
         }
     }
 
     /// Look up a cached child dentry given its parent dentry and component name.
-    pub fn lookup(&mut self, parent: &Arc<Dentry>, name: &str) -> Option<Arc<Dentry>> {
+    pub fn lookup(&self, parent: &Arc<Dentry>, name: &str) -> Option<Arc<Dentry>> {
         // SAFETY: See module-level SAFETY comment above for why this pointer is stable.
         let parent_ptr = Arc::as_ptr(parent) as usize;
-        let key = (parent_ptr, String::from(name));
-        if let Some(entry) = self.entries.get_mut(&key) {
-            self.clock = self.clock.wrapping_add(1);
-            entry.access_count = self.clock;
-            return Some(entry.dentry.clone());
-        }
-        None
+        let guard = rcu_read_lock();
+        let bucket = self.buckets[bucket_of(parent_ptr, name)].get(&guard)?;
+        let entry = bucket.iter().find(|e| e.parent == parent_ptr && e.name == name)?;
+        entry.access_count.store(self.tick(), Ordering::Relaxed);
+        Some(entry.dentry.clone())
     }
 
     /// Insert a parent-child dentry mapping into the DCache.
-    pub fn insert(&mut self, parent: &Arc<Dentry>, name: &str, dentry: Arc<Dentry>) {
+    pub fn insert(&self, parent: &Arc<Dentry>, name: &str, dentry: Arc<Dentry>) {
         // SAFETY: See module-level SAFETY comment above for why this pointer is stable.
         let parent_ptr = Arc::as_ptr(parent) as usize;
-        let key = (parent_ptr, String::from(name));
-
-        if self.entries.len() >= DCACHE_CAPACITY && !self.entries.contains_key(&key) {
+        let index = bucket_of(parent_ptr, name);
+        let matches = |e: &Arc<DCacheEntry>| e.parent == parent_ptr && e.name == name;
+        let mut len = self.update.lock();
+
+        let cached = {
+            let guard = rcu_read_lock();
+            self.buckets[index].get(&guard).is_some_and(|bucket| bucket.iter().any(matches))
+        };
+        if !cached {
             // Evict least recently used entry (smallest access_count)
-            let mut lru_key: Option<(usize, String)> = None;
-            let mut min_access = u64::MAX;
-
-            for (k, entry) in self.entries.iter() {
-                if entry.access_count < min_access {
-                    min_access = entry.access_count;
-                    lru_key = Some(k.clone());
-                }
-            }
-
-            if let Some(evict_k) = lru_key {
-                self.entries.remove(&evict_k);
+            if *len >= DCACHE_CAPACITY && self.evict_lru() {
+                *len -= 1;
             }
+            *len += 1;
         }
 
-        self.clock = self.clock.wrapping_add(1);
-        self.entries.insert(
-            key,
-            DCacheEntry {
-                dentry,
-                access_count: self.clock,
-            },
-        );
+        let mut bucket = self.copy_bucket(index);
+        bucket.retain(|e| !matches(e));
+        bucket.push(Arc::new(DCacheEntry {
+            parent: parent_ptr,
+            name: String::from(name),
+            dentry,
+            access_count: AtomicU64::new(self.tick()),
+        }));
+        self.buckets[index].assign(Some(bucket));
     }
 
     /// Remove a specific dentry entry from the DCache (e.g. on unlink / rmdir).
-    pub fn evict(&mut self, parent: &Arc<Dentry>, name: &str) {
+    pub fn evict(&self, parent: &Arc<Dentry>, name: &str) {
         // SAFETY: See module-level SAFETY comment above for why this pointer is stable.
         let parent_ptr = Arc::as_ptr(parent) as usize;
-        let key = (parent_ptr, String::from(name));
-        self.entries.remove(&key);
+        let mut len = self.update.lock();
+        if self.remove(bucket_of(parent_ptr, name), parent_ptr, name) {
+            *len -= 1;
+        }
     }
 
     /// Purge all cached dentries from the DCache.
-    pub fn purge(&mut self) {
-        self.entries.clear();
+    pub fn purge(&self) {
+        let mut len = self.update.lock();
+        for bucket in &self.buckets {
+            bucket.assign(None);
+        }
+        *len = 0;
+    }
+
+    /// Advance the LRU clock and return the new time.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+    }
+
+    /// A copy of bucket `index` to modify. Called with `update` locked.
+    fn copy_bucket(&self, index: usize) -> Bucket {
+        let guard = rcu_read_lock();
+        self.buckets[index].get(&guard).cloned().unwrap_or_default()
+    }
+
+    /// Remove the entry for `name` under `parent_ptr` from bucket `index`, returning
+    /// whether there was one. Called with `update` locked.
+    fn remove(&self, index: usize, parent_ptr: usize, name: &str) -> bool {
+        let mut bucket = self.copy_bucket(index);
+        let Some(pos) = bucket.iter().position(|e| e.parent == parent_ptr && e.name == name)
+        else {
+            return false;
+        };
+        bucket.swap_remove(pos);
+        self.buckets[index].assign((!bucket.is_empty()).then_some(bucket));
+        true
+    }
+
+    /// Remove the least recently used entry, returning whether there was one. Called
+    /// with `update` locked.
+    fn evict_lru(&self) -> bool {
+        let lru = {
+            let guard = rcu_read_lock();
+            let mut lru: Option<(usize, usize, String)> = None;
+            let mut min_access = u64::MAX;
+            for (index, bucket) in self.buckets.iter().enumerate() {
+                for entry in bucket.get(&guard).into_iter().flatten() {
+                    let access = entry.access_count.load(Ordering::Relaxed);
+                    if access < min_access {
+                        min_access = access;
+                        lru = Some((index, entry.parent, entry.name.clone()));
+                    }
+                }
+            }
+            lru
+        };
+        lru.is_some_and(|(index, parent_ptr, name)| self.remove(index, parent_ptr, &name))
+    }
+}
+
+/// Bucket of the entry for `name` under the parent at `parent_ptr` (FNV-1a).
+fn bucket_of(parent_ptr: usize, name: &str) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parent_ptr.to_le_bytes().iter().chain(name.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
     }
+    hash as usize % DCACHE_BUCKETS
 }
 
-/// Global thread-safe VFS dcache table instance.
-pub static DCACHE: Spinlock<DCache> = Spinlock::new(DCache::new());
+/// Global VFS dcache table instance.
+pub static DCACHE: DCache = DCache::new();
 
 /// Query the global VFS dcache for a cached child dentry.
 pub fn dcache_lookup(parent: &Arc<Dentry>, name: &str) -> Option<Arc<Dentry>> {
-    DCACHE.lock().lookup(parent, name)
+    DCACHE.lookup(parent, name)
 }
 
 /// Cache a resolved child dentry in the global VFS dcache.
 pub fn dcache_insert(parent: &Arc<Dentry>, name: &str, dentry: Arc<Dentry>) {
-    DCACHE.lock().insert(parent, name, dentry);
+    DCACHE.insert(parent, name, dentry);
 }
 
 /// Evict a child dentry from the global VFS dcache.
 pub fn dcache_evict(parent: &Arc<Dentry>, name: &str) {
-    DCACHE.lock().evict(parent, name);
+    DCACHE.evict(parent, name);
 }
 
 /// Purge all entries from the global VFS dcache.
 pub fn dcache_purge() {
-    DCACHE.lock().purge();
+    DCACHE.purge();
 }
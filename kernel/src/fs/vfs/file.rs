@@ -1,9 +1,80 @@
 use super::dentry::Dentry;
-use super::types::{FileOps, SeekWhence, VfsError, can_read, can_write};
+use super::types::{FileOps, Inode, SeekWhence, VfsError, can_read, can_write};
 use crate::fs::vfs::types::InodeType;
 use crate::sync::spinlock::Spinlock;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 
+/// Per-inode write access count, keyed by inode address.
+///
+/// Positive values count open writers, negative values count running executables that
+/// deny writes. Inodes with neither have no entry. Every holder keeps its inode alive,
+/// so a key cannot be reused while it is present.
+static WRITE_ACCESS: Spinlock<BTreeMap<usize, isize>> = Spinlock::new(BTreeMap::new());
+
+fn inode_key(inode: &Arc<Inode>) -> usize {
+    Arc::as_ptr(inode) as usize
+}
+
+/// Adjust the write access count of `inode` by `delta` unless its sign says otherwise.
+fn update_write_access(inode: &Arc<Inode>, delta: isize) -> Result<(), VfsError> {
+    let key = inode_key(inode);
+    let mut counts = WRITE_ACCESS.lock();
+    let count = counts.get(&key).copied().unwrap_or(0);
+    if (delta > 0 && count < 0) || (delta < 0 && count > 0) {
+        return Err(VfsError::TextBusy);
+    }
+    if count + delta == 0 {
+        counts.remove(&key);
+    } else {
+        counts.insert(key, count + delta);
+    }
+    Ok(())
+}
+
+/// Undo one [`update_write_access`] of `delta`.
+fn release_write_access(inode: &Arc<Inode>, delta: isize) {
+    let key = inode_key(inode);
+    let mut counts = WRITE_ACCESS.lock();
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= delta;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// Keeps an inode from being opened for writing while a program runs from it.
+pub struct DenyWrite {
+    inode: Arc<Inode>,
+}
+
+impl DenyWrite {
+    /// Deny writes to `inode`, failing with `TextBusy` if it is already open for writing.
+    pub fn new(inode: &Arc<Inode>) -> Result<Self, VfsError> {
+        update_write_access(inode, -1)?;
+        Ok(Self {
+            inode: inode.clone(),
+        })
+    }
+}
+
+impl Clone for DenyWrite {
+    fn clone(&self) -> Self {
+        // The count is already negative, so adding another denial cannot fail.
+        let _ = update_write_access(&self.inode, -1);
+        Self {
+            inode: self.inode.clone(),
+        }
+    }
+}
+
+impl Drop for DenyWrite {
+    fn drop(&mut self) {
+        release_write_access(&self.inode, -1);
+    }
+}
+
 /// An open file description, tying a dentry to per-open state (offset, flags)
 /// and the I/O operations obtained from the inode.
 pub struct File {
@@ -15,6 +86,8 @@ pub struct File {
     pub flags: u32,
     /// Per-open I/O operations from the inode.
     pub ops: Arc<dyn FileOps>,
+    /// Whether this description holds write access to a regular file.
+    write_access: bool,
 }
 
 impl File {
@@ -25,9 +98,23 @@ impl File {
             offset: Spinlock::new(0),
             flags,
             ops,
+            write_access: false,
         }
     }
 
+    /// Open `dentry` with `flags`.
+    ///
+    /// Opening a regular file for writing fails with `TextBusy` while it is being executed.
+    pub fn open(dentry: Arc<Dentry>, flags: u32, ops: Arc<dyn FileOps>) -> Result<Self, VfsError> {
+        let write_access = can_write(flags) && dentry.inode.inode_type == InodeType::File;
+        if write_access {
+            update_write_access(&dentry.inode, 1)?;
+        }
+        let mut file = Self::new(dentry, flags, ops);
+        file.write_access = write_access;
+        Ok(file)
+    }
+
     /// Read from the file, advancing the offset. Enforces `O_RDONLY`/`O_RDWR`.
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, VfsError> {
         if !can_read(self.flags) {
@@ -91,3 +178,11 @@ impl File {
         Ok(*off)
     }
 }
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.write_access {
+            release_write_access(&self.dentry.inode, 1);
+        }
+    }
+}
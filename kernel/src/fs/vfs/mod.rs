@@ -7,9 +7,9 @@ pub mod types;
 
 pub use dcache::{dcache_evict, dcache_insert, dcache_lookup, dcache_purge};
 pub use dentry::Dentry;
-pub use file::File;
-pub use mount::{MOUNT_TABLE, Mount, MountTable};
-pub use path::{create_file, open_file, read_file, resolve_path};
+pub use file::{DenyWrite, File};
+pub use mount::{MOUNT_TABLE, Mount, MountFlags, MountTable};
+pub use path::{create_file, open_file, read_dentry, read_file, resolve_path};
 pub use types::{
     FileOps, FileSystem, Inode, InodeOps, InodeType, LinuxStat, O_CREAT, O_RDONLY, O_RDWR,
     O_WRONLY, SuperBlock, VfsError, can_read, can_write,
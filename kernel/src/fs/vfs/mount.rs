@@ -1,12 +1,24 @@
 use super::dentry::Dentry;
 use super::types::{FileSystem, SuperBlock, VfsError};
-use crate::sync::rwlock::RwLock;
+use crate::sync::rcu::RcuCell;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 
 /// The global mount table, shared across all filesystem operations.
-pub static MOUNT_TABLE: RwLock<MountTable> = RwLock::new(MountTable::new());
+///
+/// Path lookups read it without locking. Mounting updates a copy, published once the
+/// write guard is dropped, so readers never wait for a filesystem to mount.
+pub static MOUNT_TABLE: RcuCell<MountTable> = RcuCell::new(MountTable::new());
+
+bitflags::bitflags! {
+    /// Per-mount options.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MountFlags: u32 {
+        /// `MS_NOEXEC`: files on this mount may not be executed.
+        const NOEXEC = 1 << 3;
+    }
+}
 
 /// A single mount point binding a filesystem instance to a path in the VFS tree.
 pub struct Mount {
@@ -16,12 +28,15 @@ pub struct Mount {
     pub superblock: Arc<SuperBlock>,
     /// The root dentry of this mounted filesystem.
     pub root_dentry: Arc<Dentry>,
+    /// Options this filesystem was mounted with.
+    pub flags: MountFlags,
 }
 
 /// Global table tracking all mounted filesystems.
 ///
 /// Mounts are stored keyed by their mount-point path. The table supports
 /// longest-prefix matching for path resolution.
+#[derive(Clone)]
 pub struct MountTable {
     mounts: BTreeMap<String, Arc<Mount>>,
 }
@@ -39,12 +54,23 @@ impl MountTable {
     /// Calls `fs.mount()` to produce a fresh superblock and root inode,
     /// wraps the root inode in a dentry, and registers the mount.
     pub fn mount(&mut self, path: &str, fs: &dyn FileSystem) -> Result<Arc<Mount>, VfsError> {
+        self.mount_with_flags(path, fs, MountFlags::empty())
+    }
+
+    /// Mount a filesystem at the given path with the given options.
+    pub fn mount_with_flags(
+        &mut self,
+        path: &str,
+        fs: &dyn FileSystem,
+        flags: MountFlags,
+    ) -> Result<Arc<Mount>, VfsError> {
         let sb = fs.mount()?;
         let root_dentry = Arc::new(Dentry::new(path.into(), sb.root_inode.clone()));
         let mount = Arc::new(Mount {
             mount_point: path.into(),
             superblock: Arc::new(sb),
             root_dentry,
+            flags,
         });
         self.mounts.insert(path.into(), mount.clone());
         Ok(mount)
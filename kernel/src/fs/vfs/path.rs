@@ -119,8 +119,7 @@ fn resolve_path_symlink(path: &str, depth: usize) -> Result<Arc<Dentry>, VfsErro
         return Err(VfsError::TooManySymlinks);
     }
 
-    let mt = MOUNT_TABLE.read();
-    let (mount, remainder) = mt.lookup(path).ok_or(VfsError::NotFound)?;
+    let (mount, remainder) = MOUNT_TABLE.read().lookup(path).ok_or(VfsError::NotFound)?;
 
     let mut current = mount.root_dentry.clone();
 
@@ -150,7 +149,6 @@ fn resolve_path_symlink(path: &str, depth: usize) -> Result<Arc<Dentry>, VfsErro
         // 2. Handle symbolic link resolution
         if dentry.inode.inode_type == InodeType::Symlink {
             if let Ok(target) = dentry.inode.ops.readlink() {
-                drop(mt);
                 let current_dir = build_path(&current);
                 let mut target_full = if target.starts_with('/') {
                     target
@@ -169,7 +167,8 @@ fn resolve_path_symlink(path: &str, depth: usize) -> Result<Arc<Dentry>, VfsErro
 
         // 3. Mount boundary traversal
         let child_path = build_path(&dentry);
-        if let Some((child_mount, _)) = mt.lookup(&child_path) {
+        let child_mount = MOUNT_TABLE.read().lookup(&child_path);
+        if let Some((child_mount, _)) = child_mount {
             if child_mount.mount_point == child_path
                 && child_mount.mount_point != mount.mount_point
             {
@@ -277,6 +276,11 @@ pub fn stat(path: &str) -> Result<super::types::Stat, VfsError> {
 /// Read the entire contents of a file at `path` into a byte vector.
 pub fn read_file(path: &str) -> Result<alloc::vec::Vec<u8>, VfsError> {
     let dentry = resolve_path(path)?;
+    read_dentry(&dentry)
+}
+
+/// Read the entire contents of an already resolved file into a byte vector.
+pub fn read_dentry(dentry: &Dentry) -> Result<alloc::vec::Vec<u8>, VfsError> {
     let stat = dentry.inode.ops.stat()?;
     let file_ops = dentry.inode.ops.open()?;
 
@@ -300,5 +304,5 @@ pub fn open_file(path: &str, flags: u32) -> Result<Arc<File>, VfsError> {
     };
 
     let file_ops = dentry.inode.ops.open()?;
-    Ok(Arc::new(File::new(dentry, flags, file_ops)))
+    Ok(Arc::new(File::open(dentry, flags, file_ops)?))
 }
@@ -33,6 +33,12 @@ pub enum VfsError {
     Interrupted,
     /// Symlink resolution depth exceeded the maximum (ELOOP).
     TooManySymlinks,
+    /// A user buffer pointer was invalid (EFAULT).
+    BadAddress,
+    /// The file is being executed and cannot be written, or vice versa (ETXTBSY).
+    TextBusy,
+    /// The device is already in use (EBUSY).
+    Busy,
     /// An underlying device driver error occurred.
     DriverError(DriverError),
 }
@@ -223,6 +229,11 @@ pub trait InodeOps: Send + Sync {
         Err(VfsError::NotSupported)
     }
 
+    /// Change the permission bits (`0o7777`) of this inode.
+    fn chmod(&self, _mode: u32) -> Result<(), VfsError> {
+        Err(VfsError::NotSupported)
+    }
+
     /// Produce per-open-file I/O operations for this inode.
     fn open(&self) -> Result<Arc<dyn FileOps>, VfsError> {
         Err(VfsError::NotSupported)
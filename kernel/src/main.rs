@@ -19,8 +19,10 @@ pub mod proc;
 pub mod sched;
 pub mod sync;
 pub mod syscalls;
+pub mod time;
 pub mod tty;
 pub mod utils;
+pub mod work;
 
 #[unsafe(no_mangle)]
 unsafe extern "C" fn kmain() -> ! {
@@ -29,6 +31,7 @@ unsafe extern "C" fn kmain() -> ! {
     arch::init();
     tty::init();
     modules::init();
+    arch::paging::seal_ro_after_init();
     proc::process::init_proc::run_init_process();
     log::info!("PetraOS Kernel booted successfully.");
     hcf();
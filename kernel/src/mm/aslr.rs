@@ -0,0 +1,98 @@
+//! User Address Space Layout Randomization
+//!
+//! Every exec picks a fresh random stack top, mmap base, brk start, interpreter base and
+//! load bias for position-independent executables. The vDSO moves with the stack. Randomization is disabled system-wide
+//! with the `norandmaps` boot option, or per process with `personality(ADDR_NO_RANDOMIZE)`.
+
+use crate::arch::rdtsc::get_random_u64;
+use crate::arch::userspace::USER_MMAP_VBASE;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// `personality` flag that disables address space randomization for the process.
+pub const ADDR_NO_RANDOMIZE: u32 = 0x0040000;
+
+/// Highest user stack address before randomization.
+pub const STACK_TOP: u64 = 0x0000_7FFF_FFFF_0000;
+/// Base of the dynamic interpreter before randomization.
+pub const INTERP_BASE: u64 = 0x0000_7F00_0000_0000;
+/// Load address of `ET_DYN` executables before randomization (two thirds of user space).
+pub const ELF_ET_DYN_BASE: u64 = 0x0000_5555_5555_4000;
+
+/// Range the stack top is moved down by (16 GiB).
+const STACK_RND_RANGE: u64 = 1 << 34;
+/// Range the mmap base, interpreter base and PIE bias are moved up by (1 TiB).
+const MMAP_RND_RANGE: u64 = 1 << 40;
+/// Range the brk start is moved up by (32 MiB).
+const BRK_RND_RANGE: u64 = 1 << 25;
+
+/// Cleared by the `norandmaps` boot option.
+#[unsafe(link_section = ".data.ro_after_init")]
+static RANDOMIZE_VA_SPACE: AtomicBool = AtomicBool::new(true);
+
+fn init() -> Result<(), &'static str> {
+    if crate::utils::bootargs::has_flag("norandmaps") {
+        RANDOMIZE_VA_SPACE.store(false, Ordering::Relaxed);
+        log::info!("ASLR: disabled by the norandmaps boot option");
+    }
+    Ok(())
+}
+
+crate::core_initcall!(init);
+
+/// Returns true if an exec by a process with this `personality` should be randomized.
+pub fn randomize(personality: u32) -> bool {
+    RANDOMIZE_VA_SPACE.load(Ordering::Relaxed) && personality & ADDR_NO_RANDOMIZE == 0
+}
+
+/// Addresses chosen for one exec.
+#[derive(Debug, Clone, Copy)]
+pub struct UserLayout {
+    randomize: bool,
+    /// Top of the initial user stack.
+    pub stack_top: u64,
+    /// Base of the vDSO mapping, one guard page above the stack.
+    pub vdso_base: u64,
+    /// First address handed out by `mmap` without a hint.
+    pub mmap_base: u64,
+    /// Load base of the dynamic interpreter.
+    pub interp_base: u64,
+    /// Load bias applied to an `ET_DYN` main executable.
+    pub pie_base: u64,
+}
+
+impl UserLayout {
+    /// Choose a layout, randomized unless `randomize` is false.
+    pub fn new(randomize: bool) -> Self {
+        let offset = |range: u64| {
+            if randomize {
+                random_page_offset(range)
+            } else {
+                0
+            }
+        };
+        let stack_top = STACK_TOP - offset(STACK_RND_RANGE);
+        Self {
+            randomize,
+            stack_top,
+            vdso_base: stack_top + 4096,
+            mmap_base: USER_MMAP_VBASE + offset(MMAP_RND_RANGE),
+            interp_base: INTERP_BASE + offset(MMAP_RND_RANGE),
+            pie_base: ELF_ET_DYN_BASE + offset(MMAP_RND_RANGE),
+        }
+    }
+
+    /// Place the brk start after the program image ending at `image_end`.
+    pub fn brk_start(&self, image_end: u64) -> u64 {
+        let start = (image_end + 4095) & !4095;
+        if self.randomize {
+            start + random_page_offset(BRK_RND_RANGE)
+        } else {
+            start
+        }
+    }
+}
+
+/// A random page-aligned offset in `[0, range)`.
+fn random_page_offset(range: u64) -> u64 {
+    (get_random_u64() % (range / 4096)) * 4096
+}
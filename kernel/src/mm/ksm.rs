@@ -0,0 +1,380 @@
+//! Kernel Same-page Merging (KSM).
+//!
+//! The `ksmd` kernel thread walks the anonymous VMAs that user space marked with
+//! `madvise(MADV_MERGEABLE)`, checksums every resident 4 KiB page and merges pages with
+//! identical contents into one read-only frame. Merged pages carry `COW_FLAG`, so the first
+//! write to one of them goes through the ordinary copy-on-write path of
+//! `AddrSpace::handle_page_fault` and gets a private copy back.
+//!
+//! Two indexes keyed by page checksum drive the merging:
+//! - the *stable tree* holds frames that are already shared and therefore write-protected;
+//! - the *unstable tree* holds candidates seen during the current full scan. A page only
+//!   enters it once its checksum stayed the same across two full scans, so pages that are
+//!   written all the time are not merged just to be copied again.
+//!
+//! KSM keeps its own reference on each stable frame. Frames left with only that reference
+//! are released at the end of every full scan.
+//!
+//! Tunables and statistics are exposed in `/proc/ksm`.
+
+use crate::fs::vfs::types::VfsError;
+use crate::mm::{AddrSpace, ArchPageTable, PageFaultError, PageTable, PMM};
+use crate::proc::{ProcessId, ProcessState};
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Whether `ksmd` is scanning (`run` in `/proc/ksm`).
+static RUN: AtomicBool = AtomicBool::new(true);
+/// Pages examined per wake-up (`pages_to_scan`).
+static PAGES_TO_SCAN: AtomicU64 = AtomicU64::new(100);
+/// Time `ksmd` sleeps between batches (`sleep_millisecs`).
+static SLEEP_MILLISECS: AtomicU64 = AtomicU64::new(20);
+
+/// Largest `pages_to_scan`, so that a single batch stays short.
+const MAX_PAGES_TO_SCAN: u64 = 10_000;
+/// Largest `sleep_millisecs`, the range of the `unsigned int` Linux takes.
+const MAX_SLEEP_MILLISECS: u64 = u32::MAX as u64;
+
+/// Completed passes over all mergeable memory.
+static FULL_SCANS: AtomicU64 = AtomicU64::new(0);
+/// Pages examined since boot.
+static PAGES_SCANNED: AtomicU64 = AtomicU64::new(0);
+
+/// A mergeable page awaiting a partner in the unstable tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Candidate {
+    pid: ProcessId,
+    virt: VirtAddr,
+}
+
+struct Ksm {
+    /// Checksum -> shared, write-protected frames with that checksum.
+    stable: BTreeMap<u64, Vec<PhysAddr>>,
+    /// All frames in `stable`, for O(log n) membership tests.
+    stable_frames: BTreeSet<u64>,
+    /// Checksum -> unmerged page seen during the current scan.
+    unstable: BTreeMap<u64, Candidate>,
+    /// Checksums recorded during the previous and the current full scan.
+    old_checksums: BTreeMap<(ProcessId, VirtAddr), u64>,
+    checksums: BTreeMap<(ProcessId, VirtAddr), u64>,
+    /// Mergeable ranges of the current scan and the position within them.
+    ranges: Vec<(ProcessId, VirtAddr, VirtAddr)>,
+    cursor: usize,
+    next_addr: VirtAddr,
+}
+
+impl Ksm {
+    const fn new() -> Self {
+        Self {
+            stable: BTreeMap::new(),
+            stable_frames: BTreeSet::new(),
+            unstable: BTreeMap::new(),
+            old_checksums: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+            ranges: Vec::new(),
+            cursor: 0,
+            next_addr: VirtAddr::zero(),
+        }
+    }
+
+    /// Next page of the current scan, or `None` once every range was visited.
+    fn next_page(&mut self) -> Option<Candidate> {
+        while let Some(&(pid, start, end)) = self.ranges.get(self.cursor) {
+            let virt = self.next_addr.max(start);
+            if virt < end {
+                self.next_addr = virt + 4096u64;
+                return Some(Candidate { pid, virt });
+            }
+            self.cursor += 1;
+        }
+        None
+    }
+
+    /// Finish a full scan: drop unused stable frames and start over with `ranges`.
+    fn restart(&mut self, ranges: Vec<(ProcessId, VirtAddr, VirtAddr)>) {
+        let stable_frames = &mut self.stable_frames;
+        self.stable.retain(|_, frames| {
+            frames.retain(|&frame| {
+                if PMM.get_ref(frame) > 1 {
+                    return true;
+                }
+                stable_frames.remove(&frame.as_u64());
+                PMM.free_page(frame);
+                false
+            });
+            !frames.is_empty()
+        });
+
+        self.unstable.clear();
+        self.old_checksums = core::mem::take(&mut self.checksums);
+        self.ranges = ranges;
+        self.cursor = 0;
+        self.next_addr = VirtAddr::zero();
+    }
+}
+
+static KSM: Spinlock<Ksm> = Spinlock::new(Ksm::new());
+
+fn page_bytes(frame: PhysAddr) -> &'static [u8] {
+    let ptr = (frame.as_u64() + crate::mm::hhdm_offset()) as *const u8;
+    // SAFETY: Every physical frame is reachable through the HHDM.
+    unsafe { core::slice::from_raw_parts(ptr, 4096) }
+}
+
+/// 64-bit FNV-1a hash of a 4 KiB frame.
+fn page_checksum(frame: PhysAddr) -> u64 {
+    page_bytes(frame).iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn pages_identical(a: PhysAddr, b: PhysAddr) -> bool {
+    page_bytes(a) == page_bytes(b)
+}
+
+/// Returns true if `frame` is a KSM-shared frame.
+pub fn is_stable_frame(frame: PhysAddr) -> bool {
+    KSM.lock().stable_frames.contains(&frame.as_u64())
+}
+
+/// Give private copies back to every KSM-shared page in `[start, end)` (`MADV_UNMERGEABLE`).
+pub fn unmerge_range(
+    addr_space: &mut AddrSpace<ArchPageTable>,
+    start: VirtAddr,
+    end: VirtAddr,
+) -> Result<(), PageFaultError> {
+    let mut addr = start;
+    while addr < end {
+        let shared = addr_space
+            .page_table()
+            .translate(addr)
+            .is_some_and(is_stable_frame);
+        let writable = addr_space
+            .find_vma(addr)
+            .is_some_and(|area| area.flags.contains(PageTableFlags::WRITABLE));
+        if shared && writable {
+            addr_space.handle_page_fault(
+                addr,
+                PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION,
+            )?;
+        }
+        addr += 4096u64;
+    }
+    Ok(())
+}
+
+/// Collect the mergeable ranges of every live process.
+fn mergeable_ranges() -> Vec<(ProcessId, VirtAddr, VirtAddr)> {
+    let mut ranges = Vec::new();
+    for proc_arc in crate::proc::all_processes() {
+        let proc = proc_arc.lock();
+        if proc.state == ProcessState::Zombie {
+            continue;
+        }
+        let addr_space = proc.address_space.lock();
+        for area in addr_space.vm_areas().filter(|area| area.ksm_eligible()) {
+            ranges.push((proc.pid, area.start, area.end));
+        }
+    }
+    ranges
+}
+
+/// Examine one page and merge it if an identical page is known.
+fn scan_page(page: Candidate) {
+    let addr_space_arc = match crate::proc::find_process(page.pid) {
+        Some(proc_arc) => proc_arc.lock().address_space.clone(),
+        None => return,
+    };
+    let mut addr_space = addr_space_arc.lock();
+
+    if !addr_space.find_vma(page.virt).is_some_and(|area| area.ksm_eligible()) {
+        return;
+    }
+    let (mut phys, mut flags) = match addr_space.page_table().get_entry(page.virt) {
+        Some(entry) => entry,
+        None => return,
+    };
+    if flags.contains(PageTableFlags::HUGE_PAGE) {
+        // Mergeable VMAs are not faulted in as huge pages, but may predate `MADV_MERGEABLE`.
+        if addr_space.split_huge_page(page.virt).is_err() {
+            return;
+        }
+        (phys, flags) = match addr_space.page_table().get_entry(page.virt) {
+            Some(entry) => entry,
+            None => return,
+        };
+    }
+    if flags.contains(PageTableFlags::HUGE_PAGE) || is_stable_frame(phys) {
+        return;
+    }
+
+    PAGES_SCANNED.fetch_add(1, Ordering::Relaxed);
+    let checksum = page_checksum(phys);
+
+    // 1. An identical page is already shared: write-protect this one so its contents can
+    // no longer change under us, compare and switch it over.
+    let stable: Vec<PhysAddr> = KSM.lock().stable.get(&checksum).cloned().unwrap_or_default();
+    for frame in stable {
+        if pages_identical(frame, phys) {
+            if addr_space.write_protect_page(page.virt, phys) && pages_identical(frame, phys) {
+                addr_space.replace_page(page.virt, phys, frame);
+            }
+            return;
+        }
+    }
+
+    // 2. Only pages whose contents did not change since the last full scan are candidates.
+    let partner = {
+        let mut ksm = KSM.lock();
+        let key = (page.pid, page.virt);
+        ksm.checksums.insert(key, checksum);
+        if ksm.old_checksums.get(&key) != Some(&checksum) {
+            return;
+        }
+        match ksm.unstable.get(&checksum).copied() {
+            Some(partner) if partner != page => {
+                ksm.unstable.remove(&checksum);
+                partner
+            }
+            _ => {
+                ksm.unstable.insert(checksum, page);
+                return;
+            }
+        }
+    };
+    drop(addr_space);
+
+    // 3. Promote the partner found in the unstable tree to a stable frame...
+    let frame = match promote(partner, checksum) {
+        Some(frame) => frame,
+        None => {
+            KSM.lock().unstable.insert(checksum, page);
+            return;
+        }
+    };
+
+    // 4. ...and merge this page into it.
+    let mut addr_space = addr_space_arc.lock();
+    if addr_space.write_protect_page(page.virt, phys) && pages_identical(frame, phys) {
+        addr_space.replace_page(page.virt, phys, frame);
+    }
+}
+
+/// Write-protect the page of `candidate` and add its frame to the stable tree.
+fn promote(candidate: Candidate, checksum: u64) -> Option<PhysAddr> {
+    let addr_space_arc = crate::proc::find_process(candidate.pid)?
+        .lock()
+        .address_space
+        .clone();
+    let mut addr_space = addr_space_arc.lock();
+
+    if !addr_space.find_vma(candidate.virt).is_some_and(|area| area.ksm_eligible()) {
+        return None;
+    }
+    let frame = addr_space.page_table().translate(candidate.virt)?;
+    if !addr_space.write_protect_page(candidate.virt, frame) || page_checksum(frame) != checksum {
+        return None;
+    }
+
+    // KSM's own reference keeps the frame alive while it is in the stable tree.
+    PMM.inc_ref(frame);
+    let mut ksm = KSM.lock();
+    ksm.stable.entry(checksum).or_default().push(frame);
+    ksm.stable_frames.insert(frame.as_u64());
+    Some(frame)
+}
+
+/// Scan up to `budget` pages, starting a new full scan when the current one is done.
+fn scan_batch(budget: u64) {
+    for _ in 0..budget {
+        let next = KSM.lock().next_page();
+        match next {
+            Some(page) => scan_page(page),
+            None => {
+                let ranges = mergeable_ranges();
+                let mut ksm = KSM.lock();
+                if !ksm.ranges.is_empty() {
+                    FULL_SCANS.fetch_add(1, Ordering::Relaxed);
+                }
+                let idle = ranges.is_empty();
+                ksm.restart(ranges);
+                if idle {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn ksmd() -> i32 {
+    while !crate::proc::kthread_should_stop() {
+        if RUN.load(Ordering::Relaxed) {
+            scan_batch(PAGES_TO_SCAN.load(Ordering::Relaxed));
+        }
+
+        let sleep_ns = SLEEP_MILLISECS.load(Ordering::Relaxed).saturating_mul(1_000_000);
+        crate::time::timer::sleep_until(crate::time::now_ns().saturating_add(sleep_ns));
+    }
+    0
+}
+
+/// Render `/proc/ksm`.
+fn show_ksm() -> String {
+    let ksm = KSM.lock();
+    let mut pages_shared = 0u64;
+    let mut pages_sharing = 0u64;
+    for frame in ksm.stable.values().flatten() {
+        // One reference is KSM's own; the rest are mappings.
+        let mappers = PMM.get_ref(*frame).saturating_sub(1) as u64;
+        if mappers > 0 {
+            pages_shared += 1;
+            pages_sharing += mappers - 1;
+        }
+    }
+
+    alloc::format!(
+        "run {}\npages_to_scan {}\nsleep_millisecs {}\npages_shared {}\npages_sharing {}\npages_unshared {}\npages_scanned {}\nfull_scans {}\n",
+        RUN.load(Ordering::Relaxed) as u8,
+        PAGES_TO_SCAN.load(Ordering::Relaxed),
+        SLEEP_MILLISECS.load(Ordering::Relaxed),
+        pages_shared,
+        pages_sharing,
+        ksm.unstable.len(),
+        PAGES_SCANNED.load(Ordering::Relaxed),
+        FULL_SCANS.load(Ordering::Relaxed),
+    )
+}
+
+/// Apply a `<tunable> <value>` write to `/proc/ksm`. Only root may tune KSM.
+fn store_ksm(value: &str, euid: u32) -> Result<(), VfsError> {
+    crate::fs::procfs::require_root(euid)?;
+    let (key, value) = value.split_once(' ').ok_or(VfsError::InvalidInput)?;
+    let value: u64 = value.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    match key {
+        "run" if value <= 1 => RUN.store(value == 1, Ordering::Relaxed),
+        "pages_to_scan" if (1..=MAX_PAGES_TO_SCAN).contains(&value) => {
+            PAGES_TO_SCAN.store(value, Ordering::Relaxed)
+        }
+        "sleep_millisecs" if value <= MAX_SLEEP_MILLISECS => {
+            SLEEP_MILLISECS.store(value, Ordering::Relaxed)
+        }
+        _ => return Err(VfsError::InvalidInput),
+    }
+    Ok(())
+}
+
+/// Start `ksmd` and register `/proc/ksm`.
+pub fn init() -> Result<(), &'static str> {
+    crate::proc::kthread_run("ksmd", ksmd);
+    crate::fs::procfs::register_proc_entry("ksm", show_ksm, Some(store_ksm));
+    log::info!("[KSM] Started ksmd");
+    Ok(())
+}
+
+crate::late_initcall!(init);
@@ -1,17 +1,35 @@
 pub mod alloc;
+pub mod aslr;
+pub mod ksm;
+pub mod oom;
 pub mod pmm;
 pub mod vmm;
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 pub use pmm::PMM;
-pub use vmm::{AddrSpace, AddrSpaceError, COW_FLAG, PageFaultError, PageTable, VmArea, VmAreaKind};
+pub use vmm::{
+    AddrSpace, AddrSpaceError, COW_FLAG, HUGE_PAGE_SIZE, PageFaultError, PageTable, VmArea,
+    VmAreaKind, VmFlags,
+};
 pub use crate::arch::paging::{
-    ArchPageTable, active_cr3, ensure_mapped, map_mmio, read_cr2,
+    ArchPageTable, active_cr3, ensure_mapped, map_mmio, preallocate_kernel_pml4_entry, read_cr2,
 };
 
+/// Page table root active at boot, used by kernel threads that own no address space.
+#[unsafe(link_section = ".data.ro_after_init")]
+static KERNEL_ROOT: AtomicU64 = AtomicU64::new(0);
+
 pub fn init() {
+    KERNEL_ROOT.store(active_cr3().as_u64(), Ordering::Relaxed);
     PMM.init();
 }
 
+/// Physical address of the kernel's boot page table root.
+pub fn kernel_root() -> u64 {
+    KERNEL_ROOT.load(Ordering::Relaxed)
+}
+
 pub fn hhdm_offset() -> u64 {
     crate::limine::HHDM_REQUEST
         .get_response()
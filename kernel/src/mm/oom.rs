@@ -0,0 +1,177 @@
+//! Out-of-memory killer.
+//!
+//! Invoked when the page fault or mmap/brk paths fail to obtain a physical frame from
+//! the `PMM`. Every live user process is scored by its resident anonymous memory,
+//! adjusted by its `oom_score_adj`, and the worst offender is killed with `SIGKILL` so
+//! that its frames can be returned to the buddy allocator.
+
+use crate::proc::{Process, ProcessId, ProcessState};
+use crate::sync::spinlock::Spinlock;
+use alloc::sync::Arc;
+
+/// Lowest `oom_score_adj`; processes with this value are never selected.
+pub const OOM_SCORE_ADJ_MIN: i16 = -1000;
+/// Highest `oom_score_adj`; processes with this value are always preferred.
+pub const OOM_SCORE_ADJ_MAX: i16 = 1000;
+
+/// Allocation retries after OOM kills before a caller gives up: the page fault path
+/// kills the faulting process, the mmap path fails with `ENOMEM`.
+pub const OOM_MAX_RETRIES: u32 = 8;
+
+/// The init process is never considered as an OOM victim.
+const INIT_PID: ProcessId = ProcessId(1);
+
+/// Serializes OOM kills so that concurrent allocation failures do not slaughter
+/// several processes for a single shortage.
+static OOM_LOCK: Spinlock<()> = Spinlock::new(());
+
+/// Badness of a process in pages, or `None` if it must not be killed.
+///
+/// The score is the number of resident anonymous pages plus `oom_score_adj`
+/// thousandths of total RAM, mirroring the classic Linux heuristic. Processes that are
+/// already dying, or whose death would free no anonymous memory, are not candidates.
+pub fn badness(proc: &Process) -> Option<u64> {
+    if proc.pid == INIT_PID
+        || proc.state == ProcessState::Zombie
+        || proc.oom_victim
+        || proc.pending_signals.has(crate::ipc::signal::SIGKILL)
+        || proc.oom_score_adj <= OOM_SCORE_ADJ_MIN
+    {
+        return None;
+    }
+
+    let resident = proc.address_space.lock().resident_anon_pages() as i64;
+    if resident == 0 {
+        return None;
+    }
+    let total = crate::mm::PMM.total_pages() as i64;
+    let points = resident + proc.oom_score_adj as i64 * total / 1000;
+
+    // A process with resident memory keeps a score of at least 1 so that a negative
+    // adjustment lowers its priority without making it immune.
+    Some(points.max(1) as u64)
+}
+
+/// Normalized badness in `[0, 1000]`, as reported by `/proc/[pid]/oom_score`.
+pub fn oom_score(proc: &Process) -> u64 {
+    let total = crate::mm::PMM.total_pages().max(1) as u64;
+    badness(proc).map_or(0, |points| (points * 1000 / total).min(1000))
+}
+
+/// Pick the process with the highest badness score.
+fn select_victim() -> Option<(Arc<Spinlock<Process>>, u64)> {
+    let mut victim: Option<(Arc<Spinlock<Process>>, u64)> = None;
+
+    for proc_arc in crate::proc::all_processes() {
+        let points = match badness(&proc_arc.lock()) {
+            Some(points) => points,
+            None => continue,
+        };
+        if victim.as_ref().is_none_or(|(_, best)| points > *best) {
+            victim = Some((proc_arc, points));
+        }
+    }
+
+    victim
+}
+
+/// A victim picked earlier whose mappings are not torn down yet. Picking another one
+/// before its memory is back would kill several processes for a single shortage.
+fn dying_victim() -> Option<ProcessId> {
+    crate::proc::all_processes().into_iter().find_map(|proc_arc| {
+        let proc = proc_arc.lock();
+        let dying = proc.oom_victim && proc.address_space.lock().resident_anon_pages() > 0;
+        dying.then_some(proc.pid)
+    })
+}
+
+/// Kill the process with the highest badness score to relieve memory pressure.
+///
+/// `order` is the buddy order of the allocation that failed and is only used for the
+/// report. Returns the PID of the killed process, or `None` if no eligible victim
+/// exists. The victim's user mappings are torn down immediately so the caller can
+/// retry its allocation; if the victim is the calling process, the caller must not
+/// return to user space and should exit instead. While an earlier victim is still
+/// being torn down, returns its PID without killing anything.
+///
+/// The caller must not hold any process or address-space lock.
+pub fn out_of_memory(order: usize) -> Option<ProcessId> {
+    let guard = OOM_LOCK.lock();
+    if let Some(pid) = dying_victim() {
+        return Some(pid);
+    }
+
+    let caller = crate::proc::current_process().map(|p| p.lock().pid);
+
+    log::error!(
+        "[OOM] Out of memory: order-{} allocation failed by PID {}",
+        order,
+        caller.map_or(0, |pid| pid.as_u64())
+    );
+    log::error!(
+        "[OOM] Free pages: {} / {}",
+        crate::mm::PMM.free_pages_count(),
+        crate::mm::PMM.total_pages()
+    );
+    log::error!("[OOM] [  pid  ]  anon_rss  oom_score_adj  name");
+    for proc_arc in crate::proc::all_processes() {
+        let proc = proc_arc.lock();
+        if proc.state == ProcessState::Zombie {
+            continue;
+        }
+        let rss = proc.address_space.lock().resident_anon_pages();
+        log::error!(
+            "[OOM] [{:>7}]  {:>8}  {:>13}  {}",
+            proc.pid.as_u64(),
+            rss,
+            proc.oom_score_adj,
+            proc.cmdline.args.first().map_or("?", |s| s.as_str())
+        );
+    }
+
+    let (victim_arc, points) = match select_victim() {
+        Some(v) => v,
+        None => {
+            log::error!("[OOM] No killable process found");
+            return None;
+        }
+    };
+
+    let mut victim = victim_arc.lock();
+    let pid = victim.pid;
+    let ppid = victim.ppid;
+    let rss_kb = victim.address_space.lock().resident_anon_pages() * 4;
+
+    log::error!(
+        "[OOM] Killed process {} ({}) score {}, anon-rss {} kB, oom_score_adj {}",
+        pid.as_u64(),
+        victim.cmdline.args.first().map_or("?", |s| s.as_str()),
+        points,
+        rss_kb,
+        victim.oom_score_adj
+    );
+
+    victim.oom_victim = true;
+    drop(victim);
+    // Killing reaches the scheduler, which must not nest under `OOM_LOCK`.
+    drop(guard);
+
+    let address_space = {
+        let mut victim = victim_arc.lock();
+        let _ = victim.send_signal(crate::ipc::signal::SIGKILL);
+        victim.address_space.clone()
+    };
+    address_space.lock().unmap_all();
+
+    // The calling process notifies its own parent on its way out.
+    if Some(pid) != caller
+        && ppid.as_u64() > 0
+        && let Some(parent_arc) = crate::proc::find_process(ppid)
+    {
+        let _ = parent_arc
+            .lock()
+            .send_signal(crate::ipc::signal::SIGCHLD);
+    }
+
+    Some(pid)
+}
@@ -107,6 +107,34 @@ impl BuddyAllocator {
         }
     }
 
+    /// Order recorded on the head page of an allocated block.
+    pub fn block_order(&self, paddr: PhysAddr) -> usize {
+        let idx = self.get_page_index(paddr);
+        if idx < self.page_map.len() {
+            self.page_map[idx].order as usize
+        } else {
+            0
+        }
+    }
+
+    /// Break an allocated block of `order` into independently freeable order-0 pages.
+    ///
+    /// Every sub-page inherits the head page's reference count, so each mapping that
+    /// referenced the whole block now holds one reference on every sub-page.
+    pub fn split_block(&mut self, paddr: PhysAddr, order: usize) {
+        let head = self.get_page_index(paddr);
+        let pages = 1usize << order;
+        if head + pages > self.page_map.len() || self.page_map[head].order as usize != order {
+            return;
+        }
+        let ref_count = self.page_map[head].ref_count;
+        for page in &mut self.page_map[head..head + pages] {
+            page.order = 0;
+            page.ref_count = ref_count;
+            page.flags.remove(PageFlags::FREE);
+        }
+    }
+
     /// Safely split a larger free block and remove the node from the free list.
     unsafe fn remove_block(&mut self, block: *mut IntrusiveNode, order: usize) {
         unsafe {
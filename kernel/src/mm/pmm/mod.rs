@@ -1,4 +1,4 @@
 pub mod buddy;
 pub mod pmm;
 
-pub use pmm::PMM;
+pub use pmm::{HUGE_PAGE_ORDER, PMM};
@@ -2,6 +2,9 @@ use crate::mm::pmm::buddy::{BuddyAllocator, Page, PageFlags};
 use crate::sync::spinlock::Spinlock;
 use x86_64::PhysAddr;
 
+/// Buddy order of a 2 MiB huge page (512 contiguous 4 KiB frames).
+pub const HUGE_PAGE_ORDER: usize = 9;
+
 pub struct PhysicalMemoryManagement {
     allocator: Spinlock<Option<BuddyAllocator>>,
 }
@@ -162,38 +165,44 @@ impl PhysicalMemoryManagement {
         );
         let mut guard = self.allocator.lock();
         if let Some(ref mut allocator) = *guard {
-            let page_idx = allocator.get_page_index(paddr);
-            if page_idx >= allocator.page_map_len() {
-                log::error!("pmm::free_pages: page index {} out of bounds", page_idx);
-                return;
-            }
+            Self::put_block(allocator, paddr, order);
+        }
+    }
 
-            if !allocator.is_usable(page_idx) {
-                log::error!(
-                    "pmm::free_pages: attempting to free non-usable page at physical address {:#x}",
-                    paddr.as_u64()
-                );
-                return;
-            }
+    /// Drop a reference to the block of `order` at `paddr` and return it to the free
+    /// lists once none is left. Called with the allocator locked.
+    fn put_block(allocator: &mut BuddyAllocator, paddr: PhysAddr, order: usize) {
+        let page_idx = allocator.get_page_index(paddr);
+        if page_idx >= allocator.page_map_len() {
+            log::error!("pmm::free_pages: page index {} out of bounds", page_idx);
+            return;
+        }
 
-            if allocator.is_free(page_idx) {
-                log::error!(
-                    "pmm::free_pages: double free detected at physical address {:#x}",
-                    paddr.as_u64()
-                );
-                return;
-            }
+        if !allocator.is_usable(page_idx) {
+            log::error!(
+                "pmm::free_pages: attempting to free non-usable page at physical address {:#x}",
+                paddr.as_u64()
+            );
+            return;
+        }
 
-            let new_ref = allocator.dec_ref(paddr);
-            if new_ref > 0 {
-                // Page is still referenced by another shared mapping (COW)
-                return;
-            }
+        if allocator.is_free(page_idx) {
+            log::error!(
+                "pmm::free_pages: double free detected at physical address {:#x}",
+                paddr.as_u64()
+            );
+            return;
+        }
 
-            // SAFETY: We checked that the page is usable, not already free, and ref_count reached 0.
-            unsafe {
-                allocator.free_block_internal(paddr, order);
-            }
+        let new_ref = allocator.dec_ref(paddr);
+        if new_ref > 0 {
+            // Page is still referenced by another shared mapping (COW)
+            return;
+        }
+
+        // SAFETY: We checked that the page is usable, not already free, and ref_count reached 0.
+        unsafe {
+            allocator.free_block_internal(paddr, order);
         }
     }
 
@@ -235,6 +244,78 @@ impl PhysicalMemoryManagement {
         self.free_pages(paddr, 0);
     }
 
+    /// Allocate a naturally aligned 2 MiB block for a huge page mapping.
+    pub fn alloc_huge_page(&self) -> Option<PhysAddr> {
+        self.alloc_pages(HUGE_PAGE_ORDER)
+    }
+
+    /// Returns true if `paddr` heads a still-intact (unsplit) block of `order` that a
+    /// single mapping references.
+    pub fn is_exclusive_compound(&self, paddr: PhysAddr, order: usize) -> bool {
+        let guard = self.allocator.lock();
+        if let Some(ref allocator) = *guard {
+            allocator.block_order(paddr) == order && allocator.get_ref(paddr) == 1
+        } else {
+            false
+        }
+    }
+
+    /// Convert an allocated block into individually refcounted order-0 pages.
+    ///
+    /// Used when a huge page mapping is split so its 4 KiB pieces can be COW-copied
+    /// or freed independently. Does nothing if the block was already split.
+    pub fn split_pages(&self, paddr: PhysAddr, order: usize) {
+        let mut guard = self.allocator.lock();
+        if let Some(ref mut allocator) = *guard {
+            allocator.split_block(paddr, order);
+        }
+    }
+
+    /// Take an additional reference on every frame backing a huge page mapping.
+    pub fn inc_ref_huge(&self, paddr: PhysAddr) {
+        let mut guard = self.allocator.lock();
+        if let Some(ref mut allocator) = *guard {
+            if allocator.block_order(paddr) == HUGE_PAGE_ORDER {
+                allocator.inc_ref(paddr);
+            } else {
+                for i in 0..(1u64 << HUGE_PAGE_ORDER) {
+                    allocator.inc_ref(paddr + i * 4096);
+                }
+            }
+        }
+    }
+
+    /// Drop one huge page mapping's reference without freeing any frame.
+    pub fn dec_ref_huge(&self, paddr: PhysAddr) {
+        let mut guard = self.allocator.lock();
+        if let Some(ref mut allocator) = *guard {
+            if allocator.block_order(paddr) == HUGE_PAGE_ORDER {
+                allocator.dec_ref(paddr);
+            } else {
+                for i in 0..(1u64 << HUGE_PAGE_ORDER) {
+                    allocator.dec_ref(paddr + i * 4096);
+                }
+            }
+        }
+    }
+
+    /// Drop one huge page mapping's reference, freeing frames that reach zero.
+    ///
+    /// Handles both intact compound blocks and blocks already split by another mapping.
+    /// The allocator stays locked throughout so a concurrent split cannot interleave.
+    pub fn free_huge_page(&self, paddr: PhysAddr) {
+        let mut guard = self.allocator.lock();
+        if let Some(ref mut allocator) = *guard {
+            if allocator.block_order(paddr) == HUGE_PAGE_ORDER {
+                Self::put_block(allocator, paddr, HUGE_PAGE_ORDER);
+            } else {
+                for i in 0..(1u64 << HUGE_PAGE_ORDER) {
+                    Self::put_block(allocator, paddr + i * 4096, 0);
+                }
+            }
+        }
+    }
+
     /// Get the total number of usable pages in the system.
     pub fn total_pages(&self) -> usize {
         let guard = self.allocator.lock();
@@ -2,6 +2,6 @@ pub mod paging;
 pub mod types;
 pub mod vma;
 
-pub use paging::PageTable;
-pub use types::VmAreaKind;
+pub use paging::{HUGE_PAGE_SIZE, PageTable};
+pub use types::{VmAreaKind, VmFlags};
 pub use vma::{AddrSpace, AddrSpaceError, COW_FLAG, PageFaultError, VmArea};
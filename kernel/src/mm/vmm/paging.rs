@@ -2,6 +2,9 @@ use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, UnmapError
 use x86_64::structures::paging::{PageTableFlags, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 
+/// Size of a huge page mapped by a single page-directory entry (2 MiB).
+pub const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
 pub trait PageTable: Send + Sync {
     /// Create a new page table by allocating a root directory and copying kernel-space mappings.
     fn new() -> Result<Self, MapToError<Size4KiB>>
@@ -53,12 +56,43 @@ pub trait PageTable: Send + Sync {
         flags: PageTableFlags,
     ) -> Result<(), FlagUpdateError>;
 
+    /// Map a 2 MiB-aligned virtual region to a 2 MiB-aligned physical block with one huge entry.
+    fn map_huge(
+        &mut self,
+        page: VirtAddr,
+        frame: PhysAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>>;
+
+    /// Unmap a huge page, returning the physical base of the 2 MiB block.
+    fn unmap_huge(&mut self, page: VirtAddr) -> Result<PhysAddr, UnmapError>;
+
+    /// Change the flags of a huge page mapping.
+    fn remap_huge(&mut self, page: VirtAddr, flags: PageTableFlags) -> Result<(), FlagUpdateError>;
+
+    /// Replace a huge page mapping with 512 equivalent 4 KiB mappings of the same frames.
+    fn split_huge(&mut self, page: VirtAddr) -> Result<(), MapToError<Size4KiB>>;
+
+    /// Returns true if no huge or 4 KiB mapping exists anywhere in the 2 MiB region at `page`.
+    fn huge_slot_vacant(&self, page: VirtAddr) -> bool;
+
+    /// Invalidate the translations changed since the last flush on every CPU that may
+    /// cache them, and wait until all of them are done.
+    ///
+    /// Updates only invalidate the local TLB; this must run before frames or page tables
+    /// that were unmapped are reused.
+    fn flush_tlb(&mut self);
+
     /// Translate a virtual address to its corresponding physical address.
     fn translate(&self, virt: VirtAddr) -> Option<PhysAddr>;
 
     /// Retrieve physical frame address and raw page entry flags for a virtual address.
     fn get_entry(&self, virt: VirtAddr) -> Option<(PhysAddr, PageTableFlags)>;
 
+    /// Count the 4 KiB pages mapped in `[start, end)`, a huge page counting as the 4 KiB
+    /// pages it covers. Only visits page tables that exist.
+    fn count_mapped(&self, start: VirtAddr, end: VirtAddr) -> usize;
+
     /// Activate this page table by loading it into the MMU.
     ///
     /// # Safety
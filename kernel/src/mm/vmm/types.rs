@@ -12,9 +12,28 @@ pub enum VmAreaKind {
         file: Arc<dyn crate::fs::FileOps>,
         offset: usize,
         file_size: usize,
+        /// Path of the file when it was mapped, as reported in core dumps.
+        path: Arc<str>,
     },
 }
 
+bitflags::bitflags! {
+    /// Per-VMA behaviour hints set through `madvise(2)` and `mmap(2)` flags.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct VmFlags: u32 {
+        /// `MADV_HUGEPAGE`: collapse into transparent huge pages where possible.
+        const HUGEPAGE   = 1 << 0;
+        /// `MADV_NOHUGEPAGE`: never back this VMA with transparent huge pages.
+        const NOHUGEPAGE = 1 << 1;
+        /// `MAP_HUGETLB`: must be backed by huge pages; failing that is an error.
+        const HUGETLB    = 1 << 2;
+        /// `MADV_MERGEABLE`: let the KSM scanner merge identical pages.
+        const MERGEABLE  = 1 << 3;
+        /// Kernel-provided mapping such as the vDSO; `mprotect` may not change it.
+        const SPECIAL    = 1 << 4;
+    }
+}
+
 impl PartialEq for VmAreaKind {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -27,11 +46,13 @@ impl PartialEq for VmAreaKind {
                     file: f1,
                     offset: o1,
                     file_size: s1,
+                    ..
                 },
                 VmAreaKind::File {
                     file: f2,
                     offset: o2,
                     file_size: s2,
+                    ..
                 },
             ) => o1 == o2 && s1 == s2 && Arc::ptr_eq(f1, f2),
             _ => false,
@@ -1,5 +1,5 @@
-use crate::mm::vmm::paging::PageTable;
-use crate::mm::vmm::types::VmAreaKind;
+use crate::mm::vmm::paging::{HUGE_PAGE_SIZE, PageTable};
+use crate::mm::vmm::types::{VmAreaKind, VmFlags};
 use alloc::collections::BTreeMap;
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, UnmapError};
@@ -14,6 +14,7 @@ pub struct VmArea {
     pub end: VirtAddr,
     pub flags: PageTableFlags,
     pub kind: VmAreaKind,
+    pub vm_flags: VmFlags,
 }
 
 impl VmArea {
@@ -26,7 +27,33 @@ impl VmArea {
     /// Returns true if two VMAs have identical permissions and backing kind and can be merged.
     #[inline]
     pub fn can_merge(&self, other: &Self) -> bool {
-        self.flags == other.flags && self.kind == other.kind
+        self.flags == other.flags && self.kind == other.kind && self.vm_flags == other.vm_flags
+    }
+
+    /// Returns true if this VMA may be backed by 2 MiB pages.
+    ///
+    /// Anonymous memory is eligible by default (THP "always" policy) unless the
+    /// VMA was opted out with `MADV_NOHUGEPAGE` or is merged by KSM at 4 KiB granularity.
+    #[inline]
+    pub fn huge_eligible(&self) -> bool {
+        self.vm_flags.contains(VmFlags::HUGETLB)
+            || (self.kind == VmAreaKind::Anonymous
+                && !self.vm_flags.intersects(VmFlags::NOHUGEPAGE | VmFlags::MERGEABLE))
+    }
+
+    /// Returns true if the KSM scanner may merge pages of this VMA.
+    #[inline]
+    pub fn ksm_eligible(&self) -> bool {
+        self.kind == VmAreaKind::Anonymous
+            && self.vm_flags.contains(VmFlags::MERGEABLE)
+            && !self.vm_flags.contains(VmFlags::HUGETLB)
+    }
+
+    /// Returns the 2 MiB-aligned region containing `addr` if it lies entirely inside this VMA.
+    #[inline]
+    pub fn huge_region(&self, addr: VirtAddr) -> Option<VirtAddr> {
+        let base = addr.align_down(HUGE_PAGE_SIZE);
+        (base >= self.start && base + HUGE_PAGE_SIZE <= self.end).then_some(base)
     }
 }
 
@@ -38,6 +65,7 @@ pub enum AddrSpaceError {
     PagingError(MapToError<Size4KiB>),
     UnmapError(UnmapError),
     FlagUpdateError(FlagUpdateError),
+    PermissionDenied,
 }
 
 #[derive(Debug)]
@@ -49,6 +77,28 @@ pub enum PageFaultError {
     RemapError(FlagUpdateError),
 }
 
+/// Allocate a 2 MiB block and zero it through the HHDM.
+fn alloc_zeroed_huge_frame() -> Option<PhysAddr> {
+    let frame = crate::mm::PMM.alloc_huge_page()?;
+    let dest_ptr = (frame.as_u64() + crate::mm::hhdm_offset()) as *mut u8;
+    // SAFETY: Zeroing a newly allocated, exclusively owned 2 MiB physical block.
+    unsafe {
+        core::ptr::write_bytes(dest_ptr, 0, HUGE_PAGE_SIZE as usize);
+    }
+    Some(frame)
+}
+
+/// Page-table flags for an existing mapping after its VMA's protection becomes `flags`.
+///
+/// Pages still shared copy-on-write stay read-only until the write fault breaks sharing.
+fn protect_entry_flags(flags: PageTableFlags, entry_flags: PageTableFlags) -> PageTableFlags {
+    if entry_flags.contains(COW_FLAG) {
+        (flags & !PageTableFlags::WRITABLE) | COW_FLAG
+    } else {
+        flags
+    }
+}
+
 /// Architecture-independent Virtual Memory Address Space representation.
 ///
 /// Wraps a hardware page table implementation `P` and manages registered `VmArea` regions.
@@ -73,31 +123,45 @@ impl<P: PageTable> AddrSpace<P> {
         &mut self.page_table
     }
 
+    /// Returns true if `virt` is currently mapped by a 2 MiB page.
+    fn is_huge_mapped(&self, virt: VirtAddr) -> bool {
+        matches!(self.page_table.get_entry(virt), Some((_, flags)) if flags.contains(PageTableFlags::HUGE_PAGE))
+    }
+
     /// Duplicate the virtual address space using Copy-On-Write (COW) semantics for writable pages.
     pub fn clone(&mut self) -> Result<Self, AddrSpaceError> {
         let mut new_page_table = P::new().map_err(AddrSpaceError::PagingError)?;
 
-        // Track every child mapping we've made: (virt, phys, was_cow).
+        // Track every child mapping we've made: (virt, phys, was_cow, huge).
         // On failure we use this to unmap + dec_ref + revert parent PTEs.
-        let mut child_maps: alloc::vec::Vec<(VirtAddr, PhysAddr, bool)> = alloc::vec::Vec::new();
+        let mut child_maps: alloc::vec::Vec<(VirtAddr, PhysAddr, bool, bool)> =
+            alloc::vec::Vec::new();
 
         for (&_vaddr, area) in &self.vm_areas {
-            let size = (area.end - area.start) as usize;
-            let num_pages = size / 4096;
-
-            'pages: for i in 0..num_pages {
-                let page_virt = area.start + (i as u64 * 4096);
-                let parent_phys = match self.page_table.translate(page_virt) {
-                    Some(p) => p,
-                    None => continue 'pages,
+            let mut page_virt = area.start;
+
+            'pages: while page_virt < area.end {
+                let (parent_phys, entry_flags) = match self.page_table.get_entry(page_virt) {
+                    Some(e) => e,
+                    None => {
+                        page_virt += 4096u64;
+                        continue 'pages;
+                    }
                 };
+                let huge = entry_flags.contains(PageTableFlags::HUGE_PAGE);
+                let step = if huge { HUGE_PAGE_SIZE } else { 4096 };
 
                 match &area.kind {
                     VmAreaKind::Anonymous | VmAreaKind::File { .. } => {
                         if area.flags.contains(PageTableFlags::WRITABLE) {
                             // COW: mark parent PTE read-only + COW first.
                             let cow_flags = (area.flags & !PageTableFlags::WRITABLE) | COW_FLAG;
-                            if let Err(err) = self.page_table.remap(page_virt, cow_flags) {
+                            let remapped = if huge {
+                                self.page_table.remap_huge(page_virt, cow_flags)
+                            } else {
+                                self.page_table.remap(page_virt, cow_flags)
+                            };
+                            if let Err(err) = remapped {
                                 Self::rollback_clone(
                                     &mut self.page_table,
                                     &mut new_page_table,
@@ -107,9 +171,18 @@ impl<P: PageTable> AddrSpace<P> {
                                 return Err(AddrSpaceError::FlagUpdateError(err));
                             }
                             // Map the same frame into the child under COW flags.
-                            if let Err(err) = new_page_table.map(page_virt, parent_phys, cow_flags) {
+                            let mapped = if huge {
+                                new_page_table.map_huge(page_virt, parent_phys, cow_flags)
+                            } else {
+                                new_page_table.map(page_virt, parent_phys, cow_flags)
+                            };
+                            if let Err(err) = mapped {
                                 // Revert the parent remap we just did.
-                                let _ = self.page_table.remap(page_virt, area.flags);
+                                if huge {
+                                    let _ = self.page_table.remap_huge(page_virt, entry_flags);
+                                } else {
+                                    let _ = self.page_table.remap(page_virt, entry_flags);
+                                }
                                 Self::rollback_clone(
                                     &mut self.page_table,
                                     &mut new_page_table,
@@ -118,11 +191,20 @@ impl<P: PageTable> AddrSpace<P> {
                                 );
                                 return Err(AddrSpaceError::PagingError(err));
                             }
-                            crate::mm::PMM.inc_ref(parent_phys);
-                            child_maps.push((page_virt, parent_phys, true));
+                            if huge {
+                                crate::mm::PMM.inc_ref_huge(parent_phys);
+                            } else {
+                                crate::mm::PMM.inc_ref(parent_phys);
+                            }
+                            child_maps.push((page_virt, parent_phys, true, huge));
                         } else {
                             // Read-only page: share directly without COW remap.
-                            if let Err(err) = new_page_table.map(page_virt, parent_phys, area.flags) {
+                            let mapped = if huge {
+                                new_page_table.map_huge(page_virt, parent_phys, area.flags)
+                            } else {
+                                new_page_table.map(page_virt, parent_phys, area.flags)
+                            };
+                            if let Err(err) = mapped {
                                 Self::rollback_clone(
                                     &mut self.page_table,
                                     &mut new_page_table,
@@ -131,8 +213,12 @@ impl<P: PageTable> AddrSpace<P> {
                                 );
                                 return Err(AddrSpaceError::PagingError(err));
                             }
-                            crate::mm::PMM.inc_ref(parent_phys);
-                            child_maps.push((page_virt, parent_phys, false));
+                            if huge {
+                                crate::mm::PMM.inc_ref_huge(parent_phys);
+                            } else {
+                                crate::mm::PMM.inc_ref(parent_phys);
+                            }
+                            child_maps.push((page_virt, parent_phys, false, huge));
                         }
                     }
                     VmAreaKind::Device { .. } => {
@@ -148,9 +234,14 @@ impl<P: PageTable> AddrSpace<P> {
                         }
                     }
                 }
+
+                page_virt += step;
             }
         }
 
+        // Threads on other CPUs must stop writing to pages that are now shared.
+        self.page_table.flush_tlb();
+
         Ok(Self {
             page_table: new_page_table,
             vm_areas: self.vm_areas.clone(),
@@ -162,21 +253,31 @@ impl<P: PageTable> AddrSpace<P> {
     fn rollback_clone(
         parent_pt: &mut P,
         child_pt: &mut P,
-        child_maps: &[(VirtAddr, PhysAddr, bool)],
+        child_maps: &[(VirtAddr, PhysAddr, bool, bool)],
         vm_areas: &alloc::collections::BTreeMap<VirtAddr, VmArea>,
     ) {
-        for &(virt, phys, was_cow) in child_maps {
-            let _ = child_pt.unmap(virt);
-            crate::mm::PMM.dec_ref(phys);
+        for &(virt, phys, was_cow, huge) in child_maps {
+            if huge {
+                let _ = child_pt.unmap_huge(virt);
+                crate::mm::PMM.dec_ref_huge(phys);
+            } else {
+                let _ = child_pt.unmap(virt);
+                crate::mm::PMM.dec_ref(phys);
+            }
             if was_cow {
                 // Restore parent PTE to its original writable flags.
-                if let Some(area) = vm_areas.range(..=virt).next_back().map(|(_, a)| a) {
-                    if area.contains(virt) {
+                if let Some(area) = vm_areas.range(..=virt).next_back().map(|(_, a)| a)
+                    && area.contains(virt)
+                {
+                    if huge {
+                        let _ = parent_pt.remap_huge(virt, area.flags);
+                    } else {
                         let _ = parent_pt.remap(virt, area.flags);
                     }
                 }
             }
         }
+        parent_pt.flush_tlb();
     }
 
     /// Efficiently lookup the VMA containing the specified virtual address ($O(\log N)$).
@@ -206,6 +307,104 @@ impl<P: PageTable> AddrSpace<P> {
         }
     }
 
+    /// Split the VMA containing `addr` into `[start, addr)` and `[addr, end)`.
+    ///
+    /// Does nothing if `addr` is not strictly inside a VMA. File-backed tails have
+    /// their file offset advanced so both halves keep mapping the same bytes.
+    fn split_vma_at(&mut self, addr: VirtAddr) {
+        let head_start = match self.find_vma(addr) {
+            Some(area) if area.start < addr => area.start,
+            _ => return,
+        };
+        let head = self.vm_areas.get_mut(&head_start).expect("VMA vanished during split");
+        let mut tail = head.clone();
+        head.end = addr;
+        tail.start = addr;
+        if let VmAreaKind::File { offset, .. } = &mut tail.kind {
+            *offset += (addr - head_start) as usize;
+        }
+        self.vm_areas.insert(addr, tail);
+    }
+
+    /// Starts of all VMAs overlapping `[start, end)` after splitting them at both bounds.
+    ///
+    /// Huge pages straddling a bound are split too, so no huge mapping ever crosses a
+    /// VMA boundary.
+    fn isolate_range(&mut self, start: VirtAddr, end: VirtAddr) -> alloc::vec::Vec<VirtAddr> {
+        for bound in [start, end] {
+            if !bound.is_aligned(HUGE_PAGE_SIZE) && self.is_huge_mapped(bound) {
+                let _ = self.split_huge_page(bound);
+            }
+        }
+        self.split_vma_at(start);
+        self.split_vma_at(end);
+        self.vm_areas
+            .range(start..end)
+            .map(|(&vma_start, _)| vma_start)
+            .collect()
+    }
+
+    /// Replace the 2 MiB mapping covering `virt` by 512 4 KiB mappings of the same frames.
+    ///
+    /// The physical block is split as well, so each 4 KiB piece carries its own
+    /// reference count and can be COW-copied or freed independently.
+    pub fn split_huge_page(&mut self, virt: VirtAddr) -> Result<(), MapToError<Size4KiB>> {
+        let base = virt.align_down(HUGE_PAGE_SIZE);
+        let block = match self.page_table.get_entry(base) {
+            Some((phys, flags)) if flags.contains(PageTableFlags::HUGE_PAGE) => phys,
+            _ => return Ok(()),
+        };
+        self.page_table.split_huge(base)?;
+        self.page_table.flush_tlb();
+        crate::mm::PMM.split_pages(block, crate::mm::pmm::HUGE_PAGE_ORDER);
+        Ok(())
+    }
+
+    /// Unmap every page in `[start, end)`, splitting huge pages that straddle either bound.
+    ///
+    /// Frame references are dropped only when `release` is set (i.e. not device memory),
+    /// and only once no CPU can reach the frames through its TLB any more.
+    fn zap_range(&mut self, start: VirtAddr, end: VirtAddr, release: bool) {
+        // (frame, huge) pairs to release after the TLB shootdown.
+        let mut freed: alloc::vec::Vec<(PhysAddr, bool)> = alloc::vec::Vec::new();
+        let mut addr = start;
+        while addr < end {
+            if self.is_huge_mapped(addr) {
+                let base = addr.align_down(HUGE_PAGE_SIZE);
+                if base >= start && base + HUGE_PAGE_SIZE <= end {
+                    if let Ok(block) = self.page_table.unmap_huge(base) {
+                        freed.push((block, true));
+                    }
+                    addr = base + HUGE_PAGE_SIZE;
+                    continue;
+                }
+                if self.split_huge_page(addr).is_err() {
+                    // Cannot split without a page-table frame; drop the whole huge page.
+                    if let Ok(block) = self.page_table.unmap_huge(base) {
+                        freed.push((block, true));
+                    }
+                    addr = base + HUGE_PAGE_SIZE;
+                    continue;
+                }
+            }
+            if let Ok(frame) = self.page_table.unmap(addr) {
+                freed.push((frame, false));
+            }
+            addr += 4096u64;
+        }
+
+        self.page_table.flush_tlb();
+        if release {
+            for (frame, huge) in freed {
+                if huge {
+                    crate::mm::PMM.free_huge_page(frame);
+                } else {
+                    crate::mm::PMM.free_page(frame);
+                }
+            }
+        }
+    }
+
     /// Map a contiguous range of virtual memory to physical RAM, MMIO, or a File eagerly.
     pub fn map_area(
         &mut self,
@@ -213,10 +412,34 @@ impl<P: PageTable> AddrSpace<P> {
         size: usize,
         flags: PageTableFlags,
         kind: VmAreaKind,
+    ) -> Result<(), AddrSpaceError> {
+        self.map_area_with_flags(start, size, flags, kind, VmFlags::empty())
+    }
+
+    /// Like [`AddrSpace::map_area`], with explicit VMA behaviour flags.
+    ///
+    /// Anonymous memory is mapped with 2 MiB pages wherever a whole aligned 2 MiB
+    /// region fits, falling back to 4 KiB pages when no huge block is available.
+    /// `VmFlags::HUGETLB` areas must be 2 MiB aligned and fail instead of falling back.
+    pub fn map_area_with_flags(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: PageTableFlags,
+        kind: VmAreaKind,
+        vm_flags: VmFlags,
     ) -> Result<(), AddrSpaceError> {
         if size == 0 || !start.is_aligned(4096u64) || size % 4096 != 0 {
             return Err(AddrSpaceError::InvalidRange);
         }
+        let hugetlb = vm_flags.contains(VmFlags::HUGETLB);
+        if hugetlb
+            && (!start.is_aligned(HUGE_PAGE_SIZE)
+                || !(size as u64).is_multiple_of(HUGE_PAGE_SIZE)
+                || kind != VmAreaKind::Anonymous)
+        {
+            return Err(AddrSpaceError::InvalidRange);
+        }
 
         let end = start + size as u64;
 
@@ -225,18 +448,47 @@ impl<P: PageTable> AddrSpace<P> {
             return Err(AddrSpaceError::OverlappingArea);
         }
 
-        let num_pages = size / 4096;
-        let mut mapped_pages: usize = 0;
+        let area = VmArea {
+            start,
+            end,
+            flags,
+            kind,
+            vm_flags,
+        };
+        let kind = &area.kind;
+        let mut mapped: usize = 0;
         let hhdm = crate::mm::hhdm_offset();
 
-        for i in 0..num_pages {
-            let page_virt = start + (i as u64 * 4096);
-            let frame_phys = match &kind {
+        while mapped < size {
+            let page_virt = start + mapped as u64;
+
+            if area.huge_eligible() && area.huge_region(page_virt) == Some(page_virt) {
+                if let Some(frame) = alloc_zeroed_huge_frame() {
+                    match self.page_table.map_huge(page_virt, frame, flags) {
+                        Ok(()) => {
+                            mapped += HUGE_PAGE_SIZE as usize;
+                            continue;
+                        }
+                        Err(err) => {
+                            crate::mm::PMM.free_huge_page(frame);
+                            if hugetlb {
+                                self.rollback_mapping(start, mapped, kind);
+                                return Err(AddrSpaceError::PagingError(err));
+                            }
+                        }
+                    }
+                } else if hugetlb {
+                    self.rollback_mapping(start, mapped, kind);
+                    return Err(AddrSpaceError::PagingError(MapToError::FrameAllocationFailed));
+                }
+            }
+
+            let frame_phys = match kind {
                 VmAreaKind::Anonymous => {
                     let frame = match crate::mm::PMM.alloc_page() {
                         Some(f) => f,
                         None => {
-                            self.rollback_mapping(start, mapped_pages, &kind);
+                            self.rollback_mapping(start, mapped, kind);
                             return Err(AddrSpaceError::PagingError(MapToError::FrameAllocationFailed));
                         }
                     };
@@ -247,22 +499,23 @@ impl<P: PageTable> AddrSpace<P> {
                     }
                     frame
                 }
-                VmAreaKind::Device { phys_start } => *phys_start + (i as u64 * 4096),
+                VmAreaKind::Device { phys_start } => *phys_start + mapped as u64,
                 VmAreaKind::File {
                     file,
                     offset,
                     file_size,
+                    ..
                 } => {
                     let frame = match crate::mm::PMM.alloc_page() {
                         Some(f) => f,
                         None => {
-                            self.rollback_mapping(start, mapped_pages, &kind);
+                            self.rollback_mapping(start, mapped, kind);
                             return Err(AddrSpaceError::PagingError(MapToError::FrameAllocationFailed));
                         }
                     };
                     let dest_ptr = (frame.as_u64() + hhdm) as *mut u8;
 
-                    let page_file_offset = offset + (i * 4096);
+                    let page_file_offset = offset + mapped;
                     let bytes_written = if page_file_offset < *file_size {
                         let bytes_to_read = core::cmp::min(4096, *file_size - page_file_offset);
                         let buf_slice =
@@ -285,9 +538,9 @@ impl<P: PageTable> AddrSpace<P> {
             };
 
             match self.page_table.map(page_virt, frame_phys, flags) {
-                Ok(_) => mapped_pages += 1,
+                Ok(_) => mapped += 4096,
                 Err(err) => {
-                    self.rollback_mapping(start, mapped_pages, &kind);
+                    self.rollback_mapping(start, mapped, kind);
                     if matches!(kind, VmAreaKind::Anonymous | VmAreaKind::File { .. }) {
                         crate::mm::PMM.free_page(frame_phys);
                     }
@@ -296,6 +549,31 @@ impl<P: PageTable> AddrSpace<P> {
             }
         }
 
+        self.vm_areas.insert(start, area);
+
+        Ok(())
+    }
+
+    /// Register a VMA over `[start, start + size)` without populating it.
+    ///
+    /// Every page is faulted in on first access: zero-filled for anonymous memory, read
+    /// from the file for file-backed areas.
+    pub fn reserve_area(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: PageTableFlags,
+        kind: VmAreaKind,
+        vm_flags: VmFlags,
+    ) -> Result<(), AddrSpaceError> {
+        if size == 0 || !start.is_aligned(4096u64) || size % 4096 != 0 {
+            return Err(AddrSpaceError::InvalidRange);
+        }
+        let end = start + size as u64;
+        if self.check_overlap(start, end) {
+            return Err(AddrSpaceError::OverlappingArea);
+        }
+
         self.vm_areas.insert(
             start,
             VmArea {
@@ -303,96 +581,302 @@ impl<P: PageTable> AddrSpace<P> {
                 end,
                 flags,
                 kind,
+                vm_flags,
             },
         );
-
         Ok(())
     }
 
-    fn rollback_mapping(&mut self, start: VirtAddr, mapped_pages: usize, kind: &VmAreaKind) {
-        for j in 0..mapped_pages {
-            let rollback_virt = start + (j as u64 * 4096);
-            if let Ok(frame) = self.page_table.unmap(rollback_virt) {
-                if matches!(kind, VmAreaKind::Anonymous | VmAreaKind::File { .. }) {
-                    crate::mm::PMM.free_page(frame);
-                }
-            }
-        }
+    fn rollback_mapping(&mut self, start: VirtAddr, mapped_bytes: usize, kind: &VmAreaKind) {
+        let release = matches!(kind, VmAreaKind::Anonymous | VmAreaKind::File { .. });
+        self.zap_range(start, start + mapped_bytes as u64, release);
     }
 
     /// Unmap and remove any VMAs or parts of VMAs overlapping [start, end).
     pub fn unmap_range(&mut self, start: VirtAddr, end: VirtAddr) -> Result<(), AddrSpaceError> {
-        let mut to_remove = alloc::vec::Vec::new();
-        let mut to_add = alloc::vec::Vec::new();
-
-        for (&vma_start, vma) in &self.vm_areas {
-            if vma.start < end && vma.end > start {
-                to_remove.push(vma_start);
-
-                if vma.start < start {
-                    to_add.push(VmArea {
-                        start: vma.start,
-                        end: start,
-                        flags: vma.flags,
-                        kind: vma.kind.clone(),
-                    });
+        for vma_start in self.isolate_range(start, end) {
+            if let Some(area) = self.vm_areas.remove(&vma_start) {
+                let release = !matches!(area.kind, VmAreaKind::Device { .. });
+                self.zap_range(area.start, area.end, release);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmap a virtual memory area starting at the specified virtual address.
+    pub fn unmap_area(&mut self, start: VirtAddr) -> Result<(), AddrSpaceError> {
+        let area = self
+            .vm_areas
+            .remove(&start)
+            .ok_or(AddrSpaceError::InvalidRange)?;
+
+        let release = matches!(area.kind, VmAreaKind::Anonymous | VmAreaKind::File { .. });
+        self.zap_range(area.start, area.end, release);
+
+        Ok(())
+    }
+
+    /// Change the protection of every mapped page in `[start, end)` (mprotect semantics).
+    ///
+    /// VMAs are split at the range bounds and take on `flags`; huge pages that straddle
+    /// a bound are split. Pages still shared copy-on-write remain read-only.
+    /// Ranges touching a `VmFlags::SPECIAL` area are refused with `PermissionDenied`.
+    pub fn protect_range(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), AddrSpaceError> {
+        let special = self
+            .vm_areas
+            .range(..end)
+            .rev()
+            .take_while(|(_, area)| area.end > start)
+            .any(|(_, area)| area.vm_flags.contains(VmFlags::SPECIAL));
+        if special {
+            return Err(AddrSpaceError::PermissionDenied);
+        }
+
+        let result = self.protect_pages(start, end, flags);
+        self.page_table.flush_tlb();
+        result
+    }
+
+    /// Apply `flags` to the VMAs and mapped pages in `[start, end)` for `protect_range`.
+    fn protect_pages(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), AddrSpaceError> {
+        for vma_start in self.isolate_range(start, end) {
+            let (area_start, area_end) = match self.vm_areas.get_mut(&vma_start) {
+                Some(area) => {
+                    area.flags = flags;
+                    (area.start, area.end)
                 }
+                None => continue,
+            };
 
-                if vma.end > end {
-                    to_add.push(VmArea {
-                        start: end,
-                        end: vma.end,
-                        flags: vma.flags,
-                        kind: vma.kind.clone(),
-                    });
+            let mut addr = area_start;
+            while addr < area_end {
+                let entry_flags = match self.page_table.get_entry(addr) {
+                    Some((_, f)) => f,
+                    None => {
+                        addr += 4096u64;
+                        continue;
+                    }
+                };
+                if entry_flags.contains(PageTableFlags::HUGE_PAGE) {
+                    let base = addr.align_down(HUGE_PAGE_SIZE);
+                    if base >= area_start && base + HUGE_PAGE_SIZE <= area_end {
+                        self.page_table
+                            .remap_huge(base, protect_entry_flags(flags, entry_flags))
+                            .map_err(AddrSpaceError::FlagUpdateError)?;
+                        addr = base + HUGE_PAGE_SIZE;
+                        continue;
+                    }
+                    self.split_huge_page(addr)
+                        .map_err(AddrSpaceError::PagingError)?;
                 }
+                let entry_flags = entry_flags & !PageTableFlags::HUGE_PAGE;
+                self.page_table
+                    .remap(addr, protect_entry_flags(flags, entry_flags))
+                    .map_err(AddrSpaceError::FlagUpdateError)?;
+                addr += 4096u64;
             }
         }
 
-        for k in to_remove {
-            self.vm_areas.remove(&k);
-        }
-        for v in to_add {
-            self.vm_areas.insert(v.start, v);
-        }
+        Ok(())
+    }
 
-        for page_virt_u64 in (start.as_u64()..end.as_u64()).step_by(4096) {
-            let page_virt = VirtAddr::new(page_virt_u64);
-            if let Ok(old_frame) = self.page_table.unmap(page_virt) {
-                crate::mm::PMM.free_page(old_frame);
+    /// Set and clear behaviour flags on all VMAs in `[start, end)`, splitting at the bounds.
+    pub fn update_vm_flags(&mut self, start: VirtAddr, end: VirtAddr, set: VmFlags, clear: VmFlags) {
+        for vma_start in self.isolate_range(start, end) {
+            if let Some(area) = self.vm_areas.get_mut(&vma_start) {
+                area.vm_flags.remove(clear);
+                area.vm_flags.insert(set);
             }
         }
+    }
 
-        Ok(())
+    /// Drop the pages backing `[start, end)` while keeping the VMAs (MADV_DONTNEED).
+    ///
+    /// Later accesses fault in zero-filled (anonymous) or re-read (file) pages.
+    pub fn discard_range(&mut self, start: VirtAddr, end: VirtAddr) {
+        let spans: alloc::vec::Vec<(VirtAddr, VirtAddr, bool)> = self
+            .vm_areas
+            .values()
+            .filter(|area| area.start < end && area.end > start)
+            .map(|area| {
+                (
+                    area.start.max(start),
+                    area.end.min(end),
+                    !matches!(area.kind, VmAreaKind::Device { .. }),
+                )
+            })
+            .collect();
+        for (span_start, span_end, release) in spans {
+            self.zap_range(span_start, span_end, release);
+        }
     }
 
-    /// Unmap a virtual memory area starting at the specified virtual address.
-    pub fn unmap_area(&mut self, start: VirtAddr) -> Result<(), AddrSpaceError> {
-        let area = self
+    /// Collapse fully populated or partially populated 2 MiB regions of huge-page
+    /// eligible VMAs in `[start, end)` into single huge page mappings.
+    ///
+    /// Regions containing shared (COW or refcounted) frames are skipped, since
+    /// collapsing them would silently break sharing. Returns the number of regions collapsed.
+    pub fn collapse_range(&mut self, start: VirtAddr, end: VirtAddr) -> usize {
+        let hhdm = crate::mm::hhdm_offset();
+        let candidates: alloc::vec::Vec<(VirtAddr, PageTableFlags)> = self
             .vm_areas
-            .remove(&start)
-            .ok_or(AddrSpaceError::InvalidRange)?;
+            .values()
+            .filter(|area| area.start < end && area.end > start && area.huge_eligible())
+            .flat_map(|area| {
+                let first = area.start.max(start).align_up(HUGE_PAGE_SIZE);
+                let last = area.end.min(end);
+                let flags = area.flags;
+                (first.as_u64()..last.as_u64())
+                    .step_by(HUGE_PAGE_SIZE as usize)
+                    .map(VirtAddr::new)
+                    .filter(move |base| *base + HUGE_PAGE_SIZE <= last)
+                    .map(move |base| (base, flags))
+            })
+            .collect();
+
+        let mut collapsed = 0;
+        for (base, flags) in candidates {
+            if self.is_huge_mapped(base) {
+                continue;
+            }
 
-        let size = (area.end - area.start) as usize;
-        let num_pages = size / 4096;
+            let mut populated = false;
+            let mut exclusive = true;
+            for i in 0..512u64 {
+                if let Some((phys, entry_flags)) = self.page_table.get_entry(base + i * 4096) {
+                    populated = true;
+                    if entry_flags.contains(COW_FLAG) || crate::mm::PMM.get_ref(phys) != 1 {
+                        exclusive = false;
+                        break;
+                    }
+                }
+            }
+            if !populated || !exclusive {
+                continue;
+            }
+
+            let block = match crate::mm::PMM.alloc_huge_page() {
+                Some(b) => b,
+                None => break,
+            };
+            for i in 0..512u64 {
+                let dest = (block.as_u64() + i * 4096 + hhdm) as *mut u8;
+                // SAFETY: Copying exclusively owned 4 KiB frames into the new 2 MiB block.
+                unsafe {
+                    match self.page_table.translate(base + i * 4096) {
+                        Some(src) => core::ptr::copy_nonoverlapping(
+                            (src.as_u64() + hhdm) as *const u8,
+                            dest,
+                            4096,
+                        ),
+                        None => core::ptr::write_bytes(dest, 0, 4096),
+                    }
+                }
+            }
 
-        for i in 0..num_pages {
-            let page_virt = area.start + (i as u64 * 4096);
-            match self.page_table.unmap(page_virt) {
-                Ok(frame) => {
-                    if matches!(area.kind, VmAreaKind::Anonymous | VmAreaKind::File { .. }) {
+            self.zap_range(base, base + HUGE_PAGE_SIZE, true);
+            if self.page_table.map_huge(base, block, flags).is_err() {
+                // The old pages are gone; keep the copied contents reachable at 4 KiB.
+                crate::mm::PMM.split_pages(block, crate::mm::pmm::HUGE_PAGE_ORDER);
+                for i in 0..512u64 {
+                    let frame = block + i * 4096;
+                    if self.page_table.map(base + i * 4096, frame, flags).is_err() {
                         crate::mm::PMM.free_page(frame);
                     }
                 }
-                Err(UnmapError::PageNotMapped) => {}
-                Err(err) => {
-                    self.vm_areas.insert(start, area);
-                    return Err(AddrSpaceError::UnmapError(err));
+                continue;
+            }
+            collapsed += 1;
+        }
+        collapsed
+    }
+
+    /// Make the 4 KiB page at `virt` read-only and copy-on-write if it is still backed by `expected`.
+    ///
+    /// Returns false if the mapping changed or is a huge page.
+    pub fn write_protect_page(&mut self, virt: VirtAddr, expected: PhysAddr) -> bool {
+        match self.page_table.get_entry(virt) {
+            Some((phys, flags)) if phys == expected && !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                let cow_flags = (flags & !PageTableFlags::WRITABLE) | COW_FLAG;
+                if cow_flags == flags {
+                    return true;
+                }
+                if self.page_table.remap(virt, cow_flags).is_err() {
+                    return false;
                 }
+                // The caller compares page contents next; no CPU may still write to it.
+                self.page_table.flush_tlb();
+                true
             }
+            _ => false,
         }
+    }
 
-        Ok(())
+    /// Point the 4 KiB page at `virt`, currently backed by `expected`, at the shared
+    /// `frame` instead, read-only and copy-on-write.
+    ///
+    /// Takes a reference on `frame` and drops the one held on `expected`. Returns false,
+    /// leaving the mapping untouched, if it no longer refers to `expected`.
+    pub fn replace_page(&mut self, virt: VirtAddr, expected: PhysAddr, frame: PhysAddr) -> bool {
+        let flags = match self.page_table.get_entry(virt) {
+            Some((phys, flags)) if phys == expected && !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                flags
+            }
+            _ => return false,
+        };
+        if self.page_table.unmap(virt).is_err() {
+            return false;
+        }
+        let cow_flags = (flags & !PageTableFlags::WRITABLE) | COW_FLAG;
+        if self.page_table.map(virt, frame, cow_flags).is_err() {
+            // The page table walk just succeeded, so restoring the old entry cannot fail.
+            let _ = self.page_table.map(virt, expected, flags);
+            return false;
+        }
+        self.page_table.flush_tlb();
+        crate::mm::PMM.inc_ref(frame);
+        crate::mm::PMM.free_page(expected);
+        true
+    }
+
+    /// Iterate over all registered VMAs in ascending address order.
+    pub fn vm_areas(&self) -> impl Iterator<Item = &VmArea> {
+        self.vm_areas.values()
+    }
+
+    /// Count the pages of anonymous VMAs that are currently backed by a physical frame.
+    ///
+    /// Used by the OOM killer as the primary badness metric.
+    pub fn resident_anon_pages(&self) -> usize {
+        let mut resident = 0;
+        for area in self.vm_areas.values() {
+            if area.kind != VmAreaKind::Anonymous {
+                continue;
+            }
+            resident += self.page_table.count_mapped(area.start, area.end);
+        }
+        resident
+    }
+
+    /// Tear down every user mapping and release the backing frames.
+    ///
+    /// The page table itself stays alive; only leaf mappings and VMAs are dropped.
+    pub fn unmap_all(&mut self) {
+        let starts: alloc::vec::Vec<VirtAddr> = self.vm_areas.keys().copied().collect();
+        for start in starts {
+            let _ = self.unmap_area(start);
+        }
     }
 
     /// Architecture-independent Page Fault Resolution Algorithm.
@@ -429,12 +913,34 @@ impl<P: PageTable> AddrSpace<P> {
 
         let page_virt = VirtAddr::new(fault_addr.as_u64() & !4095);
 
+        // 3a. Write to a huge COW page: reuse it if exclusively owned, otherwise split it
+        // and let the 4 KiB path below copy only the faulting page.
+        let base = page_virt.align_down(HUGE_PAGE_SIZE);
+        if let Some((block, entry_flags)) = self.page_table.get_entry(base)
+            && entry_flags.contains(PageTableFlags::HUGE_PAGE)
+            && access.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+            && entry_flags.contains(COW_FLAG)
+        {
+            if crate::mm::PMM.is_exclusive_compound(block, crate::mm::pmm::HUGE_PAGE_ORDER) {
+                self.page_table
+                    .remap_huge(base, area.flags)
+                    .map_err(PageFaultError::RemapError)?;
+                return Ok(());
+            }
+            self.split_huge_page(base)
+                .map_err(|_| PageFaultError::FrameAllocationFailed)?;
+        }
+
         // 3. Check if page is present in page table for COW resolution
         if let Some((parent_phys, entry_flags)) = self.page_table.get_entry(page_virt) {
             let is_cow_entry = entry_flags.contains(COW_FLAG);
             if access.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
                 && (is_cow_entry || area.flags.contains(PageTableFlags::WRITABLE))
             {
+                if entry_flags.contains(PageTableFlags::HUGE_PAGE) {
+                    // Writable huge mapping: stale TLB entry, nothing to fix up.
+                    return Ok(());
+                }
                 let ref_count = crate::mm::PMM.get_ref(parent_phys);
                 if ref_count > 1 {
                     // Shared COW frame: allocate a new physical frame and copy contents
@@ -451,9 +957,10 @@ impl<P: PageTable> AddrSpace<P> {
 
                     // Unmap old frame and map new frame with original VMA flags (writable, no COW)
                     let _ = self.page_table.unmap(page_virt);
-                    self.page_table
-                        .map(page_virt, new_frame, area.flags)
-                        .map_err(PageFaultError::PagingError)?;
+                    let mapped = self.page_table.map(page_virt, new_frame, area.flags);
+                    // Other threads must not keep reading the old frame.
+                    self.page_table.flush_tlb();
+                    mapped.map_err(PageFaultError::PagingError)?;
 
                     // Decrement reference count on old parent frame
                     crate::mm::PMM.dec_ref(parent_phys);
@@ -472,7 +979,23 @@ impl<P: PageTable> AddrSpace<P> {
             return Ok(()); // Spurious fault
         }
 
-        // 4. Page is not present in hardware page table: handle demand paging for registered VMA
+        // 4. Page is not present in hardware page table: handle demand paging for registered VMA.
+        // Prefer a whole 2 MiB page when the aligned region fits the VMA and is still empty.
+        if area.huge_eligible() {
+            if let Some(base) = area.huge_region(page_virt)
+                && self.page_table.huge_slot_vacant(base)
+                && let Some(block) = alloc_zeroed_huge_frame()
+            {
+                if self.page_table.map_huge(base, block, area.flags).is_ok() {
+                    return Ok(());
+                }
+                crate::mm::PMM.free_huge_page(block);
+            }
+            if area.vm_flags.contains(VmFlags::HUGETLB) {
+                return Err(PageFaultError::FrameAllocationFailed);
+            }
+        }
+
         let hhdm = crate::mm::hhdm_offset();
         let frame_phys = match &area.kind {
             VmAreaKind::Anonymous => {
@@ -494,6 +1017,7 @@ impl<P: PageTable> AddrSpace<P> {
                 file,
                 offset,
                 file_size,
+                ..
             } => {
                 let frame = crate::mm::PMM
                     .alloc_page()
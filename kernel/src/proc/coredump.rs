@@ -0,0 +1,548 @@
+//! ELF core dumps of processes killed by a signal.
+//!
+//! A dump starts with a `PT_NOTE` segment describing the process and the crashing thread,
+//! followed by one `PT_LOAD` segment per VMA, so it can be loaded with `gdb <exe> <core>`.
+//! The file name comes from `/proc/sys/kernel/core_pattern` and its size is capped by
+//! `RLIMIT_CORE`, which is 0 (no dumps) unless raised.
+
+use crate::arch::coredump::{FpuState, UserRegs};
+use crate::fs::{File, InodeType, O_CREAT, O_WRONLY, VfsError};
+use crate::ipc::signal::SigSet;
+use crate::mm::{AddrSpace, ArchPageTable, PageTable, VmArea, VmAreaKind};
+use crate::proc::{Process, ProcessId, ThreadId};
+use crate::proc::loader::header::*;
+use crate::proc::process::rlimit::RLIMIT_CORE;
+use crate::sync::spinlock::Spinlock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::size_of;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+/// Pattern used until one is written to `/proc/sys/kernel/core_pattern`.
+const DEFAULT_CORE_PATTERN: &str = "core";
+
+/// Longest `core_pattern` accepted, as in Linux.
+const CORE_PATTERN_MAX: usize = 127;
+
+/// Most `PT_LOAD` segments a dump holds; `e_phnum` is 16 bits wide.
+const MAX_SEGMENTS: usize = u16::MAX as usize - 1;
+
+const PAGE_SIZE: u64 = 4096;
+
+static CORE_PATTERN: Spinlock<String> = Spinlock::new(String::new());
+
+/// The thread whose fatal signal caused the dump.
+pub struct CrashedThread {
+    pub tid: ThreadId,
+    pub regs: UserRegs,
+    /// Signals pending for the thread
+    pub sig_pending: SigSet,
+    /// Signals the thread had blocked
+    pub sig_blocked: SigSet,
+}
+
+/// What a dump needs of the dumping process, copied while the process is locked so that
+/// the file is written with neither the process nor the thread lock held.
+pub struct CoreProcess {
+    pid: ProcessId,
+    ppid: ProcessId,
+    pgid: ProcessId,
+    uid: u32,
+    gid: u32,
+    /// `RLIMIT_CORE`
+    limit: u64,
+    cwd: String,
+    args: Vec<String>,
+    auxv: Vec<(u64, u64)>,
+    /// The VMAs at the time of the crash, at most `MAX_SEGMENTS` of them
+    areas: Vec<VmArea>,
+    address_space: Arc<Spinlock<AddrSpace<ArchPageTable>>>,
+    fpu: FpuState,
+}
+
+impl CoreProcess {
+    /// Snapshot `proc` for a dump, or `None` if it must not dump core.
+    ///
+    /// Must run on the crashing thread, whose FPU state is saved before the file system
+    /// gets a chance to run.
+    pub fn capture(proc: &Process) -> Option<Self> {
+        let limit = proc.rlimits[RLIMIT_CORE].rlim_cur;
+        // Like Linux with `suid_dumpable` = 0, never dump a process running with other rights.
+        if limit == 0 || proc.uid != proc.euid || proc.gid != proc.egid {
+            return None;
+        }
+        let areas = proc.address_space.lock().vm_areas().take(MAX_SEGMENTS).cloned().collect();
+        Some(Self {
+            pid: proc.pid,
+            ppid: proc.ppid,
+            pgid: proc.pgid,
+            uid: proc.uid,
+            gid: proc.gid,
+            limit,
+            cwd: proc.cwd.clone(),
+            args: proc.cmdline.args.clone(),
+            auxv: proc.saved_auxv.clone(),
+            areas,
+            address_space: proc.address_space.clone(),
+            fpu: FpuState::save(),
+        })
+    }
+
+    /// Command name: the last component of `argv[0]`, at most 15 bytes.
+    fn comm(&self) -> &str {
+        let arg0 = self.args.first().map_or("", |arg| arg.as_str());
+        let name = arg0.rsplit('/').next().unwrap_or(arg0);
+        let mut end = name.len().min(15);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        &name[..end]
+    }
+}
+
+/// `struct elf_prstatus`: state of one thread, the payload of `NT_PRSTATUS`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElfPrStatus {
+    /// `si_signo`, `si_code` and `si_errno`
+    info: [i32; 3],
+    cursig: u16,
+    _pad0: u16,
+    sigpend: u64,
+    sighold: u64,
+    pid: i32,
+    ppid: i32,
+    pgrp: i32,
+    sid: i32,
+    /// User, system, children's user and children's system time as `timeval`s
+    times: [u64; 8],
+    regs: UserRegs,
+    fpvalid: i32,
+    _pad1: u32,
+}
+
+/// `struct elf_prpsinfo`: process summary, the payload of `NT_PRPSINFO`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElfPrPsInfo {
+    state: u8,
+    sname: u8,
+    zomb: u8,
+    nice: i8,
+    _pad0: u32,
+    flag: u64,
+    uid: u32,
+    gid: u32,
+    pid: i32,
+    ppid: i32,
+    pgrp: i32,
+    sid: i32,
+    fname: [u8; 16],
+    psargs: [u8; 80],
+}
+
+/// View a note or header structure as bytes.
+fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    // SAFETY: Only used on `repr(C)` structures whose padding is spelled out as fields,
+    // so every byte is initialized.
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// Append a note named `CORE` to `notes`, padding name and descriptor to 4 bytes.
+fn push_note(notes: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    const NAME: &[u8] = b"CORE\0";
+    notes.extend_from_slice(&(NAME.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&note_type.to_ne_bytes());
+    notes.extend_from_slice(NAME);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+}
+
+/// Copy `src` into the fixed-size, NUL-terminated field `dst`.
+fn copy_str(dst: &mut [u8], src: &[u8]) {
+    let len = src.len().min(dst.len() - 1);
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+/// Expand the `%` specifiers of `pattern` for `core`, killed by `sig`.
+///
+/// Supports `%p` (PID), `%u`/`%g` (UID/GID), `%s` (signal), `%t` (time of dump),
+/// `%h` (host name), `%e` (command name) and `%%`. Unknown specifiers are dropped.
+fn expand_pattern(pattern: &str, core: &CoreProcess, sig: u8) -> String {
+    let mut name = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            name.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('%') => write!(name, "%"),
+            Some('p') => write!(name, "{}", core.pid.as_u64()),
+            Some('u') => write!(name, "{}", core.uid),
+            Some('g') => write!(name, "{}", core.gid),
+            Some('s') => write!(name, "{}", sig),
+            Some('t') => {
+                let secs = crate::time::timekeeping::realtime_ns() / 1_000_000_000;
+                write!(name, "{}", secs)
+            }
+            Some('h') => write!(name, "{}", crate::syscalls::sys_info::HOSTNAME),
+            Some('e') => write!(name, "{}", core.comm()),
+            _ => Ok(()),
+        };
+    }
+    name
+}
+
+/// Build the `PT_NOTE` segment.
+fn build_notes(core: &CoreProcess, thread: &CrashedThread, sig: u8) -> Vec<u8> {
+    let mut notes = Vec::new();
+
+    let prstatus = ElfPrStatus {
+        info: [sig as i32, 0, 0],
+        cursig: sig as u16,
+        _pad0: 0,
+        sigpend: thread.sig_pending,
+        sighold: thread.sig_blocked,
+        pid: thread.tid.0 as i32,
+        ppid: core.ppid.as_u64() as i32,
+        pgrp: core.pgid.as_u64() as i32,
+        sid: 0,
+        times: [0; 8],
+        regs: thread.regs,
+        fpvalid: 1,
+        _pad1: 0,
+    };
+    push_note(&mut notes, NT_PRSTATUS, as_bytes(&prstatus));
+
+    let mut prpsinfo = ElfPrPsInfo {
+        state: 0,
+        sname: b'R',
+        zomb: 0,
+        nice: 0,
+        _pad0: 0,
+        flag: 0,
+        uid: core.uid,
+        gid: core.gid,
+        pid: core.pid.as_u64() as i32,
+        ppid: core.ppid.as_u64() as i32,
+        pgrp: core.pgid.as_u64() as i32,
+        sid: 0,
+        fname: [0; 16],
+        psargs: [0; 80],
+    };
+    copy_str(&mut prpsinfo.fname, core.comm().as_bytes());
+    let psargs = core.args.join(" ");
+    copy_str(&mut prpsinfo.psargs, psargs.as_bytes());
+    push_note(&mut notes, NT_PRPSINFO, as_bytes(&prpsinfo));
+
+    let mut auxv = Vec::with_capacity((core.auxv.len() + 1) * 16);
+    for &(key, val) in core.auxv.iter().chain(core::iter::once(&(AT_NULL, 0))) {
+        auxv.extend_from_slice(&key.to_ne_bytes());
+        auxv.extend_from_slice(&val.to_ne_bytes());
+    }
+    push_note(&mut notes, NT_AUXV, &auxv);
+
+    // NT_FILE: count and page size, then (start, end, page offset) per mapping, then names
+    let files: Vec<(&VmArea, usize, &Arc<str>)> = core
+        .areas
+        .iter()
+        .filter_map(|area| match &area.kind {
+            VmAreaKind::File { offset, path, .. } => Some((area, *offset, path)),
+            _ => None,
+        })
+        .collect();
+    let mut file_note = Vec::new();
+    file_note.extend_from_slice(&(files.len() as u64).to_ne_bytes());
+    file_note.extend_from_slice(&PAGE_SIZE.to_ne_bytes());
+    for (area, offset, _) in &files {
+        file_note.extend_from_slice(&area.start.as_u64().to_ne_bytes());
+        file_note.extend_from_slice(&area.end.as_u64().to_ne_bytes());
+        file_note.extend_from_slice(&(*offset as u64 / PAGE_SIZE).to_ne_bytes());
+    }
+    for (_, _, path) in &files {
+        file_note.extend_from_slice(path.as_bytes());
+        file_note.push(0);
+    }
+    push_note(&mut notes, NT_FILE, &file_note);
+
+    push_note(&mut notes, NT_FPREGSET, &core.fpu.0);
+    notes
+}
+
+/// Whether the contents of `area` belong in the dump.
+///
+/// Device registers are not read, and unwritable file mappings are left for the debugger
+/// to take from the file named in `NT_FILE`.
+fn should_dump(area: &VmArea) -> bool {
+    match area.kind {
+        VmAreaKind::Device { .. } => !area.flags.contains(PageTableFlags::NO_CACHE),
+        VmAreaKind::File { .. } => area.flags.contains(PageTableFlags::WRITABLE),
+        VmAreaKind::Anonymous => true,
+    }
+}
+
+/// `p_flags` matching the protection of `area`.
+fn segment_flags(area: &VmArea) -> u32 {
+    let mut flags = 0;
+    if area.flags.contains(PageTableFlags::PRESENT) {
+        flags |= PF_R;
+        if area.flags.contains(PageTableFlags::WRITABLE) {
+            flags |= PF_W;
+        }
+        if !area.flags.contains(PageTableFlags::NO_EXECUTE) {
+            flags |= PF_X;
+        }
+    }
+    flags
+}
+
+/// Read the page at `page` of `area` into `buf` as the process would see it.
+///
+/// Pages never faulted in read as zero, or as the file contents for file mappings.
+/// The address space is only locked while the page is copied.
+fn read_page(
+    addr_space: &Spinlock<AddrSpace<ArchPageTable>>,
+    area: &VmArea,
+    page: VirtAddr,
+    buf: &mut [u8],
+) {
+    {
+        let addr_space = addr_space.lock();
+        if let Some(phys) = addr_space.page_table().translate(page) {
+            let src = (phys.as_u64() + crate::mm::hhdm_offset()) as *const u8;
+            // SAFETY: `phys` is a mapped, page-aligned user frame reachable through the HHDM,
+            // and stays mapped while the address space is locked.
+            unsafe { core::ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len()) };
+            return;
+        }
+    }
+
+    buf.fill(0);
+    if let VmAreaKind::File {
+        file,
+        offset,
+        file_size,
+        ..
+    } = &area.kind
+    {
+        let file_offset = offset + (page - area.start) as usize;
+        let len = file_size.saturating_sub(file_offset).min(buf.len());
+        let _ = file.read(file_offset, &mut buf[..len]);
+    }
+}
+
+/// Appends to the core file, stopping at `RLIMIT_CORE`.
+struct CoreWriter {
+    file: Arc<File>,
+    written: u64,
+    limit: u64,
+}
+
+impl CoreWriter {
+    /// Append `buf`. Returns `false` once the limit is reached or a write fails.
+    fn write(&mut self, buf: &[u8]) -> bool {
+        let room = self.limit.saturating_sub(self.written);
+        let len = buf.len().min(usize::try_from(room).unwrap_or(usize::MAX));
+        let mut done = 0;
+        while done < len {
+            match self.file.write(&buf[done..len]) {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => done += n,
+            }
+        }
+        self.written += len as u64;
+        len == buf.len()
+    }
+
+    /// Zero-fill up to file offset `offset`.
+    fn pad_to(&mut self, offset: u64) -> bool {
+        const ZEROES: [u8; 512] = [0; 512];
+        while self.written < offset {
+            let len = (offset - self.written).min(ZEROES.len() as u64) as usize;
+            if !self.write(&ZEROES[..len]) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Create the core file named by `core_pattern`, replacing any existing one.
+///
+/// Like Linux, an existing file is only reused if the dumping user owns it and it has no
+/// other links.
+fn create_core_file(core: &CoreProcess, sig: u8) -> Result<(Arc<File>, String), VfsError> {
+    let pattern = CORE_PATTERN.lock().clone();
+    if pattern.starts_with('|') {
+        // Piping the dump to a helper program is not supported.
+        return Err(VfsError::NotSupported);
+    }
+    let name = expand_pattern(&pattern, core, sig);
+    if name.is_empty() {
+        return Err(VfsError::InvalidInput);
+    }
+
+    // Resolve against the saved cwd: relative lookups would take the process lock.
+    let path = crate::fs::normalize_path(&core.cwd, &name);
+    match crate::fs::stat(&path) {
+        Ok(stat) if stat.uid != core.uid || stat.nlink > 1 => {
+            return Err(VfsError::PermissionDenied);
+        }
+        Ok(_) | Err(VfsError::NotFound) => {}
+        Err(err) => return Err(err),
+    }
+    let file = crate::fs::open_file(&path, O_WRONLY | O_CREAT)?;
+    if file.dentry.inode.inode_type != InodeType::File {
+        return Err(VfsError::PermissionDenied);
+    }
+    file.ops.truncate(0)?;
+    match file.dentry.inode.ops.chmod(0o600) {
+        Ok(()) | Err(VfsError::NotSupported) => {}
+        Err(err) => return Err(err),
+    }
+    Ok((file, path))
+}
+
+/// Write a core dump of `core`, which is being killed by `sig` raised in `thread`.
+///
+/// Only the crashing thread's registers are recorded. Must be called without the process
+/// or thread locks held, since writing the file may sleep. Returns whether a core file was
+/// written, which `wait` reports as `WCOREDUMP`.
+pub fn dump_core(core: &CoreProcess, thread: &CrashedThread, sig: u8) -> bool {
+    let (file, path) = match create_core_file(core, sig) {
+        Ok(created) => created,
+        Err(err) => {
+            log::warn!("PID {}: cannot create core file: {:?}", core.pid, err);
+            return false;
+        }
+    };
+
+    let notes = build_notes(core, thread, sig);
+    let areas = &core.areas;
+
+    // Layout: ELF header, program headers, notes, then page-aligned segment contents
+    let phnum = 1 + areas.len();
+    let notes_offset = (size_of::<Elf64Header>() + phnum * size_of::<Elf64Phdr>()) as u64;
+    let mut data_offset = (notes_offset + notes.len() as u64).next_multiple_of(PAGE_SIZE);
+
+    let mut ident = [0u8; 16];
+    ident[..4].copy_from_slice(&ELF_MAGIC);
+    ident[4] = ELF_CLASS_64;
+    ident[5] = ELF_DATA_2LSB;
+    ident[6] = 1; // EV_CURRENT
+    let header = Elf64Header {
+        e_ident: ident,
+        e_type: ET_CORE,
+        e_machine: EM_X86_64,
+        e_version: 1,
+        e_entry: 0,
+        e_phoff: size_of::<Elf64Header>() as u64,
+        e_shoff: 0,
+        e_flags: 0,
+        e_ehsize: size_of::<Elf64Header>() as u16,
+        e_phentsize: size_of::<Elf64Phdr>() as u16,
+        e_phnum: phnum as u16,
+        e_shentsize: 0,
+        e_shnum: 0,
+        e_shstrndx: 0,
+    };
+
+    let mut phdrs = Vec::with_capacity(phnum);
+    phdrs.push(Elf64Phdr {
+        p_type: PT_NOTE,
+        p_flags: 0,
+        p_offset: notes_offset,
+        p_vaddr: 0,
+        p_paddr: 0,
+        p_filesz: notes.len() as u64,
+        p_memsz: 0,
+        p_align: 4,
+    });
+    for area in areas {
+        let size = area.end - area.start;
+        let filesz = if should_dump(area) { size } else { 0 };
+        phdrs.push(Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags: segment_flags(area),
+            p_offset: data_offset,
+            p_vaddr: area.start.as_u64(),
+            p_paddr: 0,
+            p_filesz: filesz,
+            p_memsz: size,
+            p_align: PAGE_SIZE,
+        });
+        data_offset += filesz;
+    }
+
+    let mut writer = CoreWriter {
+        file,
+        written: 0,
+        limit: core.limit,
+    };
+    let mut complete = writer.write(as_bytes(&header))
+        && phdrs.iter().all(|phdr| writer.write(as_bytes(phdr)))
+        && writer.write(&notes);
+
+    let mut page = alloc::vec![0u8; PAGE_SIZE as usize];
+    for (area, phdr) in areas.iter().zip(&phdrs[1..]) {
+        if !complete {
+            break;
+        }
+        if phdr.p_filesz == 0 {
+            continue;
+        }
+        complete = writer.pad_to(phdr.p_offset);
+        let mut addr = area.start;
+        while complete && addr < area.end {
+            read_page(&core.address_space, area, addr, &mut page);
+            complete = writer.write(&page);
+            addr += PAGE_SIZE;
+        }
+    }
+
+    log::info!(
+        "PID {} ({}) dumped core to {} ({} bytes{})",
+        core.pid,
+        core.comm(),
+        path,
+        writer.written,
+        if complete { "" } else { ", truncated" }
+    );
+    true
+}
+
+/// Render `/proc/sys/kernel/core_pattern`.
+fn show_core_pattern() -> String {
+    let mut pattern = CORE_PATTERN.lock().clone();
+    pattern.push('\n');
+    pattern
+}
+
+/// Apply a write to `/proc/sys/kernel/core_pattern`.
+fn store_core_pattern(value: &str, euid: u32) -> Result<(), VfsError> {
+    crate::fs::procfs::require_root(euid)?;
+    if value.len() > CORE_PATTERN_MAX {
+        return Err(VfsError::InvalidInput);
+    }
+    *CORE_PATTERN.lock() = value.into();
+    Ok(())
+}
+
+/// Set the default pattern and register `/proc/sys/kernel/core_pattern`.
+pub fn init() -> Result<(), &'static str> {
+    *CORE_PATTERN.lock() = DEFAULT_CORE_PATTERN.into();
+    crate::fs::procfs::register_proc_entry(
+        "sys/kernel/core_pattern",
+        show_core_pattern,
+        Some(store_core_pattern),
+    );
+    Ok(())
+}
+
+crate::fs_initcall!(init);
@@ -0,0 +1,165 @@
+//! Kernel threads.
+//!
+//! A kernel thread runs a closure on its own kernel stack, on the kernel page tables and
+//! outside of any process. [`kthread_create`] sets one up without running it, so it can be
+//! bound to a CPU first, and [`KThread::wake`] starts it; [`kthread_run`] does both.
+//!
+//! [`kthread_stop`] asks a kernel thread to return and waits until it has. Threads that
+//! run until stopped check [`kthread_should_stop`] whenever they wake up.
+
+use super::thread::{Thread, ThreadState};
+use crate::sync::spinlock::Spinlock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+type KThreadFn = Box<dyn FnOnce() -> i32 + Send>;
+
+/// Stop request and exit status shared by a kernel thread and its [`KThread`] handle.
+pub struct KThreadControl {
+    should_stop: AtomicBool,
+    exit: Spinlock<KThreadExit>,
+}
+
+struct KThreadExit {
+    /// Return value of the closure, `None` until the thread returned.
+    code: Option<Option<i32>>,
+    /// Thread waiting in `kthread_stop`.
+    stopper: Option<Arc<Spinlock<Thread>>>,
+}
+
+/// What a new kernel thread starts with, handed over through its entry argument.
+struct KThreadStart {
+    func: KThreadFn,
+    control: Arc<KThreadControl>,
+}
+
+/// Handle to a kernel thread created by [`kthread_create`].
+pub struct KThread {
+    thread: Arc<Spinlock<Thread>>,
+    control: Arc<KThreadControl>,
+}
+
+extern "C" fn kthread_entry(arg: *mut u8) {
+    // SAFETY: `arg` is the box leaked by `kthread_create`, which only this thread takes.
+    let start = unsafe { Box::from_raw(arg as *mut KThreadStart) };
+    let KThreadStart { func, control } = *start;
+    // A thread stopped before it first ran never calls its closure.
+    let code = (!control.should_stop.load(Ordering::Acquire)).then(func);
+
+    let stopper = crate::arch::without_interrupts(|| {
+        let mut exit = control.exit.lock();
+        exit.code = Some(code);
+        exit.stopper.take()
+    });
+    if let Some(stopper) = stopper {
+        Thread::unblock(stopper);
+    }
+}
+
+/// Create a kernel thread named `name` that runs `func`, without starting it.
+pub fn kthread_create<F>(name: &str, func: F) -> KThread
+where
+    F: FnOnce() -> i32 + Send + 'static,
+{
+    let control = Arc::new(KThreadControl {
+        should_stop: AtomicBool::new(false),
+        exit: Spinlock::new(KThreadExit {
+            code: None,
+            stopper: None,
+        }),
+    });
+    let start = Box::new(KThreadStart {
+        func: Box::new(func),
+        control: control.clone(),
+    });
+    let mut thread = Thread::new_kernel(name, kthread_entry, Box::into_raw(start) as *mut u8);
+    thread.kthread = Some(control.clone());
+    KThread {
+        thread: Arc::new(Spinlock::new(thread)),
+        control,
+    }
+}
+
+/// Create a kernel thread named `name` that runs `func`, and start it.
+pub fn kthread_run<F>(name: &str, func: F) -> KThread
+where
+    F: FnOnce() -> i32 + Send + 'static,
+{
+    let kthread = kthread_create(name, func);
+    kthread.wake();
+    kthread
+}
+
+impl KThread {
+    /// The thread itself.
+    pub fn thread(&self) -> &Arc<Spinlock<Thread>> {
+        &self.thread
+    }
+
+    /// Keep the thread on `cpu`. Only takes effect before the thread is started.
+    pub fn bind(&self, cpu: u32) {
+        let mut t_lock = self.thread.lock();
+        if t_lock.state == ThreadState::Creating {
+            t_lock.cpus_allowed = 1 << cpu;
+            t_lock.cpu = cpu;
+        }
+    }
+
+    /// Start the thread if it was not started yet, or wake it if it sleeps.
+    pub fn wake(&self) {
+        let mut t_lock = self.thread.lock();
+        match t_lock.state {
+            ThreadState::Creating => {
+                t_lock.state = ThreadState::Ready;
+                drop(t_lock);
+                crate::arch::without_interrupts(|| {
+                    crate::sched::SCHEDULER.lock().add_thread(self.thread.clone());
+                });
+            }
+            ThreadState::Sleeping => {
+                drop(t_lock);
+                Thread::unblock(self.thread.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ask `kthread` to stop, wake it, and wait until it returns.
+///
+/// Returns the value its closure returned, or `None` if it was stopped before it ran.
+/// Must not be called by the thread itself.
+pub fn kthread_stop(kthread: KThread) -> Option<i32> {
+    kthread.control.should_stop.store(true, Ordering::Release);
+    kthread.wake();
+
+    let current = crate::proc::current_thread();
+    loop {
+        let code = crate::arch::without_interrupts(|| {
+            let mut exit = kthread.control.exit.lock();
+            if exit.code.is_none()
+                && let Some(current) = &current
+            {
+                current.lock().state = ThreadState::Sleeping;
+                exit.stopper = Some(current.clone());
+            }
+            exit.code
+        });
+        if let Some(code) = code {
+            return code;
+        }
+        match current {
+            Some(_) => crate::sched::schedule(false),
+            // Nothing to sleep on during early boot.
+            None => crate::arch::cpu_relax(),
+        }
+    }
+}
+
+/// Whether [`kthread_stop`] was called for the calling kernel thread.
+pub fn kthread_should_stop() -> bool {
+    crate::proc::current_thread()
+        .and_then(|thread| thread.lock().kthread.clone())
+        .is_some_and(|control| control.should_stop.load(Ordering::Acquire))
+}
@@ -1,7 +1,10 @@
+use super::ExecError;
+use super::file::ExecFile;
 use super::header::*;
 use crate::mm::ArchPageTable;
 use crate::mm::PageTable;
-use crate::mm::{AddrSpace, VmAreaKind};
+use crate::mm::aslr::UserLayout;
+use crate::mm::{AddrSpace, VmAreaKind, VmFlags};
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::VirtAddr;
 
@@ -10,12 +13,36 @@ pub struct LoadedElf {
     pub entry_point: VirtAddr,
     pub stack_pointer: VirtAddr,
     pub addr_space: AddrSpace<ArchPageTable>,
+    /// Initial program break, just past the main executable's image.
+    pub brk_start: VirtAddr,
+    /// Auxiliary vector passed to the image, without the terminating `AT_NULL`.
+    pub auxv: alloc::vec::Vec<(u64, u64)>,
+}
+
+/// Facts about the exec reported to the new image through the auxiliary vector.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecInfo<'a> {
+    /// Path passed to `execve` (`AT_EXECFN`); argv[0] is used when empty.
+    pub filename: &'a str,
+    pub uid: u32,
+    pub euid: u32,
+    pub gid: u32,
+    pub egid: u32,
+}
+
+impl ExecInfo<'_> {
+    /// Whether the image runs with different privileges than its invoker (`AT_SECURE`).
+    fn secure(&self) -> bool {
+        self.uid != self.euid || self.gid != self.egid
+    }
 }
 
 /// Object-Oriented ELF Parser and Loader.
 pub struct Elf<'a> {
     data: &'a [u8],
     header: &'a Elf64Header,
+    /// File the segments are demand-paged from; without one they are copied from `data`.
+    file: Option<&'a ExecFile>,
 }
 
 impl<'a> Elf<'a> {
@@ -28,11 +55,44 @@ impl<'a> Elf<'a> {
         // SAFETY: We validated that the slice is long enough to contain the Elf64Header.
         let header = unsafe { &*(data.as_ptr() as *const Elf64Header) };
 
-        let elf = Self { data, header };
+        let elf = Self {
+            data,
+            header,
+            file: None,
+        };
         elf.validate()?;
         Ok(elf)
     }
 
+    /// Parse the headers of `file`, mapping its segments from the file when loaded.
+    pub fn from_file(file: &'a ExecFile) -> Result<Self, &'static str> {
+        let mut elf = Self::new(file.head())?;
+        elf.file = Some(file);
+        Ok(elf)
+    }
+
+    /// Bytes from the start of the file that must be in memory to load it: the ELF
+    /// header, the program headers and the `PT_INTERP` path.
+    ///
+    /// If the program headers lie beyond `data`, only their end is known yet.
+    pub fn headers_end(&self) -> Result<usize, &'static str> {
+        let ph_size = self.header.e_phnum as usize * self.header.e_phentsize as usize;
+        let ph_end = (self.header.e_phoff as usize)
+            .checked_add(ph_size)
+            .ok_or("Program headers out of bounds")?;
+        if ph_end > self.data.len() {
+            return Ok(ph_end);
+        }
+        let interp_end = self
+            .program_headers()?
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_INTERP)
+            .map(|phdr| phdr.p_offset.saturating_add(phdr.p_filesz) as usize)
+            .max()
+            .unwrap_or(0);
+        Ok(ph_end.max(interp_end).max(core::mem::size_of::<Elf64Header>()))
+    }
+
     /// Retrieve the entry point virtual address from the ELF header.
     pub fn entry_point(&self) -> VirtAddr {
         VirtAddr::new(self.header.e_entry)
@@ -163,23 +223,47 @@ impl<'a> Elf<'a> {
         Ok(None)
     }
 
+    /// Largest `p_align` among the PT_LOAD segments, at least one page.
+    fn load_align(&self) -> Result<u64, &'static str> {
+        let align = self
+            .program_headers()?
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_align.is_power_of_two())
+            .map(|phdr| phdr.p_align)
+            .max()
+            .unwrap_or(4096);
+        Ok(align.max(4096))
+    }
+
     /// Maps the loadable segments, creates the user address space, allocates a user stack,
     /// sets up System V AMD64 ABI argc/argv/envp/auxv parameters, and returns loaded image information.
+    ///
+    /// `layout` supplies the (possibly randomized) stack top, interpreter base and PIE bias,
+    /// `exec` the values reported in the auxiliary vector.
     pub fn load_with_cmdline(
         &self,
         cmdline: Option<&crate::proc::process::CommandLine>,
-    ) -> Result<LoadedElf, &'static str> {
-        let page_table = ArchPageTable::new().map_err(|_| "Failed to create PML4 page table")?;
+        layout: &UserLayout,
+        exec: &ExecInfo,
+    ) -> Result<LoadedElf, ExecError> {
+        let page_table = ArchPageTable::new()
+            .map_err(|_| ExecError::NoMemory("Failed to create PML4 page table"))?;
         let mut addr_space = AddrSpace::new(page_table);
 
-        self.load_segments(&mut addr_space, 0)?;
+        // Position-independent executables are linked at 0 and need a load bias.
+        let load_bias = if self.header.e_type == ET_DYN {
+            layout.pie_base & !(self.load_align()? - 1)
+        } else {
+            0
+        };
+        let image_end = self.load_segments(&mut addr_space, load_bias)?;
 
-        let mut entry_point = self.entry_point();
+        let mut entry_point = self.entry_point() + load_bias;
         let mut at_base = 0;
 
         if let Some(interp_path) = self.interpreter_path()? {
-            let interp_bytes = match crate::fs::read_file(interp_path) {
-                Ok(data) => data,
+            let interp_dentry = match crate::fs::resolve_path(interp_path) {
+                Ok(dentry) => dentry,
                 Err(_) => {
                     let alt_path = if interp_path.starts_with("/usr/lib/") {
                         alloc::format!("/lib/{}", &interp_path[9..])
@@ -188,16 +272,16 @@ impl<'a> Elf<'a> {
                     } else {
                         alloc::string::String::from("/lib/ld.so")
                     };
-                    crate::fs::read_file(&alt_path)
-                        .map_err(|_| "Failed to read dynamic interpreter from VFS")?
+                    crate::fs::resolve_path(&alt_path)?
                 }
             };
 
-            let interp_elf = Elf::new(&interp_bytes)?;
-            const INTERP_BASE: u64 = 0x7F00_0000_0000;
-            interp_elf.load_segments(&mut addr_space, INTERP_BASE)?;
-            entry_point = VirtAddr::new(INTERP_BASE + interp_elf.entry_point().as_u64());
-            at_base = INTERP_BASE;
+            let interp_file = ExecFile::open(&interp_dentry)?;
+            let interp_elf = Elf::from_file(&interp_file)?;
+            let interp_base = layout.interp_base & !(interp_elf.load_align()? - 1);
+            interp_elf.load_segments(&mut addr_space, interp_base)?;
+            entry_point = VirtAddr::new(interp_base + interp_elf.entry_point().as_u64());
+            at_base = interp_base;
         }
 
         let phdr_addr = {
@@ -210,7 +294,7 @@ impl<'a> Elf<'a> {
                 }
             }
             if let Some(a) = addr {
-                a
+                load_bias + a
             } else {
                 let mut load_base = 0x400000;
                 for phdr in ph_slice {
@@ -219,27 +303,37 @@ impl<'a> Elf<'a> {
                         break;
                     }
                 }
-                load_base + self.header.e_phoff
+                load_bias + load_base + self.header.e_phoff
             }
         };
 
-        let auxv = alloc::vec![
+        let vdso = crate::arch::vdso::map(&mut addr_space, VirtAddr::new(layout.vdso_base))
+            .map_err(ExecError::NoMemory)?;
+
+        let mut auxv = alloc::vec![
+            (AT_HWCAP, crate::arch::cpu::features::hwcap()),
+            (AT_PAGESZ, 4096),
+            (AT_CLKTCK, 100),
             (AT_PHDR, phdr_addr),
             (AT_PHENT, self.header.e_phentsize as u64),
             (AT_PHNUM, self.header.e_phnum as u64),
-            (AT_PAGESZ, 4096),
             (AT_BASE, at_base),
             (AT_FLAGS, 0),
-            (AT_ENTRY, self.entry_point().as_u64()),
-            (AT_UID, 0),
-            (AT_EUID, 0),
-            (AT_GID, 0),
-            (AT_EGID, 0),
-            (AT_SECURE, 0),
+            (AT_ENTRY, self.entry_point().as_u64() + load_bias),
+            (AT_UID, exec.uid as u64),
+            (AT_EUID, exec.euid as u64),
+            (AT_GID, exec.gid as u64),
+            (AT_EGID, exec.egid as u64),
+            (AT_SECURE, exec.secure() as u64),
+            (AT_HWCAP2, crate::arch::cpu::features::hwcap2()),
+            (AT_MINSIGSTKSZ, crate::arch::signal::MINSIGSTKSZ),
         ];
+        if let Some(ehdr) = vdso {
+            auxv.push((AT_SYSINFO_EHDR, ehdr.as_u64()));
+        }
 
         let stack_size = 256 * 1024; // 256 KiB stack
-        let stack_top = VirtAddr::new(0x7FFF_FFFF_0000);
+        let stack_top = VirtAddr::new(layout.stack_top);
         let stack_start = stack_top - stack_size as u64;
         let stack_flags = PageTableFlags::PRESENT
             | PageTableFlags::WRITABLE
@@ -247,10 +341,10 @@ impl<'a> Elf<'a> {
 
         addr_space
             .map_area(stack_start, stack_size, stack_flags, VmAreaKind::Anonymous)
-            .map_err(|_| "Failed to map user stack VMA")?;
+            .map_err(|_| ExecError::NoMemory("Failed to map user stack VMA"))?;
 
         let initial_sp = if let Some(cmd) = cmdline {
-            Self::setup_user_stack(&mut addr_space, stack_top, cmd, &auxv)?
+            Self::setup_user_stack(&mut addr_space, stack_top, cmd, exec.filename, &mut auxv)?
         } else {
             stack_top
         };
@@ -259,27 +353,33 @@ impl<'a> Elf<'a> {
             entry_point,
             stack_pointer: initial_sp,
             addr_space,
+            brk_start: VirtAddr::new(layout.brk_start(image_end)),
+            auxv,
         })
     }
 
     /// Maps the loadable segments, creates the user address space, allocates a user stack,
     /// and returns the loaded image information.
-    pub fn load(&self) -> Result<LoadedElf, &'static str> {
-        self.load_with_cmdline(None)
+    pub fn load(&self) -> Result<LoadedElf, ExecError> {
+        self.load_with_cmdline(None, &UserLayout::new(false), &ExecInfo::default())
     }
 
     /// Setup the System V AMD64 ABI user stack frame with argc, argv, envp, auxv, and string tables.
+    ///
+    /// `auxv` is completed with the entries pointing into the string area: `AT_RANDOM`,
+    /// `AT_PLATFORM` and `AT_EXECFN` (`filename`, or argv[0] if it is empty).
     fn setup_user_stack(
         addr_space: &mut AddrSpace<ArchPageTable>,
         stack_top: VirtAddr,
         cmdline: &crate::proc::process::CommandLine,
-        auxv: &[(u64, u64)],
-    ) -> Result<VirtAddr, &'static str> {
+        filename: &str,
+        auxv: &mut alloc::vec::Vec<(u64, u64)>,
+    ) -> Result<VirtAddr, ExecError> {
         let hhdm = crate::mm::hhdm_offset();
         let mut cur_sp = stack_top.as_u64();
 
         // Helper closure to copy byte slice onto the stack at decremented SP
-        let mut write_user_bytes = |sp: &mut u64, bytes: &[u8]| -> Result<u64, &'static str> {
+        let mut write_user_bytes = |sp: &mut u64, bytes: &[u8]| -> Result<u64, ExecError> {
             *sp -= bytes.len() as u64;
             let target_vaddr = *sp;
 
@@ -293,7 +393,7 @@ impl<'a> Elf<'a> {
                 let phys = addr_space
                     .page_table()
                     .translate(VirtAddr::new(page_v))
-                    .ok_or("Failed to translate user stack page")?;
+                    .ok_or(ExecError::NoMemory("Failed to translate user stack page"))?;
 
                 unsafe {
                     let dest = ((phys.as_u64() + hhdm) as *mut u8).add(page_off);
@@ -304,11 +404,22 @@ impl<'a> Elf<'a> {
             Ok(target_vaddr)
         };
 
-        // 1. Push 16 bytes of random entropy for AT_RANDOM (stack canary)
-        let random_entropy = [
-            0x4b, 0x1f, 0x93, 0x7c, 0xa2, 0x5e, 0x08, 0xd4,
-            0x39, 0xf1, 0x60, 0xbb, 0x8d, 0x24, 0xee, 0x57,
-        ];
+        // 1. Push the execfn and platform strings, then 16 random bytes for AT_RANDOM
+        // (used by libc for the stack protector canary and pointer mangling)
+        let execfn = if filename.is_empty() {
+            cmdline.args.first().map_or("", |arg| arg.as_str())
+        } else {
+            filename
+        };
+        let mut execfn_bytes = alloc::vec::Vec::with_capacity(execfn.len() + 1);
+        execfn_bytes.extend_from_slice(execfn.as_bytes());
+        execfn_bytes.push(0);
+        let execfn_vaddr = write_user_bytes(&mut cur_sp, &execfn_bytes)?;
+        let platform_vaddr = write_user_bytes(&mut cur_sp, b"x86_64\0")?;
+
+        let mut random_entropy = [0u8; 16];
+        random_entropy[..8].copy_from_slice(&crate::arch::rdtsc::get_random_u64().to_ne_bytes());
+        random_entropy[8..].copy_from_slice(&crate::arch::rdtsc::get_random_u64().to_ne_bytes());
         let random_vaddr = write_user_bytes(&mut cur_sp, &random_entropy)?;
 
         // 2. Push environment strings (null-terminated)
@@ -331,22 +442,17 @@ impl<'a> Elf<'a> {
             arg_ptrs.push(str_vaddr);
         }
 
-        let execfn_vaddr = arg_ptrs.first().copied().unwrap_or(0);
-
         // 4. Align cur_sp to 8 bytes
         cur_sp &= !7;
 
-        // Build complete auxiliary vector table including dynamic entries
-        let mut full_auxv = alloc::vec::Vec::with_capacity(auxv.len() + 3);
-        full_auxv.extend_from_slice(auxv);
-        full_auxv.push((AT_RANDOM, random_vaddr));
-        if execfn_vaddr != 0 {
-            full_auxv.push((AT_EXECFN, execfn_vaddr));
-        }
+        // Complete the auxiliary vector with the entries pointing into the string area
+        auxv.push((AT_RANDOM, random_vaddr));
+        auxv.push((AT_PLATFORM, platform_vaddr));
+        auxv.push((AT_EXECFN, execfn_vaddr));
 
         // Calculate total table entries:
         // argc (1) + argv pointers (N) + NULL (1) + envp pointers (M) + NULL (1) + auxv (K * 2) + AT_NULL (2)
-        let total_entries = 1 + arg_ptrs.len() + 1 + env_ptrs.len() + 1 + full_auxv.len() * 2 + 2;
+        let total_entries = 1 + arg_ptrs.len() + 1 + env_ptrs.len() + 1 + auxv.len() * 2 + 2;
         let total_table_bytes = total_entries * 8;
 
         // System V AMD64 ABI requires RSP to be 16-byte aligned at process entry
@@ -355,7 +461,7 @@ impl<'a> Elf<'a> {
         }
 
         // Helper to push a single u64 value
-        let mut write_u64 = |sp: &mut u64, val: u64| -> Result<(), &'static str> {
+        let mut write_u64 = |sp: &mut u64, val: u64| -> Result<(), ExecError> {
             let bytes = val.to_ne_bytes();
             write_user_bytes(sp, &bytes)?;
             Ok(())
@@ -366,7 +472,7 @@ impl<'a> Elf<'a> {
         write_u64(&mut cur_sp, 0)?; // AT_NULL a_type
 
         // 6. Push auxiliary vectors
-        for &(key, val) in full_auxv.iter().rev() {
+        for &(key, val) in auxv.iter().rev() {
             write_u64(&mut cur_sp, val)?;
             write_u64(&mut cur_sp, key)?;
         }
@@ -410,27 +516,35 @@ impl<'a> Elf<'a> {
     }
 
     /// Load and map all PT_LOAD segments with an optional base virtual offset.
+    ///
+    /// Returns the end address of the highest segment.
     pub fn load_segments(
         &self,
         addr_space: &mut AddrSpace<ArchPageTable>,
         base_offset: u64,
-    ) -> Result<(), &'static str> {
+    ) -> Result<u64, ExecError> {
         let ph_slice = self.program_headers()?;
+        let mut image_end = 0;
         for phdr in ph_slice {
             if phdr.p_type == PT_LOAD {
                 self.load_segment(addr_space, phdr, base_offset)?;
+                image_end = image_end.max(base_offset + phdr.p_vaddr + phdr.p_memsz);
             }
         }
-        Ok(())
+        Ok(image_end)
     }
 
-    /// Map a single program segment and copy file data to allocated physical frames.
+    /// Map a single program segment.
+    ///
+    /// Segments of an [`ExecFile`] are mapped lazily: the file-backed pages from the file,
+    /// the rest of the `.bss` as anonymous memory. Otherwise the file data is copied into
+    /// freshly allocated frames.
     fn load_segment(
         &self,
         addr_space: &mut AddrSpace<ArchPageTable>,
         phdr: &Elf64Phdr,
         base_offset: u64,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), ExecError> {
         let vaddr = base_offset + phdr.p_vaddr;
         let start_vaddr = VirtAddr::new(vaddr);
         let end_vaddr = start_vaddr + phdr.p_memsz;
@@ -451,16 +565,20 @@ impl<'a> Elf<'a> {
             flags |= PageTableFlags::NO_EXECUTE;
         }
 
+        if let Some(file) = self.file {
+            return Self::map_segment(addr_space, file, phdr, vaddr, aligned_end, flags);
+        }
+
         addr_space
             .map_area(aligned_start, aligned_size, flags, VmAreaKind::Anonymous)
-            .map_err(|_| "Failed to map ELF segment VMA")?;
+            .map_err(|_| ExecError::NoMemory("Failed to map ELF segment VMA"))?;
 
         let file_offset = phdr.p_offset as usize;
         let file_size = phdr.p_filesz as usize;
 
         if file_size > 0 {
             if file_offset + file_size > self.data.len() {
-                return Err("ELF segment file offset out of bounds");
+                return Err(ExecError::BadFormat("ELF segment file offset out of bounds"));
             }
 
             let hhdm = crate::mm::hhdm_offset();
@@ -469,7 +587,7 @@ impl<'a> Elf<'a> {
                 let phys_addr = addr_space
                     .page_table()
                     .translate(page_virt)
-                    .ok_or("Failed to translate user virtual page to physical page")?;
+                    .ok_or(ExecError::NoMemory("Failed to translate ELF segment page"))?;
 
                 let page_start = page_virt_u64;
                 let page_end = page_start + 4096;
@@ -501,4 +619,66 @@ impl<'a> Elf<'a> {
 
         Ok(())
     }
+
+    /// Map the segment `phdr`, loaded at `vaddr`, as demand-paged VMAs up to `aligned_end`.
+    ///
+    /// Pages holding file data are read from `file`. Bytes past `p_filesz` in the last of
+    /// them, and all pages after it, are zero-filled.
+    fn map_segment(
+        addr_space: &mut AddrSpace<ArchPageTable>,
+        file: &ExecFile,
+        phdr: &Elf64Phdr,
+        vaddr: u64,
+        aligned_end: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), ExecError> {
+        let page_offset = vaddr & 4095;
+        if phdr.p_offset & 4095 != page_offset {
+            return Err(ExecError::BadFormat("ELF segment not page-aligned in the file"));
+        }
+        let data_end = phdr
+            .p_offset
+            .checked_add(phdr.p_filesz)
+            .filter(|&end| end <= file.size() as u64)
+            .ok_or(ExecError::BadFormat("ELF segment file offset out of bounds"))?;
+        if phdr.p_filesz > phdr.p_memsz {
+            return Err(ExecError::BadFormat("ELF segment file size exceeds memory size"));
+        }
+
+        let aligned_start = VirtAddr::new(vaddr - page_offset);
+        let file_end = if phdr.p_filesz > 0 {
+            VirtAddr::new(vaddr + phdr.p_filesz).align_up(4096u64)
+        } else {
+            aligned_start
+        };
+        if file_end > aligned_start {
+            addr_space
+                .reserve_area(
+                    aligned_start,
+                    (file_end - aligned_start) as usize,
+                    flags,
+                    VmAreaKind::File {
+                        file: file.ops().clone(),
+                        offset: (phdr.p_offset - page_offset) as usize,
+                        file_size: data_end as usize,
+                        path: file.path().clone(),
+                    },
+                    VmFlags::empty(),
+                )
+                .map_err(|_| ExecError::NoMemory("Failed to map ELF segment VMA"))?;
+        }
+
+        if aligned_end > file_end {
+            addr_space
+                .reserve_area(
+                    file_end,
+                    (aligned_end - file_end) as usize,
+                    flags,
+                    VmAreaKind::Anonymous,
+                    VmFlags::empty(),
+                )
+                .map_err(|_| ExecError::NoMemory("Failed to map ELF bss VMA"))?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,89 @@
+//! Executable files opened for loading.
+
+use super::ExecError;
+use super::elf::Elf;
+use crate::fs::{Dentry, FileOps};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Bytes read up front: a `#!` line, the ELF header and, in practice, the program headers
+/// and interpreter path all fit.
+const HEAD_SIZE: usize = 4096;
+
+/// Most header bytes an ELF file may make the loader hold in memory.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// An executable opened for `execve`.
+///
+/// Only the start of the file is kept in memory. Segments are mapped from the file and
+/// faulted in page by page, so the rest is never read unless the program touches it.
+pub struct ExecFile {
+    head: Vec<u8>,
+    ops: Arc<dyn FileOps>,
+    size: usize,
+    path: Arc<str>,
+}
+
+impl ExecFile {
+    /// Open the file behind `dentry` and read its headers.
+    pub fn open(dentry: &Dentry) -> Result<Self, ExecError> {
+        let size = dentry.inode.ops.stat()?.size as usize;
+        let ops = dentry.inode.ops.open()?;
+        let mut file = Self {
+            head: Vec::new(),
+            ops,
+            size,
+            path: crate::fs::build_path(dentry).into(),
+        };
+        file.read_head(size.min(HEAD_SIZE))?;
+
+        // ELF headers may reach past the first page; keep reading until they are in memory.
+        while let Ok(elf) = Elf::new(&file.head) {
+            let needed = elf.headers_end()?;
+            if needed <= file.head.len() {
+                break;
+            }
+            if needed > file.size.min(MAX_HEADERS_SIZE) {
+                return Err(ExecError::BadFormat("ELF headers out of bounds"));
+            }
+            file.read_head(needed)?;
+        }
+        Ok(file)
+    }
+
+    /// Replace the in-memory head with the first `len` bytes of the file.
+    fn read_head(&mut self, len: usize) -> Result<(), ExecError> {
+        let mut buf = alloc::vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            let n = self.ops.read(filled, &mut buf[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        buf.truncate(filled);
+        self.head = buf;
+        Ok(())
+    }
+
+    /// The first bytes of the file, covering at least its headers.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// I/O operations reading the whole file, for file-backed mappings.
+    pub fn ops(&self) -> &Arc<dyn FileOps> {
+        &self.ops
+    }
+
+    /// Size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Absolute path of the file, recorded in its mappings.
+    pub fn path(&self) -> &Arc<str> {
+        &self.path
+    }
+}
@@ -54,8 +54,20 @@ pub const EM_X86_64: u16 = 0x3E;
 pub const PT_LOAD: u32 = 1;
 pub const PT_INTERP: u32 = 3;
 pub const PT_PHDR: u32 = 6;
+pub const ET_CORE: u16 = 4;
+pub const PT_NOTE: u32 = 4;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
 pub const SHT_STRTAB: u32 = 3;
 
+// Core file note types
+pub const NT_PRSTATUS: u32 = 1;
+pub const NT_FPREGSET: u32 = 2;
+pub const NT_PRPSINFO: u32 = 3;
+pub const NT_AUXV: u32 = 6;
+pub const NT_FILE: u32 = 0x4649_4c45;
+
 // System V AMD64 ABI Auxiliary Vector Types (auxv)
 pub const AT_NULL: u64 = 0;
 pub const AT_IGNORE: u64 = 1;
@@ -81,4 +93,5 @@ pub const AT_RANDOM: u64 = 25;
 pub const AT_HWCAP2: u64 = 26;
 pub const AT_EXECFN: u64 = 31;
 pub const AT_SYSINFO_EHDR: u64 = 33;
+pub const AT_MINSIGSTKSZ: u64 = 51;
 
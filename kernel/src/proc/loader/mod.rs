@@ -1,5 +1,39 @@
 pub mod elf;
+pub mod file;
 pub mod header;
 
 pub use elf::{Elf, LoadedElf};
+pub use file::ExecFile;
 pub use header::{Elf64Header, Elf64Phdr, Elf64Shdr};
+
+use crate::fs::VfsError;
+
+/// Reasons an `execve` can fail, each mapping to the errno Linux reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// The file is not a valid executable or script (ENOEXEC).
+    BadFormat(&'static str),
+    /// Not a regular file, no execute permission, or on a `noexec` mount (EACCES).
+    AccessDenied,
+    /// The argument and environment strings exceed `ARG_MAX` (E2BIG).
+    ArgListTooLong,
+    /// `#!` interpreters nest deeper than the loader allows (ELOOP).
+    TooManyInterpreters,
+    /// Building the new address space ran out of memory (ENOMEM).
+    NoMemory(&'static str),
+    /// Looking up or reading the file failed.
+    Vfs(VfsError),
+}
+
+/// The ELF parser reports malformed input as plain messages.
+impl From<&'static str> for ExecError {
+    fn from(msg: &'static str) -> Self {
+        ExecError::BadFormat(msg)
+    }
+}
+
+impl From<VfsError> for ExecError {
+    fn from(err: VfsError) -> Self {
+        ExecError::Vfs(err)
+    }
+}
@@ -1,11 +1,15 @@
+pub mod coredump;
+pub mod kthread;
 pub mod loader;
 pub mod process;
 pub mod thread;
 
+pub use kthread::{kthread_create, kthread_run, kthread_should_stop, kthread_stop, KThread};
 pub use loader::elf::{Elf, LoadedElf};
 pub use process::{
-    find_process, find_processes_by_pgid, next_pid, register_process, unregister_process,
-    Process, ProcessId, ProcessState, ProcessTable, PROCESS_TABLE,
+    all_processes, find_process, find_processes_by_pgid, last_pid, next_pid, nr_threads,
+    register_process, unregister_process, Process, ProcessId, ProcessState, ProcessTable,
+    PROCESS_TABLE,
 };
 pub use thread::{thread_exit, Thread, ThreadContext, ThreadId, ThreadState};
 
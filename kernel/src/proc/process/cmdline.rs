@@ -1,8 +1,35 @@
 //! Process Command-Line Arguments & Environment Management.
 
+use crate::syscalls::SyscallError;
+use crate::syscalls::uaccess::{UserPtr, strndup_user};
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::ffi::CStr;
+
+/// Longest single argument or environment string accepted by `execve`.
+pub const MAX_ARG_STRLEN: usize = 32 * 4096;
+/// Most argument or environment strings accepted by `execve`.
+pub const MAX_ARG_STRINGS: usize = 0x7FFF_FFFF;
+/// Largest combined size of the argument and environment strings and their pointers
+/// accepted by `execve`; half of the initial user stack.
+pub const ARG_MAX: usize = 128 * 1024;
+
+/// Copy a NULL-terminated array of user string pointers.
+fn copy_strings(array: UserPtr<u64>) -> Result<Vec<Vec<u8>>, SyscallError> {
+    let mut strings = Vec::new();
+    if array.is_null() {
+        return Ok(strings);
+    }
+    loop {
+        let ptr = array.add(strings.len()).read()?;
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        if strings.len() >= MAX_ARG_STRINGS {
+            return Err(SyscallError::E2BIG);
+        }
+        strings.push(strndup_user(ptr, MAX_ARG_STRLEN)?.ok_or(SyscallError::E2BIG)?);
+    }
+}
 
 /// Represents parsed command line arguments and environment variables for a process.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -31,58 +58,20 @@ impl CommandLine {
         }
     }
 
-    /// Safely construct a `CommandLine` from raw C pointers (`argc`, `argv`, `envp`).
+    /// Copy the NULL-terminated `argv` and `envp` string arrays out of user space.
     ///
-    /// # Safety
-    /// `argv` must point to an array of `argc` valid null-terminated C string pointers.
-    /// `envp` must point to a null-terminated array of null-terminated C string pointers (or null).
-    pub unsafe fn from_raw(
-        argc: usize,
-        argv: *const *const u8,
-        envp: *const *const u8,
-    ) -> Result<Self, &'static str> {
-        let mut args = Vec::new();
-        if !argv.is_null() && crate::syscalls::is_user_ptr_valid(argv as u64, 8) {
-            let mut i = 0;
-            loop {
-                if argc > 0 && i >= argc {
-                    break;
-                }
-                let ptr_addr = (argv as u64).wrapping_add((i * 8) as u64);
-                if !crate::syscalls::is_user_ptr_valid(ptr_addr, 8) {
-                    break;
-                }
-                let arg_ptr = unsafe { *(ptr_addr as *const *const u8) };
-                if arg_ptr.is_null() || !crate::syscalls::is_user_ptr_valid(arg_ptr as u64, 1) {
-                    break;
-                }
-                let c_str = unsafe { CStr::from_ptr(arg_ptr as *const i8) };
-                let str_slice = c_str.to_str().map_err(|_| "Invalid UTF-8 in argv")?;
-                args.push(String::from(str_slice));
-                i += 1;
-            }
-        }
-
-        let mut env = Vec::new();
-        if !envp.is_null() && crate::syscalls::is_user_ptr_valid(envp as u64, 8) {
-            let mut i = 0;
-            loop {
-                let ptr_addr = (envp as u64).wrapping_add((i * 8) as u64);
-                if !crate::syscalls::is_user_ptr_valid(ptr_addr, 8) {
-                    break;
-                }
-                let env_ptr = unsafe { *(ptr_addr as *const *const u8) };
-                if env_ptr.is_null() || !crate::syscalls::is_user_ptr_valid(env_ptr as u64, 1) {
-                    break;
-                }
-                let c_str = unsafe { CStr::from_ptr(env_ptr as *const i8) };
-                if let Ok(str_slice) = c_str.to_str() {
-                    env.push(String::from(str_slice));
-                }
-                i += 1;
-            }
-        }
-
+    /// A null array is treated as empty. Faults yield `EFAULT`, and strings longer than
+    /// `MAX_ARG_STRLEN` or arrays longer than `MAX_ARG_STRINGS` yield `E2BIG`.
+    pub fn from_user(argv: UserPtr<u64>, envp: UserPtr<u64>) -> Result<Self, SyscallError> {
+        let args = copy_strings(argv)?
+            .into_iter()
+            .map(|bytes| String::from_utf8(bytes).map_err(|_| SyscallError::EINVAL))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Environment entries that are not valid UTF-8 are dropped.
+        let env = copy_strings(envp)?
+            .into_iter()
+            .filter_map(|bytes| String::from_utf8(bytes).ok())
+            .collect();
         Ok(Self { args, env })
     }
 
@@ -91,6 +80,15 @@ impl CommandLine {
         self.args.len()
     }
 
+    /// Bytes the strings and their pointers occupy on the new image's stack.
+    pub fn exec_size(&self) -> usize {
+        self.args
+            .iter()
+            .chain(self.env.iter())
+            .map(|s| s.len() + 1 + core::mem::size_of::<u64>())
+            .sum()
+    }
+
     /// Returns slice of argument strings.
     pub fn argv(&self) -> &[String] {
         &self.args
@@ -85,9 +85,10 @@ pub fn create_init_process() -> Result<(Arc<Spinlock<Process>>, u64, u64), &'sta
                 .insert(init_tid, init_thread.clone());
             super::process_table::register_process(proc_arc.clone());
 
-            // Register as active thread on BSP CPU 0
+            // Register as the thread running on the boot CPU
             crate::arch::without_interrupts(|| {
-                crate::sched::SCHEDULER.lock().current_threads[0] = Some(init_thread);
+                let cpu_id = crate::arch::cpu_id();
+                crate::sched::SCHEDULER.lock().set_current(cpu_id, init_thread);
             });
 
             return Ok((proc_arc, entry_point, stack_top));
@@ -118,6 +119,11 @@ pub fn run_init_process() -> ! {
         .unwrap_or(0);
     drop(p_lock);
 
+    // Let the scheduler use the boot CPU. Interrupts stay off until `iretq` so that no
+    // tick switches away from the boot stack before init is entered.
+    crate::arch::disable_interrupts();
+    crate::sched::init_cpu(crate::arch::cpu_id());
+
     // Jump to user mode (Ring 3) with valid kernel stack top for TSS RSP0
     unsafe {
         jump_to_userspace(entry_point, stack_top, kernel_rsp0, cr3);
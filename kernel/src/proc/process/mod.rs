@@ -3,13 +3,15 @@ pub mod init_proc;
 pub mod pid;
 pub mod process;
 pub mod process_table;
+pub mod rlimit;
 
 pub use cmdline::CommandLine;
 pub use init_proc::{DEFAULT_INIT_EXEC_PATHS, create_init_process, run_init_process};
-pub use pid::{next_pid, ProcessId};
+pub use pid::{last_pid, next_pid, ProcessId};
 pub use process::{Process, ProcessState};
+pub use rlimit::RLimit64;
 pub use process_table::{
-    find_process, find_processes_by_pgid, register_process, unregister_process, ProcessTable,
-    PROCESS_TABLE,
+    all_processes, find_process, find_processes_by_pgid, nr_threads, register_process,
+    unregister_process, ProcessTable, PROCESS_TABLE,
 };
 
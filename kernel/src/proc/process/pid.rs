@@ -47,3 +47,8 @@ impl fmt::Display for ProcessId {
 pub fn next_pid() -> ProcessId {
     ProcessId::next()
 }
+
+/// Most recently allocated PID, 0 if none was allocated yet.
+pub fn last_pid() -> u64 {
+    NEXT_PID.load(Ordering::Relaxed) - 1
+}
@@ -1,18 +1,22 @@
-use super::cmdline::CommandLine;
+use super::cmdline::{ARG_MAX, CommandLine};
 use super::pid::{ProcessId, next_pid};
 use super::process_table::{register_process, unregister_process};
+use super::rlimit::{RLIM_NLIMITS, RLimit64, default_rlimits};
 use crate::arch::userspace;
-use crate::fs::FdTable;
+use crate::fs::{DenyWrite, Dentry, FdTable, InodeType, MOUNT_TABLE, MountFlags};
 use crate::ipc::signal::{MAX_SIGNALS, PendingSignals, SigAction};
 use crate::mm::ArchPageTable;
 use crate::mm::PageTable;
+use crate::mm::aslr::UserLayout;
+use crate::proc::loader::{ExecError, ExecFile};
+use crate::proc::loader::elf::{Elf, ExecInfo};
 use crate::mm::vmm::AddrSpace;
 use crate::proc::thread::{Thread, ThreadId, ThreadState};
 use crate::sync::spinlock::Spinlock;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
-use x86_64::VirtAddr;
-use x86_64::structures::paging::PageTableFlags;
+use alloc::vec::Vec;
 
 /// State of a process.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -88,6 +92,27 @@ pub struct Process {
 
     /// Next virtual address for mmap allocation
     pub mmap_bump: u64,
+
+    /// OOM killer badness adjustment in `[-1000, 1000]`; -1000 makes the process unkillable
+    pub oom_score_adj: i16,
+
+    /// Set once the OOM killer picked the process, so it is not picked again while dying
+    pub oom_victim: bool,
+
+    /// Execution domain flags set by `personality` (e.g. `ADDR_NO_RANDOMIZE`)
+    pub personality: u32,
+
+    /// Denies writes to the running executable (`ETXTBSY`) until exec or exit
+    pub exe_guard: Option<DenyWrite>,
+
+    /// Resource limits, indexed by `RLIMIT_*`
+    pub rlimits: [RLimit64; RLIM_NLIMITS],
+
+    /// Auxiliary vector the current image was started with
+    pub saved_auxv: Vec<(u64, u64)>,
+
+    /// Signal that killed the process and whether it dumped core, as reported by `wait`
+    pub term_signal: Option<(u8, bool)>,
 }
 
 impl Process {
@@ -126,31 +151,39 @@ impl Process {
             heap_start: userspace::USER_HEAP_VBASE,
             heap_brk: userspace::USER_HEAP_VBASE,
             mmap_bump: userspace::USER_MMAP_VBASE,
+            oom_score_adj: 0,
+            oom_victim: false,
+            personality: 0,
+            exe_guard: None,
+            rlimits: default_rlimits(),
+            saved_auxv: Vec::new(),
+            term_signal: None,
         }
     }
 
-    /// Execute an executable file with raw argument and environment pointers.
-    pub fn execute(
+    /// Execute an executable file with a structured `CommandLine` (argv + envp).
+    ///
+    /// A relative `file_name` is resolved against this process's working directory.
+    pub fn execute_cmdline(
         &mut self,
         file_name: &str,
-        argc: usize,
-        argv: *const *const u8,
-        envp: *const *const u8,
-    ) -> Result<(u64, u64), &'static str> {
-        let cmdline = if !argv.is_null() {
-            unsafe { CommandLine::from_raw(argc, argv, envp)? }
-        } else {
-            CommandLine::default()
-        };
-        self.execute_cmdline(file_name, cmdline)
+        cmdline: CommandLine,
+    ) -> Result<(u64, u64), ExecError> {
+        let dentry = crate::fs::resolve_path(&crate::fs::normalize_path(&self.cwd, file_name))?;
+        self.execute_file(dentry, file_name, cmdline)
     }
 
-    /// Execute an executable file with a structured `CommandLine` (argv + envp).
-    pub fn execute_cmdline(
+    /// Replace this process image with the program in `dentry`.
+    ///
+    /// `file_name` is the path the new image is told it was started as. `#!` scripts are
+    /// run through their interpreter. Nothing about the process changes unless the new
+    /// image loads successfully.
+    pub fn execute_file(
         &mut self,
+        dentry: Arc<Dentry>,
         file_name: &str,
-        cmdline: CommandLine,
-    ) -> Result<(u64, u64), &'static str> {
+        mut cmdline: CommandLine,
+    ) -> Result<(u64, u64), ExecError> {
         log::info!(
             "Executing process '{}' (PID {}) with {} arg(s) and {} env var(s)",
             file_name,
@@ -159,126 +192,118 @@ impl Process {
             cmdline.envp().len()
         );
 
-        let binary_data =
-            crate::fs::read_file(file_name).map_err(|_| "Failed to read binary from VFS")?;
-        if binary_data.is_empty() {
-            return Err("Executable file is empty");
-        }
+        // 1. Follow `#!` interpreters to the binary that actually runs
+        let mut dentry = dentry;
+        let mut script_name = String::from(file_name);
+        let mut depth = 0;
+        let exec_file = loop {
+            self.check_exec_access(&dentry)?;
+            let file = ExecFile::open(&dentry)?;
+            let Some((interpreter, optional_arg)) = parse_shebang(file.head())? else {
+                break file;
+            };
 
-        // 1. Shebang (`#!`) script interpreter support
-        if binary_data.starts_with(b"#!") {
-            let first_line_end = binary_data
-                .iter()
-                .position(|&b| b == b'\n')
-                .unwrap_or(binary_data.len());
-            if let Ok(line_str) = core::str::from_utf8(&binary_data[2..first_line_end]) {
-                let trimmed = line_str.trim();
-                let mut parts = trimmed.split_whitespace();
-                if let Some(interpreter) = parts.next() {
-                    let mut new_args = alloc::vec::Vec::new();
-                    new_args.push(alloc::string::String::from(interpreter));
-                    if let Some(arg) = parts.next() {
-                        new_args.push(alloc::string::String::from(arg));
-                    }
-                    new_args.push(alloc::string::String::from(file_name));
-                    if cmdline.argc() > 1 {
-                        for arg in &cmdline.args[1..] {
-                            new_args.push(arg.clone());
-                        }
-                    }
-                    let new_cmdline = CommandLine::new(new_args, cmdline.env.clone());
-                    return self.execute_cmdline(interpreter, new_cmdline);
-                }
+            depth += 1;
+            if depth > MAX_INTERP_DEPTH {
+                return Err(ExecError::TooManyInterpreters);
+            }
+
+            let mut new_args = Vec::with_capacity(cmdline.argc() + 2);
+            new_args.push(String::from(interpreter));
+            if let Some(arg) = optional_arg {
+                new_args.push(String::from(arg));
             }
+            new_args.push(script_name);
+            new_args.extend(cmdline.args.drain(..).skip(1));
+            cmdline.args = new_args;
+
+            script_name = String::from(interpreter);
+            dentry = crate::fs::resolve_path(&crate::fs::normalize_path(&self.cwd, interpreter))?;
+        };
+
+        // 2. Make sure the strings fit on the new stack
+        if cmdline.exec_size() > ARG_MAX {
+            return Err(ExecError::ArgListTooLong);
         }
 
-        // 2. Close-on-exec (FD_CLOEXEC) descriptor cleanup
+        // 3. Keep the binary, which is paged in on demand, from being written while it runs
+        let exe_guard = DenyWrite::new(&dentry.inode)?;
+
+        // 4. Build the new image in an address space of its own
+        let layout = UserLayout::new(crate::mm::aslr::randomize(self.personality));
+        let exec_info = ExecInfo {
+            filename: file_name,
+            uid: self.uid,
+            euid: self.euid,
+            gid: self.gid,
+            egid: self.egid,
+        };
+        let elf = Elf::from_file(&exec_file)?;
+        let loaded_elf = elf.load_with_cmdline(Some(&cmdline), &layout, &exec_info)?;
+
+        // 5. Point of no return: close FD_CLOEXEC descriptors
         self.fd_table.close_on_exec();
 
-        // 3. Reset non-ignored signals to default handlers
+        // 6. Reset non-ignored signals to default handlers
         for action in self.sig_actions.iter_mut() {
             if action.handler != crate::ipc::signal::SIG_IGN {
                 *action = Default::default();
             }
         }
 
-        // 4. Try loading as ELF binary
-        match crate::proc::loader::elf::Elf::new(&binary_data) {
-            Ok(elf) => match elf.load_with_cmdline(Some(&cmdline)) {
-                Ok(loaded_elf) => {
-                    self.address_space = Arc::new(Spinlock::new(loaded_elf.addr_space));
-                    self.cmdline = cmdline;
-                    self.heap_start = userspace::USER_HEAP_VBASE;
-                    self.heap_brk = userspace::USER_HEAP_VBASE;
-                    self.mmap_bump = userspace::USER_MMAP_VBASE;
-                    self.state = ProcessState::Running;
-                    return Ok((
-                        loaded_elf.entry_point.as_u64(),
-                        loaded_elf.stack_pointer.as_u64(),
-                    ));
-                }
-                Err(err) => {
-                    log::error!("[Process] ELF loading failed for '{}': {}", file_name, err);
-                }
-            },
-            Err(err) => {
-                log::error!("[Process] ELF parsing failed for '{}': {}", file_name, err);
-            }
+        // 7. Switch over to the new image, leaving the old page tables before they are freed
+        let new_root = loaded_elf.addr_space.page_table().root().as_u64();
+        let old_space = core::mem::replace(
+            &mut self.address_space,
+            Arc::new(Spinlock::new(loaded_elf.addr_space)),
+        );
+        if crate::arch::active_address_space_root()
+            == old_space.lock().page_table().root().as_u64()
+        {
+            // SAFETY: `new_root` is the PML4 of the fully loaded new image.
+            unsafe { crate::arch::set_address_space_root(new_root) };
         }
+        drop(old_space);
+        self.exe_guard = Some(exe_guard);
+        self.saved_auxv = loaded_elf.auxv;
+        self.cmdline = cmdline;
+        self.heap_start = loaded_elf.brk_start.as_u64();
+        self.heap_brk = loaded_elf.brk_start.as_u64();
+        self.mmap_bump = layout.mmap_base;
+        self.state = ProcessState::Running;
+        Ok((loaded_elf.entry_point.as_u64(), loaded_elf.stack_pointer.as_u64()))
+    }
 
-        // 5. Fallback for raw binary payloads
-        let mut addr_space_guard = self.address_space.lock();
-        let addr_space = &mut *addr_space_guard;
-
-        let code_vaddr = VirtAddr::new(userspace::USER_CODE_VBASE);
-        let code_flags =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-
-        addr_space
-            .map_area(
-                code_vaddr,
-                4096,
-                code_flags,
-                crate::mm::VmAreaKind::Anonymous,
-            )
-            .map_err(|_| "Failed to map user code VMA")?;
-
-        let code_phys = addr_space
-            .page_table()
-            .translate(code_vaddr)
-            .ok_or("Failed to translate user code virtual page")?;
-
-        let hhdm = crate::mm::hhdm_offset();
-        let code_ptr = (code_phys.as_u64() + hhdm) as *mut u8;
-        let copy_len = core::cmp::min(binary_data.len(), 4096);
-
-        // SAFETY: Copying binary content into physical frame.
-        unsafe {
-            core::ptr::copy_nonoverlapping(binary_data.as_ptr(), code_ptr, copy_len);
+    /// Check that `dentry` is a regular file this process may execute.
+    fn check_exec_access(&self, dentry: &Dentry) -> Result<(), ExecError> {
+        if dentry.inode.inode_type != InodeType::File {
+            return Err(ExecError::AccessDenied);
         }
 
-        let stack_vaddr = VirtAddr::new(userspace::USER_STACK_VTOP - 4096);
-        let stack_flags =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-
-        addr_space
-            .map_area(
-                stack_vaddr,
-                4096,
-                stack_flags,
-                crate::mm::VmAreaKind::Anonymous,
-            )
-            .map_err(|_| "Failed to map user stack VMA")?;
-
-        drop(addr_space_guard);
-
-        self.cmdline = cmdline;
-        self.heap_start = userspace::USER_HEAP_VBASE;
-        self.heap_brk = userspace::USER_HEAP_VBASE;
-        self.mmap_bump = userspace::USER_MMAP_VBASE;
-        self.state = ProcessState::Running;
+        let path = crate::fs::build_path(dentry);
+        let noexec = MOUNT_TABLE
+            .read()
+            .lookup(&path)
+            .is_some_and(|(mount, _)| mount.flags.contains(MountFlags::NOEXEC));
+        if noexec {
+            return Err(ExecError::AccessDenied);
+        }
 
-        Ok((userspace::USER_CODE_VBASE, userspace::USER_STACK_VTOP))
+        let stat = dentry.inode.ops.stat().map_err(|_| ExecError::AccessDenied)?;
+        let exec_bits = if self.euid == 0 {
+            // Root may run anything that is executable by someone.
+            0o111
+        } else if self.euid == stat.uid {
+            0o100
+        } else if self.egid == stat.gid {
+            0o010
+        } else {
+            0o001
+        };
+        if stat.mode & exec_bits == 0 {
+            return Err(ExecError::AccessDenied);
+        }
+        Ok(())
     }
 
     /// Fork a child process duplicating this process (POSIX fork).
@@ -315,6 +340,11 @@ impl Process {
         child_proc.heap_start = p_lock.heap_start;
         child_proc.heap_brk = p_lock.heap_brk;
         child_proc.mmap_bump = p_lock.mmap_bump;
+        child_proc.oom_score_adj = p_lock.oom_score_adj;
+        child_proc.personality = p_lock.personality;
+        child_proc.exe_guard = p_lock.exe_guard.clone();
+        child_proc.rlimits = p_lock.rlimits;
+        child_proc.saved_auxv = p_lock.saved_auxv.clone();
         child_proc.state = p_lock.state;
 
         let child = Arc::new(Spinlock::new(child_proc));
@@ -323,7 +353,7 @@ impl Process {
         let mut child_threads = BTreeMap::new();
         let child_tid = crate::proc::thread::next_tid();
 
-        let (thread_name, thread_weight, sig_mask, fs_base, gs_base) =
+        let (thread_name, thread_weight, sig_mask, fs_base, gs_base, cpus_allowed, sched, group) =
             if let Some(calling_thread) = crate::proc::current_thread() {
                 let t_lock = calling_thread.lock();
                 (
@@ -332,6 +362,9 @@ impl Process {
                     t_lock.sig_mask,
                     t_lock.context.fs_base,
                     t_lock.context.gs_base,
+                    t_lock.cpus_allowed,
+                    t_lock.child_sched_params(),
+                    t_lock.sched_group.clone(),
                 )
             } else if let Some((_, t_arc)) = p_lock.threads.iter().next() {
                 let t_lock = t_arc.lock();
@@ -341,9 +374,14 @@ impl Process {
                     t_lock.sig_mask,
                     t_lock.context.fs_base,
                     t_lock.context.gs_base,
+                    t_lock.cpus_allowed,
+                    t_lock.child_sched_params(),
+                    t_lock.sched_group.clone(),
                 )
             } else {
-                (alloc::string::String::from("fork_child"), 1024, 0, 0, 0)
+                let name = alloc::string::String::from("fork_child");
+                let sched = crate::proc::thread::thread::SchedParams::default();
+                (name, 1024, 0, 0, 0, crate::sched::CPU_MASK_ALL, sched, None)
             };
 
         let mut child_thread = Thread::new(
@@ -362,6 +400,9 @@ impl Process {
         child_thread.context.fs_base = fs_base;
         child_thread.context.gs_base = gs_base;
         child_thread.sig_mask = sig_mask;
+        child_thread.cpus_allowed = cpus_allowed;
+        child_thread.set_sched_params(sched);
+        child_thread.sched_group = group;
         child_thread.kernel_stack = Some(child_kstack);
         child_thread.state = ThreadState::Ready;
 
@@ -405,7 +446,7 @@ impl Process {
                 c_lock.pgid.as_u64() == (-pid_req) as u64
             };
             if matches {
-                matching_pids.push((child_pid, c_lock.state, c_lock.exit_code));
+                matching_pids.push((child_pid, c_lock.state, c_lock.wait_status()));
             }
         }
 
@@ -413,10 +454,8 @@ impl Process {
             return Err(crate::syscalls::SyscallError::ECHILD);
         }
 
-        for (child_pid, state, exit_code) in matching_pids {
+        for (child_pid, state, status) in matching_pids {
             if state == ProcessState::Zombie {
-                let code = exit_code.unwrap_or(0);
-                let status = (code & 0xFF) << 8;
                 self.children.remove(&child_pid);
                 unregister_process(child_pid);
                 return Ok(Some((child_pid, status)));
@@ -458,6 +497,23 @@ impl Process {
         }
     }
 
+    /// Status `wait` reports once the process is a zombie.
+    ///
+    /// A process killed by a signal reports the signal number, with `WCOREDUMP` (0x80)
+    /// set if it dumped core; otherwise the exit code is in bits 8-15.
+    pub fn wait_status(&self) -> i32 {
+        match self.term_signal {
+            Some((sig, core_dumped)) => sig as i32 | if core_dumped { 0x80 } else { 0 },
+            None => (self.exit_code.unwrap_or(0) & 0xFF) << 8,
+        }
+    }
+
+    /// Terminate the process because of the fatal signal `sig`.
+    pub fn exit_by_signal(&mut self, sig: u8, core_dumped: bool) {
+        self.term_signal = Some((sig, core_dumped));
+        self.exit(128 + sig as i32);
+    }
+
     /// Terminate the process.
     ///
     /// Marks the process zombie and removes all **queued** (non-current) threads from the
@@ -467,6 +523,7 @@ impl Process {
     pub fn exit(&mut self, status: i32) {
         self.state = ProcessState::Zombie;
         self.exit_code = Some(status);
+        self.exe_guard = None;
 
         crate::arch::without_interrupts(|| {
             // Determine the TID of the currently-running thread on this CPU.
@@ -484,8 +541,9 @@ impl Process {
                 t_lock.state = ThreadState::Zombie;
                 drop(t_lock);
                 if !is_current {
-                    // Thread is in the run queue (not current_threads[cpu]) — remove it.
+                    // Thread is in a run queue or running on another CPU — remove or kick it.
                     sched.remove_thread(tid);
+                    sched.kick_thread(thread);
                 }
                 // If is_current: leave it in current_threads[cpu]; block_current() handles it.
             }
@@ -523,7 +581,7 @@ impl Process {
         // Special immediate signals
         if sig == crate::ipc::signal::SIGKILL {
             log::info!("Process PID {} terminated by SIGKILL", self.pid);
-            self.exit(128 + sig as i32);
+            self.exit_by_signal(sig, false);
             return Ok(());
         }
 
@@ -561,22 +619,28 @@ impl Process {
     }
 
     /// Evaluate and handle pending signals for a process thread prior to user return.
+    ///
+    /// Takes the process and thread locks itself, so that a core dump is written after
+    /// releasing them.
     pub fn handle_pending_signals(
-        &mut self,
-        thread: &mut Thread,
+        proc_arc: &Arc<Spinlock<Process>>,
+        thread_arc: &Arc<Spinlock<Thread>>,
         frame: &mut crate::arch::syscall::syscall::SyscallFrame,
     ) {
-        let sig_opt = self
+        let mut proc = proc_arc.lock();
+        let mut thread = thread_arc.lock();
+        let sig_mask = thread.sig_mask;
+        let sig_opt = proc
             .pending_signals
-            .dequeue(thread.sig_mask)
-            .or_else(|| thread.pending_signals.dequeue(thread.sig_mask));
+            .dequeue(sig_mask)
+            .or_else(|| thread.pending_signals.dequeue(sig_mask));
 
         let sig = match sig_opt {
             Some(s) => s,
             None => return,
         };
 
-        let action = self.sig_actions[(sig - 1) as usize];
+        let action = proc.sig_actions[(sig - 1) as usize];
 
         if action.handler == crate::ipc::signal::SIG_IGN {
             return;
@@ -584,17 +648,36 @@ impl Process {
 
         if action.handler == crate::ipc::signal::SIG_DFL {
             match crate::ipc::signal::default_action(sig) {
-                crate::ipc::signal::SignalDefaultAction::Terminate
-                | crate::ipc::signal::SignalDefaultAction::CoreDump => {
-                    log::info!("Process PID {} killed by signal {}", self.pid, sig);
-                    self.exit(128 + sig as i32);
+                crate::ipc::signal::SignalDefaultAction::Terminate => {
+                    log::info!("Process PID {} killed by signal {}", proc.pid, sig);
+                    proc.exit_by_signal(sig, false);
+                }
+                crate::ipc::signal::SignalDefaultAction::CoreDump => {
+                    log::info!("Process PID {} killed by signal {}", proc.pid, sig);
+                    let crashed = crate::proc::coredump::CrashedThread {
+                        tid: thread.tid,
+                        regs: crate::arch::coredump::UserRegs::from_syscall_frame(
+                            frame,
+                            thread.context.fs_base,
+                            thread.context.gs_base,
+                        ),
+                        sig_pending: proc.pending_signals.mask | thread.pending_signals.mask,
+                        sig_blocked: thread.sig_mask,
+                    };
+                    let core = crate::proc::coredump::CoreProcess::capture(&proc);
+                    drop(thread);
+                    drop(proc);
+                    let core_dumped = core.is_some_and(|core| {
+                        crate::proc::coredump::dump_core(&core, &crashed, sig)
+                    });
+                    proc_arc.lock().exit_by_signal(sig, core_dumped);
                 }
                 crate::ipc::signal::SignalDefaultAction::Stop => {
-                    self.state = ProcessState::Stopped;
+                    proc.state = ProcessState::Stopped;
                     thread.state = ThreadState::Stopped;
                 }
                 crate::ipc::signal::SignalDefaultAction::Continue => {
-                    self.state = ProcessState::Running;
+                    proc.state = ProcessState::Running;
                     thread.state = ThreadState::Running;
                 }
                 crate::ipc::signal::SignalDefaultAction::Ignore => {}
@@ -611,3 +694,36 @@ impl Process {
         }
     }
 }
+
+/// Most `#!` interpreters one exec may pass through before failing with `ELOOP`.
+const MAX_INTERP_DEPTH: usize = 4;
+
+/// Longest `#!` line accepted, including the `#!` itself.
+const MAX_SHEBANG_LEN: usize = 256;
+
+/// Split the `#!` line of a script into its interpreter and optional argument.
+///
+/// Like Linux, everything after the interpreter up to the end of the line is passed as a
+/// single argument. Returns `None` if `data` is not a script.
+fn parse_shebang(data: &[u8]) -> Result<Option<(&str, Option<&str>)>, ExecError> {
+    if !data.starts_with(b"#!") {
+        return Ok(None);
+    }
+    let line_end = match data.iter().take(MAX_SHEBANG_LEN).position(|&b| b == b'\n') {
+        Some(end) => end,
+        None if data.len() <= MAX_SHEBANG_LEN => data.len(),
+        None => return Err(ExecError::BadFormat("#! line too long")),
+    };
+    let line = core::str::from_utf8(&data[2..line_end])
+        .map_err(|_| ExecError::BadFormat("#! line is not valid UTF-8"))?
+        .trim_matches([' ', '\t', '\r']);
+
+    let (interpreter, rest) = line
+        .split_once([' ', '\t'])
+        .unwrap_or((line, ""));
+    if interpreter.is_empty() {
+        return Err(ExecError::BadFormat("#! line names no interpreter"));
+    }
+    let arg = rest.trim_matches(|c| c == ' ' || c == '\t');
+    Ok(Some((interpreter, (!arg.is_empty()).then_some(arg))))
+}
@@ -43,6 +43,16 @@ impl ProcessTable {
             .cloned()
             .collect()
     }
+
+    /// Snapshot every registered process, ordered by `ProcessId`.
+    pub fn all(&self) -> Vec<Arc<Spinlock<Process>>> {
+        self.table.lock().values().cloned().collect()
+    }
+
+    /// Number of threads of all registered processes.
+    pub fn nr_threads(&self) -> usize {
+        self.table.lock().values().map(|proc| proc.lock().threads.len()).sum()
+    }
 }
 
 /// Global static instance of the ProcessTable.
@@ -56,6 +66,7 @@ pub fn register_process(proc: Arc<Spinlock<Process>>) {
 /// Unregister a process from the global process table upon termination/reap.
 pub fn unregister_process(pid: ProcessId) {
     PROCESS_TABLE.unregister(pid);
+    crate::fs::procfs::flush_pid(pid);
 }
 
 /// Find a process in the global process table by its `ProcessId`.
@@ -67,3 +78,13 @@ pub fn find_process(pid: ProcessId) -> Option<Arc<Spinlock<Process>>> {
 pub fn find_processes_by_pgid(pgid: ProcessId) -> Vec<Arc<Spinlock<Process>>> {
     PROCESS_TABLE.find_by_pgid(pgid)
 }
+
+/// Snapshot all processes in the global process table.
+pub fn all_processes() -> Vec<Arc<Spinlock<Process>>> {
+    PROCESS_TABLE.all()
+}
+
+/// Number of threads of all processes in the global process table.
+pub fn nr_threads() -> usize {
+    PROCESS_TABLE.nr_threads()
+}
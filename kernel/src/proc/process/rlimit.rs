@@ -0,0 +1,50 @@
+//! Per-process resource limits (`getrlimit`, `setrlimit`, `prlimit64`).
+
+/// No limit.
+pub const RLIM_INFINITY: u64 = !0u64;
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIMIT_LOCKS: usize = 10;
+pub const RLIMIT_SIGPENDING: usize = 11;
+pub const RLIMIT_MSGQUEUE: usize = 12;
+pub const RLIMIT_NICE: usize = 13;
+pub const RLIMIT_RTPRIO: usize = 14;
+pub const RLIMIT_RTTIME: usize = 15;
+
+/// Number of resources with a limit.
+pub const RLIM_NLIMITS: usize = 16;
+
+/// Linux 64-bit resource limit structure.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RLimit64 {
+    pub rlim_cur: u64,
+    pub rlim_max: u64,
+}
+
+impl RLimit64 {
+    pub const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+}
+
+/// Limits of the first process; every other process inherits its parent's.
+///
+/// Core dumps are off by default but may be enabled by raising the soft limit.
+pub fn default_rlimits() -> [RLimit64; RLIM_NLIMITS] {
+    let mut limits = [RLimit64::new(RLIM_INFINITY, RLIM_INFINITY); RLIM_NLIMITS];
+    limits[RLIMIT_STACK] = RLimit64::new(8 * 1024 * 1024, 64 * 1024 * 1024);
+    limits[RLIMIT_CORE] = RLimit64::new(0, RLIM_INFINITY);
+    limits[RLIMIT_NPROC] = RLimit64::new(4096, 4096);
+    limits[RLIMIT_NOFILE] = RLimit64::new(1024, 4096);
+    limits
+}
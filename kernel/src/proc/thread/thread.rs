@@ -3,15 +3,47 @@ use crate::arch::cpu::context::ThreadContext;
 use crate::arch::cpu::stack::KernelStack;
 use crate::ipc::signal::{PendingSignals, SigSet};
 use crate::ipc::signal::{SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SIGKILL, SIGSTOP};
+use crate::proc::kthread::KThreadControl;
 use crate::proc::process::Process;
+use crate::sched::fair::CPU_MASK_ALL;
+use crate::sched::group::SchedGroup;
 use crate::sched::nice::Nice;
+use crate::sched::policy::{SchedPolicy, WEIGHT_IDLEPRIO};
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::HeldLocks;
 use crate::sync::spinlock::Spinlock;
+#[cfg(feature = "lockdep")]
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, AtomicU32};
 
 /// Default requested time slice for threads in nanoseconds (10 ms).
 pub const DEFAULT_THREAD_SLICE_NS: u64 = 10_000_000;
 
+/// Kernel stack size for threads created by `Thread::spawn_kernel`.
+pub const KERNEL_THREAD_STACK_SIZE: usize = 16 * 1024;
+
+/// Scheduling parameters passed on from a parent thread to its child.
+#[derive(Debug, Clone, Copy)]
+pub struct SchedParams {
+    pub policy: SchedPolicy,
+    pub rt_priority: u8,
+    pub nice: Nice,
+    pub slice_ns: u64,
+}
+
+impl Default for SchedParams {
+    fn default() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            nice: Nice::DEFAULT,
+            slice_ns: DEFAULT_THREAD_SLICE_NS,
+        }
+    }
+}
+
 /// Represents the execution state of a thread.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ThreadState {
@@ -55,6 +87,18 @@ pub struct Thread {
     /// Thread weight for proportional sharing (higher weight = more CPU time)
     pub weight: u32,
 
+    /// Scheduling policy
+    pub policy: SchedPolicy,
+
+    /// Real-time priority ([1, 99]) under SCHED_FIFO and SCHED_RR, 0 otherwise
+    pub rt_priority: u8,
+
+    /// Time left of the SCHED_RR quantum in nanoseconds
+    pub rr_slice_left_ns: u64,
+
+    /// Whether children start with the default policy instead of this thread's
+    pub reset_on_fork: bool,
+
     /// Signal mask (blocked signals for this specific thread)
     pub sig_mask: SigSet,
 
@@ -64,8 +108,30 @@ pub struct Thread {
     /// State of the thread
     pub state: ThreadState,
 
+    /// CPU the thread last ran on or is queued on
+    pub cpu: u32,
+
+    /// Set while a CPU runs on this thread's stack, including the tail of a switch away
+    pub on_cpu: AtomicBool,
+
+    /// Spinlocks the thread held when it was switched out, counted in debug builds
+    pub held_spinlocks: AtomicU32,
+
+    /// Locks the thread held when it was switched out, tracked by lockdep
+    #[cfg(feature = "lockdep")]
+    pub held_locks: Box<HeldLocks>,
+
+    /// CPUs the thread may run on, bit `n` for CPU `n`
+    pub cpus_allowed: u32,
+
+    /// Scheduling group the thread shares CPU time in, or `None` for the root queue
+    pub sched_group: Option<Arc<SchedGroup>>,
+
     /// Exit code, if the thread has exited
     pub exit_code: Option<u32>,
+
+    /// Stop request and exit status, for threads created by `kthread_create`
+    pub kthread: Option<Arc<KThreadControl>>,
 }
 
 impl Thread {
@@ -83,13 +149,50 @@ impl Thread {
             slice_ns: DEFAULT_THREAD_SLICE_NS,
             nice,
             weight: effective_weight,
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            rr_slice_left_ns: 0,
+            reset_on_fork: false,
             sig_mask: 0,
             pending_signals: PendingSignals::new(),
             state: ThreadState::Creating,
+            cpu: 0,
+            on_cpu: AtomicBool::new(false),
+            held_spinlocks: AtomicU32::new(0),
+            #[cfg(feature = "lockdep")]
+            held_locks: Box::new(HeldLocks::new()),
+            cpus_allowed: CPU_MASK_ALL,
+            sched_group: None,
             exit_code: None,
+            kthread: None,
         }
     }
 
+    /// Create a kernel thread that runs `entry(arg)` once it is queued on the scheduler.
+    ///
+    /// Kernel threads belong to no process and run on the kernel's boot page tables.
+    pub fn new_kernel(name: &str, entry: extern "C" fn(*mut u8), arg: *mut u8) -> Self {
+        let mut thread = Thread::new(super::tid::next_tid(), String::from(name), 0, Weak::new());
+        let mut kernel_stack = KernelStack::new(KERNEL_THREAD_STACK_SIZE);
+        thread.context.init(kernel_stack.as_slice_mut(), entry, arg);
+        thread.context.cr3 = crate::mm::kernel_root() as usize;
+        thread.kernel_stack = Some(kernel_stack);
+        thread
+    }
+
+    /// Create a kernel thread running `entry(arg)` and queue it on the scheduler.
+    pub fn spawn_kernel(
+        name: &str,
+        entry: extern "C" fn(*mut u8),
+        arg: *mut u8,
+    ) -> Arc<Spinlock<Thread>> {
+        let thread = Arc::new(Spinlock::new(Self::new_kernel(name, entry, arg)));
+        crate::arch::without_interrupts(|| {
+            crate::sched::SCHEDULER.lock().add_thread(thread.clone());
+        });
+        thread
+    }
+
     /// Returns the kernel stack top virtual address if allocated.
     pub fn kernel_stack_top(&self) -> u64 {
         self.kernel_stack.as_ref().map(|s| s.top()).unwrap_or(0)
@@ -98,7 +201,56 @@ impl Thread {
     /// Sets the thread nice value and updates its associated CPU weight.
     pub fn set_nice(&mut self, nice: Nice) {
         self.nice = nice;
-        self.weight = nice.weight();
+        if self.policy != SchedPolicy::Idle {
+            self.weight = nice.weight();
+        }
+    }
+
+    /// Sets the scheduling policy and real-time priority.
+    ///
+    /// Only updates the thread; moving it between scheduling classes is up to
+    /// `Scheduler::set_policy`.
+    pub fn set_policy(&mut self, policy: SchedPolicy, rt_priority: u8) {
+        if policy == SchedPolicy::Idle {
+            self.weight = WEIGHT_IDLEPRIO;
+        } else if self.policy == SchedPolicy::Idle {
+            self.weight = self.nice.weight();
+        }
+        self.policy = policy;
+        self.rt_priority = if policy.is_realtime() { rt_priority } else { 0 };
+        self.rr_slice_left_ns = crate::sched::rt::rr_timeslice_ns();
+    }
+
+    /// Whether the thread belongs to the real-time scheduling class.
+    pub fn is_realtime(&self) -> bool {
+        self.policy.is_realtime()
+    }
+
+    /// Scheduling parameters a child forked from this thread starts with.
+    ///
+    /// With `reset_on_fork`, the child gets the default policy and slice and no negative
+    /// nice value.
+    pub fn child_sched_params(&self) -> SchedParams {
+        if self.reset_on_fork {
+            SchedParams {
+                nice: self.nice.max(Nice::DEFAULT),
+                ..SchedParams::default()
+            }
+        } else {
+            SchedParams {
+                policy: self.policy,
+                rt_priority: self.rt_priority,
+                nice: self.nice,
+                slice_ns: self.slice_ns,
+            }
+        }
+    }
+
+    /// Take over scheduling parameters, e.g. those inherited from a parent.
+    pub fn set_sched_params(&mut self, params: SchedParams) {
+        self.set_nice(params.nice);
+        self.slice_ns = params.slice_ns;
+        self.set_policy(params.policy, params.rt_priority);
     }
 
     /// Yield the CPU to another thread.
@@ -124,6 +276,34 @@ impl Thread {
         }
     }
 
+    /// Unblock the thread from an interrupt handler or with interrupts disabled.
+    ///
+    /// A thread the interrupted code holds locked cannot be woken without deadlocking, so
+    /// it is woken by the next timer event instead.
+    pub fn wake(thread: Arc<Spinlock<Thread>>) {
+        if let Err(thread) = Self::try_unblock(thread) {
+            crate::time::timer::add(crate::time::now_ns(), thread);
+        }
+    }
+
+    /// Unblock the thread unless it is locked, handing it back if it is.
+    ///
+    /// Never waits for the thread lock, so it may be used where the interrupted code might
+    /// hold it.
+    pub fn try_unblock(thread: Arc<Spinlock<Thread>>) -> Result<(), Arc<Spinlock<Thread>>> {
+        let Some(mut t) = thread.try_lock() else {
+            return Err(thread);
+        };
+        if t.state == ThreadState::Sleeping {
+            t.state = ThreadState::Ready;
+            drop(t);
+            crate::arch::without_interrupts(|| {
+                crate::sched::SCHEDULER.lock().add_thread(thread);
+            });
+        }
+        Ok(())
+    }
+
     /// Terminate the thread.
     pub fn exit(&mut self, status: u32) {
         self.state = ThreadState::Zombie;
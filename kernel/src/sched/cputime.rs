@@ -0,0 +1,182 @@
+//! Per-CPU time accounting, as reported by `/proc/stat`.
+//!
+//! Time is charged when the scheduler tick runs, to what the CPU was doing at that point:
+//! idle, user code of a thread (nice if the thread is niced) or kernel code. Stretches a
+//! CPU ran without its tick are charged when it next switches threads, to user time for
+//! threads of processes and to system time for kernel threads. Time spent in interrupt
+//! handlers and softirqs is measured as they run, and taken out of the next charge.
+
+use super::{MAX_CPUS, SCHEDULER, Scheduler};
+use crate::time::timekeeping;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Categories of CPU time, in the order of the columns of `/proc/stat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuTime {
+    User,
+    Nice,
+    System,
+    Idle,
+    IoWait,
+    Irq,
+    SoftIrq,
+    Steal,
+}
+
+pub const NR_CPU_TIMES: usize = 8;
+
+/// Nanoseconds each CPU spent in each [`CpuTime`] category.
+static CPU_TIMES: [[AtomicU64; NR_CPU_TIMES]; MAX_CPUS] =
+    [const { [const { AtomicU64::new(0) }; NR_CPU_TIMES] }; MAX_CPUS];
+
+/// Interrupt handler time of each CPU not yet taken out of a charge.
+static IRQ_PENDING_NS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Softirq time of each CPU not yet taken out of a charge.
+static SOFTIRQ_PENDING_NS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// When each CPU entered the interrupt handler it runs.
+static IRQ_ENTRY_NS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Interrupts handled and context switches made since boot, on all CPUs.
+static NR_IRQS: AtomicU64 = AtomicU64::new(0);
+static NR_SWITCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Called on entry to a device or timer interrupt handler on `cpu`.
+pub fn irq_enter(cpu: u32) {
+    NR_IRQS.fetch_add(1, Ordering::Relaxed);
+    if let Some(entry) = IRQ_ENTRY_NS.get(cpu as usize) {
+        entry.store(crate::time::now_ns(), Ordering::Relaxed);
+    }
+}
+
+/// Called when the interrupt handler entered with [`irq_enter`] is done on `cpu`.
+pub fn irq_exit(cpu: u32) {
+    let Some(entry) = IRQ_ENTRY_NS.get(cpu as usize) else {
+        return;
+    };
+    let elapsed = crate::time::now_ns().saturating_sub(entry.load(Ordering::Relaxed));
+    IRQ_PENDING_NS[cpu as usize].fetch_add(elapsed, Ordering::Relaxed);
+}
+
+/// Record that `cpu` spent `ns` running softirqs.
+pub fn softirq_time(cpu: u32, ns: u64) {
+    if let Some(pending) = SOFTIRQ_PENDING_NS.get(cpu as usize) {
+        pending.fetch_add(ns, Ordering::Relaxed);
+    }
+}
+
+/// Count a context switch.
+pub fn count_switch() {
+    NR_SWITCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Charge `delta_ns` of `cpu` to what it runs.
+///
+/// `user_mode` tells whether the tick interrupted user code, or is `None` for a stretch
+/// without a tick. Called with the scheduler locked.
+pub fn account(sched: &Scheduler, cpu: u32, delta_ns: u64, user_mode: Option<bool>) {
+    let Some(times) = CPU_TIMES.get(cpu as usize) else {
+        return;
+    };
+    let kind = if sched.runs_idle(cpu as usize) {
+        CpuTime::Idle
+    } else {
+        match &sched.current_threads[cpu as usize] {
+            Some(thread) => {
+                let t_lock = thread.lock();
+                let user = user_mode.unwrap_or_else(|| t_lock.process.strong_count() > 0);
+                match (user, i8::from(t_lock.nice) > 0) {
+                    (true, true) => CpuTime::Nice,
+                    (true, false) => CpuTime::User,
+                    (false, _) => CpuTime::System,
+                }
+            }
+            None => CpuTime::Idle,
+        }
+    };
+
+    // Only `cpu` itself adds to its pending time, and not while this runs.
+    let mut left_ns = delta_ns;
+    for (pending, kind) in [
+        (&IRQ_PENDING_NS[cpu as usize], CpuTime::Irq),
+        (&SOFTIRQ_PENDING_NS[cpu as usize], CpuTime::SoftIrq),
+    ] {
+        let ns = pending.load(Ordering::Relaxed).min(left_ns);
+        pending.fetch_sub(ns, Ordering::Relaxed);
+        times[kind as usize].fetch_add(ns, Ordering::Relaxed);
+        left_ns -= ns;
+    }
+    times[kind as usize].fetch_add(left_ns, Ordering::Relaxed);
+}
+
+/// Nanoseconds `cpu` spent in each [`CpuTime`] category.
+pub fn cpu_times(cpu: usize) -> [u64; NR_CPU_TIMES] {
+    core::array::from_fn(|kind| CPU_TIMES[cpu][kind].load(Ordering::Relaxed))
+}
+
+/// Interrupts handled since boot.
+pub fn nr_irqs() -> u64 {
+    NR_IRQS.load(Ordering::Relaxed)
+}
+
+/// Context switches made since boot.
+pub fn nr_switches() -> u64 {
+    NR_SWITCHES.load(Ordering::Relaxed)
+}
+
+/// Length of the `USER_HZ` clock ticks `/proc/stat` reports times in.
+const USER_TICK_NS: u64 = 10_000_000;
+
+fn write_cpu_line(out: &mut String, name: &str, times: &[u64; NR_CPU_TIMES]) {
+    let _ = write!(out, "{}", name);
+    for ns in times {
+        let _ = write!(out, " {}", ns / USER_TICK_NS);
+    }
+    // No guests run here.
+    let _ = writeln!(out, " 0 0");
+}
+
+/// `/proc/stat`: CPU times in total and per CPU, and counters since boot.
+fn show_stat() -> String {
+    let online: Vec<usize> = crate::arch::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        (0..MAX_CPUS).filter(|&cpu| sched.is_online(cpu as u32)).collect()
+    });
+    let per_cpu: Vec<[u64; NR_CPU_TIMES]> = online.iter().map(|&cpu| cpu_times(cpu)).collect();
+    let mut total = [0; NR_CPU_TIMES];
+    for times in &per_cpu {
+        for (sum, ns) in total.iter_mut().zip(times) {
+            *sum += ns;
+        }
+    }
+
+    let mut out = String::new();
+    write_cpu_line(&mut out, "cpu", &total);
+    for (cpu, times) in online.iter().zip(&per_cpu) {
+        write_cpu_line(&mut out, &format!("cpu{}", cpu), times);
+    }
+    let boot_ns = timekeeping::realtime_ns().saturating_sub(timekeeping::boottime_ns());
+    let _ = write!(
+        out,
+        "intr {}\nctxt {}\nbtime {}\nprocesses {}\nprocs_running {}\nprocs_blocked 0\n",
+        nr_irqs(),
+        nr_switches(),
+        boot_ns / timekeeping::NSEC_PER_SEC,
+        crate::proc::last_pid(),
+        super::loadavg::nr_running(),
+    );
+    out
+}
+
+/// Register `/proc/stat`.
+pub fn init() -> Result<(), &'static str> {
+    crate::fs::procfs::register_proc_entry("stat", show_stat, None);
+    Ok(())
+}
+
+crate::fs_initcall!(init);
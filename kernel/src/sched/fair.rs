@@ -2,216 +2,1340 @@
 //!
 //! EEVDF schedules threads based on proportional share fairness by tracking:
 //! 1. Virtual runtime (`vruntime`): accumulated CPU time scaled by thread weight.
-//! 2. System virtual time (`min_vruntime`): monotonic virtual time baseline of the system.
-//! 3. Eligibility: a thread $i$ is eligible to run when $v_i \le \text{min\_vruntime}$.
+//! 2. Virtual time $V$: the load-weighted average `vruntime` of the runnable threads,
+//!    $V = \frac{\sum w_i v_i}{\sum w_i}$, kept relative to the monotonic baseline
+//!    `min_vruntime`.
+//! 3. Eligibility: a thread $i$ is eligible to run when $v_i \le V$, i.e. it has not
+//!    received more than its share.
 //! 4. Virtual deadline (`vdeadline`): virtual time by which the requested time slice
 //!    should complete, computed as $d_i = v_i + \frac{q_i \cdot w_0}{w_i}$.
 //!
-//! The scheduler always selects the eligible thread with the earliest virtual deadline.
+//! The scheduler always selects the eligible thread with the earliest virtual deadline,
+//! found in O(log n) through the augmented tree of [`crate::sched::tree`].
+//!
+//! Threads can be bundled in scheduling groups ([`crate::sched::group`]). A group takes
+//! part in the queue of its parent as one entity with its own weight and has a queue of
+//! its own for its members, so picking descends from the root queue through the chosen
+//! groups until it reaches a thread, and runtime is charged to every level on the way up.
+//!
+//! Every CPU has its own root [`RunQueue`] with its own virtual time. Waking threads are placed
+//! on an idle CPU when there is one, and queues are evened out by pulling threads from the
+//! busiest CPU, both periodically and whenever a CPU runs out of work. Placement and
+//! balancing only ever put a thread on a CPU in its affinity mask (`cpus_allowed`).
+//!
+//! [`Scheduler`] also drives the real-time class of [`crate::sched::rt`], whose threads
+//! run before any thread queued here.
 
 use crate::proc::thread::{Thread, ThreadId, ThreadState};
-use crate::sched::nice::NICE_0_WEIGHT;
+use crate::sched::nice::{NICE_0_WEIGHT, Nice};
+use crate::sched::policy::SchedPolicy;
+use crate::sched::rt::{RtRunQueue, rr_timeslice_ns};
+use crate::sched::group::{GroupId, SchedGroup};
+use crate::sched::tree::{Entity, EntityId, EntityTree};
 use crate::sync::spinlock::Spinlock;
+use crate::time::tick::{self, TickMode};
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 /// Maximum number of CPUs supported.
 pub const MAX_CPUS: usize = 8;
 
+/// Affinity mask allowing every CPU.
+pub const CPU_MASK_ALL: u32 = (1 << MAX_CPUS) - 1;
+
 /// Default time slice for threads in nanoseconds (10 ms).
 pub const BASE_SLICE_NS: u64 = 10_000_000;
 
+/// Shortest time slice a thread may request in nanoseconds (0.1 ms).
+pub const MIN_SLICE_NS: u64 = 100_000;
+
+/// Longest time slice a thread may request in nanoseconds (100 ms).
+pub const MAX_SLICE_NS: u64 = 100_000_000;
+
+/// Scheduler ticks between periodic load balancing passes on a CPU.
+const BALANCE_INTERVAL_TICKS: u64 = 4;
+
+/// Smallest weight a group has on a CPU, however little of its load runs there.
+const MIN_SHARES: u32 = 2;
+
+/// Virtual length of a thread's requested time slice.
+fn vslice(thread: &Thread) -> u64 {
+    let weight = weight_of(thread);
+    let slice_ns = if thread.slice_ns > 0 {
+        thread.slice_ns
+    } else {
+        BASE_SLICE_NS
+    };
+    (slice_ns * NICE_0_WEIGHT as u64) / weight as u64
+}
+
+/// Virtual length of the default time slice of a group entity of `weight`.
+fn group_vslice(weight: u32) -> u64 {
+    (BASE_SLICE_NS * NICE_0_WEIGHT as u64) / weight.max(1) as u64
+}
+
+/// Weight of a thread for fair sharing.
+fn weight_of(thread: &Thread) -> u32 {
+    if thread.weight > 0 {
+        thread.weight
+    } else {
+        NICE_0_WEIGHT
+    }
+}
+
+/// Fair entities of one CPU, either the root queue or the queue of a group.
+///
+/// Entities live in an [`EntityTree`] ordered by virtual deadline. Their scheduling keys
+/// are copied into the tree when they are queued and written back when they leave it,
+/// so picking the next entity takes no thread locks.
+pub struct RunQueue {
+    /// Queued entities ordered by virtual deadline.
+    tree: EntityTree,
+
+    /// Deadline each queued entity is sorted by, to find it in `tree`.
+    deadlines: BTreeMap<EntityId, u64>,
+
+    /// Monotonic virtual time baseline of this queue; `avg_vruntime` is relative to it.
+    pub min_vruntime: u64,
+
+    /// Sum of `weight * (vruntime - min_vruntime)` over the queued entities and the
+    /// running one.
+    avg_vruntime: i128,
+
+    /// Sum of the weights of the same entities.
+    avg_load: u64,
+
+    /// Running entity picked from here, with its virtual runtime and weight.
+    curr: Option<(EntityId, u64, u32)>,
+
+    /// Number of thread entities in `tree`.
+    nr_threads: usize,
+}
+
+impl RunQueue {
+    const fn new() -> Self {
+        Self {
+            tree: EntityTree::new(),
+            deadlines: BTreeMap::new(),
+            min_vruntime: 0,
+            avg_vruntime: 0,
+            avg_load: 0,
+            curr: None,
+            nr_threads: 0,
+        }
+    }
+
+    /// Number of queued entities.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Whether no entity is queued.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Number of threads queued directly here.
+    pub fn nr_threads(&self) -> usize {
+        self.nr_threads
+    }
+
+    /// Whether no entity is queued or running.
+    fn is_idle(&self) -> bool {
+        self.tree.is_empty() && self.curr.is_none()
+    }
+
+    /// Whether `id` is queued.
+    fn contains(&self, id: EntityId) -> bool {
+        self.deadlines.contains_key(&id)
+    }
+
+    /// Whether `id` is queued or running.
+    fn holds(&self, id: EntityId) -> bool {
+        self.contains(id) || self.curr_id() == Some(id)
+    }
+
+    /// The running entity.
+    fn curr_id(&self) -> Option<EntityId> {
+        self.curr.map(|(id, _, _)| id)
+    }
+
+    /// Total weight of the queued entities and the running one.
+    fn load(&self) -> u64 {
+        self.avg_load
+    }
+
+    /// Load-weighted average virtual runtime $V$ of the queued entities and the running one.
+    pub fn avg_vruntime(&self) -> u64 {
+        if self.avg_load == 0 {
+            return self.min_vruntime;
+        }
+        let avg = self.avg_vruntime.div_euclid(self.avg_load as i128);
+        (self.min_vruntime as i128 + avg).max(0) as u64
+    }
+
+    /// Whether an entity at `vruntime` is eligible, i.e. $v_i \le V$.
+    ///
+    /// Compared without dividing, as $(v_i - m) \sum w \le \sum w (v - m)$ with
+    /// $m$ = `min_vruntime`, so that rounding cannot leave every entity ineligible.
+    fn eligible(&self, vruntime: u64) -> bool {
+        let key = vruntime as i128 - self.min_vruntime as i128;
+        self.avg_vruntime >= key * self.avg_load as i128
+    }
+
+    fn add_avg(&mut self, vruntime: u64, weight: u32) {
+        self.avg_vruntime += (vruntime as i128 - self.min_vruntime as i128) * weight as i128;
+        self.avg_load += weight as u64;
+    }
+
+    fn sub_avg(&mut self, vruntime: u64, weight: u32) {
+        self.avg_vruntime -= (vruntime as i128 - self.min_vruntime as i128) * weight as i128;
+        self.avg_load -= weight as u64;
+    }
+
+    /// Advance `min_vruntime` to the smallest `vruntime` of the queued and running
+    /// entities, if that is later, and rebase the average on it.
+    fn update_min_vruntime(&mut self) {
+        let smallest = match (self.curr.map(|(_, v, _)| v), self.tree.min_vruntime()) {
+            (Some(curr), Some(queued)) => curr.min(queued),
+            (curr, queued) => match curr.or(queued) {
+                Some(v) => v,
+                None => return,
+            },
+        };
+        if smallest > self.min_vruntime {
+            let delta = (smallest - self.min_vruntime) as i128;
+            self.avg_vruntime -= delta * self.avg_load as i128;
+            self.min_vruntime = smallest;
+        }
+    }
+
+    /// Queue a thread with a fresh virtual deadline.
+    ///
+    /// The thread's `vruntime` is normalized against `min_vruntime` to avoid starvation or
+    /// excessive priority after sleeping.
+    fn enqueue(&mut self, thread: Arc<Spinlock<Thread>>) {
+        let mut t_lock = thread.lock();
+
+        // Prevent waking threads from gaining unfair CPU time if they slept for long.
+        if t_lock.vruntime < self.min_vruntime {
+            t_lock.vruntime = self.min_vruntime;
+        }
+
+        // Calculate virtual deadline: d_i = v_i + (q_i * w_0) / w_i
+        t_lock.vdeadline = t_lock.vruntime.saturating_add(vslice(&t_lock));
+
+        if t_lock.state != ThreadState::Sleeping {
+            t_lock.state = ThreadState::Ready;
+        }
+        drop(t_lock);
+
+        self.insert(thread);
+    }
+
+    /// Queue a thread keeping its virtual runtime and deadline.
+    fn insert(&mut self, thread: Arc<Spinlock<Thread>>) {
+        let entity = {
+            let t_lock = thread.lock();
+            Entity {
+                id: EntityId::Thread(t_lock.tid),
+                vruntime: t_lock.vruntime,
+                vdeadline: t_lock.vdeadline,
+                weight: weight_of(&t_lock),
+                cpus_allowed: t_lock.cpus_allowed,
+                thread: Some(thread.clone()),
+            }
+        };
+        self.insert_entity(entity);
+    }
+
+    /// Queue an entity with the keys it carries.
+    fn insert_entity(&mut self, entity: Entity) {
+        self.add_avg(entity.vruntime, entity.weight);
+        self.deadlines.insert(entity.id, entity.vdeadline);
+        if entity.thread.is_some() {
+            self.nr_threads += 1;
+        }
+        self.tree.insert(entity);
+        self.update_min_vruntime();
+    }
+
+    /// Update the CPUs the queued entity `id` may run on.
+    fn set_cpus_allowed(&mut self, id: EntityId, mask: u32) {
+        if let Some(mut entity) = self.take(id) {
+            entity.cpus_allowed = mask;
+            self.insert_entity(entity);
+        }
+    }
+
+    /// Take `id` out of the tree and the average.
+    fn take(&mut self, id: EntityId) -> Option<Entity> {
+        let deadline = self.deadlines.remove(&id)?;
+        let entity = self.tree.remove((deadline, id))?;
+        self.sub_avg(entity.vruntime, entity.weight);
+        if entity.thread.is_some() {
+            self.nr_threads -= 1;
+        }
+        Some(entity)
+    }
+
+    /// Remove a queued entity.
+    fn dequeue(&mut self, id: EntityId) -> Option<Entity> {
+        let entity = self.take(id)?;
+        self.update_min_vruntime();
+        Some(entity)
+    }
+
+    /// Hand a thread leaving the tree back with its scheduling keys written back.
+    ///
+    /// Returns `None` for group entities.
+    fn release(entity: Entity, running: bool) -> Option<Arc<Spinlock<Thread>>> {
+        let thread = entity.thread?;
+        let mut t_lock = thread.lock();
+        t_lock.vruntime = entity.vruntime;
+        t_lock.vdeadline = entity.vdeadline;
+        // A thread preempted on its way to sleep keeps its state so that it still blocks.
+        if running && t_lock.state != ThreadState::Sleeping {
+            t_lock.state = ThreadState::Running;
+        }
+        drop(t_lock);
+        Some(thread)
+    }
+
+    /// Remove a queued thread by its `ThreadId`.
+    fn remove(&mut self, tid: ThreadId) -> Option<Arc<Spinlock<Thread>>> {
+        let entity = self.dequeue(EntityId::Thread(tid))?;
+        Self::release(entity, false)
+    }
+
+    /// Remove and return the next entity according to EEVDF rules:
+    ///
+    /// 1. An entity is **eligible** if its virtual runtime $v_i \le V$, the weighted average.
+    /// 2. Among eligible entities, pick the one with the earliest virtual deadline
+    ///    ($\min(d_i)$).
+    /// 3. If the running entity holds the average down so that none is eligible, pick the
+    ///    earliest deadline.
+    ///
+    /// The picked entity becomes the running entity of this queue. Its keys are not written
+    /// back anywhere; that is up to the caller.
+    fn pick(&mut self) -> Option<Entity> {
+        self.put_curr();
+        let (_, id) = self
+            .tree
+            .earliest_eligible(|vruntime| self.eligible(vruntime))
+            .or_else(|| self.tree.first().map(Entity::key))?;
+
+        let entity = self.take(id)?;
+        // The running entity still counts towards the average.
+        self.add_avg(entity.vruntime, entity.weight);
+        self.curr = Some((entity.id, entity.vruntime, entity.weight));
+        self.update_min_vruntime();
+        Some(entity)
+    }
+
+    /// Stop counting the running entity, which leaves the CPU, and return it with its
+    /// virtual runtime and weight.
+    fn put_curr(&mut self) -> Option<(EntityId, u64, u32)> {
+        let curr = self.curr.take()?;
+        self.sub_avg(curr.1, curr.2);
+        self.update_min_vruntime();
+        Some(curr)
+    }
+
+    /// Account the running entity at its new virtual runtime and `weight`.
+    fn update_curr(&mut self, vruntime: u64, weight: u32) {
+        if let Some((id, old_vruntime, old_weight)) = self.curr {
+            self.sub_avg(old_vruntime, old_weight);
+            self.add_avg(vruntime, weight);
+            self.curr = Some((id, vruntime, weight));
+            self.update_min_vruntime();
+        }
+    }
+
+    /// The queued thread to hand over to CPU `dst`, among those allowed to run there.
+    ///
+    /// A CPU that ran dry takes the most urgent thread (earliest deadline); periodic
+    /// balancing takes the one that would run last here (latest deadline).
+    fn migration_candidate(&self, most_urgent: bool, dst: usize) -> Option<ThreadId> {
+        let mut allowed = self.tree.iter().filter_map(|entity| match entity.id {
+            EntityId::Thread(tid) if entity.cpus_allowed & (1 << dst) != 0 => Some(tid),
+            _ => None,
+        });
+        if most_urgent {
+            allowed.next()
+        } else {
+            allowed.last()
+        }
+    }
+}
+
+/// Run queue and entity keys of a scheduling group on one CPU.
+struct GroupRunQueue {
+    /// Members of the group queued or running on this CPU.
+    rq: RunQueue,
+
+    /// Virtual runtime of the group's entity in its parent while it is not queued there.
+    vruntime: u64,
+}
+
+impl GroupRunQueue {
+    const fn new() -> Self {
+        Self {
+            rq: RunQueue::new(),
+            vruntime: 0,
+        }
+    }
+}
+
+/// Scheduler state of a [`SchedGroup`].
+struct TaskGroup {
+    group: Arc<SchedGroup>,
+    cpus: [GroupRunQueue; MAX_CPUS],
+}
+
+/// Copy of [`Scheduler::online_mask`] that can be read without locking the scheduler.
+static ONLINE: AtomicU32 = AtomicU32::new(0);
+
+/// CPUs that accept threads, read without the scheduler lock.
+pub fn online_mask() -> u32 {
+    ONLINE.load(Ordering::Acquire)
+}
+
 /// The Earliest Eligible Virtual Deadline First (EEVDF) Scheduler.
 pub struct Scheduler {
-    /// The run queue of ready threads indexed by ThreadId.
-    run_queue: BTreeMap<ThreadId, Arc<Spinlock<Thread>>>,
+    /// Root run queues of ready threads and groups per CPU.
+    rqs: [RunQueue; MAX_CPUS],
+
+    /// Scheduling groups that have threads, with their run queues on every CPU.
+    groups: BTreeMap<GroupId, TaskGroup>,
+
+    /// Run queues of ready real-time threads per CPU.
+    rt_rqs: [RtRunQueue; MAX_CPUS],
 
     /// The currently running threads per CPU.
     pub current_threads: [Option<Arc<Spinlock<Thread>>>; MAX_CPUS],
 
-    /// Monotonic system virtual time baseline.
-    pub min_vruntime: u64,
+    /// Thread each CPU runs when its run queue is empty.
+    idle_threads: [Option<Arc<Spinlock<Thread>>>; MAX_CPUS],
+
+    /// Bit `n` is set once CPU `n` has an idle thread and accepts threads.
+    online: u32,
+
+    /// Scheduler ticks seen per CPU, for pacing load balancing.
+    ticks: [u64; MAX_CPUS],
 }
 
 impl Scheduler {
     /// Creates a new, empty `Scheduler`.
     pub const fn new() -> Self {
         Self {
-            run_queue: BTreeMap::new(),
+            rqs: [const { RunQueue::new() }; MAX_CPUS],
+            groups: BTreeMap::new(),
+            rt_rqs: [const { RtRunQueue::new() }; MAX_CPUS],
             current_threads: [const { None }; MAX_CPUS],
-            min_vruntime: 0,
+            idle_threads: [const { None }; MAX_CPUS],
+            online: 0,
+            ticks: [0; MAX_CPUS],
+        }
+    }
+
+    /// Register the idle thread of `cpu_id` and start placing threads on that CPU.
+    pub fn set_idle(&mut self, cpu_id: u32, thread: Arc<Spinlock<Thread>>) {
+        self.idle_threads[cpu_id as usize] = Some(thread);
+        self.online |= 1 << cpu_id;
+        ONLINE.fetch_or(1 << cpu_id, Ordering::Release);
+    }
+
+    /// Whether `cpu_id` has been brought up with [`Scheduler::set_idle`].
+    pub fn is_online(&self, cpu_id: u32) -> bool {
+        (cpu_id as usize) < MAX_CPUS && self.online & (1 << cpu_id) != 0
+    }
+
+    /// Install `thread` as running on `cpu_id` without going through a context switch.
+    ///
+    /// Used for the first user thread, which the boot CPU enters directly.
+    pub fn set_current(&mut self, cpu_id: u32, thread: Arc<Spinlock<Thread>>) {
+        {
+            let mut t_lock = thread.lock();
+            t_lock.state = ThreadState::Running;
+            t_lock.cpu = cpu_id;
+            t_lock.on_cpu.store(true, Ordering::Relaxed);
         }
+        self.current_threads[cpu_id as usize] = Some(thread);
+    }
+
+    /// CPUs that accept threads, bit `n` for CPU `n`.
+    pub fn online_mask(&self) -> u32 {
+        self.online
+    }
+
+    /// Whether threads `cpu_id` may run now are waiting in its run queues.
+    pub fn has_queued(&self, cpu_id: u32) -> bool {
+        !self.rqs[cpu_id as usize].is_empty() || self.rt_runnable(cpu_id as usize)
     }
 
-    /// Adds a thread to the run queue.
+    /// Adds a thread to the run queue of the CPU chosen by [`Scheduler::select_cpu`], or
+    /// by [`Scheduler::select_cpu_rt`] for real-time threads.
     ///
-    /// The thread's `vruntime` is normalized against the system `min_vruntime`
-    /// to avoid starvation or excessive priority after sleeping. Its virtual deadline
-    /// is then computed based on its allocated time slice and weight.
+    /// Threads that are already running or queued are left where they are: a wakeup that
+    /// races with the thread going to sleep is picked up by `block_current`.
     pub fn add_thread(&mut self, thread: Arc<Spinlock<Thread>>) {
-        let mut t_lock = thread.lock();
+        let (tid, prev_cpu, allowed, rt_prio) = {
+            let t_lock = thread.lock();
+            let rt_prio = t_lock.is_realtime().then_some(t_lock.rt_priority);
+            (t_lock.tid, t_lock.cpu as usize, t_lock.cpus_allowed, rt_prio)
+        };
+        if self.running_cpu(&thread).is_some() || self.queued_cpu(tid).is_some() {
+            return;
+        }
 
-        // Prevent waking threads from gaining unfair CPU time if they slept for long.
-        if t_lock.vruntime < self.min_vruntime {
-            t_lock.vruntime = self.min_vruntime;
+        match rt_prio {
+            Some(prio) => {
+                let cpu = self.select_cpu_rt(prev_cpu, allowed);
+                self.migrate(&thread, prev_cpu, cpu);
+                self.rt_rqs[cpu].enqueue(thread);
+                self.check_preempt_rt(cpu, prio);
+            }
+            None => {
+                let cpu = self.select_cpu(prev_cpu, allowed);
+                self.migrate(&thread, prev_cpu, cpu);
+                self.enqueue_fair(cpu, thread, true);
+                if self.runs_idle(cpu) {
+                    self.resched_cpu(cpu);
+                } else if tick::is_stopped(cpu as u32) {
+                    // The thread running there no longer has the CPU to itself.
+                    self.preempt_cpu(cpu);
+                }
+            }
         }
+    }
 
-        // Calculate virtual deadline: d_i = v_i + (q_i * w_0) / w_i
-        let weight = if t_lock.weight > 0 {
-            t_lock.weight
-        } else {
-            NICE_0_WEIGHT
+    /// Removes a thread from its run queue by its `ThreadId`.
+    pub fn remove_thread(&mut self, tid: ThreadId) -> Option<Arc<Spinlock<Thread>>> {
+        if let Some((cpu, group)) = self.fair_queue_of(tid) {
+            return self.remove_fair(cpu, group, tid);
+        }
+        let cpu = (0..MAX_CPUS).find(|&cpu| self.rt_rqs[cpu].contains(tid))?;
+        self.rt_rqs[cpu].remove(tid)
+    }
+
+    /// Change the scheduling policy of `thread` and move it to the matching class.
+    ///
+    /// A queued thread is queued again right away; a running one is preempted, which
+    /// requeues it in its new class.
+    pub fn set_policy(
+        &mut self,
+        thread: &Arc<Spinlock<Thread>>,
+        policy: SchedPolicy,
+        rt_priority: u8,
+        reset_on_fork: bool,
+    ) {
+        let tid = {
+            let mut t_lock = thread.lock();
+            t_lock.set_policy(policy, rt_priority);
+            t_lock.reset_on_fork = reset_on_fork;
+            t_lock.tid
         };
-        let slice_ns = if t_lock.slice_ns > 0 {
-            t_lock.slice_ns
-        } else {
-            BASE_SLICE_NS
+        if let Some(queued) = self.remove_thread(tid) {
+            self.add_thread(queued);
+        } else if let Some(cpu) = self.running_cpu(thread) {
+            self.preempt_cpu(cpu);
+        }
+    }
+
+    /// Change the nice value and requested slice of `thread`.
+    ///
+    /// If the thread is queued or running in the fair class, its lag behind the average
+    /// virtual time $V$ is scaled by the ratio of old to new weight, so that it keeps the
+    /// same claim to CPU time, and its virtual deadline is computed from the new slice.
+    /// A queued thread is taken out of its run queue meanwhile to be sorted anew.
+    pub fn reweight(&mut self, thread: &Arc<Spinlock<Thread>>, nice: Nice, slice_ns: u64) {
+        let (tid, cpu, realtime, group) = {
+            let t_lock = thread.lock();
+            let group = t_lock.sched_group.as_ref().map(|group| group.id);
+            (t_lock.tid, t_lock.cpu as usize, t_lock.is_realtime(), group)
         };
-        let vslice = (slice_ns * NICE_0_WEIGHT as u64) / weight as u64;
-        t_lock.vdeadline = t_lock.vruntime.saturating_add(vslice);
+        if cpu >= MAX_CPUS || realtime {
+            let mut t_lock = thread.lock();
+            t_lock.set_nice(nice);
+            t_lock.slice_ns = slice_ns;
+            return;
+        }
 
-        t_lock.state = ThreadState::Ready;
-        let tid = t_lock.tid;
+        let avg_vruntime = self.fair_rq(cpu, group).avg_vruntime() as i128;
+        // Taken out of its group's queue only for a moment, so the group stays queued.
+        let queued = self.fair_rq(cpu, group).remove(tid);
+        let running = queued.is_none() && self.running_cpu(thread) == Some(cpu);
+
+        let mut t_lock = thread.lock();
+        let old_weight = weight_of(&t_lock);
+        t_lock.set_nice(nice);
+        t_lock.slice_ns = slice_ns;
+        if queued.is_some() || running {
+            let lag = avg_vruntime - t_lock.vruntime as i128;
+            let lag = lag * old_weight as i128 / weight_of(&t_lock) as i128;
+            t_lock.vruntime = (avg_vruntime - lag).max(0) as u64;
+            t_lock.vdeadline = t_lock.vruntime.saturating_add(vslice(&t_lock));
+        }
+        let (vruntime, weight) = (t_lock.vruntime, weight_of(&t_lock));
         drop(t_lock);
 
-        self.run_queue.insert(tid, thread);
+        if let Some(queued) = queued {
+            self.fair_rq(cpu, group).insert(queued);
+        } else if running {
+            self.fair_rq(cpu, group).update_curr(vruntime, weight);
+        }
     }
 
-    /// Removes a thread from the run queue by its `ThreadId`.
-    pub fn remove_thread(&mut self, tid: ThreadId) -> Option<Arc<Spinlock<Thread>>> {
-        self.run_queue.remove(&tid)
+    /// Move `thread` to the scheduling group `group`, or to the root queue for `None`.
+    ///
+    /// A queued thread is queued again in its new group right away. A running thread stops
+    /// counting towards its old group now and joins the new one when it leaves the CPU.
+    pub fn set_group(&mut self, thread: &Arc<Spinlock<Thread>>, group: Option<Arc<SchedGroup>>) {
+        let tid = thread.lock().tid;
+        let queued = self.remove_thread(tid);
+        if queued.is_none()
+            && let Some(cpu) = self.running_cpu(thread)
+        {
+            self.put_prev_fair(cpu, false);
+        }
+        thread.lock().sched_group = group;
+        if let Some(queued) = queued {
+            self.add_thread(queued);
+        }
     }
 
-    /// Picks the next thread to run for `cpu_id` according to EEVDF rules:
+    /// Restrict `thread` to the CPUs in `mask` and move it off a CPU it may no longer use.
     ///
-    /// 1. A thread is **eligible** if its virtual runtime $v_i \le \text{min\_vruntime}$.
-    /// 2. If no threads are eligible, advance $\text{min\_vruntime}$ to $\min(v_i)$.
-    /// 3. Among eligible threads, pick the one with the earliest virtual deadline ($\min(d_i)$).
+    /// A queued thread is placed again right away. A thread running elsewhere is preempted
+    /// and moves when it is requeued; if it runs on the calling CPU, the caller has to
+    /// reschedule.
+    pub fn set_affinity(&mut self, thread: &Arc<Spinlock<Thread>>, mask: u32) {
+        let tid = {
+            let mut t_lock = thread.lock();
+            t_lock.cpus_allowed = mask;
+            t_lock.tid
+        };
+        if let Some((cpu, group)) = self.fair_queue_of(tid) {
+            self.fair_rq(cpu, group).set_cpus_allowed(EntityId::Thread(tid), mask);
+        }
+        if let Some(cpu) = self.queued_cpu(tid) {
+            if mask & (1 << cpu) == 0
+                && let Some(queued) = self.remove_thread(tid)
+            {
+                self.add_thread(queued);
+            }
+        } else if let Some(cpu) = self.running_cpu(thread)
+            && mask & (1 << cpu) == 0
+        {
+            self.resched_cpu(cpu);
+        }
+    }
+
+    /// If `thread` is running on another CPU, make that CPU reschedule so that it notices
+    /// a state change such as the thread becoming a zombie.
+    pub fn kick_thread(&self, thread: &Arc<Spinlock<Thread>>) {
+        if let Some(cpu) = self.running_cpu(thread) {
+            self.resched_cpu(cpu);
+        }
+    }
+
+    /// Picks the next thread to run for `cpu_id`.
+    ///
+    /// Real-time threads come first unless the CPU is throttled. A CPU without work first
+    /// pulls some from the busiest CPU; with nothing to pull it runs its idle thread.
     pub fn pick_next(&mut self, cpu_id: u32) -> Option<Arc<Spinlock<Thread>>> {
-        // Prevent secondary AP cores from concurrently executing BSP threads on the same stack
-        if cpu_id != 0 {
-            self.current_threads[cpu_id as usize] = None;
-            return None;
+        let cpu = cpu_id as usize;
+        if !self.has_queued(cpu_id) {
+            self.balance(cpu, true);
         }
 
-        if self.run_queue.is_empty() {
-            self.current_threads[cpu_id as usize] = None;
-            return None;
+        let rt_next = if self.rt_runnable(cpu) {
+            self.rt_rqs[cpu].pick()
+        } else {
+            None
+        };
+        let next = rt_next
+            .or_else(|| self.pick_fair(cpu))
+            .or_else(|| self.idle_threads[cpu].clone());
+        if let Some(thread) = &next {
+            thread.lock().cpu = cpu_id;
         }
 
-        // Step 1: Find min vruntime across all queued threads to advance system virtual time if needed.
-        let mut min_rq_vruntime = u64::MAX;
-        for thread in self.run_queue.values() {
-            let t_lock = thread.lock();
-            if t_lock.vruntime < min_rq_vruntime {
-                min_rq_vruntime = t_lock.vruntime;
+        self.current_threads[cpu] = next.clone();
+        next
+    }
+
+    /// Charges the currently running thread on `cpu_id` for the last tick: `vruntime` for
+    /// fair threads, the quantum for SCHED_RR and the throttling budget for both
+    /// real-time policies.
+    ///
+    /// `delta_ns` is the time elapsed since the last tick (e.g. 10 ms = 10_000_000 ns).
+    /// Returns whether the CPU should reschedule. Fair threads give up the CPU every tick;
+    /// real-time threads keep it until their quantum ends or the CPU is throttled.
+    pub fn tick(&mut self, cpu_id: u32, delta_ns: u64) -> bool {
+        let cpu = cpu_id as usize;
+        let mut rt_ns = 0;
+        let mut fair_curr = None;
+        let mut resched = true;
+        if !self.runs_idle(cpu)
+            && let Some(thread) = &self.current_threads[cpu]
+        {
+            let mut t_lock = thread.lock();
+
+            match t_lock.policy {
+                SchedPolicy::Fifo => {
+                    rt_ns = delta_ns;
+                    resched = false;
+                }
+                SchedPolicy::RoundRobin => {
+                    rt_ns = delta_ns;
+                    t_lock.rr_slice_left_ns = t_lock.rr_slice_left_ns.saturating_sub(delta_ns);
+                    resched = t_lock.rr_slice_left_ns == 0;
+                    if resched {
+                        t_lock.rr_slice_left_ns = rr_timeslice_ns();
+                    }
+                }
+                _ => {
+                    let weight = weight_of(&t_lock);
+                    let vruntime_delta = (delta_ns * NICE_0_WEIGHT as u64) / weight as u64;
+
+                    t_lock.vruntime = t_lock.vruntime.saturating_add(vruntime_delta);
+                    fair_curr = Some((t_lock.vruntime, weight));
+                }
             }
         }
+        if let Some((vruntime, weight)) = fair_curr {
+            self.update_fair_curr(cpu, vruntime, weight, delta_ns);
+        }
+
+        self.rt_rqs[cpu].account(delta_ns, rt_ns);
+        if rt_ns > 0 && self.rt_rqs[cpu].throttled() {
+            resched = true;
+        }
+
+        self.ticks[cpu] += 1;
+        if self.ticks[cpu].is_multiple_of(BALANCE_INTERVAL_TICKS) {
+            self.balance(cpu, false);
+            self.reap_groups();
+            self.kick_idle(cpu);
+        }
+        resched
+    }
+
+    /// How `cpu_id` needs the scheduler tick for what it runs now.
+    ///
+    /// Real-time threads need it for their quantum and for throttling, which also has to
+    /// run out while throttled threads wait; a single fair thread only needs it now and
+    /// then to keep its accounting current.
+    pub fn tick_mode(&self, cpu_id: u32) -> TickMode {
+        let cpu = cpu_id as usize;
+        if !self.is_online(cpu_id) || self.has_queued(cpu_id) || !self.rt_rqs[cpu].is_empty() {
+            TickMode::Periodic
+        } else if self.runs_idle(cpu) {
+            TickMode::Idle
+        } else if self.running_rt_prio(cpu).is_some() {
+            TickMode::Periodic
+        } else {
+            TickMode::Single
+        }
+    }
+
+    /// Voluntarily yield the CPU for `cpu_id`.
+    pub fn yield_current(&mut self, cpu_id: u32) {
+        self.requeue_current(cpu_id, true);
+    }
+
+    /// Put the current thread of `cpu_id` back in the run queue without charging it a
+    /// slice, as when another thread needs to run now.
+    pub fn preempt_current(&mut self, cpu_id: u32) {
+        self.requeue_current(cpu_id, false);
+    }
+
+    /// Blocks the current thread on `cpu_id` (removes it from CPU without putting back in
+    /// run queue).
+    ///
+    /// A thread that was woken again before it got here stays runnable.
+    pub fn block_current(&mut self, cpu_id: u32) {
+        let cpu = cpu_id as usize;
+        let is_idle = self.runs_idle(cpu);
+        let Some(thread) = self.current_threads[cpu].take() else {
+            return;
+        };
+        self.put_prev_fair(cpu, false);
+        if is_idle {
+            return;
+        }
+
+        let mut t_lock = thread.lock();
+        if matches!(t_lock.state, ThreadState::Running | ThreadState::Ready) {
+            t_lock.state = ThreadState::Ready;
+            drop(t_lock);
+            self.requeue(cpu, thread, false);
+        }
+    }
+
+    /// Take the current thread off `cpu_id` and queue it again, unless it is the idle
+    /// thread or has exited. With `charge_slice`, it goes behind the other queued threads.
+    fn requeue_current(&mut self, cpu_id: u32, charge_slice: bool) {
+        let cpu = cpu_id as usize;
+        let is_idle = self.runs_idle(cpu);
+        let Some(thread) = self.current_threads[cpu].take() else {
+            return;
+        };
+        self.put_prev_fair(cpu, charge_slice);
+        if is_idle {
+            return;
+        }
+
+        let mut t_lock = thread.lock();
+        if t_lock.state == ThreadState::Zombie {
+            return;
+        }
+        if charge_slice && !t_lock.is_realtime() {
+            let vslice = vslice(&t_lock);
+            let group = t_lock.sched_group.as_ref().map(|group| group.id);
+            let min_vruntime = self.fair_rq_ref(cpu, group).min_vruntime;
 
-        if min_rq_vruntime != u64::MAX && self.min_vruntime < min_rq_vruntime {
-            self.min_vruntime = min_rq_vruntime;
+            // Advance vruntime and virtual deadline so other queued threads run first
+            t_lock.vruntime = t_lock.vruntime.max(min_vruntime).saturating_add(vslice);
+            t_lock.vdeadline = t_lock.vruntime.saturating_add(vslice);
         }
+        // Sleeping and stopped threads keep their state; they are only passing through.
+        if t_lock.state == ThreadState::Running {
+            t_lock.state = ThreadState::Ready;
+        }
+        drop(t_lock);
 
-        // Step 2: Select the eligible thread with the earliest virtual deadline.
-        // Eligibility: vruntime <= self.min_vruntime
-        // Metric: (vdeadline, vruntime, tid)
-        let mut best_tid: Option<ThreadId> = None;
-        let mut best_deadline = u64::MAX;
-        let mut best_vruntime = u64::MAX;
+        // A preempted real-time thread stays at the front of its priority's list.
+        self.requeue(cpu, thread, !charge_slice);
+    }
 
-        for (&tid, thread) in self.run_queue.iter() {
+    /// Put a thread that just left `cpu` back in its run queue, keeping its virtual time,
+    /// or on another CPU if its affinity no longer includes `cpu`. Real-time threads go to
+    /// the front of their list with `at_head`, otherwise to the back.
+    fn requeue(&mut self, cpu: usize, thread: Arc<Spinlock<Thread>>, at_head: bool) {
+        let (tid, allowed, rt_prio) = {
             let t_lock = thread.lock();
-            let is_eligible = t_lock.vruntime <= self.min_vruntime;
-
-            if is_eligible {
-                if t_lock.vdeadline < best_deadline
-                    || (t_lock.vdeadline == best_deadline && t_lock.vruntime < best_vruntime)
-                {
-                    best_deadline = t_lock.vdeadline;
-                    best_vruntime = t_lock.vruntime;
-                    best_tid = Some(tid);
+            let rt_prio = t_lock.is_realtime().then_some(t_lock.rt_priority);
+            (t_lock.tid, t_lock.cpus_allowed, rt_prio)
+        };
+        let target = if allowed & (1 << cpu) != 0 || allowed & self.online == 0 {
+            cpu
+        } else if rt_prio.is_some() {
+            self.select_cpu_rt(cpu, allowed)
+        } else {
+            self.select_cpu(cpu, allowed)
+        };
+        if target != cpu {
+            self.migrate(&thread, cpu, target);
+        }
+
+        match rt_prio {
+            Some(prio) => {
+                self.rt_rqs[target].insert(tid, prio, thread, at_head);
+                if target != cpu {
+                    self.check_preempt_rt(target, prio);
+                }
+            }
+            None => {
+                self.enqueue_fair(target, thread, false);
+                if target != cpu && self.runs_idle(target) {
+                    self.resched_cpu(target);
                 }
             }
         }
+    }
 
-        // Fallback: If no thread was eligible due to rounding/bounds, pick the one with earliest deadline.
-        if best_tid.is_none() {
-            for (&tid, thread) in self.run_queue.iter() {
-                let t_lock = thread.lock();
-                if t_lock.vdeadline < best_deadline
-                    || (t_lock.vdeadline == best_deadline && t_lock.vruntime < best_vruntime)
-                {
-                    best_deadline = t_lock.vdeadline;
-                    best_vruntime = t_lock.vruntime;
-                    best_tid = Some(tid);
-                }
+    /// Whether `cpu` has nothing but its idle thread to run right now.
+    pub(super) fn runs_idle(&self, cpu: usize) -> bool {
+        match (&self.current_threads[cpu], &self.idle_threads[cpu]) {
+            (None, _) => true,
+            (Some(current), Some(idle)) => Arc::ptr_eq(current, idle),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether real-time threads are queued on `cpu` and may run there now.
+    fn rt_runnable(&self, cpu: usize) -> bool {
+        !self.rt_rqs[cpu].is_empty() && !self.rt_rqs[cpu].throttled()
+    }
+
+    /// Real-time priority of the thread running on `cpu`, if it is a real-time thread.
+    fn running_rt_prio(&self, cpu: usize) -> Option<u8> {
+        if self.runs_idle(cpu) {
+            return None;
+        }
+        let current = self.current_threads[cpu].as_ref()?;
+        let t_lock = current.lock();
+        t_lock.is_realtime().then_some(t_lock.rt_priority)
+    }
+
+    /// Number of runnable threads on `cpu`, including the one running there.
+    fn load(&self, cpu: usize) -> usize {
+        self.nr_fair_queued(cpu) + self.rt_rqs[cpu].len() + usize::from(!self.runs_idle(cpu))
+    }
+
+    /// Number of runnable threads on all CPUs, including the ones running.
+    pub fn nr_running(&self) -> usize {
+        self.online_cpus().map(|cpu| self.load(cpu)).sum()
+    }
+
+    fn online_cpus(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CPUS).filter(|&cpu| self.online & (1 << cpu) != 0)
+    }
+
+    /// CPU currently running `thread`, if any.
+    fn running_cpu(&self, thread: &Arc<Spinlock<Thread>>) -> Option<usize> {
+        (0..MAX_CPUS).find(|&cpu| {
+            self.current_threads[cpu]
+                .as_ref()
+                .is_some_and(|current| Arc::ptr_eq(current, thread))
+        })
+    }
+
+    /// CPU whose run queues hold `tid`, if any.
+    fn queued_cpu(&self, tid: ThreadId) -> Option<usize> {
+        self.fair_queue_of(tid)
+            .map(|(cpu, _)| cpu)
+            .or_else(|| (0..MAX_CPUS).find(|&cpu| self.rt_rqs[cpu].contains(tid)))
+    }
+
+    /// Fair run queue of `group` on `cpu`; the root queue for `None` or a group without
+    /// threads.
+    fn fair_rq(&mut self, cpu: usize, group: Option<GroupId>) -> &mut RunQueue {
+        match group.and_then(|id| self.groups.get_mut(&id)) {
+            Some(task_group) => &mut task_group.cpus[cpu].rq,
+            None => &mut self.rqs[cpu],
+        }
+    }
+
+    /// Shared counterpart of [`Scheduler::fair_rq`].
+    fn fair_rq_ref(&self, cpu: usize, group: Option<GroupId>) -> &RunQueue {
+        match group.and_then(|id| self.groups.get(&id)) {
+            Some(task_group) => &task_group.cpus[cpu].rq,
+            None => &self.rqs[cpu],
+        }
+    }
+
+    /// Group the entity of group `id` is queued in, `None` for the root queue.
+    fn parent_of(&self, id: GroupId) -> Option<GroupId> {
+        let task_group = self.groups.get(&id)?;
+        task_group.group.parent.as_ref().map(|parent| parent.id)
+    }
+
+    /// Set up run queues for `group` and its ancestors, unless they already have some.
+    fn attach_group(&mut self, group: &Arc<SchedGroup>) {
+        let mut group = Some(group);
+        while let Some(g) = group {
+            if self.groups.contains_key(&g.id) {
+                break;
             }
+            let task_group = TaskGroup {
+                group: g.clone(),
+                cpus: [const { GroupRunQueue::new() }; MAX_CPUS],
+            };
+            self.groups.insert(g.id, task_group);
+            group = g.parent.as_ref();
         }
+    }
 
-        let selected_tid = best_tid?;
-        let selected_thread = self.run_queue.remove(&selected_tid)?;
+    /// Weight of the entity of group `id` on `cpu`: the weight of the group, split between
+    /// CPUs in proportion to the load of its members there.
+    fn group_shares(&self, id: GroupId, cpu: usize) -> u32 {
+        let Some(task_group) = self.groups.get(&id) else {
+            return NICE_0_WEIGHT;
+        };
+        let weight = task_group.group.weight();
+        let total: u64 = task_group.cpus.iter().map(|group_rq| group_rq.rq.load()).sum();
+        if total == 0 {
+            return weight;
+        }
+        let local = task_group.cpus[cpu].rq.load();
+        ((weight as u64 * local / total) as u32).max(MIN_SHARES)
+    }
 
-        let mut t_lock = selected_thread.lock();
-        t_lock.state = ThreadState::Running;
-        let selected_vruntime = t_lock.vruntime;
-        drop(t_lock);
+    /// Queue a fair thread on `cpu` in the run queue of its group, then the entities of
+    /// the group and its ancestors that are not on their parents' queues yet. With `fresh`
+    /// the thread gets a new virtual deadline, as when it becomes runnable.
+    fn enqueue_fair(&mut self, cpu: usize, thread: Arc<Spinlock<Thread>>, fresh: bool) {
+        let group = thread.lock().sched_group.clone();
+        if let Some(group) = &group {
+            self.attach_group(group);
+        }
+        let group = group.map(|group| group.id);
 
-        // Advance system virtual time monotonically to selected thread's vruntime
-        if selected_vruntime > self.min_vruntime {
-            self.min_vruntime = selected_vruntime;
+        let rq = self.fair_rq(cpu, group);
+        if fresh {
+            rq.enqueue(thread);
+        } else {
+            rq.insert(thread);
         }
 
-        self.current_threads[cpu_id as usize] = Some(selected_thread.clone());
-        Some(selected_thread)
+        let mut group = group;
+        while let Some(id) = group {
+            let parent = self.parent_of(id);
+            if self.fair_rq_ref(cpu, parent).holds(EntityId::Group(id)) {
+                break;
+            }
+            let weight = self.group_shares(id, cpu);
+            let vruntime = self.groups.get(&id).map_or(0, |tg| tg.cpus[cpu].vruntime);
+            let parent_rq = self.fair_rq(cpu, parent);
+            // Like a waking thread, a group does not get to catch up on time it was idle.
+            let vruntime = vruntime.max(parent_rq.min_vruntime);
+            parent_rq.insert_entity(Entity {
+                id: EntityId::Group(id),
+                thread: None,
+                vruntime,
+                vdeadline: vruntime.saturating_add(group_vslice(weight)),
+                weight,
+                cpus_allowed: u32::MAX,
+            });
+            group = parent;
+        }
     }
 
-    /// Updates the `vruntime` of the currently running thread on `cpu_id`.
-    ///
-    /// `delta_ns` is the time elapsed since the last tick (e.g. 10 ms = 10_000_000 ns).
-    pub fn tick(&mut self, cpu_id: u32, delta_ns: u64) {
-        if let Some(thread) = &self.current_threads[cpu_id as usize] {
-            let mut t_lock = thread.lock();
+    /// Remove the fair thread `tid` from the queue of `group` on `cpu`, then dequeue the
+    /// entities of the group and its ancestors that have nothing left to run there.
+    fn remove_fair(
+        &mut self,
+        cpu: usize,
+        group: Option<GroupId>,
+        tid: ThreadId,
+    ) -> Option<Arc<Spinlock<Thread>>> {
+        let thread = self.fair_rq(cpu, group).remove(tid)?;
 
-            let weight = if t_lock.weight > 0 {
-                t_lock.weight
-            } else {
-                NICE_0_WEIGHT
+        let mut group = group;
+        while let Some(id) = group {
+            if !self.fair_rq_ref(cpu, Some(id)).is_idle() {
+                break;
+            }
+            let parent = self.parent_of(id);
+            // A running group is dealt with when it is put back.
+            let Some(entity) = self.fair_rq(cpu, parent).dequeue(EntityId::Group(id)) else {
+                break;
             };
-            let vruntime_delta = (delta_ns * NICE_0_WEIGHT as u64) / weight as u64;
+            if let Some(task_group) = self.groups.get_mut(&id) {
+                task_group.cpus[cpu].vruntime = entity.vruntime;
+            }
+            group = parent;
+        }
+        Some(thread)
+    }
 
-            t_lock.vruntime = t_lock.vruntime.saturating_add(vruntime_delta);
+    /// Pick the next fair thread of `cpu`, descending from the root queue through the
+    /// picked groups. Every entity on the way becomes the running one of its queue.
+    fn pick_fair(&mut self, cpu: usize) -> Option<Arc<Spinlock<Thread>>> {
+        let mut group = None;
+        loop {
+            let rq = match group {
+                None => &mut self.rqs[cpu],
+                Some(id) => &mut self.groups.get_mut(&id)?.cpus[cpu].rq,
+            };
+            let entity = rq.pick()?;
+            match entity.id {
+                EntityId::Thread(_) => return RunQueue::release(entity, true),
+                EntityId::Group(id) => {
+                    if let Some(task_group) = self.groups.get_mut(&id) {
+                        task_group.cpus[cpu].vruntime = entity.vruntime;
+                    }
+                    group = Some(id);
+                }
+            }
         }
     }
 
-    /// Voluntarily yield the CPU for `cpu_id`.
-    pub fn yield_current(&mut self, cpu_id: u32) {
-        if let Some(thread) = self.current_threads[cpu_id as usize].take() {
-            let mut t_lock = thread.lock();
-            let weight = if t_lock.weight > 0 {
-                t_lock.weight
-            } else {
-                NICE_0_WEIGHT
+    /// Groups running on `cpu` from the root down, each with the group it is queued in.
+    fn running_groups(&self, cpu: usize) -> Vec<(Option<GroupId>, GroupId)> {
+        let mut path = Vec::new();
+        let mut group = None;
+        while let Some(EntityId::Group(id)) = self.fair_rq_ref(cpu, group).curr_id() {
+            if !self.groups.contains_key(&id) {
+                break;
+            }
+            path.push((group, id));
+            group = Some(id);
+        }
+        path
+    }
+
+    /// Put back the fair entities running on `cpu` as its thread leaves: the thread, then
+    /// its groups from the innermost out. A group goes back to its parent's queue if it
+    /// still has members on this CPU. With `charge_slice`, groups are charged a slice like
+    /// the thread, so that they do not gain on the threads they compete with.
+    fn put_prev_fair(&mut self, cpu: usize, charge_slice: bool) {
+        let path = self.running_groups(cpu);
+        let innermost = path.last().map(|&(_, id)| id);
+        self.fair_rq(cpu, innermost).put_curr();
+
+        for (parent, id) in path.into_iter().rev() {
+            let members = !self.fair_rq_ref(cpu, Some(id)).is_idle();
+            let parent_rq = self.fair_rq(cpu, parent);
+            let Some((_, mut vruntime, weight)) = parent_rq.put_curr() else {
+                continue;
             };
-            let slice_ns = if t_lock.slice_ns > 0 {
-                t_lock.slice_ns
-            } else {
-                BASE_SLICE_NS
+            if charge_slice {
+                let vslice = group_vslice(weight);
+                vruntime = vruntime.max(parent_rq.min_vruntime).saturating_add(vslice);
+            }
+            if members {
+                parent_rq.insert_entity(Entity {
+                    id: EntityId::Group(id),
+                    thread: None,
+                    vruntime,
+                    vdeadline: vruntime.saturating_add(group_vslice(weight)),
+                    weight,
+                    cpus_allowed: u32::MAX,
+                });
+            } else if let Some(task_group) = self.groups.get_mut(&id) {
+                task_group.cpus[cpu].vruntime = vruntime;
+            }
+        }
+    }
+
+    /// Charge `delta_ns` of runtime to the fair entities running on `cpu`: the thread, now
+    /// at `vruntime` with `weight`, and every group above it, whose weight follows the
+    /// load of its members.
+    fn update_fair_curr(&mut self, cpu: usize, vruntime: u64, weight: u32, delta_ns: u64) {
+        let path = self.running_groups(cpu);
+        let innermost = path.last().map(|&(_, id)| id);
+        self.fair_rq(cpu, innermost).update_curr(vruntime, weight);
+
+        for (parent, id) in path.into_iter().rev() {
+            let shares = self.group_shares(id, cpu);
+            let parent_rq = self.fair_rq(cpu, parent);
+            let Some((_, vruntime, _)) = parent_rq.curr else {
+                continue;
             };
-            let vslice = (slice_ns * NICE_0_WEIGHT as u64) / weight as u64;
+            let vruntime_delta = (delta_ns * NICE_0_WEIGHT as u64) / shares as u64;
+            let vruntime = vruntime.saturating_add(vruntime_delta);
+            parent_rq.update_curr(vruntime, shares);
+            if let Some(task_group) = self.groups.get_mut(&id) {
+                task_group.cpus[cpu].vruntime = vruntime;
+            }
+        }
+    }
 
-            // Advance vruntime and virtual deadline so other queued threads run first
-            t_lock.vruntime = t_lock.vruntime.max(self.min_vruntime).saturating_add(vslice);
+    /// Drop the run queues of groups that no thread belongs to any more.
+    fn reap_groups(&mut self) {
+        self.groups.retain(|_, task_group| {
+            Arc::strong_count(&task_group.group) > 1
+                || task_group.cpus.iter().any(|group_rq| !group_rq.rq.is_idle())
+        });
+    }
+
+    /// CPU and group whose fair run queue holds `tid`, if any.
+    fn fair_queue_of(&self, tid: ThreadId) -> Option<(usize, Option<GroupId>)> {
+        let id = EntityId::Thread(tid);
+        (0..MAX_CPUS).find_map(|cpu| {
+            if self.rqs[cpu].contains(id) {
+                return Some((cpu, None));
+            }
+            self.groups
+                .iter()
+                .find(|(_, task_group)| task_group.cpus[cpu].rq.contains(id))
+                .map(|(&group, _)| (cpu, Some(group)))
+        })
+    }
+
+    /// Number of fair threads queued on `cpu`, in the root queue and all groups.
+    fn nr_fair_queued(&self, cpu: usize) -> usize {
+        let grouped: usize = self
+            .groups
+            .values()
+            .map(|task_group| task_group.cpus[cpu].rq.nr_threads())
+            .sum();
+        self.rqs[cpu].nr_threads() + grouped
+    }
+
+    /// The fair thread queued on `src` to hand over to CPU `dst`, with its group; see
+    /// [`RunQueue::migration_candidate`]. Threads of the root queue are tried first.
+    fn fair_migration_candidate(
+        &self,
+        src: usize,
+        most_urgent: bool,
+        dst: usize,
+    ) -> Option<(Option<GroupId>, ThreadId)> {
+        let grouped = self
+            .groups
+            .iter()
+            .map(|(&id, task_group)| (Some(id), &task_group.cpus[src].rq));
+        core::iter::once((None, &self.rqs[src]))
+            .chain(grouped)
+            .find_map(|(group, rq)| Some((group, rq.migration_candidate(most_urgent, dst)?)))
+    }
+
+    /// Choose the CPU for a thread that becomes runnable after last running on `prev_cpu`.
+    ///
+    /// Only online CPUs in `allowed` are considered, unless none of them is online. The
+    /// previous CPU is kept while it is idle, since its caches may still be warm;
+    /// otherwise the least loaded candidate wins. Before any CPU is online everything
+    /// stays on the boot CPU.
+    fn select_cpu(&self, prev_cpu: usize, allowed: u32) -> usize {
+        if let Some(cpu) = self.bound_offline_cpu(allowed) {
+            return cpu;
+        }
+        if self.online == 0 {
+            return 0;
+        }
+        let candidates = match self.online & allowed {
+            0 => self.online,
+            mask => mask,
+        };
+        if prev_cpu < MAX_CPUS && candidates & (1 << prev_cpu) != 0 && self.load(prev_cpu) == 0 {
+            return prev_cpu;
+        }
+        self.online_cpus()
+            .filter(|&cpu| candidates & (1 << cpu) != 0)
+            .min_by_key(|&cpu| (self.load(cpu), cpu != prev_cpu))
+            .unwrap_or(0)
+    }
+
+    /// The CPU a thread bound to just that CPU waits for while it is not online yet, like
+    /// the per-CPU kernel threads of the boot CPU, which joins scheduling last.
+    fn bound_offline_cpu(&self, allowed: u32) -> Option<usize> {
+        let cpu = allowed.trailing_zeros() as usize;
+        (allowed.is_power_of_two() && cpu < MAX_CPUS && self.online & allowed == 0).then_some(cpu)
+    }
+
+    /// Choose the CPU for a real-time thread that becomes runnable after last running on
+    /// `prev_cpu`: the allowed CPU running the least important work, preferring idle
+    /// CPUs, then CPUs running fair threads, then lower real-time priorities.
+    fn select_cpu_rt(&self, prev_cpu: usize, allowed: u32) -> usize {
+        if let Some(cpu) = self.bound_offline_cpu(allowed) {
+            return cpu;
+        }
+        if self.online == 0 {
+            return 0;
+        }
+        let candidates = match self.online & allowed {
+            0 => self.online,
+            mask => mask,
+        };
+        self.online_cpus()
+            .filter(|&cpu| candidates & (1 << cpu) != 0)
+            .min_by_key(|&cpu| {
+                let running = self.running_rt_prio(cpu).map_or(0, |prio| prio as u32 + 1);
+                (running, self.rt_rqs[cpu].len(), self.load(cpu), cpu != prev_cpu)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Preempt `cpu` if a real-time thread of priority `prio` was just queued there and
+    /// outranks what it is running.
+    fn check_preempt_rt(&self, cpu: usize, prio: u8) {
+        if self.rt_rqs[cpu].throttled() {
+            return;
+        }
+        if self.running_rt_prio(cpu).is_none_or(|running| running < prio) {
+            self.preempt_cpu(cpu);
+        }
+    }
+
+    /// Carry a thread's virtual time over from the run queue of `from` to that of `to`.
+    ///
+    /// Each CPU keeps its own virtual clock, so only the lag relative to the average
+    /// virtual time $V$ is meaningful across CPUs.
+    fn migrate(&self, thread: &Arc<Spinlock<Thread>>, from: usize, to: usize) {
+        let mut t_lock = thread.lock();
+        if from != to && from < MAX_CPUS {
+            let group = t_lock.sched_group.as_ref().map(|group| group.id);
+            let src_min = self.fair_rq_ref(from, group).avg_vruntime();
+            let dst_min = self.fair_rq_ref(to, group).avg_vruntime();
+            let vslice = t_lock.vdeadline.saturating_sub(t_lock.vruntime);
+            t_lock.vruntime = t_lock.vruntime.saturating_sub(src_min).saturating_add(dst_min);
             t_lock.vdeadline = t_lock.vruntime.saturating_add(vslice);
-            t_lock.state = ThreadState::Ready;
-            let tid = t_lock.tid;
-            drop(t_lock);
+        }
+        t_lock.cpu = to as u32;
+    }
+
+    /// Pull one queued thread from the busiest CPU onto `cpu`.
+    ///
+    /// An idle CPU takes work from any CPU with threads waiting; a busy one only evens out
+    /// an imbalance of at least two threads, so that threads do not bounce back and forth.
+    /// CPUs without a queued thread allowed on `cpu` are passed over.
+    fn balance(&mut self, cpu: usize, idle: bool) {
+        if self.online & (1 << cpu) == 0 {
+            return;
+        }
+        if idle && self.pull_rt(cpu) {
+            return;
+        }
 
-            self.run_queue.insert(tid, thread);
+        let local = self.load(cpu);
+        let busiest = self
+            .online_cpus()
+            .filter(|&other| other != cpu && !self.rqs[other].is_empty())
+            .filter_map(|other| {
+                let (group, tid) = self.fair_migration_candidate(other, idle, cpu)?;
+                Some((other, group, tid))
+            })
+            .max_by_key(|&(other, _, _)| self.load(other));
+        let Some((src, group, tid)) = busiest else {
+            return;
+        };
+        if !idle && self.load(src) < local + 2 {
+            return;
         }
+
+        let Some(thread) = self.remove_fair(src, group, tid) else {
+            return;
+        };
+        self.migrate(&thread, src, cpu);
+        self.enqueue_fair(cpu, thread, false);
     }
 
-    /// Blocks the current thread on `cpu_id` (removes it from CPU without putting back in run queue).
-    pub fn block_current(&mut self, cpu_id: u32) {
-        self.current_threads[cpu_id as usize] = None;
+    /// Pull the most important real-time thread waiting on another CPU onto `cpu`, which
+    /// ran out of work. Returns whether a thread was pulled.
+    fn pull_rt(&mut self, cpu: usize) -> bool {
+        if self.rt_rqs[cpu].throttled() {
+            return false;
+        }
+        let candidate = self
+            .online_cpus()
+            .filter(|&other| other != cpu && !self.rt_rqs[other].is_empty())
+            .filter_map(|other| {
+                let (prio, tid) = self.rt_rqs[other].migration_candidate(cpu)?;
+                Some((prio, other, tid))
+            })
+            .max_by_key(|&(prio, _, _)| prio);
+        let Some((prio, src, tid)) = candidate else {
+            return false;
+        };
+        let Some(thread) = self.rt_rqs[src].remove(tid) else {
+            return false;
+        };
+        self.migrate(&thread, src, cpu);
+        self.rt_rqs[cpu].insert(tid, prio, thread, false);
+        true
+    }
+
+    /// Wake an idle CPU whose tick is stopped to pull work from `cpu`, which has threads
+    /// waiting; idle CPUs without a tick do not balance on their own.
+    fn kick_idle(&self, cpu: usize) {
+        if !self.has_queued(cpu as u32) {
+            return;
+        }
+        let idle = self
+            .online_cpus()
+            .find(|&other| other != cpu && self.runs_idle(other) && tick::is_stopped(other as u32));
+        if let Some(idle) = idle {
+            self.resched_cpu(idle);
+        }
+    }
+
+    /// Make `cpu` run the scheduler soon.
+    fn resched_cpu(&self, cpu: usize) {
+        if cpu as u32 != crate::arch::cpu_id() {
+            crate::arch::interrupts::send_reschedule(cpu as u32);
+        }
+    }
+
+    /// Make `cpu` reschedule as soon as it can, even if it is the calling CPU: there the
+    /// reschedule interrupt is taken once interrupts are enabled again.
+    fn preempt_cpu(&self, cpu: usize) {
+        crate::arch::interrupts::send_reschedule(cpu as u32);
     }
 }
@@ -0,0 +1,67 @@
+//! Scheduling groups for hierarchical fairness.
+//!
+//! A group is scheduled as a single entity in the fair run queue of its parent and owns a
+//! run queue of its own for its members, so CPU time is first shared between groups and
+//! then between the threads of each group. Threads without a group, like kernel threads,
+//! compete directly in the root queue.
+//!
+//! Every session gets its own group (autogroup): `setsid` creates one, and children stay
+//! in the group of their parent. One user running `make -j32` in one terminal then gets as
+//! much CPU time as a shell in another, rather than 32 times as much. The nice value of an
+//! autogroup is set through `/proc/[pid]/autogroup`.
+
+use crate::sched::nice::Nice;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicI8, AtomicU64, Ordering};
+
+/// Identifies a scheduling group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GroupId(pub u64);
+
+impl core::fmt::Display for GroupId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+static NEXT_GROUP_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A scheduling group, shared by its member threads.
+///
+/// Only the identity and the tunables live here; the run queues of the group belong to
+/// the scheduler, which sets them up while the group has runnable threads.
+#[derive(Debug)]
+pub struct SchedGroup {
+    pub id: GroupId,
+
+    /// Group the entity of this group is queued in, or `None` for the root queue.
+    pub parent: Option<Arc<SchedGroup>>,
+
+    /// Nice value deciding the weight of the group in its parent.
+    nice: AtomicI8,
+}
+
+impl SchedGroup {
+    /// Create a group below `parent` with the default nice value.
+    pub fn new(parent: Option<Arc<SchedGroup>>) -> Arc<Self> {
+        Arc::new(Self {
+            id: GroupId(NEXT_GROUP_ID.fetch_add(1, Ordering::Relaxed)),
+            parent,
+            nice: AtomicI8::new(Nice::DEFAULT.value()),
+        })
+    }
+
+    pub fn nice(&self) -> Nice {
+        Nice::from_raw_clamped(self.nice.load(Ordering::Relaxed))
+    }
+
+    /// Change the nice value; the scheduler picks up the new weight on its next tick.
+    pub fn set_nice(&self, nice: Nice) {
+        self.nice.store(nice.value(), Ordering::Relaxed);
+    }
+
+    /// Weight of the group across all CPUs.
+    pub fn weight(&self) -> u32 {
+        self.nice().weight()
+    }
+}
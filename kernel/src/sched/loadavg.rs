@@ -0,0 +1,115 @@
+//! System load averages, as reported by `sysinfo(2)` and `/proc/loadavg`.
+//!
+//! Every [`LOAD_FREQ_NS`] the number of active threads (running or queued on any CPU)
+//! is folded into exponentially decaying averages over 1, 5 and 15 minutes, in the same
+//! fixed point arithmetic as Linux. Threads in uninterruptible sleep would count too, but
+//! nothing sleeps uninterruptibly here. The averages are folded from the scheduler tick,
+//! and by readers for periods that passed while every CPU idled with its tick stopped.
+
+use super::{SCHEDULER, Scheduler};
+use crate::sync::spinlock::Spinlock;
+use alloc::string::String;
+use core::fmt::Write;
+
+/// Bits of fraction in the fixed point averages.
+pub const FSHIFT: u32 = 11;
+/// 1.0 in fixed point.
+pub const FIXED_1: u64 = 1 << FSHIFT;
+
+/// Interval between samples, 5 s as in Linux.
+pub const LOAD_FREQ_NS: u64 = 5_000_000_000;
+
+/// Decay per sample of the 1, 5 and 15 minute averages: `FIXED_1 / exp(5 s / period)`.
+const EXP: [u64; 3] = [1884, 2014, 2037];
+
+/// Periods folded at most at once; after that long idle the averages have decayed to 0.
+const MAX_MISSED: u64 = 1024;
+
+struct LoadAvg {
+    /// 1, 5 and 15 minute averages in fixed point.
+    avenrun: [u64; 3],
+    /// When the next sample is due, on the monotonic clock.
+    next_ns: u64,
+}
+
+static LOADAVG: Spinlock<LoadAvg> = Spinlock::new(LoadAvg {
+    avenrun: [0; 3],
+    next_ns: LOAD_FREQ_NS,
+});
+
+/// `load * exp + active * (1 - exp)`, rounded, in fixed point.
+fn calc_load(load: u64, exp: u64, active: u64) -> u64 {
+    let mut new_load = load * exp + active * (FIXED_1 - exp);
+    if active >= load {
+        new_load += FIXED_1 - 1;
+    }
+    new_load / FIXED_1
+}
+
+impl LoadAvg {
+    /// Fold the samples due by `now_ns`, with `active` threads for each.
+    fn fold(&mut self, now_ns: u64, active: usize) {
+        if now_ns < self.next_ns {
+            return;
+        }
+        let periods = (now_ns - self.next_ns) / LOAD_FREQ_NS + 1;
+        let active = active as u64 * FIXED_1;
+        for _ in 0..periods.min(MAX_MISSED) {
+            for (load, exp) in self.avenrun.iter_mut().zip(EXP) {
+                *load = calc_load(*load, exp, active);
+            }
+        }
+        self.next_ns += periods * LOAD_FREQ_NS;
+    }
+}
+
+/// Sample the load if due. Called from the scheduler tick with the scheduler locked.
+pub fn update(sched: &Scheduler, now_ns: u64) {
+    let mut loadavg = LOADAVG.lock();
+    if now_ns >= loadavg.next_ns {
+        loadavg.fold(now_ns, sched.nr_running());
+    }
+}
+
+/// The 1, 5 and 15 minute load averages, in fixed point with [`FSHIFT`] fraction bits.
+pub fn averages() -> [u64; 3] {
+    crate::arch::without_interrupts(|| {
+        let now = crate::time::now_ns();
+        let sched = SCHEDULER.lock();
+        let mut loadavg = LOADAVG.lock();
+        loadavg.fold(now, sched.nr_running());
+        loadavg.avenrun
+    })
+}
+
+/// Number of threads running or queued on any CPU.
+pub fn nr_running() -> usize {
+    crate::arch::without_interrupts(|| SCHEDULER.lock().nr_running())
+}
+
+/// `/proc/loadavg`: the three averages, running and total threads, and the last PID.
+fn show_loadavg() -> String {
+    let mut out = String::new();
+    for load in averages() {
+        // Round to two decimals.
+        let load = load + FIXED_1 / 200;
+        let frac = ((load & (FIXED_1 - 1)) * 100) >> FSHIFT;
+        let _ = write!(out, "{}.{:02} ", load >> FSHIFT, frac);
+    }
+    let _ = writeln!(
+        out,
+        "{}/{} {}",
+        nr_running(),
+        crate::proc::nr_threads(),
+        crate::proc::last_pid()
+    );
+    out
+}
+
+/// Register `/proc/loadavg`.
+pub fn init() -> Result<(), &'static str> {
+    crate::fs::procfs::register_proc_entry("loadavg", show_loadavg, None);
+    Ok(())
+}
+
+crate::fs_initcall!(init);
@@ -1,39 +1,209 @@
+pub mod cputime;
 pub mod fair;
+pub mod group;
+pub mod loadavg;
 pub mod nice;
+pub mod policy;
+pub mod rt;
+pub mod tree;
 
 use crate::arch::cpu::context::{switch_context, switch_context_to};
-
-
+use crate::arch::cpu::stack::KernelStack;
+use crate::proc::thread::thread::KERNEL_THREAD_STACK_SIZE;
+use crate::proc::thread::{Thread, ThreadState, next_tid};
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, HeldLocks};
 use crate::sync::spinlock::Spinlock;
-use alloc::sync::Arc;
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-pub use fair::{BASE_SLICE_NS, MAX_CPUS, Scheduler};
+pub use fair::{BASE_SLICE_NS, CPU_MASK_ALL, MAX_CPUS, Scheduler};
 pub use nice::{nice_to_weight, Nice, MAX_NICE, MIN_NICE, NICE_0_WEIGHT};
 
 /// Global EEVDF Scheduler instance
 pub static SCHEDULER: Spinlock<Scheduler> = Spinlock::new(Scheduler::new());
 
+/// Why the current thread leaves the CPU in [`schedule`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SwitchReason {
+    /// Time slice used up or CPU given away: requeue behind the other threads.
+    Yield,
+    /// Sleeping or exiting: requeue only if woken in the meantime.
+    Block,
+    /// Another thread was made runnable here: requeue without penalty.
+    Preempt,
+}
+
 /// The main scheduling routine.
 /// If `yielding` is true, the current thread is placed back in the run queue.
 /// If `yielding` is false, the current thread is blocked (or exiting) and is not put back.
 pub fn schedule(yielding: bool) {
+    if yielding {
+        switch(SwitchReason::Yield);
+    } else {
+        switch(SwitchReason::Block);
+    }
+}
+
+/// CPUs that run threads or will once booted: the online ones and the calling CPU, which
+/// during boot is the boot CPU, joining scheduling only when it starts init.
+///
+/// Takes no lock, so it can be called under any other lock.
+pub fn possible_mask() -> u32 {
+    let online = fair::online_mask();
+    let cpu = crate::arch::cpu_id();
+    if (cpu as usize) < MAX_CPUS { online | (1 << cpu) } else { online }
+}
+
+/// Reschedule after a reschedule IPI, keeping the current thread's place in its queue.
+pub fn preempt() {
+    switch(SwitchReason::Preempt);
+}
+
+/// Create the idle thread of `cpu_id` and let the scheduler place threads on that CPU.
+///
+/// The CPU only takes part in scheduling after this; until then `schedule` leaves
+/// whatever it is running alone, which keeps boot code on its own stack.
+pub fn init_cpu(cpu_id: u32) {
+    if cpu_id as usize >= MAX_CPUS {
+        log::warn!("sched: CPU {} is beyond MAX_CPUS and stays idle.", cpu_id);
+        return;
+    }
+
+    let mut idle = Thread::new(next_tid(), format!("idle/{}", cpu_id), 0, Weak::new());
+    let mut kernel_stack = KernelStack::new(KERNEL_THREAD_STACK_SIZE);
+    idle.context.init(kernel_stack.as_slice_mut(), idle_entry, core::ptr::null_mut());
+    idle.context.cr3 = crate::mm::kernel_root() as usize;
+    idle.kernel_stack = Some(kernel_stack);
+    idle.cpu = cpu_id;
+    idle.state = ThreadState::Ready;
+
+    let idle = Arc::new(Spinlock::new(idle));
+    crate::arch::without_interrupts(|| SCHEDULER.lock().set_idle(cpu_id, idle));
+    log::info!("sched: CPU {} online.", cpu_id);
+}
+
+/// Body of the per-CPU idle threads: run queued work, otherwise sleep until an interrupt.
+extern "C" fn idle_entry(_arg: *mut u8) {
+    loop {
+        crate::arch::disable_interrupts();
+        let cpu_id = crate::arch::cpu_id();
+        if SCHEDULER.lock().has_queued(cpu_id) {
+            crate::arch::enable_interrupts();
+            schedule(true);
+        } else {
+            crate::sync::rcu::idle_enter(cpu_id);
+            // A wakeup IPI arriving after the check is taken right after `hlt`.
+            crate::arch::enable_interrupts_and_halt();
+            crate::sync::rcu::idle_exit(cpu_id);
+        }
+    }
+}
+
+/// Wait until the CPU that last ran `thread` is off its stack, then claim it.
+fn claim(on_cpu: &AtomicBool) {
+    while on_cpu.load(Ordering::Acquire) {
+        crate::arch::cpu_relax();
+    }
+    on_cpu.store(true, Ordering::Relaxed);
+}
+
+/// Load the address space, TLS base and kernel stack of the thread about to run and
+/// return its saved stack pointer.
+///
+/// Must only be called once `next` has been claimed, as its context is saved by the CPU
+/// that switched away from it.
+fn prepare_switch(next: &Spinlock<Thread>) -> u64 {
+    let (next_rsp, next_cr3, next_kstack_top, next_fs_base) = {
+        let n = next.lock();
+        (
+            n.context.rsp as u64,
+            n.context.cr3 as u64,
+            n.kernel_stack_top(),
+            n.context.fs_base,
+        )
+    };
+
+    // Switch page directory if changing address spaces
+    if next_cr3 != 0 {
+        let active_cr3 = crate::arch::active_address_space_root();
+        if next_cr3 != active_cr3 {
+            // SAFETY: next_cr3 is a valid PML4 physical root address for the target process.
+            unsafe {
+                crate::arch::set_address_space_root(next_cr3);
+            }
+        }
+    }
+
+    // Restore IA32_FS_BASE for TLS context
+    crate::arch::cpu::msr::write_fs_base(next_fs_base);
+
+    // Update TSS RSP0 and CpuLocal kernel stack pointer for Ring 3 transitions
+    if next_kstack_top != 0 {
+        crate::arch::cpu::tss::set_rsp0(next_kstack_top);
+    }
+
+    next_rsp
+}
+
+/// Hand the CPU's count of held spinlocks over from `prev` to `next`, which is claimed.
+fn switch_held_spinlocks(prev: &AtomicU32, next: &AtomicU32) {
+    let held = crate::sync::spinlock::swap_held_count(next.load(Ordering::Relaxed));
+    prev.store(held, Ordering::Relaxed);
+}
+
+fn switch(reason: SwitchReason) {
     // Disable interrupts on the local CPU while holding SCHEDULER lock to prevent deadlock
     let saved_flags = crate::arch::disable_interrupts();
 
     let cpu_id = crate::arch::cpu_id();
+    crate::sync::rcu::note_context_switch(cpu_id);
     let mut sched = SCHEDULER.lock();
+    if !sched.is_online(cpu_id) {
+        // Still booting: there is no idle thread to fall back on yet.
+        drop(sched);
+        if saved_flags {
+            crate::arch::enable_interrupts();
+        }
+        return;
+    }
     let prev_thread = sched.current_threads[cpu_id as usize].clone();
 
-    if yielding {
-        sched.yield_current(cpu_id);
-    } else {
-        sched.block_current(cpu_id);
+    // Without a periodic tick, charge the time since the last one before switching.
+    if crate::time::tick::is_stopped(cpu_id) {
+        let elapsed = crate::time::tick::take_elapsed(cpu_id);
+        cputime::account(&sched, cpu_id, elapsed, None);
+        sched.tick(cpu_id, elapsed);
     }
 
-    let next_thread = sched.pick_next(cpu_id);
+    match reason {
+        SwitchReason::Yield => sched.yield_current(cpu_id),
+        SwitchReason::Block => sched.block_current(cpu_id),
+        SwitchReason::Preempt => sched.preempt_current(cpu_id),
+    }
+
+    let Some(next) = sched.pick_next(cpu_id) else {
+        drop(sched);
+        if saved_flags {
+            crate::arch::enable_interrupts();
+        }
+        return;
+    };
+    crate::time::tick::reprogram(cpu_id, sched.tick_mode(cpu_id));
+
+    let (next_on_cpu, next_held) = {
+        let n = next.lock();
+        (&n.on_cpu as *const AtomicBool, &n.held_spinlocks as *const AtomicU32)
+    };
+    #[cfg(feature = "lockdep")]
+    let next_held_locks = {
+        let n = next.lock();
+        &*n.held_locks as *const HeldLocks
+    };
 
-    match (prev_thread, next_thread) {
-        (Some(prev), Some(next)) => {
+    match prev_thread {
+        Some(prev) => {
             if Arc::ptr_eq(&prev, &next) {
                 drop(sched);
                 if saved_flags {
@@ -41,107 +211,61 @@ pub fn schedule(yielding: bool) {
                 }
                 return; // Nothing to do
             }
+            cputime::count_switch();
             // Get raw pointers
-            let prev_rsp_ptr = {
+            let (prev_rsp_ptr, prev_on_cpu, prev_held) = {
                 let mut p = prev.lock();
                 p.context.fs_base = crate::arch::cpu::msr::read_fs_base();
-                &mut p.context.rsp as *mut usize as *mut u64
-            };
-            let (next_rsp, next_cr3, next_kstack_top, next_fs_base) = {
-                let n = next.lock();
                 (
-                    n.context.rsp as u64,
-                    n.context.cr3 as u64,
-                    n.kernel_stack_top(),
-                    n.context.fs_base,
+                    &mut p.context.rsp as *mut usize as *mut u64,
+                    p.on_cpu.as_ptr() as *mut u8,
+                    &p.held_spinlocks as *const AtomicU32,
                 )
             };
+            #[cfg(feature = "lockdep")]
+            let prev_held_locks = {
+                let mut p = prev.lock();
+                &mut *p.held_locks as *mut HeldLocks
+            };
 
             drop(sched);
 
-            // Switch page directory if changing address spaces
-            if next_cr3 != 0 {
-                let active_cr3 = crate::arch::active_address_space_root();
-                if next_cr3 != active_cr3 {
-                    // SAFETY: next_cr3 is a valid PML4 physical root address for the target process.
-                    unsafe {
-                        crate::arch::set_address_space_root(next_cr3);
-                    }
-                }
-            }
-
-            // Restore IA32_FS_BASE for TLS context
-            crate::arch::cpu::msr::write_fs_base(next_fs_base);
+            // SAFETY: `next` is kept alive as the current thread of this CPU.
+            claim(unsafe { &*next_on_cpu });
+            let next_rsp = prepare_switch(&next);
+            drop(next);
+            // SAFETY: `prev` is kept alive by this frame and `next` as the current thread.
+            switch_held_spinlocks(unsafe { &*prev_held }, unsafe { &*next_held });
+            #[cfg(feature = "lockdep")]
+            // SAFETY: As above; the boxes live as long as their threads.
+            lockdep::switch_held(Some(unsafe { &mut *prev_held_locks }), unsafe {
+                &*next_held_locks
+            });
 
-            // Update TSS RSP0 and CpuLocal kernel stack pointer for Ring 3 transitions
-            if next_kstack_top != 0 {
-                crate::arch::cpu::tss::set_rsp0(next_kstack_top);
-            }
-
-            // SAFETY: Switching CPU context between valid thread stack pointers.
-            unsafe { switch_context(prev_rsp_ptr, next_rsp) };
+            // SAFETY: Switching CPU context between valid thread stack pointers; `prev`
+            // is released through `prev_on_cpu` once its stack is no longer in use.
+            unsafe { switch_context(prev_rsp_ptr, next_rsp, prev_on_cpu) };
 
             if saved_flags {
                 crate::arch::enable_interrupts();
             }
         }
-        (None, Some(next)) => {
-            // First ever thread switch (from kmain)
-            let (next_rsp, next_cr3, next_kstack_top, next_fs_base) = {
-                let n = next.lock();
-                (
-                    n.context.rsp as u64,
-                    n.context.cr3 as u64,
-                    n.kernel_stack_top(),
-                    n.context.fs_base,
-                )
-            };
+        None => {
+            // First switch on this CPU, away from its boot stack.
             drop(sched);
 
-            if next_cr3 != 0 {
-                let active_cr3 = crate::arch::active_address_space_root();
-                if next_cr3 != active_cr3 {
-                    // SAFETY: next_cr3 is a valid PML4 physical root address for the target process.
-                    unsafe {
-                        crate::arch::set_address_space_root(next_cr3);
-                    }
-                }
-            }
-
-            crate::arch::cpu::msr::write_fs_base(next_fs_base);
-
-            if next_kstack_top != 0 {
-                crate::arch::cpu::tss::set_rsp0(next_kstack_top);
-            }
+            // SAFETY: `next` is kept alive as the current thread of this CPU.
+            claim(unsafe { &*next_on_cpu });
+            let next_rsp = prepare_switch(&next);
+            drop(next);
+            // SAFETY: `next` is kept alive as the current thread of this CPU.
+            crate::sync::spinlock::swap_held_count(unsafe { &*next_held }.load(Ordering::Relaxed));
+            #[cfg(feature = "lockdep")]
+            // SAFETY: As above.
+            lockdep::switch_held(None, unsafe { &*next_held_locks });
 
             // SAFETY: Switching CPU context to initial thread.
             unsafe { switch_context_to(next_rsp) };
         }
-        (Some(prev), None) => {
-            if yielding {
-                // If yielding and no other thread is ready, continue running the current thread.
-                sched.current_threads[cpu_id as usize] = Some(prev);
-                drop(sched);
-                if saved_flags {
-                    crate::arch::enable_interrupts();
-                }
-                return;
-            }
-            // If blocked/exited and no runnable threads, drop scheduler lock and idle until next interrupt.
-            drop(sched);
-            if saved_flags {
-                crate::arch::enable_interrupts();
-            }
-            crate::arch::idle();
-        }
-
-        (None, None) => {
-            // Do nothing, idle or still booting.
-            drop(sched);
-            if saved_flags {
-                crate::arch::enable_interrupts();
-            }
-            return;
-        }
     }
 }
@@ -0,0 +1,77 @@
+//! Scheduling policies, numbered as in the Linux ABI.
+//!
+//! `SCHED_FIFO` and `SCHED_RR` threads belong to the real-time class and always run before
+//! the fair class; the other policies are scheduled by EEVDF.
+
+pub const SCHED_NORMAL: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+pub const SCHED_BATCH: u32 = 3;
+pub const SCHED_IDLE: u32 = 5;
+
+/// Flag or'ed into the policy: children return to `SCHED_NORMAL` on fork.
+pub const SCHED_RESET_ON_FORK: u32 = 0x4000_0000;
+
+/// Lowest real-time priority.
+pub const MIN_RT_PRIO: u8 = 1;
+
+/// Highest real-time priority.
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Fair-class weight of `SCHED_IDLE` threads, below that of nice 19.
+pub const WEIGHT_IDLEPRIO: u32 = 3;
+
+/// How a thread is scheduled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedPolicy {
+    /// EEVDF with the weight of the thread's nice value.
+    #[default]
+    Normal,
+    /// Real-time: runs until it blocks, yields or a higher priority thread is runnable.
+    Fifo,
+    /// Real-time: like `Fifo`, but rotates among equal priorities every quantum.
+    RoundRobin,
+    /// EEVDF for CPU-bound work. The fair class does not preempt on wakeup, so this
+    /// behaves like `Normal`.
+    Batch,
+    /// EEVDF with the lowest possible weight, for work that should only soak up idle time.
+    Idle,
+}
+
+impl SchedPolicy {
+    /// Parse a policy number, without `SCHED_RESET_ON_FORK`.
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            SCHED_NORMAL => Some(Self::Normal),
+            SCHED_FIFO => Some(Self::Fifo),
+            SCHED_RR => Some(Self::RoundRobin),
+            SCHED_BATCH => Some(Self::Batch),
+            SCHED_IDLE => Some(Self::Idle),
+            _ => None,
+        }
+    }
+
+    pub fn as_raw(self) -> u32 {
+        match self {
+            Self::Normal => SCHED_NORMAL,
+            Self::Fifo => SCHED_FIFO,
+            Self::RoundRobin => SCHED_RR,
+            Self::Batch => SCHED_BATCH,
+            Self::Idle => SCHED_IDLE,
+        }
+    }
+
+    /// Whether the policy belongs to the real-time class.
+    pub fn is_realtime(self) -> bool {
+        matches!(self, Self::Fifo | Self::RoundRobin)
+    }
+
+    /// Valid priorities for the policy: 1–99 for real-time policies, 0 otherwise.
+    pub fn priority_range(self) -> (u8, u8) {
+        if self.is_realtime() {
+            (MIN_RT_PRIO, MAX_RT_PRIO)
+        } else {
+            (0, 0)
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Real-time scheduling class for `SCHED_FIFO` and `SCHED_RR`.
+//!
+//! Every CPU has a [`RtRunQueue`] with one FIFO list per priority. The highest priority
+//! queued thread runs before any fair thread, and a waking thread preempts a CPU running
+//! something of lower priority. `SCHED_RR` threads go to the back of their list once their
+//! quantum is used up.
+//!
+//! Throttling keeps a runaway real-time thread from starving the rest of the system: once
+//! real-time threads on a CPU have used `sched_rt_runtime_us` of a `sched_rt_period_us`
+//! window, the CPU only runs fair threads until the window ends. A runtime of -1 turns
+//! throttling off.
+
+use crate::fs::vfs::types::VfsError;
+use crate::proc::thread::{Thread, ThreadId, ThreadState};
+use crate::sched::policy::MAX_RT_PRIO;
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+/// Default `SCHED_RR` quantum in milliseconds.
+const DEFAULT_RR_TIMESLICE_MS: u64 = 100;
+
+/// Longest `SCHED_RR` quantum in milliseconds: as on Linux, it must fit an `int` of ticks.
+const MAX_RR_TIMESLICE_MS: u64 = i32::MAX as u64 * crate::time::tick::TICK_NS / 1_000_000;
+
+/// Longest throttling window in microseconds, as on Linux.
+const MAX_RT_PERIOD_US: u64 = i32::MAX as u64;
+
+/// Default length of the throttling window in microseconds (1 s).
+const DEFAULT_RT_PERIOD_US: u64 = 1_000_000;
+
+/// Default real-time budget per window in microseconds (0.95 s).
+const DEFAULT_RT_RUNTIME_US: i64 = 950_000;
+
+static RR_TIMESLICE_MS: AtomicU64 = AtomicU64::new(DEFAULT_RR_TIMESLICE_MS);
+static RT_PERIOD_US: AtomicU64 = AtomicU64::new(DEFAULT_RT_PERIOD_US);
+static RT_RUNTIME_US: AtomicI64 = AtomicI64::new(DEFAULT_RT_RUNTIME_US);
+
+/// Set once throttling has kicked in, so that it is only reported once.
+static THROTTLE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// The `SCHED_RR` quantum in nanoseconds.
+pub fn rr_timeslice_ns() -> u64 {
+    RR_TIMESLICE_MS.load(Ordering::Relaxed).saturating_mul(1_000_000)
+}
+
+/// Queued real-time threads of a single CPU.
+pub struct RtRunQueue {
+    /// One list per priority, the next thread to run at the front.
+    queues: [VecDeque<(ThreadId, Arc<Spinlock<Thread>>)>; MAX_RT_PRIO as usize + 1],
+
+    /// Bit `p` is set while the list of priority `p` is not empty.
+    bitmap: u128,
+
+    /// Number of queued threads.
+    len: usize,
+
+    /// Time real-time threads ran in the current throttling window.
+    rt_time_ns: u64,
+
+    /// Time elapsed in the current throttling window.
+    window_ns: u64,
+
+    /// Whether the budget of the current window is used up.
+    throttled: bool,
+}
+
+impl Default for RtRunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtRunQueue {
+    pub const fn new() -> Self {
+        Self {
+            queues: [const { VecDeque::new() }; MAX_RT_PRIO as usize + 1],
+            bitmap: 0,
+            len: 0,
+            rt_time_ns: 0,
+            window_ns: 0,
+            throttled: false,
+        }
+    }
+
+    /// Number of queued threads.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no thread is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether real-time threads must leave this CPU to the fair class for now.
+    pub fn throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// Priority of the most important queued thread.
+    pub fn highest_prio(&self) -> Option<u8> {
+        (self.bitmap != 0).then(|| (127 - self.bitmap.leading_zeros()) as u8)
+    }
+
+    /// Whether `tid` is queued.
+    pub fn contains(&self, tid: ThreadId) -> bool {
+        self.queues.iter().any(|q| q.iter().any(|(t, _)| *t == tid))
+    }
+
+    /// Queue a thread that becomes runnable at the back of its priority's list.
+    pub fn enqueue(&mut self, thread: Arc<Spinlock<Thread>>) {
+        let (tid, prio) = {
+            let mut t_lock = thread.lock();
+            if t_lock.state != ThreadState::Sleeping {
+                t_lock.state = ThreadState::Ready;
+            }
+            (t_lock.tid, t_lock.rt_priority)
+        };
+        self.insert(tid, prio, thread, false);
+    }
+
+    /// Queue a thread without touching its state; `at_head` puts it before the other
+    /// threads of its priority.
+    pub fn insert(
+        &mut self,
+        tid: ThreadId,
+        prio: u8,
+        thread: Arc<Spinlock<Thread>>,
+        at_head: bool,
+    ) {
+        let prio = prio.min(MAX_RT_PRIO);
+        let queue = &mut self.queues[prio as usize];
+        if at_head {
+            queue.push_front((tid, thread));
+        } else {
+            queue.push_back((tid, thread));
+        }
+        self.bitmap |= 1 << prio;
+        self.len += 1;
+    }
+
+    /// Remove and return the first thread of the highest priority.
+    pub fn pick(&mut self) -> Option<Arc<Spinlock<Thread>>> {
+        let prio = self.highest_prio()?;
+        let (_, thread) = self.pop(prio as usize, 0)?;
+
+        let mut t_lock = thread.lock();
+        // A thread preempted on its way to sleep keeps its state so that it still blocks.
+        if t_lock.state != ThreadState::Sleeping {
+            t_lock.state = ThreadState::Running;
+        }
+        drop(t_lock);
+        Some(thread)
+    }
+
+    /// Remove `tid` from the queue.
+    pub fn remove(&mut self, tid: ThreadId) -> Option<Arc<Spinlock<Thread>>> {
+        let (prio, index) = self.queues.iter().enumerate().find_map(|(prio, q)| {
+            q.iter().position(|(t, _)| *t == tid).map(|index| (prio, index))
+        })?;
+        self.pop(prio, index).map(|(_, thread)| thread)
+    }
+
+    /// The most important queued thread allowed to run on CPU `dst`.
+    pub fn migration_candidate(&self, dst: usize) -> Option<(u8, ThreadId)> {
+        self.queues.iter().enumerate().rev().find_map(|(prio, q)| {
+            q.iter()
+                .find(|(_, thread)| thread.lock().cpus_allowed & (1 << dst) != 0)
+                .map(|(tid, _)| (prio as u8, *tid))
+        })
+    }
+
+    fn pop(&mut self, prio: usize, index: usize) -> Option<(ThreadId, Arc<Spinlock<Thread>>)> {
+        let entry = self.queues[prio].remove(index)?;
+        if self.queues[prio].is_empty() {
+            self.bitmap &= !(1 << prio);
+        }
+        self.len -= 1;
+        Some(entry)
+    }
+
+    /// Advance the throttling window by `delta_ns`, `rt_ns` of which real-time threads ran.
+    pub fn account(&mut self, delta_ns: u64, rt_ns: u64) {
+        self.window_ns += delta_ns;
+        self.rt_time_ns += rt_ns;
+
+        if self.window_ns >= RT_PERIOD_US.load(Ordering::Relaxed).saturating_mul(1_000) {
+            self.window_ns = 0;
+            self.rt_time_ns = 0;
+            self.throttled = false;
+        }
+
+        let runtime_us = RT_RUNTIME_US.load(Ordering::Relaxed);
+        if runtime_us >= 0
+            && !self.throttled
+            && self.rt_time_ns >= (runtime_us as u64).saturating_mul(1_000)
+        {
+            self.throttled = true;
+            if !THROTTLE_REPORTED.swap(true, Ordering::Relaxed) {
+                log::warn!("sched: RT throttling activated");
+            }
+        }
+    }
+}
+
+fn show_rr_timeslice() -> String {
+    alloc::format!("{}\n", RR_TIMESLICE_MS.load(Ordering::Relaxed))
+}
+
+/// A value of 0 or less restores the default quantum, as on Linux.
+fn store_rr_timeslice(value: &str, euid: u32) -> Result<(), VfsError> {
+    crate::fs::procfs::require_root(euid)?;
+    let ms: i64 = value.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    if ms > MAX_RR_TIMESLICE_MS as i64 {
+        return Err(VfsError::InvalidInput);
+    }
+    let ms = if ms <= 0 { DEFAULT_RR_TIMESLICE_MS } else { ms as u64 };
+    RR_TIMESLICE_MS.store(ms, Ordering::Relaxed);
+    Ok(())
+}
+
+fn show_rt_period() -> String {
+    alloc::format!("{}\n", RT_PERIOD_US.load(Ordering::Relaxed))
+}
+
+fn store_rt_period(value: &str, euid: u32) -> Result<(), VfsError> {
+    crate::fs::procfs::require_root(euid)?;
+    let us: u64 = value.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    let runtime = RT_RUNTIME_US.load(Ordering::Relaxed);
+    if us == 0 || us > MAX_RT_PERIOD_US || (runtime >= 0 && runtime as u64 > us) {
+        return Err(VfsError::InvalidInput);
+    }
+    RT_PERIOD_US.store(us, Ordering::Relaxed);
+    Ok(())
+}
+
+fn show_rt_runtime() -> String {
+    alloc::format!("{}\n", RT_RUNTIME_US.load(Ordering::Relaxed))
+}
+
+fn store_rt_runtime(value: &str, euid: u32) -> Result<(), VfsError> {
+    crate::fs::procfs::require_root(euid)?;
+    let us: i64 = value.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    if us < -1 || (us >= 0 && us as u64 > RT_PERIOD_US.load(Ordering::Relaxed)) {
+        return Err(VfsError::InvalidInput);
+    }
+    RT_RUNTIME_US.store(us, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Register the real-time tunables under `/proc/sys/kernel`.
+pub fn init() -> Result<(), &'static str> {
+    use crate::fs::procfs::register_proc_entry;
+    register_proc_entry(
+        "sys/kernel/sched_rr_timeslice_ms",
+        show_rr_timeslice,
+        Some(store_rr_timeslice),
+    );
+    register_proc_entry("sys/kernel/sched_rt_period_us", show_rt_period, Some(store_rt_period));
+    register_proc_entry(
+        "sys/kernel/sched_rt_runtime_us",
+        show_rt_runtime,
+        Some(store_rt_runtime),
+    );
+    Ok(())
+}
+
+crate::fs_initcall!(init);
@@ -0,0 +1,283 @@
+//! Augmented AVL tree of queued fair entities, threads and groups.
+//!
+//! Entities are ordered by virtual deadline (ties broken by their ID) and every node
+//! caches the smallest `vruntime` in its subtree. That is enough to find the eligible
+//! entity with the earliest deadline in O(log n): descend left while the left subtree
+//! holds an eligible entity, otherwise take the node itself if it is eligible, otherwise
+//! go right.
+
+use crate::proc::thread::{Thread, ThreadId};
+use crate::sched::group::GroupId;
+use crate::sync::spinlock::Spinlock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Identifies a scheduling entity within a run queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntityId {
+    Thread(ThreadId),
+    Group(GroupId),
+}
+
+/// A queued thread or group together with the scheduling keys it is sorted and picked by.
+pub struct Entity {
+    pub id: EntityId,
+    /// The thread, for thread entities.
+    pub thread: Option<Arc<Spinlock<Thread>>>,
+    pub vruntime: u64,
+    pub vdeadline: u64,
+    pub weight: u32,
+    /// CPUs the thread may run on, copied so that balancing need not lock it. Groups
+    /// allow every CPU.
+    pub cpus_allowed: u32,
+}
+
+impl Entity {
+    /// Sort key of the entity.
+    pub fn key(&self) -> (u64, EntityId) {
+        (self.vdeadline, self.id)
+    }
+}
+
+type Link = Option<Box<Node>>;
+
+struct Node {
+    entity: Entity,
+    /// Smallest `vruntime` in the subtree rooted here.
+    min_vruntime: u64,
+    height: u8,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    fn new(entity: Entity) -> Self {
+        Self {
+            min_vruntime: entity.vruntime,
+            entity,
+            height: 1,
+            left: None,
+            right: None,
+        }
+    }
+
+    /// Recompute the cached height and minimum from the children.
+    fn update(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+        let mut min = self.entity.vruntime;
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            min = min.min(child.min_vruntime);
+        }
+        self.min_vruntime = min;
+    }
+}
+
+fn height(link: &Link) -> u8 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn rotate_right(mut node: Box<Node>) -> Box<Node> {
+    let Some(mut left) = node.left.take() else {
+        return node;
+    };
+    node.left = left.right.take();
+    node.update();
+    left.right = Some(node);
+    left.update();
+    left
+}
+
+fn rotate_left(mut node: Box<Node>) -> Box<Node> {
+    let Some(mut right) = node.right.take() else {
+        return node;
+    };
+    node.right = right.left.take();
+    node.update();
+    right.left = Some(node);
+    right.update();
+    right
+}
+
+/// Restore the AVL invariant at `node`, whose subtrees differ in height by at most two.
+fn rebalance(mut node: Box<Node>) -> Box<Node> {
+    node.update();
+    let left = height(&node.left) as i16;
+    let right = height(&node.right) as i16;
+
+    if left > right + 1 {
+        if let Some(l) = node.left.take() {
+            node.left = Some(if height(&l.left) < height(&l.right) {
+                rotate_left(l)
+            } else {
+                l
+            });
+        }
+        return rotate_right(node);
+    }
+    if right > left + 1 {
+        if let Some(r) = node.right.take() {
+            node.right = Some(if height(&r.right) < height(&r.left) {
+                rotate_right(r)
+            } else {
+                r
+            });
+        }
+        return rotate_left(node);
+    }
+    node
+}
+
+fn insert(link: Link, entity: Entity) -> Box<Node> {
+    let Some(mut node) = link else {
+        return Box::new(Node::new(entity));
+    };
+    if entity.key() < node.entity.key() {
+        node.left = Some(insert(node.left.take(), entity));
+    } else {
+        node.right = Some(insert(node.right.take(), entity));
+    }
+    rebalance(node)
+}
+
+/// Detach the leftmost node of the subtree, returning the rest of the subtree and it.
+fn take_min(mut node: Box<Node>) -> (Link, Box<Node>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (rest, min) = take_min(left);
+            node.left = rest;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+fn remove(link: Link, key: (u64, EntityId), removed: &mut Option<Entity>) -> Link {
+    let mut node = link?;
+    match key.cmp(&node.entity.key()) {
+        core::cmp::Ordering::Less => node.left = remove(node.left.take(), key, removed),
+        core::cmp::Ordering::Greater => node.right = remove(node.right.take(), key, removed),
+        core::cmp::Ordering::Equal => {
+            let Node {
+                entity, left, right, ..
+            } = *node;
+            *removed = Some(entity);
+            return match (left, right) {
+                (None, child) | (child, None) => child,
+                (left, Some(right)) => {
+                    let (rest, mut successor) = take_min(right);
+                    successor.left = left;
+                    successor.right = rest;
+                    Some(rebalance(successor))
+                }
+            };
+        }
+    }
+    Some(rebalance(node))
+}
+
+/// Queued entities ordered by virtual deadline.
+pub struct EntityTree {
+    root: Link,
+    len: usize,
+}
+
+impl Default for EntityTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntityTree {
+    pub const fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Smallest `vruntime` of all entities.
+    pub fn min_vruntime(&self) -> Option<u64> {
+        self.root.as_ref().map(|root| root.min_vruntime)
+    }
+
+    pub fn insert(&mut self, entity: Entity) {
+        self.root = Some(insert(self.root.take(), entity));
+        self.len += 1;
+    }
+
+    /// Remove the entity with sort key `key`.
+    pub fn remove(&mut self, key: (u64, EntityId)) -> Option<Entity> {
+        let mut removed = None;
+        self.root = remove(self.root.take(), key, &mut removed);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Sort key of the entity with the earliest deadline among those whose `vruntime`
+    /// satisfies `eligible`, which must hold for all values below one it holds for.
+    pub fn earliest_eligible(&self, eligible: impl Fn(u64) -> bool) -> Option<(u64, EntityId)> {
+        let mut node = self.root.as_deref();
+        while let Some(n) = node {
+            // Eligible entities on the left have earlier deadlines than anything else.
+            if let Some(left) = n.left.as_deref()
+                && eligible(left.min_vruntime)
+            {
+                node = Some(left);
+                continue;
+            }
+            if eligible(n.entity.vruntime) {
+                return Some(n.entity.key());
+            }
+            node = n.right.as_deref();
+        }
+        None
+    }
+
+    /// The entity with the earliest deadline.
+    pub fn first(&self) -> Option<&Entity> {
+        let mut node = self.root.as_deref()?;
+        while let Some(left) = node.left.as_deref() {
+            node = left;
+        }
+        Some(&node.entity)
+    }
+
+    /// Entities in deadline order.
+    pub fn iter(&self) -> Iter<'_> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+}
+
+/// In-order iterator over an [`EntityTree`].
+pub struct Iter<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iter<'a> {
+    fn push_left(&mut self, mut node: Option<&'a Node>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = n.left.as_deref();
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Entity;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        Some(&node.entity)
+    }
+}
@@ -0,0 +1,103 @@
+//! Completions: waiting for an event signalled by another thread or an interrupt handler.
+//!
+//! Each [`Completion::complete`] lets one waiter through, including one that only starts
+//! waiting afterwards; [`Completion::complete_all`] lets every waiter through until the
+//! completion is reinitialised.
+
+use super::waitqueue::{self, WaitQueue};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// `done` count of a completion that was completed for all waiters.
+const COMPLETE_ALL: u32 = u32::MAX;
+
+pub struct Completion {
+    /// Completions not consumed by a waiter yet.
+    done: AtomicU32,
+    waiters: WaitQueue,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Self {
+            done: AtomicU32::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Signal the completion to one waiter. May be called from interrupt handlers.
+    pub fn complete(&self) {
+        let mut done = self.done.load(Ordering::Relaxed);
+        while done < COMPLETE_ALL - 1 {
+            match self.done.compare_exchange_weak(
+                done,
+                done + 1,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => done = current,
+            }
+        }
+        self.waiters.wake_one();
+    }
+
+    /// Signal the completion to all current and future waiters. May be called from
+    /// interrupt handlers.
+    pub fn complete_all(&self) {
+        self.done.store(COMPLETE_ALL, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Sleep until the completion is signalled.
+    #[track_caller]
+    pub fn wait_for_completion(&self) {
+        waitqueue::might_sleep();
+        self.waiters.wait(|| self.try_wait_for_completion(), None);
+    }
+
+    /// Sleep until the completion is signalled, for at most `timeout_ns` nanoseconds.
+    /// Returns whether it was signalled.
+    #[track_caller]
+    pub fn wait_for_completion_timeout(&self, timeout_ns: u64) -> bool {
+        waitqueue::might_sleep();
+        let deadline = crate::time::now_ns().saturating_add(timeout_ns);
+        self.waiters.wait(|| self.try_wait_for_completion(), Some(deadline))
+    }
+
+    /// Consume a completion if one was signalled, without sleeping.
+    pub fn try_wait_for_completion(&self) -> bool {
+        let mut done = self.done.load(Ordering::Acquire);
+        loop {
+            match done {
+                0 => return false,
+                COMPLETE_ALL => return true,
+                _ => {}
+            }
+            match self.done.compare_exchange_weak(
+                done,
+                done - 1,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return true,
+                Err(current) => done = current,
+            }
+        }
+    }
+
+    /// Whether a completion was signalled and not consumed yet.
+    pub fn completion_done(&self) -> bool {
+        self.done.load(Ordering::Acquire) != 0
+    }
+
+    /// Forget signalled completions, so the completion can be reused.
+    pub fn reinit(&self) {
+        self.done.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,79 @@
+//! Condition variables for the sleeping [`Mutex`].
+//!
+//! [`CondVar::wait`] releases the mutex and sleeps in one step, so a notification sent
+//! after the waiter checked its condition under the mutex is not missed. Waits may end
+//! spuriously: callers re-check their condition, which [`CondVar::wait_while`] does.
+
+use super::mutex::{Mutex, MutexGuard};
+use super::waitqueue::{self, WaitQueue};
+
+pub struct CondVar {
+    waiters: WaitQueue,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release the mutex held by `guard`, sleep until notified, and take it again.
+    #[track_caller]
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        waitqueue::might_sleep();
+        self.sleep(guard, None)
+    }
+
+    /// Like [`CondVar::wait`], waking up after `timeout_ns` nanoseconds at the latest.
+    #[track_caller]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout_ns: u64,
+    ) -> MutexGuard<'a, T> {
+        waitqueue::might_sleep();
+        let deadline = crate::time::now_ns().saturating_add(timeout_ns);
+        self.sleep(guard, Some(deadline))
+    }
+
+    /// Wait while `cond` holds for the data protected by `guard`.
+    #[track_caller]
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        waitqueue::might_sleep();
+        while cond(&mut *guard) {
+            guard = self.sleep(guard, None);
+        }
+        guard
+    }
+
+    fn sleep<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline_ns: Option<u64>,
+    ) -> MutexGuard<'a, T> {
+        let mutex: &'a Mutex<T> = guard.mutex();
+        self.waiters.sleep_after(|| drop(guard), deadline_ns);
+        mutex.lock()
+    }
+
+    /// Wake one waiting thread. May be called from interrupt handlers.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    /// Wake all waiting threads. May be called from interrupt handlers.
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for CondVar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
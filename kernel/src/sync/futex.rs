@@ -9,6 +9,7 @@
 
 use crate::proc::thread::{Thread, ThreadId, ThreadState};
 use crate::sync::spinlock::Spinlock;
+use crate::syscalls::uaccess::UserPtr;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
 
@@ -84,14 +85,11 @@ impl FutexManager {
     }
 
     /// Enqueues a thread into the wait queue for `key` if `*uaddr == expected_val`.
-    ///
-    /// # Safety
-    /// `uaddr` must point to valid user-space memory accessible in the current address space.
-    pub unsafe fn wait_prepare(
+    pub fn wait_prepare(
         &mut self,
         key: FutexKey,
         thread: Arc<Spinlock<Thread>>,
-        uaddr: *const u32,
+        uaddr: UserPtr<u32>,
         expected_val: u32,
         bitset: u32,
         deadline_ns: Option<u64>,
@@ -100,8 +98,7 @@ impl FutexManager {
             return Err(FutexError::InvalidArgument);
         }
 
-        // SAFETY: The caller guarantees `uaddr` is valid and mapped. We perform volatile read.
-        let current_val = unsafe { core::ptr::read_volatile(uaddr) };
+        let current_val = uaddr.read().map_err(|_| FutexError::Fault)?;
         if current_val != expected_val {
             return Err(FutexError::WouldBlock);
         }
@@ -0,0 +1,471 @@
+//! Lock dependency validator (lockdep), built with the `lockdep` feature.
+//!
+//! Every [`Spinlock`](super::spinlock::Spinlock), [`Mutex`](super::mutex::Mutex) and
+//! [`RwLock`](super::rwlock::RwLock) belongs to a lock class: the place in the source it
+//! was created at. The locks of all threads share the class of the `new` call that created
+//! them, while each static lock has a class of its own. Whenever a lock is taken while
+//! others are held, lockdep records that the classes of the held locks come before its
+//! class, and reports on the serial log, with the sites and backtraces involved:
+//!
+//! - a cycle in that order, which can deadlock even if it has not yet: one thread holds A
+//!   and waits for B while another holds B and waits for A;
+//! - a class taken in interrupt handlers or softirqs as well as with interrupts enabled
+//!   outside of them, where the handler can interrupt the holder on its own CPU;
+//! - a thread taking a lock it already holds.
+//!
+//! Taking two locks of the same class at once, such as the locks of two threads, is not
+//! checked. Lockdep starts once the heap is up and turns itself off after the first report,
+//! since what follows may be fallout from it. Backtraces walk frame pointers, which the
+//! makefile turns on along with the feature.
+
+use crate::sched::MAX_CPUS;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+/// Where a lock was created or taken.
+pub type Site = &'static Location<'static>;
+
+/// Lock classes lockdep keeps track of; class ids are 1-based `u16`s.
+const MAX_CLASSES: usize = 4096;
+
+/// Locks a thread may hold at once.
+const MAX_HELD: usize = 24;
+
+/// Return addresses kept of each backtrace.
+const TRACE_DEPTH: usize = 8;
+
+/// Class usage: taken in an interrupt handler or softirq.
+const USED_IN_IRQ: usize = 0;
+/// Class usage: taken with interrupts enabled outside of interrupt handlers.
+const ENABLED_IRQ: usize = 1;
+
+/// Set once the heap is up, cleared after the first report.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Lock class of a lock, kept in each lock.
+pub struct LockdepMap {
+    key: Site,
+    /// Class id, 0 until the lock is first taken.
+    class: AtomicU16,
+}
+
+impl LockdepMap {
+    pub const fn new(key: Site) -> Self {
+        Self {
+            key,
+            class: AtomicU16::new(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Exclusive,
+    /// Read side of a reader-writer lock, which may be taken again by its holder.
+    Shared,
+}
+
+#[derive(Clone, Copy)]
+struct Trace {
+    frames: [usize; TRACE_DEPTH],
+    len: usize,
+}
+
+impl Trace {
+    fn capture() -> Self {
+        let mut frames = [0; TRACE_DEPTH];
+        let len = crate::arch::backtrace::capture(&mut frames);
+        Self { frames, len }
+    }
+
+    fn print(&self) {
+        for frame in &self.frames[..self.len] {
+            log::error!("    [<{:#018x}>]", frame);
+        }
+    }
+}
+
+/// Site and backtrace of a lock acquisition.
+#[derive(Clone, Copy)]
+struct Acquisition {
+    site: Site,
+    trace: Trace,
+}
+
+impl Acquisition {
+    fn print(&self, what: &str) {
+        log::error!("  {} at {}", what, self.site);
+        self.trace.print();
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: u16,
+    lock: usize,
+    kind: LockKind,
+    acquired: Acquisition,
+}
+
+/// Locks a thread holds, in the order it took them.
+#[derive(Clone, Copy)]
+pub struct HeldLocks {
+    locks: [Option<HeldLock>; MAX_HELD],
+    depth: usize,
+}
+
+impl HeldLocks {
+    pub const fn new() -> Self {
+        Self {
+            locks: [None; MAX_HELD],
+            depth: 0,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &HeldLock> {
+        self.locks[..self.depth].iter().flatten()
+    }
+}
+
+impl Default for HeldLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct HeldSlot(UnsafeCell<HeldLocks>);
+
+// SAFETY: Each slot is only used by its own CPU with interrupts disabled.
+unsafe impl Sync for HeldSlot {}
+
+/// Locks held by the thread running on each CPU, handed over on context switches.
+static HELD: [HeldSlot; MAX_CPUS] =
+    [const { HeldSlot(UnsafeCell::new(HeldLocks::new())) }; MAX_CPUS];
+
+/// Set while a CPU runs lockdep, whose own locks, e.g. of the heap, are not tracked.
+static BUSY: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Run `f` on the locks held on the calling CPU, unless lockdep is off or runs already.
+fn with_held(f: impl FnOnce(usize, &mut HeldLocks)) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    crate::arch::without_interrupts(|| {
+        let cpu = crate::arch::cpu_id() as usize;
+        let (Some(slot), Some(busy)) = (HELD.get(cpu), BUSY.get(cpu)) else {
+            return;
+        };
+        if busy.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        // SAFETY: Only this CPU uses its slot, with interrupts disabled, and `busy` keeps
+        // the locks taken by `f` from getting here again.
+        f(cpu, unsafe { &mut *slot.0.get() });
+        busy.store(false, Ordering::Relaxed);
+    });
+}
+
+struct Class {
+    site: Site,
+    /// Classes taken while this one is held, with the first time each was.
+    after: BTreeMap<u16, Dependency>,
+    /// First acquisition in each usage context.
+    usage: [Option<Acquisition>; 2],
+}
+
+/// A class taken while another was held.
+#[derive(Clone, Copy)]
+struct Dependency {
+    held: Acquisition,
+    taken: Acquisition,
+}
+
+struct Graph {
+    /// Class id by creation site.
+    ids: BTreeMap<(&'static str, u32, u32), u16>,
+    classes: Vec<Class>,
+}
+
+impl Graph {
+    fn class(&mut self, id: u16) -> &mut Class {
+        &mut self.classes[id as usize - 1]
+    }
+
+    fn site(&self, id: u16) -> Site {
+        self.classes[id as usize - 1].site
+    }
+
+    /// Classes on a path of dependencies from `from` to `to`, both included.
+    fn path(&self, from: u16, to: u16) -> Option<Vec<u16>> {
+        let mut parent = BTreeMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut path = Vec::from([to]);
+                let mut id = to;
+                while id != from {
+                    id = parent[&id];
+                    path.push(id);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &next in self.classes[id as usize - 1].after.keys() {
+                if next != from && !parent.contains_key(&next) {
+                    parent.insert(next, id);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The dependency graph, behind a plain spinning flag: a `Spinlock` would be tracked.
+struct GraphLock {
+    locked: AtomicBool,
+    graph: UnsafeCell<Graph>,
+}
+
+// SAFETY: The graph is only reached through `with`, which serialises access.
+unsafe impl Sync for GraphLock {}
+
+impl GraphLock {
+    fn with<R>(&self, f: impl FnOnce(&mut Graph) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            crate::arch::cpu_relax();
+        }
+        // SAFETY: The flag is held.
+        let result = f(unsafe { &mut *self.graph.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+static GRAPH: GraphLock = GraphLock {
+    locked: AtomicBool::new(false),
+    graph: UnsafeCell::new(Graph {
+        ids: BTreeMap::new(),
+        classes: Vec::new(),
+    }),
+};
+
+/// Class id of `map`, registered on first use. `None` once there are too many classes.
+fn class_of(map: &LockdepMap) -> Option<u16> {
+    let id = map.class.load(Ordering::Relaxed);
+    if id != 0 {
+        return Some(id);
+    }
+    let id = GRAPH.with(|graph| {
+        let key = (map.key.file(), map.key.line(), map.key.column());
+        if let Some(&id) = graph.ids.get(&key) {
+            return Some(id);
+        }
+        if graph.classes.len() >= MAX_CLASSES {
+            return None;
+        }
+        graph.classes.push(Class {
+            site: map.key,
+            after: BTreeMap::new(),
+            usage: [None; 2],
+        });
+        let id = graph.classes.len() as u16;
+        graph.ids.insert(key, id);
+        Some(id)
+    });
+    match id {
+        Some(id) => map.class.store(id, Ordering::Relaxed),
+        None => {
+            turn_off();
+            log::error!("lockdep: more than {} lock classes", MAX_CLASSES);
+            log::error!("lockdep: turning off");
+        }
+    }
+    id
+}
+
+/// Stop checking, so reports are not followed by their fallout.
+fn turn_off() -> bool {
+    ENABLED.swap(false, Ordering::Relaxed)
+}
+
+/// Called when lock `lock` of class `map` is about to be waited for at `site`, or when a
+/// `trylock` took it, which cannot deadlock and is only recorded as held.
+pub fn acquire(map: &LockdepMap, lock: *const (), kind: LockKind, trylock: bool, site: Site) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let irqs_enabled = crate::arch::interrupts_enabled();
+    let trace = Trace::capture();
+    with_held(|cpu, held| {
+        let Some(class) = class_of(map) else {
+            return;
+        };
+        let new = HeldLock {
+            class,
+            lock: lock as usize,
+            kind,
+            acquired: Acquisition { site, trace },
+        };
+
+        if !trylock {
+            let recursive = held.iter().find(|h| {
+                h.lock == new.lock && (h.kind == LockKind::Exclusive || kind == LockKind::Exclusive)
+            });
+            if let Some(prev) = recursive {
+                report_recursion(cpu, prev, &new);
+                return;
+            }
+            let in_interrupt = crate::work::softirq::in_interrupt(cpu);
+            if !check_usage(cpu, &new, in_interrupt, irqs_enabled) {
+                return;
+            }
+            for prev in held.iter().filter(|h| h.class != class) {
+                if !add_dependency(cpu, prev, &new) {
+                    return;
+                }
+            }
+        }
+
+        if held.depth == MAX_HELD {
+            if turn_off() {
+                log::error!("lockdep: more than {} locks held on CPU {}", MAX_HELD, cpu);
+                new.acquired.print("taking");
+                log::error!("lockdep: turning off");
+            }
+            return;
+        }
+        held.locks[held.depth] = Some(new);
+        held.depth += 1;
+    });
+}
+
+/// Called when lock `lock` is released.
+pub fn release(lock: *const ()) {
+    with_held(|_, held| {
+        let lock = lock as usize;
+        let index = (0..held.depth).rev().find(|&i| held.locks[i].is_some_and(|h| h.lock == lock));
+        // Locks taken before lockdep started are not found.
+        if let Some(index) = index {
+            held.locks.copy_within(index + 1..held.depth, index);
+            held.depth -= 1;
+            held.locks[held.depth] = None;
+        }
+    });
+}
+
+/// Record that `new` is taken in the context given, and report if its class is also taken
+/// in the other one. Returns `false` if it reported.
+fn check_usage(cpu: usize, new: &HeldLock, in_interrupt: bool, irqs_enabled: bool) -> bool {
+    let usage = if in_interrupt {
+        USED_IN_IRQ
+    } else if irqs_enabled {
+        ENABLED_IRQ
+    } else {
+        return true;
+    };
+    GRAPH.with(|graph| {
+        let class = graph.class(new.class);
+        if class.usage[usage].is_some() {
+            return true;
+        }
+        class.usage[usage] = Some(new.acquired);
+        let Some(other) = class.usage[1 - usage] else {
+            return true;
+        };
+        if !turn_off() {
+            return false;
+        }
+        let (in_irq, irqs_on) =
+            if usage == USED_IN_IRQ { (new.acquired, other) } else { (other, new.acquired) };
+        log::error!("======================================================");
+        log::error!("lockdep: inconsistent interrupt state on CPU {}", cpu);
+        log::error!("lock class created at {} is", class.site);
+        in_irq.print("taken in interrupt context");
+        irqs_on.print("and with interrupts enabled");
+        log::error!("an interrupt taking it can interrupt its holder and spin forever");
+        log::error!("lockdep: turning off");
+        false
+    })
+}
+
+/// Record that `new` is taken while `prev` is held, and report if that closes a cycle.
+/// Returns `false` if it reported.
+fn add_dependency(cpu: usize, prev: &HeldLock, new: &HeldLock) -> bool {
+    GRAPH.with(|graph| {
+        if graph.class(prev.class).after.contains_key(&new.class) {
+            return true;
+        }
+        let Some(path) = graph.path(new.class, prev.class) else {
+            let dependency = Dependency {
+                held: prev.acquired,
+                taken: new.acquired,
+            };
+            graph.class(prev.class).after.insert(new.class, dependency);
+            return true;
+        };
+        if !turn_off() {
+            return false;
+        }
+        log::error!("======================================================");
+        log::error!("lockdep: possible circular locking dependency on CPU {}", cpu);
+        log::error!("taking lock of class created at {}", graph.site(new.class));
+        new.acquired.print("taken");
+        log::error!("while holding lock of class created at {}", graph.site(prev.class));
+        prev.acquired.print("taken");
+        log::error!("but the opposite order was seen before:");
+        for pair in path.windows(2) {
+            let dependency = graph.class(pair[0]).after[&pair[1]];
+            log::error!("class created at {}", graph.site(pair[0]));
+            dependency.held.print("held");
+            log::error!("then class created at {}", graph.site(pair[1]));
+            dependency.taken.print("taken");
+        }
+        log::error!("lockdep: turning off");
+        false
+    })
+}
+
+fn report_recursion(cpu: usize, prev: &HeldLock, new: &HeldLock) {
+    if !turn_off() {
+        return;
+    }
+    let site = GRAPH.with(|graph| graph.site(new.class));
+    log::error!("======================================================");
+    log::error!("lockdep: recursive locking on CPU {}", cpu);
+    log::error!("taking lock of class created at {}", site);
+    new.acquired.print("taken");
+    prev.acquired.print("already held, taken");
+    log::error!("lockdep: turning off");
+}
+
+/// Hand the locks held on the calling CPU over from the thread switched away from to the
+/// one switched to. Called with interrupts disabled.
+pub fn switch_held(prev: Option<&mut HeldLocks>, next: &HeldLocks) {
+    let Some(slot) = HELD.get(crate::arch::cpu_id() as usize) else {
+        return;
+    };
+    // SAFETY: Only this CPU uses its slot, with interrupts disabled, and the scheduler
+    // does not run inside lockdep.
+    let held = unsafe { &mut *slot.0.get() };
+    if let Some(prev) = prev {
+        *prev = *held;
+    }
+    *held = *next;
+}
+
+/// Start checking locks taken from now on.
+fn init() -> Result<(), &'static str> {
+    ENABLED.store(true, Ordering::Relaxed);
+    log::info!("lockdep: checking lock order, up to {} classes", MAX_CLASSES);
+    Ok(())
+}
+
+crate::early_initcall!(init);
@@ -1,7 +1,18 @@
+pub mod completion;
+pub mod condvar;
 pub mod futex;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod mutex;
+pub mod rcu;
 pub mod rwlock;
+pub mod rwsem;
+pub mod semaphore;
 pub mod spinlock;
+pub mod waitqueue;
+
+pub use completion::Completion;
+pub use condvar::CondVar;
 
 pub use futex::{
     FutexError, FutexKey, FutexManager, FutexWaiter, FUTEX_BITSET_MATCH_ANY, FUTEX_CLOCK_REALTIME,
@@ -11,6 +22,12 @@ pub use futex::{
     FUTEX_WAKE_OP,
 };
 pub use mutex::{Mutex, MutexGuard};
+pub use rcu::{
+    call_rcu, rcu_read_lock, synchronize_rcu, RcuCell, RcuPtr, RcuReadGuard, RcuRef, RcuWriteGuard,
+};
 pub use rwlock::{RWLock, RWLockReadGuard, RWLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use rwsem::{RwSemaphore, RwSemaphoreReadGuard, RwSemaphoreWriteGuard};
+pub use semaphore::Semaphore;
 pub use spinlock::{Spinlock, SpinlockGuard};
+pub use waitqueue::{might_sleep, WaitQueue};
 
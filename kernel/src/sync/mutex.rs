@@ -1,19 +1,38 @@
 //! Mutual Exclusion Primitive (Mutex)
 //!
-//! Provides mutual exclusion for protecting shared kernel resources in a
-//! `#![no_std]` environment.
+//! Provides a sleeping mutex for protecting shared kernel resources that may be held
+//! across long operations such as disk I/O. A thread that finds the mutex locked spins
+//! briefly in case the owner is about to release it, then sleeps on a [`WaitQueue`]
+//! until it is woken by the unlock.
+//!
+//! Only threads may take a mutex: see [`might_sleep`](super::waitqueue::might_sleep) for
+//! the contexts that must use a [`Spinlock`](super::spinlock::Spinlock) instead.
 
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockKind, LockdepMap};
+use super::waitqueue::{self, WaitQueue};
+use crate::proc::thread::ThreadId;
 use core::cell::UnsafeCell;
 use core::fmt;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Attempts to take a contended mutex before sleeping on it.
+pub(super) const SPIN_LIMIT: u32 = 128;
 
 /// A mutual exclusion primitive useful for protecting shared data.
 ///
-/// This mutex provides interior mutability and ensures that only one thread/core
-/// can access the guarded data at any given time.
+/// This mutex provides interior mutability and ensures that only one thread
+/// can access the guarded data at any given time. Threads waiting for it sleep.
 pub struct Mutex<T: ?Sized> {
     lock: AtomicBool,
+    /// TID of the thread holding the mutex, 0 if unlocked or taken during early boot.
+    owner: AtomicU64,
+    waiters: WaitQueue,
+    #[cfg(feature = "lockdep")]
+    dep_map: LockdepMap,
     data: UnsafeCell<T>,
 }
 
@@ -34,9 +53,14 @@ unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
 
 impl<T> Mutex<T> {
     /// Creates a new unlocked mutex in an unlocked state.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             lock: AtomicBool::new(false),
+            owner: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+            #[cfg(feature = "lockdep")]
+            dep_map: LockdepMap::new(Location::caller()),
             data: UnsafeCell::new(data),
         }
     }
@@ -48,41 +72,92 @@ impl<T> Mutex<T> {
 }
 
 impl<T: ?Sized> Mutex<T> {
-    /// Acquires the mutex, spinning until the lock becomes available.
+    /// Acquires the mutex, sleeping until the lock becomes available.
     ///
     /// Returns an RAII [`MutexGuard`] that grants exclusive mutable access
     /// to the protected data until dropped.
+    ///
+    /// # Panics
+    /// Panics if the calling thread already holds the mutex, which would never return.
+    #[track_caller]
     pub fn lock(&self) -> MutexGuard<'_, T> {
-        while self
-            .lock
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
+        waitqueue::might_sleep();
+        #[cfg(feature = "lockdep")]
         {
-            core::hint::spin_loop();
+            let site = Location::caller();
+            lockdep::acquire(&self.dep_map, self.id(), LockKind::Exclusive, false, site);
+        }
+        let tid = waitqueue::current_tid();
+        if !self.acquire() {
+            if tid != 0 && self.owner.load(Ordering::Relaxed) == tid {
+                panic!("mutex locked recursively by thread {}", tid);
+            }
+            if !self.spin() {
+                self.waiters.wait(|| self.acquire(), None);
+            }
         }
+        self.owner.store(tid, Ordering::Relaxed);
         MutexGuard { lock: self }
     }
 
-    /// Attempts to acquire the mutex without spinning.
+    /// Attempts to acquire the mutex without sleeping.
     ///
     /// Returns `Some(MutexGuard)` if acquired, or `None` if the mutex is currently locked.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
-        if self
-            .lock
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
-        {
+        if self.acquire() {
+            #[cfg(feature = "lockdep")]
+            {
+                let site = Location::caller();
+                lockdep::acquire(&self.dep_map, self.id(), LockKind::Exclusive, true, site);
+            }
+            self.owner.store(waitqueue::current_tid(), Ordering::Relaxed);
             Some(MutexGuard { lock: self })
         } else {
             None
         }
     }
 
+    fn acquire(&self) -> bool {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Spin while the mutex stays locked by the same owner, which is likely running on
+    /// another CPU, for up to `SPIN_LIMIT` attempts. Returns whether the mutex was taken.
+    fn spin(&self) -> bool {
+        let owner = self.owner.load(Ordering::Relaxed);
+        for _ in 0..SPIN_LIMIT {
+            crate::arch::cpu_relax();
+            if !self.lock.load(Ordering::Relaxed) {
+                if self.acquire() {
+                    return true;
+                }
+            } else if self.owner.load(Ordering::Relaxed) != owner || self.waiters.has_waiters()
+            {
+                // Handed on, or others already sleep on it: queue up behind them.
+                return false;
+            }
+        }
+        false
+    }
+
     /// Returns `true` if the mutex is currently locked.
     pub fn is_locked(&self) -> bool {
         self.lock.load(Ordering::Relaxed)
     }
 
+    /// Returns the thread holding the mutex, for debugging.
+    ///
+    /// `None` if the mutex is unlocked or was taken before threads ran.
+    pub fn owner(&self) -> Option<ThreadId> {
+        match self.owner.load(Ordering::Relaxed) {
+            0 => None,
+            tid => Some(ThreadId::new(tid)),
+        }
+    }
+
     /// Returns a mutable reference to the underlying data.
     ///
     /// Since this requires a mutable reference to `self`, no locking is necessary.
@@ -96,7 +171,28 @@ impl<T: ?Sized> Mutex<T> {
     /// This function is unsafe because it drops exclusive access invariants.
     /// It should only be used in emergency paths (such as kernel panic recovery or post-crash unwinding).
     pub unsafe fn force_unlock(&self) {
+        self.unlock();
+    }
+
+    /// Identifies the mutex to lockdep.
+    #[cfg(feature = "lockdep")]
+    fn id(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    fn unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.id());
+        self.owner.store(0, Ordering::Relaxed);
         self.lock.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// The mutex this guard holds.
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
     }
 }
 
@@ -118,7 +214,7 @@ impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
 
 impl<T: ?Sized> Drop for MutexGuard<'_, T> {
     fn drop(&mut self) {
-        self.lock.lock.store(false, Ordering::Release);
+        self.lock.unlock();
     }
 }
 
@@ -129,6 +225,7 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
             d.field("data", &&*guard);
         } else {
             d.field("data", &format_args!("<locked>"));
+            d.field("owner", &self.owner());
         }
         d.finish()
     }
@@ -147,12 +244,14 @@ impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
 }
 
 impl<T: Default> Default for Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn default() -> Self {
         Self::new(Default::default())
     }
 }
 
 impl<T> From<T> for Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn from(data: T) -> Self {
         Self::new(data)
     }
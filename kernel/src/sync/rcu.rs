@@ -0,0 +1,443 @@
+//! Read-copy-update: lock-free readers for data that is read far more than written.
+//!
+//! Readers enter a read-side critical section with [`rcu_read_lock`] and follow
+//! [`RcuPtr`]s without taking any lock. Writers publish a new version of the data and
+//! free the old one only after a grace period, once every CPU has passed through a
+//! quiescent state: a point where it runs no reader, reported on context switch, on the
+//! scheduler tick, and when the CPU goes idle. [`synchronize_rcu`] waits for a grace
+//! period, [`call_rcu`] runs a callback after one.
+//!
+//! Read-side critical sections must not sleep, and are not preempted: a preemption that
+//! comes up in one is held off until [`rcu_read_unlock`](RcuReadGuard) and the reader
+//! stays on its CPU, which is what makes a context switch a quiescent state. Readers keep
+//! them short and copy out what they need, e.g. by cloning an `Arc`.
+//!
+//! [`RcuCell`] wraps a value that writers replace by updating a copy.
+
+use super::mutex::{Mutex, MutexGuard};
+use super::spinlock::Spinlock;
+use super::waitqueue::{self, WaitQueue};
+use crate::sched::MAX_CPUS;
+use crate::work::softirq::{self, RCU_SOFTIRQ};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+/// Read-side critical sections each CPU is nested in.
+static NESTING: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Set when a preemption was held off by a reader on the CPU.
+static RESCHED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Set while the CPU halts in its idle loop.
+static IDLE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Grace periods started and completed. One is in progress while they differ.
+static STARTED: AtomicU64 = AtomicU64::new(0);
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+
+/// CPUs yet to pass through a quiescent state in the current grace period.
+static QS_MASK: AtomicU32 = AtomicU32::new(0);
+
+/// Threads in [`synchronize_rcu`].
+static GP_WAITERS: WaitQueue = WaitQueue::new();
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+struct State {
+    /// Latest grace period a callback or waiter needs.
+    needed: u64,
+    /// Callbacks with the grace period they wait for, in order.
+    callbacks: VecDeque<(u64, Callback)>,
+}
+
+/// Taken with interrupts disabled.
+static STATE: Spinlock<State> = Spinlock::new(State {
+    needed: 0,
+    callbacks: VecDeque::new(),
+});
+
+/// Proof of a read-side critical section, which ends when it is dropped.
+///
+/// Pointers read through [`RcuPtr::get`] stay valid while it lives.
+pub struct RcuReadGuard {
+    cpu: usize,
+    /// Readers stay on their CPU.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Enter a read-side critical section. Sections nest, also in interrupt handlers.
+pub fn rcu_read_lock() -> RcuReadGuard {
+    // The CPU is read and the section entered without being preempted in between.
+    let cpu = crate::arch::without_interrupts(|| {
+        let cpu = crate::arch::cpu_id() as usize;
+        if let Some(nesting) = NESTING.get(cpu) {
+            nesting.fetch_add(1, Ordering::SeqCst);
+        }
+        cpu
+    });
+    RcuReadGuard {
+        cpu,
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        let Some(nesting) = NESTING.get(self.cpu) else {
+            return;
+        };
+        if nesting.fetch_sub(1, Ordering::SeqCst) != 1
+            || !RESCHED[self.cpu].swap(false, Ordering::Relaxed)
+        {
+            return;
+        }
+        // Take the preemption held off meanwhile, unless the tick has to.
+        if crate::arch::interrupts_enabled() && !softirq::in_interrupt(self.cpu) {
+            crate::sched::preempt();
+        }
+    }
+}
+
+/// Whether the calling CPU runs a read-side critical section.
+pub fn read_lock_held() -> bool {
+    NESTING
+        .get(crate::arch::cpu_id() as usize)
+        .is_some_and(|nesting| nesting.load(Ordering::Relaxed) != 0)
+}
+
+/// Called by interrupt handlers on `cpu` before preempting the thread they interrupted.
+/// Returns `true` if it is in a read-side critical section, which then preempts itself
+/// when it ends.
+pub fn defer_preemption(cpu: u32) -> bool {
+    let cpu = cpu as usize;
+    if NESTING.get(cpu).is_none_or(|nesting| nesting.load(Ordering::Relaxed) == 0) {
+        return false;
+    }
+    RESCHED[cpu].store(true, Ordering::Relaxed);
+    true
+}
+
+/// The calling CPU `cpu` is about to switch threads. Called with interrupts disabled.
+pub fn note_context_switch(cpu: u32) {
+    if NESTING.get(cpu as usize).is_some_and(|nesting| nesting.load(Ordering::Relaxed) != 0) {
+        log::error!("BUG: context switch in RCU read-side critical section on CPU {}", cpu);
+        return;
+    }
+    report_qs(cpu as usize);
+}
+
+/// Scheduler tick on the calling CPU `cpu`, from its interrupt handler: the interrupted
+/// code is quiescent unless it is a reader.
+pub fn sched_clock_irq(cpu: u32) {
+    report_qs(cpu as usize);
+}
+
+/// The calling CPU `cpu` is about to halt in its idle loop, with interrupts disabled.
+///
+/// It stays quiescent until [`idle_exit`], apart from readers in interrupt handlers, so
+/// grace periods starting meanwhile do not wait for it.
+pub fn idle_enter(cpu: u32) {
+    if let Some(idle) = IDLE.get(cpu as usize) {
+        idle.store(true, Ordering::SeqCst);
+    }
+    report_qs(cpu as usize);
+}
+
+/// The calling CPU `cpu` woke up from [`idle_enter`].
+pub fn idle_exit(cpu: u32) {
+    if let Some(idle) = IDLE.get(cpu as usize) {
+        idle.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Record a quiescent state of the calling CPU `cpu`, unless it is in a reader. Called
+/// with interrupts disabled.
+fn report_qs(cpu: usize) {
+    let Some(nesting) = NESTING.get(cpu) else {
+        return;
+    };
+    let bit = 1 << cpu;
+    if nesting.load(Ordering::SeqCst) != 0 || QS_MASK.load(Ordering::SeqCst) & bit == 0 {
+        return;
+    }
+    if QS_MASK.fetch_and(!bit, Ordering::SeqCst) == bit {
+        end_gp();
+    }
+}
+
+fn gp_in_progress() -> bool {
+    STARTED.load(Ordering::SeqCst) != COMPLETED.load(Ordering::SeqCst)
+}
+
+/// Ask for a grace period that starts after the call, starting it unless one is in
+/// progress. Returns its number.
+fn request_gp(state: &mut State) -> u64 {
+    let gp = STARTED.load(Ordering::SeqCst) + 1;
+    state.needed = state.needed.max(gp);
+    if !gp_in_progress() {
+        start_gp();
+    }
+    gp
+}
+
+/// Start the next grace period. Called with `STATE` locked.
+fn start_gp() {
+    STARTED.fetch_add(1, Ordering::SeqCst);
+    let possible = crate::sched::possible_mask();
+    let mut mask = possible;
+    for cpu in (0..MAX_CPUS).filter(|&cpu| possible & (1 << cpu) != 0) {
+        // An idle CPU without readers only runs readers that start after this.
+        if IDLE[cpu].load(Ordering::SeqCst) && NESTING[cpu].load(Ordering::SeqCst) == 0 {
+            mask &= !(1 << cpu);
+        }
+    }
+    QS_MASK.store(mask, Ordering::SeqCst);
+    if mask == 0 {
+        end_gp();
+        return;
+    }
+
+    // CPUs without a tick may not switch threads for a while: make them.
+    let this_cpu = crate::arch::cpu_id() as usize;
+    for cpu in (0..MAX_CPUS).filter(|&cpu| mask & (1 << cpu) != 0 && cpu != this_cpu) {
+        if crate::time::tick::is_stopped(cpu as u32) {
+            crate::arch::interrupts::send_reschedule(cpu as u32);
+        }
+    }
+}
+
+/// Every CPU passed through a quiescent state: end the grace period and leave waking
+/// waiters and running callbacks to the RCU softirq.
+fn end_gp() {
+    COMPLETED.store(STARTED.load(Ordering::SeqCst), Ordering::SeqCst);
+    softirq::raise_softirq(RCU_SOFTIRQ);
+}
+
+/// Handler of `RCU_SOFTIRQ`: wake waiters, run the callbacks whose grace period ended
+/// and start the grace period the others need.
+fn rcu_softirq() {
+    GP_WAITERS.wake_all();
+    let completed = COMPLETED.load(Ordering::SeqCst);
+    let ready: Vec<Callback> = crate::arch::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let done = state.callbacks.iter().take_while(|(gp, _)| *gp <= completed).count();
+        let ready = state.callbacks.drain(..done).map(|(_, callback)| callback).collect();
+        if state.needed > STARTED.load(Ordering::SeqCst) && !gp_in_progress() {
+            start_gp();
+        }
+        ready
+    });
+    for callback in ready {
+        callback();
+    }
+}
+
+/// Wait until every read-side critical section that started before the call has ended.
+#[track_caller]
+pub fn synchronize_rcu() {
+    waitqueue::might_sleep();
+    let gp = crate::arch::without_interrupts(|| {
+        let gp = request_gp(&mut STATE.lock());
+        // The caller is no reader.
+        report_qs(crate::arch::cpu_id() as usize);
+        gp
+    });
+    GP_WAITERS.wait(|| COMPLETED.load(Ordering::SeqCst) >= gp, None);
+}
+
+/// Run `callback` once every read-side critical section that started before the call
+/// has ended. It runs in softirq context, so it must not sleep.
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    let callback: Callback = Box::new(callback);
+    crate::arch::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let gp = request_gp(&mut state);
+        state.callbacks.push_back((gp, callback));
+    });
+}
+
+/// A pointer to a heap-allocated `T` that readers follow without locking.
+///
+/// Writers serialise among themselves, e.g. with a lock of their own. A replaced value is
+/// freed after a grace period.
+pub struct RcuPtr<T: Send + 'static> {
+    ptr: AtomicPtr<T>,
+    _owns: PhantomData<Box<T>>,
+}
+
+// SAFETY: The value is shared with readers on any CPU and freed wherever the grace period
+// ends, which `T: Send + Sync` allows.
+unsafe impl<T: Send + Sync + 'static> Send for RcuPtr<T> {}
+// SAFETY: See above.
+unsafe impl<T: Send + Sync + 'static> Sync for RcuPtr<T> {}
+
+impl<T: Send + 'static> RcuPtr<T> {
+    /// A pointer to nothing.
+    pub const fn null() -> Self {
+        Self {
+            ptr: AtomicPtr::new(core::ptr::null_mut()),
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            _owns: PhantomData,
+        }
+    }
+
+    /// The value pointed to, valid for the read-side critical section of `guard`.
+    pub fn get<'a>(&'a self, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        // SAFETY: The pointer is null or comes from `Box::into_raw`, and a value replaced
+        // after it was loaded is only freed once the critical section ends.
+        unsafe { self.ptr.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Point to `value` instead, freeing the previous value after a grace period.
+    pub fn assign(&self, value: Option<T>) {
+        let new = value.map_or(core::ptr::null_mut(), |value| Box::into_raw(Box::new(value)));
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        if !old.is_null() {
+            // SAFETY: `old` came from `Box::into_raw` and is no longer reachable from
+            // `self`; readers still using it are waited for.
+            let old = unsafe { Box::from_raw(old) };
+            call_rcu(move || drop(old));
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for RcuPtr<T> {
+    fn drop(&mut self) {
+        let ptr = *self.ptr.get_mut();
+        if !ptr.is_null() {
+            // SAFETY: The pointer came from `Box::into_raw`, and references handed out by
+            // `get` borrow `self`, so none are left.
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
+
+impl<T: Send + 'static> Default for RcuPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+/// A value read without locking and updated by copying it.
+///
+/// [`RcuCell::read`] enters a read-side critical section for as long as the returned
+/// reference lives. [`RcuCell::write`] hands out a copy to modify, published when the
+/// guard is dropped; writers take a sleeping lock, so they may sleep while holding it.
+pub struct RcuCell<T: Send + 'static> {
+    /// The value until the first write, never modified.
+    initial: T,
+    current: RcuPtr<T>,
+    writer: Mutex<()>,
+}
+
+impl<T: Send + Sync + 'static> RcuCell<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            initial: value,
+            current: RcuPtr::null(),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Read the current value. Must not sleep while the reference lives.
+    pub fn read(&self) -> RcuRef<'_, T> {
+        let guard = rcu_read_lock();
+        let value = self.current.get(&guard).map_or(&self.initial as *const T, |v| v as *const T);
+        // SAFETY: The value stays valid while `guard` lives, which is as long as the
+        // returned reference.
+        let value = unsafe { &*value };
+        RcuRef {
+            value,
+            _guard: guard,
+        }
+    }
+
+    /// The current value, for writers.
+    fn locked(&self, _writer: &MutexGuard<'_, ()>) -> &T {
+        // SAFETY: Only writers replace the value, and `_writer` shows that the caller is
+        // the only one.
+        unsafe { self.current.ptr.load(Ordering::Acquire).as_ref() }.unwrap_or(&self.initial)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> RcuCell<T> {
+    /// Take the writer lock to update the value. Readers keep seeing the old value until
+    /// the guard is dropped.
+    #[track_caller]
+    pub fn write(&self) -> RcuWriteGuard<'_, T> {
+        RcuWriteGuard {
+            cell: self,
+            writer: self.writer.lock(),
+            copy: None,
+        }
+    }
+}
+
+/// A value read from an [`RcuCell`], in a read-side critical section until dropped.
+pub struct RcuRef<'a, T> {
+    value: &'a T,
+    _guard: RcuReadGuard,
+}
+
+impl<T> Deref for RcuRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+/// Write access to an [`RcuCell`]. The value is copied on the first modification and
+/// the copy published when the guard is dropped.
+pub struct RcuWriteGuard<'a, T: Clone + Send + Sync + 'static> {
+    cell: &'a RcuCell<T>,
+    writer: MutexGuard<'a, ()>,
+    copy: Option<T>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Deref for RcuWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.copy {
+            Some(copy) => copy,
+            None => self.cell.locked(&self.writer),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> DerefMut for RcuWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        let cell = self.cell;
+        let writer = &self.writer;
+        self.copy.get_or_insert_with(|| cell.locked(writer).clone())
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Drop for RcuWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(copy) = self.copy.take() {
+            self.cell.current.assign(Some(copy));
+        }
+    }
+}
+
+/// Run RCU callbacks from their softirq, including those queued during early boot.
+fn init() -> Result<(), &'static str> {
+    softirq::open_softirq(RCU_SOFTIRQ, rcu_softirq);
+    softirq::raise_softirq(RCU_SOFTIRQ);
+    Ok(())
+}
+
+crate::early_initcall!(init);
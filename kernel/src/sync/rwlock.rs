@@ -3,9 +3,13 @@
 //! Provides a concurrent synchronization primitive allowing multiple readers
 //! or a single exclusive writer in a `#![no_std]` environment.
 
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockKind, LockdepMap};
 use core::cell::UnsafeCell;
 use core::fmt;
 use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 const WRITER_BIT: usize = 1 << (usize::BITS - 1);
@@ -19,6 +23,8 @@ const READER_MASK: usize = !WRITER_BIT;
 /// (shared access).
 pub struct RwLock<T: ?Sized> {
     state: AtomicUsize,
+    #[cfg(feature = "lockdep")]
+    dep_map: LockdepMap,
     data: UnsafeCell<T>,
 }
 
@@ -54,9 +60,12 @@ unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
 
 impl<T> RwLock<T> {
     /// Creates a new unlocked reader-writer lock.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             state: AtomicUsize::new(0),
+            #[cfg(feature = "lockdep")]
+            dep_map: LockdepMap::new(Location::caller()),
             data: UnsafeCell::new(data),
         }
     }
@@ -72,11 +81,14 @@ impl<T: ?Sized> RwLock<T> {
     ///
     /// Multiple threads can hold shared read access concurrently as long as
     /// no thread holds exclusive write access.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        self.lockdep_acquire(LockKind::Shared, false);
         loop {
             let count = self.state.load(Ordering::Relaxed);
             if count & WRITER_BIT != 0 || count == READER_MASK {
-                core::hint::spin_loop();
+                crate::arch::cpu_relax();
                 continue;
             }
             if self
@@ -86,13 +98,14 @@ impl<T: ?Sized> RwLock<T> {
             {
                 return RwLockReadGuard { lock: self };
             }
-            core::hint::spin_loop();
+            crate::arch::cpu_relax();
         }
     }
 
     /// Attempts to acquire shared read access without spinning.
     ///
     /// Returns `Some(RwLockReadGuard)` if acquired, or `None` if an exclusive write lock is held.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
         let count = self.state.load(Ordering::Relaxed);
         if count & WRITER_BIT != 0 || count == READER_MASK {
@@ -103,6 +116,8 @@ impl<T: ?Sized> RwLock<T> {
             .compare_exchange(count, count + 1, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            #[cfg(feature = "lockdep")]
+            self.lockdep_acquire(LockKind::Shared, true);
             Some(RwLockReadGuard { lock: self })
         } else {
             None
@@ -112,13 +127,16 @@ impl<T: ?Sized> RwLock<T> {
     /// Locks this `RwLock` with exclusive write access, spinning until acquired.
     ///
     /// Only one thread can hold write access, and no threads may hold read access concurrently.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        self.lockdep_acquire(LockKind::Exclusive, false);
         while self
             .state
             .compare_exchange_weak(0, WRITER_BIT, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
-            core::hint::spin_loop();
+            crate::arch::cpu_relax();
         }
         RwLockWriteGuard { lock: self }
     }
@@ -126,18 +144,28 @@ impl<T: ?Sized> RwLock<T> {
     /// Attempts to acquire exclusive write access without spinning.
     ///
     /// Returns `Some(RwLockWriteGuard)` if acquired, or `None` if any readers or writers exist.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
         if self
             .state
             .compare_exchange(0, WRITER_BIT, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            #[cfg(feature = "lockdep")]
+            self.lockdep_acquire(LockKind::Exclusive, true);
             Some(RwLockWriteGuard { lock: self })
         } else {
             None
         }
     }
 
+    #[cfg(feature = "lockdep")]
+    #[track_caller]
+    fn lockdep_acquire(&self, kind: LockKind, trylock: bool) {
+        let id = self as *const Self as *const ();
+        lockdep::acquire(&self.dep_map, id, kind, trylock, Location::caller());
+    }
+
     /// Returns `true` if the lock is held in either read or write mode.
     pub fn is_locked(&self) -> bool {
         self.state.load(Ordering::Relaxed) != 0
@@ -182,6 +210,8 @@ impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
 impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
     fn drop(&mut self) {
         self.lock.state.fetch_sub(1, Ordering::Release);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock as *const RwLock<T> as *const ());
     }
 }
 
@@ -204,6 +234,8 @@ impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
 impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
     fn drop(&mut self) {
         self.lock.state.store(0, Ordering::Release);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock as *const RwLock<T> as *const ());
     }
 }
 
@@ -244,12 +276,14 @@ impl<T: ?Sized + fmt::Display> fmt::Display for RwLockWriteGuard<'_, T> {
 }
 
 impl<T: Default> Default for RwLock<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn default() -> Self {
         Self::new(Default::default())
     }
 }
 
 impl<T> From<T> for RwLock<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn from(data: T) -> Self {
         Self::new(data)
     }
@@ -0,0 +1,249 @@
+//! Reader-writer semaphores.
+//!
+//! The sleeping counterpart of [`RwLock`](super::rwlock::RwLock): any number of readers or
+//! one writer hold an [`RwSemaphore`], and threads that have to wait for it sleep. Once a
+//! writer waits, new readers queue behind it, so a steady stream of readers cannot starve
+//! writers.
+
+use super::mutex::SPIN_LIMIT;
+use super::waitqueue::{self, WaitQueue};
+use crate::proc::thread::ThreadId;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Set in `state` while a writer holds the semaphore; the other bits count readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+pub struct RwSemaphore<T: ?Sized> {
+    state: AtomicUsize,
+    /// Writers sleeping or spinning until they can take the semaphore.
+    writers_waiting: AtomicUsize,
+    /// TID of the writer holding the semaphore, 0 otherwise or if taken during early boot.
+    owner: AtomicU64,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: The semaphore hands out shared references to readers and a unique one to a
+// single writer, as `RwLock` does.
+unsafe impl<T: ?Sized + Send> Send for RwSemaphore<T> {}
+// SAFETY: See above; readers on several threads share `&T`, so `T` must also be `Sync`.
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwSemaphore<T> {}
+
+/// Shared access to the data of an [`RwSemaphore`], released when dropped.
+pub struct RwSemaphoreReadGuard<'a, T: ?Sized> {
+    sem: &'a RwSemaphore<T>,
+}
+
+/// Exclusive access to the data of an [`RwSemaphore`], released when dropped.
+pub struct RwSemaphoreWriteGuard<'a, T: ?Sized> {
+    sem: &'a RwSemaphore<T>,
+}
+
+impl<T> RwSemaphore<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            owner: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwSemaphore<T> {
+    /// Take the semaphore for reading, sleeping while a writer holds or waits for it.
+    #[track_caller]
+    pub fn read(&self) -> RwSemaphoreReadGuard<'_, T> {
+        waitqueue::might_sleep();
+        if !self.acquire_read() && !self.spin(|| self.acquire_read()) {
+            self.waiters.wait(|| self.acquire_read(), None);
+        }
+        RwSemaphoreReadGuard { sem: self }
+    }
+
+    /// Take the semaphore for writing, sleeping while anyone else holds it.
+    ///
+    /// # Panics
+    /// Panics if the calling thread already holds it for writing, which would never return.
+    #[track_caller]
+    pub fn write(&self) -> RwSemaphoreWriteGuard<'_, T> {
+        waitqueue::might_sleep();
+        let tid = waitqueue::current_tid();
+        if !self.acquire_write() {
+            if tid != 0 && self.owner.load(Ordering::Relaxed) == tid {
+                panic!("rwsem write-locked recursively by thread {}", tid);
+            }
+            self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+            if !self.spin(|| self.acquire_write()) {
+                self.waiters.wait(|| self.acquire_write(), None);
+            }
+            self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.owner.store(tid, Ordering::Relaxed);
+        RwSemaphoreWriteGuard { sem: self }
+    }
+
+    /// Take the semaphore for reading if that needs no waiting.
+    pub fn try_read(&self) -> Option<RwSemaphoreReadGuard<'_, T>> {
+        self.acquire_read().then_some(RwSemaphoreReadGuard { sem: self })
+    }
+
+    /// Take the semaphore for writing if that needs no waiting.
+    pub fn try_write(&self) -> Option<RwSemaphoreWriteGuard<'_, T>> {
+        if !self.acquire_write() {
+            return None;
+        }
+        self.owner.store(waitqueue::current_tid(), Ordering::Relaxed);
+        Some(RwSemaphoreWriteGuard { sem: self })
+    }
+
+    fn acquire_read(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 || self.writers_waiting.load(Ordering::Relaxed) != 0 {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    fn acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Retry `acquire` for up to `SPIN_LIMIT` attempts while nobody sleeps on the
+    /// semaphore yet. Returns whether it succeeded.
+    fn spin(&self, acquire: impl Fn() -> bool) -> bool {
+        for _ in 0..SPIN_LIMIT {
+            crate::arch::cpu_relax();
+            if acquire() {
+                return true;
+            }
+            if self.waiters.has_waiters() {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn read_unlock(&self) {
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.waiters.wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        self.owner.store(0, Ordering::Relaxed);
+        self.state.store(0, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Readers holding the semaphore.
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) & !WRITER
+    }
+
+    /// Whether a writer holds the semaphore.
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    /// Returns the writer holding the semaphore, for debugging.
+    pub fn owner(&self) -> Option<ThreadId> {
+        match self.owner.load(Ordering::Relaxed) {
+            0 => None,
+            tid => Some(ThreadId::new(tid)),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<'a, T: ?Sized> RwSemaphoreWriteGuard<'a, T> {
+    /// Turn the write hold into a read hold, letting other readers in without letting a
+    /// writer in between.
+    pub fn downgrade(self) -> RwSemaphoreReadGuard<'a, T> {
+        let sem = self.sem;
+        core::mem::forget(self);
+        sem.owner.store(0, Ordering::Relaxed);
+        sem.state.store(1, Ordering::Release);
+        sem.waiters.wake_all();
+        RwSemaphoreReadGuard { sem }
+    }
+}
+
+impl<T: ?Sized> Deref for RwSemaphoreReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: Readers only share the data with other readers.
+        unsafe { &*self.sem.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwSemaphoreWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The writer holds the semaphore exclusively.
+        unsafe { &*self.sem.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwSemaphoreWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The writer holds the semaphore exclusively.
+        unsafe { &mut *self.sem.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSemaphoreReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.sem.read_unlock();
+    }
+}
+
+impl<T: ?Sized> Drop for RwSemaphoreWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.sem.write_unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwSemaphore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwSemaphore");
+        if let Some(guard) = self.try_read() {
+            d.field("data", &&*guard);
+        } else {
+            d.field("data", &format_args!("<locked>"));
+            d.field("owner", &self.owner());
+        }
+        d.finish()
+    }
+}
+
+impl<T: Default> Default for RwSemaphore<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
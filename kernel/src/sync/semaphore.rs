@@ -0,0 +1,74 @@
+//! Counting semaphores.
+//!
+//! A [`Semaphore`] holds a number of permits: [`Semaphore::down`] takes one, sleeping
+//! until one is available, and [`Semaphore::up`] returns one. Unlike a
+//! [`Mutex`](super::mutex::Mutex), permits are not owned, so a permit may be returned by
+//! another thread or an interrupt handler than the one that took it.
+
+use super::waitqueue::{self, WaitQueue};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `count` permits.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit, sleeping until one is available.
+    #[track_caller]
+    pub fn down(&self) {
+        waitqueue::might_sleep();
+        if !self.try_down() {
+            self.waiters.wait(|| self.try_down(), None);
+        }
+    }
+
+    /// Take a permit, sleeping for at most `timeout_ns` nanoseconds. Returns whether a
+    /// permit was taken.
+    #[track_caller]
+    pub fn down_timeout(&self, timeout_ns: u64) -> bool {
+        waitqueue::might_sleep();
+        if self.try_down() {
+            return true;
+        }
+        let deadline = crate::time::now_ns().saturating_add(timeout_ns);
+        self.waiters.wait(|| self.try_down(), Some(deadline))
+    }
+
+    /// Take a permit if one is available, without sleeping.
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.load(Ordering::Relaxed);
+        while count > 0 {
+            match self.count.compare_exchange_weak(
+                count,
+                count - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => count = current,
+            }
+        }
+        false
+    }
+
+    /// Return a permit, waking a thread waiting for one. May be called from interrupt
+    /// handlers.
+    pub fn up(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// Permits currently available.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
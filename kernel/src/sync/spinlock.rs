@@ -1,9 +1,68 @@
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockKind, LockdepMap};
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
 use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicU32;
+
+/// Spinlocks held by the thread running on each CPU, counted in debug builds so that
+/// sleeping under one is caught. The scheduler moves the count along with the thread.
+#[cfg(debug_assertions)]
+static HELD: [AtomicU32; crate::sched::MAX_CPUS] =
+    [const { AtomicU32::new(0) }; crate::sched::MAX_CPUS];
+
+#[cfg(debug_assertions)]
+fn held_slot() -> Option<&'static AtomicU32> {
+    HELD.get(crate::arch::cpu_id() as usize)
+}
+
+/// Count a spinlock taken or released on the calling CPU.
+#[cfg(debug_assertions)]
+fn held_add(taken: bool) {
+    // Without a preemption between finding the CPU and updating its count, only the
+    // calling CPU ever writes it.
+    crate::arch::without_interrupts(|| {
+        if let Some(held) = held_slot() {
+            let n = held.load(Ordering::Relaxed);
+            // Saturating: a guard taken on the boot stack may be released after the first
+            // switch replaced the count.
+            held.store(if taken { n + 1 } else { n.saturating_sub(1) }, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Spinlocks held on the calling CPU, always 0 in release builds.
+pub fn held_count() -> u32 {
+    #[cfg(debug_assertions)]
+    {
+        crate::arch::without_interrupts(|| held_slot().map_or(0, |n| n.load(Ordering::Relaxed)))
+    }
+    #[cfg(not(debug_assertions))]
+    0
+}
+
+/// Replace the count of spinlocks held on the calling CPU with the one of the thread
+/// switched to, returning that of the thread switched away from. Called with interrupts
+/// disabled.
+pub fn swap_held_count(count: u32) -> u32 {
+    #[cfg(debug_assertions)]
+    {
+        held_slot().map_or(0, |n| n.swap(count, Ordering::Relaxed))
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        let _ = count;
+        0
+    }
+}
 
 pub struct Spinlock<T> {
     lock: AtomicBool,
+    #[cfg(feature = "lockdep")]
+    dep_map: LockdepMap,
     data: UnsafeCell<T>,
 }
 
@@ -16,35 +75,64 @@ pub struct SpinlockGuard<'a, T> {
 }
 
 impl<T> Spinlock<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             lock: AtomicBool::new(false),
+            #[cfg(feature = "lockdep")]
+            dep_map: LockdepMap::new(Location::caller()),
             data: UnsafeCell::new(data),
         }
     }
 
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock(&self) -> SpinlockGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        {
+            let site = Location::caller();
+            lockdep::acquire(&self.dep_map, self.id(), LockKind::Exclusive, false, site);
+        }
         while self
             .lock
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
-            core::hint::spin_loop();
+            crate::arch::cpu_relax();
         }
-        SpinlockGuard { lock: self }
+        SpinlockGuard::new(self)
     }
 
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_lock(&self) -> Option<SpinlockGuard<'_, T>> {
         if self
             .lock
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Some(SpinlockGuard { lock: self })
+            #[cfg(feature = "lockdep")]
+            {
+                let site = Location::caller();
+                lockdep::acquire(&self.dep_map, self.id(), LockKind::Exclusive, true, site);
+            }
+            Some(SpinlockGuard::new(self))
         } else {
             None
         }
     }
+
+    /// Identifies the lock to lockdep.
+    #[cfg(feature = "lockdep")]
+    fn id(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+}
+
+impl<'a, T> SpinlockGuard<'a, T> {
+    fn new(lock: &'a Spinlock<T>) -> Self {
+        #[cfg(debug_assertions)]
+        held_add(true);
+        Self { lock }
+    }
 }
 
 impl<T> Deref for SpinlockGuard<'_, T> {
@@ -65,5 +153,9 @@ impl<T> DerefMut for SpinlockGuard<'_, T> {
 impl<T> Drop for SpinlockGuard<'_, T> {
     fn drop(&mut self) {
         self.lock.lock.store(false, Ordering::Release);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.id());
+        #[cfg(debug_assertions)]
+        held_add(false);
     }
 }
@@ -0,0 +1,182 @@
+//! Wait queues: threads sleeping until a condition holds.
+//!
+//! The sleeping locks in this module park the calling [`Thread`] on a [`WaitQueue`]
+//! instead of spinning, so they may be held across disk I/O and other long waits. In
+//! exchange they may only be taken where sleeping is allowed: not from interrupt handlers
+//! or softirqs, not with interrupts disabled and not under a [`Spinlock`].
+//! [`might_sleep`] reports callers that break this rule.
+
+use super::spinlock::{self, Spinlock};
+use crate::proc::thread::{Thread, ThreadState};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::panic::Location;
+
+/// Threads sleeping until a condition holds.
+pub struct WaitQueue {
+    /// Taken with interrupts disabled.
+    waiters: Spinlock<VecDeque<Arc<Spinlock<Thread>>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Spinlock::new(VecDeque::new()),
+        }
+    }
+
+    /// Sleep until `cond` returns `true`.
+    ///
+    /// `cond` runs with the queue locked and interrupts disabled, so a waker that makes it
+    /// true before calling [`WaitQueue::wake_one`] or [`WaitQueue::wake_all`] is never
+    /// missed. It must not sleep.
+    #[track_caller]
+    pub fn wait_until(&self, cond: impl FnMut() -> bool) {
+        might_sleep();
+        self.wait(cond, None);
+    }
+
+    /// Sleep until `cond` returns `true` or `deadline_ns` has passed, and return whether
+    /// `cond` held.
+    #[track_caller]
+    pub fn wait_until_deadline(&self, cond: impl FnMut() -> bool, deadline_ns: u64) -> bool {
+        might_sleep();
+        self.wait(cond, Some(deadline_ns))
+    }
+
+    /// Body of the waits, for callers that checked [`might_sleep`] already.
+    pub(super) fn wait(&self, mut cond: impl FnMut() -> bool, deadline_ns: Option<u64>) -> bool {
+        let expired = || deadline_ns.is_some_and(|ns| crate::time::now_ns() >= ns);
+        let Some(thread) = crate::proc::current_thread() else {
+            // Nothing to sleep on during early boot.
+            loop {
+                if crate::arch::without_interrupts(&mut cond) {
+                    return true;
+                }
+                if expired() {
+                    return false;
+                }
+                crate::arch::cpu_relax();
+            }
+        };
+
+        loop {
+            let done = crate::arch::without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if cond() {
+                    return Some(true);
+                }
+                if expired() {
+                    return Some(false);
+                }
+                thread.lock().state = ThreadState::Sleeping;
+                waiters.push_back(thread.clone());
+                None
+            });
+            if let Some(done) = done {
+                return done;
+            }
+
+            self.sleep(&thread, deadline_ns);
+        }
+    }
+
+    /// Sleep on the queue once `before_sleep` has run, until woken or until `deadline_ns`
+    /// has passed. A wakeup issued after the thread is queued, including one caused by
+    /// `before_sleep`, is not missed. Wakeups may also be spurious.
+    pub(super) fn sleep_after(&self, before_sleep: impl FnOnce(), deadline_ns: Option<u64>) {
+        let Some(thread) = crate::proc::current_thread() else {
+            before_sleep();
+            return;
+        };
+        crate::arch::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            thread.lock().state = ThreadState::Sleeping;
+            waiters.push_back(thread.clone());
+        });
+        before_sleep();
+        self.sleep(&thread, deadline_ns);
+    }
+
+    /// Switch away from `thread`, queued and sleeping, then take it off the queue in case
+    /// it was woken by something else.
+    fn sleep(&self, thread: &Arc<Spinlock<Thread>>, deadline_ns: Option<u64>) {
+        let timer = deadline_ns.map(|ns| crate::time::timer::add(ns, thread.clone()));
+        crate::sched::schedule(false);
+        if let Some(timer) = timer {
+            crate::time::timer::cancel(timer);
+        }
+        crate::arch::without_interrupts(|| {
+            self.waiters.lock().retain(|waiter| !Arc::ptr_eq(waiter, thread));
+        });
+    }
+
+    /// Wake the thread that has waited longest. Returns whether there was one.
+    ///
+    /// May be called from interrupt handlers.
+    pub fn wake_one(&self) -> bool {
+        let waiter = crate::arch::without_interrupts(|| self.waiters.lock().pop_front());
+        match waiter {
+            Some(thread) => {
+                Thread::wake(thread);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake all waiting threads. May be called from interrupt handlers.
+    pub fn wake_all(&self) {
+        let waiters =
+            crate::arch::without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+        for thread in waiters {
+            Thread::wake(thread);
+        }
+    }
+
+    /// Whether any thread waits on the queue.
+    pub fn has_waiters(&self) -> bool {
+        crate::arch::without_interrupts(|| !self.waiters.lock().is_empty())
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Report the caller if it runs where sleeping is not allowed: with interrupts disabled,
+/// in an interrupt handler or softirq, in an RCU read-side critical section, or under a
+/// spinlock. The spinlock check is only made in debug builds.
+///
+/// Called on entry to every function that may sleep, whether or not it ends up sleeping,
+/// so that misuse shows up without contention.
+#[track_caller]
+pub fn might_sleep() {
+    let irqs_disabled = !crate::arch::interrupts_enabled();
+    let held = spinlock::held_count();
+    let in_interrupt = crate::work::softirq::in_interrupt(crate::arch::cpu_id() as usize);
+    let in_rcu = super::rcu::read_lock_held();
+    if !irqs_disabled && held == 0 && !in_interrupt && !in_rcu {
+        return;
+    }
+    // Early boot spins rather than sleeping, and has interrupts disabled for a while.
+    if crate::proc::current_thread().is_none() {
+        return;
+    }
+    log::error!(
+        "BUG: sleeping function called from invalid context at {}: irqs_disabled={} \
+         in_interrupt={} rcu_read_lock={} spinlocks={}",
+        Location::caller(),
+        irqs_disabled,
+        in_interrupt,
+        in_rcu,
+        held
+    );
+}
+
+/// TID of the calling thread, 0 during early boot. Recorded as the owner of sleeping locks.
+pub(super) fn current_tid() -> u64 {
+    crate::proc::current_thread().map_or(0, |thread| thread.lock().tid.as_u64())
+}
@@ -1,4 +1,5 @@
-use super::{SyscallError, SyscallResult, is_user_ptr_valid, read_user_string};
+use super::uaccess::{UserPtr, UserSlice, copy_from_user, read_user_string};
+use super::{PATH_MAX, SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
 use crate::fs::File;
 use crate::fs::vfs::types::{InodeType, LinuxStat, O_CREAT, O_RDONLY, O_WRONLY, SeekWhence, Stat};
@@ -30,11 +31,14 @@ pub const DT_REG: u8 = 8;
 pub const DT_LNK: u8 = 10;
 pub const DT_SOCK: u8 = 12;
 
+/// Size of the kernel bounce buffer that `read()`/`write()` data passes through.
+const RW_CHUNK: usize = 64 * 1024;
+
 /// `sys_read` (SYS_READ = 0)
 /// Read from a file descriptor.
 pub fn sys_read(frame: &mut SyscallFrame) -> SyscallResult {
     let fd = frame.arg1() as i32;
-    let buf = frame.arg2() as *mut u8;
+    let buf = frame.arg2();
     let count = frame.arg3() as usize;
 
     if fd < 0 {
@@ -43,9 +47,7 @@ pub fn sys_read(frame: &mut SyscallFrame) -> SyscallResult {
     if count == 0 {
         return Ok(0);
     }
-    if !is_user_ptr_valid(buf as u64, count) {
-        return Err(SyscallError::EFAULT);
-    }
+    let user_buf = UserSlice::new(buf, count)?;
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -53,17 +55,32 @@ pub fn sys_read(frame: &mut SyscallFrame) -> SyscallResult {
     let file = proc.fd_table.get(fd)?;
     drop(proc);
 
-    // SAFETY: User buffer pointer range validated within user space bounds.
-    let user_slice = unsafe { core::slice::from_raw_parts_mut(buf, count) };
-    let bytes_read = file.read(user_slice)?;
-    Ok(bytes_read)
+    // Regular files are read until `count` is satisfied. Anything else returns after the
+    // first chunk, since reading on could block although data was already transferred.
+    let regular = file.dentry.inode.inode_type == InodeType::File;
+    let mut kbuf = alloc::vec![0u8; count.min(RW_CHUNK)];
+    let mut total = 0;
+    while total < count {
+        let want = (count - total).min(RW_CHUNK);
+        let n = match file.read(&mut kbuf[..want]) {
+            Ok(n) => n,
+            Err(err) if total == 0 => return Err(err.into()),
+            Err(_) => break,
+        };
+        user_buf.write_at(total, &kbuf[..n])?;
+        total += n;
+        if n < want || !regular {
+            break;
+        }
+    }
+    Ok(total)
 }
 
 /// `sys_write` (SYS_WRITE = 1)
 /// Write to a file descriptor.
 pub fn sys_write(frame: &mut SyscallFrame) -> SyscallResult {
     let fd = frame.arg1() as i32;
-    let buf = frame.arg2() as *const u8;
+    let buf = frame.arg2();
     let count = frame.arg3() as usize;
 
     if fd < 0 {
@@ -72,12 +89,7 @@ pub fn sys_write(frame: &mut SyscallFrame) -> SyscallResult {
     if count == 0 {
         return Ok(0);
     }
-    if !is_user_ptr_valid(buf as u64, count) {
-        return Err(SyscallError::EFAULT);
-    }
-
-    // SAFETY: User buffer pointer range validated within user space bounds.
-    let user_slice = unsafe { core::slice::from_raw_parts(buf, count) };
+    UserSlice::new(buf, count)?;
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -85,17 +97,33 @@ pub fn sys_write(frame: &mut SyscallFrame) -> SyscallResult {
     let file = proc.fd_table.get(fd)?;
     drop(proc);
 
-    let bytes_written = file.write(user_slice)?;
-    Ok(bytes_written)
+    let mut kbuf = alloc::vec![0u8; count.min(RW_CHUNK)];
+    let mut total = 0;
+    while total < count {
+        let chunk = &mut kbuf[..(count - total).min(RW_CHUNK)];
+        if let Err(err) = copy_from_user(chunk, buf + total as u64) {
+            return if total == 0 { Err(err.into()) } else { Ok(total) };
+        }
+        let n = match file.write(chunk) {
+            Ok(n) => n,
+            Err(err) if total == 0 => return Err(err.into()),
+            Err(_) => break,
+        };
+        total += n;
+        if n < chunk.len() {
+            break;
+        }
+    }
+    Ok(total)
 }
 
 /// `sys_open` (SYS_OPEN = 2)
 /// Open a file.
 pub fn sys_open(frame: &mut SyscallFrame) -> SyscallResult {
-    let path_ptr = frame.arg1() as *const u8;
+    let path_ptr = frame.arg1();
     let flags = frame.arg2() as u32;
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
 
     let dentry = match crate::fs::resolve_path(&path) {
         Ok(d) => d,
@@ -106,7 +134,7 @@ pub fn sys_open(frame: &mut SyscallFrame) -> SyscallResult {
     };
 
     let file_ops = dentry.inode.ops.open()?;
-    let file = Arc::new(File::new(dentry, flags, file_ops));
+    let file = Arc::new(File::open(dentry, flags, file_ops)?);
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -133,21 +161,13 @@ pub fn sys_close(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_stat` (SYS_STAT = 4)
 /// Get file status by path.
 pub fn sys_stat(frame: &mut SyscallFrame) -> SyscallResult {
-    let path_ptr = frame.arg1() as *const u8;
-    let statbuf = frame.arg2() as *mut LinuxStat;
+    let path_ptr = frame.arg1();
+    let statbuf = UserPtr::<LinuxStat>::new(frame.arg2());
 
-    if !is_user_ptr_valid(statbuf as u64, core::mem::size_of::<LinuxStat>()) {
-        return Err(SyscallError::EFAULT);
-    }
-
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
     let vfs_stat = crate::fs::stat(&path)?;
 
-    let linux_stat = copy_to_linux_stat(&vfs_stat);
-    // SAFETY: Writing stat struct to user statbuf after validation.
-    unsafe {
-        core::ptr::write_volatile(statbuf, linux_stat);
-    }
+    statbuf.write(copy_to_linux_stat(&vfs_stat))?;
 
     Ok(0)
 }
@@ -156,14 +176,11 @@ pub fn sys_stat(frame: &mut SyscallFrame) -> SyscallResult {
 /// Get file status by descriptor.
 pub fn sys_fstat(frame: &mut SyscallFrame) -> SyscallResult {
     let fd = frame.arg1() as i32;
-    let statbuf = frame.arg2() as *mut LinuxStat;
+    let statbuf = UserPtr::<LinuxStat>::new(frame.arg2());
 
     if fd < 0 {
         return Err(SyscallError::EBADF);
     }
-    if !is_user_ptr_valid(statbuf as u64, core::mem::size_of::<LinuxStat>()) {
-        return Err(SyscallError::EFAULT);
-    }
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -171,12 +188,7 @@ pub fn sys_fstat(frame: &mut SyscallFrame) -> SyscallResult {
     drop(proc);
 
     let vfs_stat = file.ops.stat().or_else(|_| file.dentry.inode.ops.stat())?;
-    let linux_stat = copy_to_linux_stat(&vfs_stat);
-
-    // SAFETY: Writing stat struct to user statbuf after validation.
-    unsafe {
-        core::ptr::write_volatile(statbuf, linux_stat);
-    }
+    statbuf.write(copy_to_linux_stat(&vfs_stat))?;
 
     Ok(0)
 }
@@ -277,24 +289,24 @@ pub fn sys_dup3(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_pipe` (SYS_PIPE = 22)
 /// Create an anonymous inter-process pipe.
 pub fn sys_pipe(frame: &mut SyscallFrame) -> SyscallResult {
-    let pipefd = frame.arg1() as *mut i32;
-
-    if !is_user_ptr_valid(pipefd as u64, 2 * core::mem::size_of::<i32>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let pipefd = UserPtr::<[i32; 2]>::new(frame.arg1());
 
     let (f_read, f_write) = crate::fs::pipe::create_pipe(false)?;
 
-    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
-    let proc = proc_arc.lock();
+    // Touching user memory may fault, and the fault handler locks the process.
+    let fd_table = crate::proc::current_process()
+        .ok_or(SyscallError::ESRCH)?
+        .lock()
+        .fd_table
+        .clone();
 
-    let r_fd = proc.fd_table.alloc(f_read);
-    let w_fd = proc.fd_table.alloc(f_write);
+    let r_fd = fd_table.alloc(f_read);
+    let w_fd = fd_table.alloc(f_write);
 
-    // SAFETY: User pipefd pointer range validated within Ring 3 address bounds.
-    unsafe {
-        core::ptr::write_volatile(pipefd, r_fd);
-        core::ptr::write_volatile(pipefd.add(1), w_fd);
+    if let Err(fault) = pipefd.write([r_fd, w_fd]) {
+        let _ = fd_table.close(r_fd);
+        let _ = fd_table.close(w_fd);
+        return Err(fault.into());
     }
 
     Ok(0)
@@ -303,13 +315,9 @@ pub fn sys_pipe(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_pipe2` (SYS_PIPE2 = 293)
 /// Create an anonymous pipe with flags.
 pub fn sys_pipe2(frame: &mut SyscallFrame) -> SyscallResult {
-    let pipefd = frame.arg1() as *mut i32;
+    let pipefd = UserPtr::<[i32; 2]>::new(frame.arg1());
     let flags = frame.arg2() as u32;
 
-    if !is_user_ptr_valid(pipefd as u64, 2 * core::mem::size_of::<i32>()) {
-        return Err(SyscallError::EFAULT);
-    }
-
     let nonblocking = (flags & O_NONBLOCK) != 0;
     let cloexec = if (flags & O_CLOEXEC) != 0 {
         crate::fs::fd::FD_CLOEXEC
@@ -319,16 +327,20 @@ pub fn sys_pipe2(frame: &mut SyscallFrame) -> SyscallResult {
 
     let (f_read, f_write) = crate::fs::pipe::create_pipe(nonblocking)?;
 
-    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
-    let proc = proc_arc.lock();
+    // Touching user memory may fault, and the fault handler locks the process.
+    let fd_table = crate::proc::current_process()
+        .ok_or(SyscallError::ESRCH)?
+        .lock()
+        .fd_table
+        .clone();
 
-    let r_fd = proc.fd_table.alloc_with_flags(f_read, cloexec);
-    let w_fd = proc.fd_table.alloc_with_flags(f_write, cloexec);
+    let r_fd = fd_table.alloc_with_flags(f_read, cloexec);
+    let w_fd = fd_table.alloc_with_flags(f_write, cloexec);
 
-    // SAFETY: User pipefd pointer range validated within Ring 3 address bounds.
-    unsafe {
-        core::ptr::write_volatile(pipefd, r_fd);
-        core::ptr::write_volatile(pipefd.add(1), w_fd);
+    if let Err(fault) = pipefd.write([r_fd, w_fd]) {
+        let _ = fd_table.close(r_fd);
+        let _ = fd_table.close(w_fd);
+        return Err(fault.into());
     }
 
     Ok(0)
@@ -337,26 +349,24 @@ pub fn sys_pipe2(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_getcwd` (SYS_GETCWD = 79)
 /// Get current working directory string.
 pub fn sys_getcwd(frame: &mut SyscallFrame) -> SyscallResult {
-    let buf = frame.arg1() as *mut u8;
+    let buf = frame.arg1();
     let size = frame.arg2() as usize;
 
-    if size == 0 || !is_user_ptr_valid(buf as u64, size) {
+    if size == 0 {
         return Err(SyscallError::EINVAL);
     }
+    let user_buf = UserSlice::new(buf, size)?;
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
 
-    let cwd_bytes = proc.cwd.as_bytes();
-    if cwd_bytes.len() + 1 > size {
-        return Err(SyscallError::ENOMEM);
-    }
-
-    // SAFETY: User buffer pointer range validated within Ring 3 address bounds.
-    unsafe {
-        core::ptr::copy_nonoverlapping(cwd_bytes.as_ptr(), buf, cwd_bytes.len());
-        core::ptr::write_volatile(buf.add(cwd_bytes.len()), 0);
+    let mut cwd_bytes = proc.cwd.clone().into_bytes();
+    drop(proc);
+    cwd_bytes.push(0);
+    if cwd_bytes.len() > size {
+        return Err(SyscallError::ERANGE);
     }
+    user_buf.write(&cwd_bytes)?;
 
     Ok(buf as usize)
 }
@@ -364,8 +374,8 @@ pub fn sys_getcwd(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_chdir` (SYS_CHDIR = 80)
 /// Change working directory.
 pub fn sys_chdir(frame: &mut SyscallFrame) -> SyscallResult {
-    let path_ptr = frame.arg1() as *const u8;
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path_ptr = frame.arg1();
+    let path = read_user_string(path_ptr, PATH_MAX)?;
 
     let dentry = crate::fs::resolve_path(&path)?;
     if dentry.inode.inode_type != crate::fs::vfs::types::InodeType::Directory {
@@ -433,7 +443,10 @@ pub fn sys_fcntl(frame: &mut SyscallFrame) -> SyscallResult {
     }
 }
 
-fn resolve_at_path(dfd: i32, path: &str) -> Result<alloc::string::String, SyscallError> {
+pub(crate) fn resolve_at_path(
+    dfd: i32,
+    path: &str,
+) -> Result<alloc::string::String, SyscallError> {
     if path.starts_with('/') {
         Ok(crate::fs::normalize_path("/", path))
     } else if dfd == -100 || dfd as u32 == 0xffffff9c {
@@ -457,10 +470,10 @@ fn resolve_at_path(dfd: i32, path: &str) -> Result<alloc::string::String, Syscal
 /// Open a file relative to directory descriptor.
 pub fn sys_openat(frame: &mut SyscallFrame) -> SyscallResult {
     let dfd = frame.arg1() as i32;
-    let path_ptr = frame.arg2() as *const u8;
+    let path_ptr = frame.arg2();
     let flags = frame.arg3() as u32;
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
     let full_path = resolve_at_path(dfd, &path)?;
 
     let dentry = match crate::fs::resolve_path(&full_path) {
@@ -472,7 +485,7 @@ pub fn sys_openat(frame: &mut SyscallFrame) -> SyscallResult {
     };
 
     let file_ops = dentry.inode.ops.open()?;
-    let file = Arc::new(File::new(dentry, flags, file_ops));
+    let file = Arc::new(File::open(dentry, flags, file_ops)?);
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -484,14 +497,14 @@ pub fn sys_openat(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_access` (SYS_ACCESS = 21)
 /// Check user's permissions for a file.
 pub fn sys_access(frame: &mut SyscallFrame) -> SyscallResult {
-    let path_ptr = frame.arg1() as *const u8;
+    let path_ptr = frame.arg1();
     let mode = frame.arg2() as i32;
 
     if mode < 0 || mode > 7 {
         return Err(SyscallError::EINVAL);
     }
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
     let _dentry = crate::fs::resolve_path(&path)?;
 
     Ok(0)
@@ -514,22 +527,13 @@ pub fn sys_umask(frame: &mut SyscallFrame) -> SyscallResult {
 /// Get file status relative to directory descriptor.
 pub fn sys_newfstatat(frame: &mut SyscallFrame) -> SyscallResult {
     let dfd = frame.arg1() as i32;
-    let path_ptr = frame.arg2() as *const u8;
-    let statbuf = frame.arg3() as *mut LinuxStat;
-
-    if !is_user_ptr_valid(statbuf as u64, core::mem::size_of::<LinuxStat>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let path_ptr = frame.arg2();
+    let statbuf = UserPtr::<LinuxStat>::new(frame.arg3());
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
     let full_path = resolve_at_path(dfd, &path)?;
     let vfs_stat = crate::fs::stat(&full_path)?;
-
-    let linux_stat = copy_to_linux_stat(&vfs_stat);
-    // SAFETY: Writing stat struct to user statbuf after validation.
-    unsafe {
-        core::ptr::write_volatile(statbuf, linux_stat);
-    }
+    statbuf.write(copy_to_linux_stat(&vfs_stat))?;
 
     Ok(0)
 }
@@ -538,7 +542,7 @@ pub fn sys_newfstatat(frame: &mut SyscallFrame) -> SyscallResult {
 /// Check user's permissions for a file relative to a directory file descriptor.
 pub fn sys_faccessat(frame: &mut SyscallFrame) -> SyscallResult {
     let dfd = frame.arg1() as i32;
-    let path_ptr = frame.arg2() as *const u8;
+    let path_ptr = frame.arg2();
     let mode = frame.arg3() as i32;
     let _flags = frame.arg4() as i32;
 
@@ -546,7 +550,7 @@ pub fn sys_faccessat(frame: &mut SyscallFrame) -> SyscallResult {
         return Err(SyscallError::EINVAL);
     }
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    let path = read_user_string(path_ptr, PATH_MAX)?;
     let full_path = resolve_at_path(dfd, &path)?;
     let _dentry = crate::fs::resolve_path(&full_path)?;
 
@@ -584,7 +588,7 @@ fn copy_to_linux_stat(stat: &Stat) -> LinuxStat {
 /// Get directory entries in 64-bit Linux dirent format.
 pub fn sys_getdents64(frame: &mut SyscallFrame) -> SyscallResult {
     let fd = frame.arg1() as i32;
-    let dirp = frame.arg2() as *mut u8;
+    let dirp = frame.arg2();
     let count = frame.arg3() as usize;
 
     if fd < 0 {
@@ -593,9 +597,7 @@ pub fn sys_getdents64(frame: &mut SyscallFrame) -> SyscallResult {
     if count == 0 {
         return Ok(0);
     }
-    if !is_user_ptr_valid(dirp as u64, count) {
-        return Err(SyscallError::EFAULT);
-    }
+    let user_buf = UserSlice::new(dirp, count)?;
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
@@ -650,7 +652,7 @@ pub fn sys_getdents64(frame: &mut SyscallFrame) -> SyscallResult {
         return Ok(0);
     }
 
-    let mut written_bytes = 0;
+    let mut dirents: alloc::vec::Vec<u8> = alloc::vec::Vec::new();
 
     while pos < all_entries.len() {
         let (ref name, ino, d_type) = all_entries[pos];
@@ -658,8 +660,8 @@ pub fn sys_getdents64(frame: &mut SyscallFrame) -> SyscallResult {
         let unaligned_len = 19 + name_bytes.len() + 1;
         let reclen = (unaligned_len + 7) & !7;
 
-        if written_bytes + reclen > count {
-            if written_bytes == 0 {
+        if dirents.len() + reclen > count {
+            if dirents.is_empty() {
                 return Err(SyscallError::EINVAL);
             }
             break;
@@ -667,32 +669,20 @@ pub fn sys_getdents64(frame: &mut SyscallFrame) -> SyscallResult {
 
         let off = (pos + 1) as i64;
 
-        // SAFETY: Pointer and total range verified by is_user_ptr_valid above.
-        unsafe {
-            let dest = dirp.add(written_bytes);
-            // Write d_ino (u64 at offset 0)
-            core::ptr::write_volatile(dest as *mut u64, ino);
-            // Write d_off (i64 at offset 8)
-            core::ptr::write_volatile(dest.add(8) as *mut i64, off);
-            // Write d_reclen (u16 at offset 16)
-            core::ptr::write_volatile(dest.add(16) as *mut u16, reclen as u16);
-            // Write d_type (u8 at offset 18)
-            core::ptr::write(dest.add(18), d_type);
-            // Write d_name (null-terminated string at offset 19)
-            core::ptr::copy_nonoverlapping(name_bytes.as_ptr(), dest.add(19), name_bytes.len());
-            // Null terminator
-            core::ptr::write(dest.add(19 + name_bytes.len()), 0);
-            // Zero padding up to reclen
-            let pad_start = 19 + name_bytes.len() + 1;
-            if pad_start < reclen {
-                core::ptr::write_bytes(dest.add(pad_start), 0, reclen - pad_start);
-            }
-        }
+        // d_ino (u64), d_off (i64), d_reclen (u16), d_type (u8), then the
+        // NUL-terminated d_name zero-padded up to reclen.
+        let record_start = dirents.len();
+        dirents.extend_from_slice(&ino.to_ne_bytes());
+        dirents.extend_from_slice(&off.to_ne_bytes());
+        dirents.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        dirents.push(d_type);
+        dirents.extend_from_slice(name_bytes);
+        dirents.resize(record_start + reclen, 0);
 
-        written_bytes += reclen;
         pos += 1;
     }
 
+    user_buf.write(&dirents)?;
     *file.offset.lock() = pos;
-    Ok(written_bytes)
+    Ok(dirents.len())
 }
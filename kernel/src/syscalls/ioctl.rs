@@ -1,5 +1,5 @@
 use super::{SyscallError, SyscallResult};
-use crate::fs::vfs::types::VfsError::{BadFd, InvalidInput, NotSupported};
+use crate::fs::vfs::types::VfsError::{BadAddress, BadFd, InvalidInput, NotSupported};
 use crate::arch::syscall::syscall::SyscallFrame;
 
 /// `sys_ioctl` (SYS_IOCTL = 16)
@@ -11,7 +11,7 @@ pub fn sys_ioctl(frame: &mut SyscallFrame) -> SyscallResult {
 
     crate::tty::do_ioctl(fd, cmd, arg).map_err(|e| match e {
         BadFd => SyscallError::EBADF,
-        InvalidInput => SyscallError::EFAULT,
+        BadAddress | InvalidInput => SyscallError::EFAULT,
         NotSupported => SyscallError::ENOTTY,
         _ => SyscallError::EINVAL,
     })
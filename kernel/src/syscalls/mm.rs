@@ -1,13 +1,77 @@
 use super::{SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
-use crate::mm::{PageTable, VmAreaKind};
+use crate::mm::{
+    AddrSpace, AddrSpaceError, ArchPageTable, HUGE_PAGE_SIZE, VmAreaKind, VmFlags,
+};
+use crate::sync::spinlock::Spinlock;
+use alloc::sync::Arc;
+use x86_64::structures::paging::mapper::MapToError;
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::VirtAddr;
 
+/// `mmap` flag: back the mapping with 2 MiB huge pages.
+pub const MAP_HUGETLB: i32 = 0x40000;
+
+/// `madvise` advice: no special treatment.
+pub const MADV_NORMAL: i32 = 0;
+/// `madvise` advice: expect random page references.
+pub const MADV_RANDOM: i32 = 1;
+/// `madvise` advice: expect sequential page references.
+pub const MADV_SEQUENTIAL: i32 = 2;
+/// `madvise` advice: expect access in the near future.
+pub const MADV_WILLNEED: i32 = 3;
+/// `madvise` advice: drop the backing pages; they refault zero-filled.
+pub const MADV_DONTNEED: i32 = 4;
+/// `madvise` advice: let KSM merge identical pages in the range.
+pub const MADV_MERGEABLE: i32 = 12;
+/// `madvise` advice: undo `MADV_MERGEABLE`, unsharing merged pages.
+pub const MADV_UNMERGEABLE: i32 = 13;
+/// `madvise` advice: back the range with transparent huge pages.
+pub const MADV_HUGEPAGE: i32 = 14;
+/// `madvise` advice: never back the range with transparent huge pages.
+pub const MADV_NOHUGEPAGE: i32 = 15;
+
+/// Map an area into `addr_space`, invoking the OOM killer and retrying when the PMM
+/// runs out of frames.
+///
+/// Must be called without the process lock held. If the OOM killer picks the calling
+/// process, this never returns. Gives up with `ENOMEM` after
+/// [`OOM_MAX_RETRIES`](crate::mm::oom::OOM_MAX_RETRIES) kills.
+fn map_area_or_oom(
+    addr_space: &Arc<Spinlock<AddrSpace<ArchPageTable>>>,
+    start: VirtAddr,
+    size: usize,
+    flags: PageTableFlags,
+    kind: VmAreaKind,
+    vm_flags: VmFlags,
+) -> Result<(), SyscallError> {
+    let mut oom_retries = 0;
+    loop {
+        let result = addr_space
+            .lock()
+            .map_area_with_flags(start, size, flags, kind.clone(), vm_flags);
+        match result {
+            Ok(()) => return Ok(()),
+            Err(AddrSpaceError::PagingError(MapToError::FrameAllocationFailed))
+                if oom_retries < crate::mm::oom::OOM_MAX_RETRIES =>
+            {
+                oom_retries += 1;
+                let self_pid = crate::proc::current_process().map(|p| p.lock().pid);
+                match crate::mm::oom::out_of_memory(0) {
+                    Some(victim) if Some(victim) != self_pid => continue,
+                    Some(_) => super::proc::do_exit(128 + crate::ipc::signal::SIGKILL as i32),
+                    None => return Err(SyscallError::ENOMEM),
+                }
+            }
+            Err(_) => return Err(SyscallError::ENOMEM),
+        }
+    }
+}
+
 /// `sys_brk` (SYS_BRK = 12)
 /// Change data segment size (heap break pointer).
 pub fn sys_brk(frame: &mut SyscallFrame) -> SyscallResult {
-    let new_brk = frame.arg1() as u64;
+    let new_brk = frame.arg1();
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let mut proc = proc_arc.lock();
@@ -28,13 +92,21 @@ pub fn sys_brk(frame: &mut SyscallFrame) -> SyscallResult {
                 | PageTableFlags::WRITABLE
                 | PageTableFlags::USER_ACCESSIBLE;
 
-            let mut addr_space = proc.address_space.lock();
-            let _ = addr_space.map_area(
+            let addr_space = proc.address_space.clone();
+            drop(proc);
+            if map_area_or_oom(
+                &addr_space,
                 VirtAddr::new(page_start),
                 size,
                 flags,
                 VmAreaKind::Anonymous,
-            );
+                VmFlags::empty(),
+            )
+            .is_err()
+            {
+                return Ok(current_brk as usize);
+            }
+            proc = proc_arc.lock();
         }
     }
 
@@ -45,21 +117,27 @@ pub fn sys_brk(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_mmap` (SYS_MMAP = 9)
 /// Map files or devices into memory.
 pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
-    let addr = frame.arg1() as u64;
+    let addr = frame.arg1();
     let len = frame.arg2() as usize;
     let prot = frame.arg3() as i32;
-    let _flags = frame.arg4() as i32;
+    let flags = frame.arg4() as i32;
     let fd = frame.arg5() as i32;
-    let offset = frame.arg6() as u64;
+    let offset = frame.arg6();
 
     if len == 0 {
         return Err(SyscallError::EINVAL);
     }
 
+    let hugetlb = (flags & MAP_HUGETLB) != 0;
+    if hugetlb && (fd >= 0 || (addr != 0 && !addr.is_multiple_of(HUGE_PAGE_SIZE))) {
+        return Err(SyscallError::EINVAL);
+    }
+
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let mut proc = proc_arc.lock();
 
-    let aligned_len = (len + 4095) & !4095;
+    let page_size = if hugetlb { HUGE_PAGE_SIZE as usize } else { 4096 };
+    let aligned_len = (len + page_size - 1) & !(page_size - 1);
     let target_vaddr = if addr != 0 {
         let vaddr = addr & !4095;
         // POSIX MAP_FIXED replacement: unmap any existing overlapping range
@@ -71,8 +149,13 @@ pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
         drop(addr_space);
         vaddr
     } else {
-        let vaddr = proc.mmap_bump;
-        proc.mmap_bump += aligned_len as u64;
+        // Large anonymous mappings start on a 2 MiB boundary so they can use huge pages.
+        let vaddr = if fd < 0 && aligned_len as u64 >= HUGE_PAGE_SIZE {
+            VirtAddr::new(proc.mmap_bump).align_up(HUGE_PAGE_SIZE).as_u64()
+        } else {
+            proc.mmap_bump
+        };
+        proc.mmap_bump = vaddr + aligned_len as u64;
         vaddr
     };
 
@@ -91,6 +174,7 @@ pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
                 file: file.ops.clone(),
                 offset: offset as usize,
                 file_size,
+                path: crate::fs::build_path(&file.dentry).into(),
             }
         } else {
             VmAreaKind::Anonymous
@@ -99,18 +183,22 @@ pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
         VmAreaKind::Anonymous
     };
 
-    let mut addr_space = proc.address_space.lock();
-    if addr_space
-        .map_area(
-            VirtAddr::new(target_vaddr),
-            aligned_len,
-            map_flags,
-            kind,
-        )
-        .is_err()
-    {
-        return Err(SyscallError::ENOMEM);
-    }
+    let vm_flags = if hugetlb {
+        VmFlags::HUGETLB
+    } else {
+        VmFlags::empty()
+    };
+
+    let addr_space = proc.address_space.clone();
+    drop(proc);
+    map_area_or_oom(
+        &addr_space,
+        VirtAddr::new(target_vaddr),
+        aligned_len,
+        map_flags,
+        kind,
+        vm_flags,
+    )?;
 
     Ok(target_vaddr as usize)
 }
@@ -118,7 +206,7 @@ pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_munmap` (SYS_MUNMAP = 11)
 /// Unmap files or devices from memory.
 pub fn sys_munmap(frame: &mut SyscallFrame) -> SyscallResult {
-    let addr = frame.arg1() as u64;
+    let addr = frame.arg1();
     let len = frame.arg2() as usize;
 
     if addr == 0 || !VirtAddr::new(addr).is_aligned(4096u64) || len == 0 {
@@ -146,7 +234,7 @@ pub fn sys_munmap(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_mprotect` (SYS_MPROTECT = 10)
 /// Set protection on a region of memory.
 pub fn sys_mprotect(frame: &mut SyscallFrame) -> SyscallResult {
-    let addr = frame.arg1() as u64;
+    let addr = frame.arg1();
     let len = frame.arg2() as usize;
     let prot = frame.arg3() as i32;
 
@@ -156,7 +244,7 @@ pub fn sys_mprotect(frame: &mut SyscallFrame) -> SyscallResult {
 
     let aligned_len = (len + 4095) & !4095;
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
-    let mut proc = proc_arc.lock();
+    let proc = proc_arc.lock();
 
     let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
     if (prot & 2) != 0 {
@@ -167,9 +255,60 @@ pub fn sys_mprotect(frame: &mut SyscallFrame) -> SyscallResult {
     }
 
     let mut addr_space = proc.address_space.lock();
-    for page_virt_u64 in (addr..addr + aligned_len as u64).step_by(4096) {
-        let page_virt = VirtAddr::new(page_virt_u64);
-        let _ = addr_space.page_table_mut().remap(page_virt, flags);
+    addr_space
+        .protect_range(
+            VirtAddr::new(addr & !4095),
+            VirtAddr::new((addr & !4095) + aligned_len as u64),
+            flags,
+        )
+        .map_err(|err| match err {
+            AddrSpaceError::PermissionDenied => SyscallError::EACCES,
+            _ => SyscallError::ENOMEM,
+        })?;
+
+    Ok(0)
+}
+
+/// `sys_madvise` (SYS_MADVISE = 28)
+/// Give advice about use of memory.
+pub fn sys_madvise(frame: &mut SyscallFrame) -> SyscallResult {
+    let addr = frame.arg1();
+    let len = frame.arg2() as usize;
+    let advice = frame.arg3() as i32;
+
+    if addr % 4096 != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let start = VirtAddr::new(addr);
+    let end = VirtAddr::new(addr + ((len + 4095) & !4095) as u64);
+
+    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let proc = proc_arc.lock();
+    let mut addr_space = proc.address_space.lock();
+
+    match advice {
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED => {}
+        MADV_DONTNEED => addr_space.discard_range(start, end),
+        MADV_HUGEPAGE => {
+            addr_space.update_vm_flags(start, end, VmFlags::HUGEPAGE, VmFlags::NOHUGEPAGE);
+            addr_space.collapse_range(start, end);
+        }
+        MADV_NOHUGEPAGE => {
+            addr_space.update_vm_flags(start, end, VmFlags::NOHUGEPAGE, VmFlags::HUGEPAGE);
+        }
+        MADV_MERGEABLE => {
+            addr_space.update_vm_flags(start, end, VmFlags::MERGEABLE, VmFlags::empty());
+        }
+        MADV_UNMERGEABLE => {
+            crate::mm::ksm::unmerge_range(&mut addr_space, start, end)
+                .map_err(|_| SyscallError::ENOMEM)?;
+            addr_space.update_vm_flags(start, end, VmFlags::empty(), VmFlags::MERGEABLE);
+        }
+        _ => return Err(SyscallError::EINVAL),
     }
 
     Ok(0)
@@ -3,10 +3,12 @@ pub mod fs;
 pub mod ioctl;
 pub mod mm;
 pub mod proc;
+pub mod sched;
 pub mod signals;
 pub mod sync;
 pub mod sys_info;
 pub mod time;
+pub mod uaccess;
 
 use crate::arch::syscall::SyscallFrame;
 use crate::fs::vfs::types::*;
@@ -21,11 +23,15 @@ pub enum SyscallError {
     ESRCH = 3,
     EINTR = 4,
     EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
     EBADF = 9,
     ECHILD = 10,
     EAGAIN = 11,
     ENOMEM = 12,
+    EACCES = 13,
     EFAULT = 14,
+    EBUSY = 16,
     EEXIST = 17,
     ENODEV = 19,
     ENOTDIR = 20,
@@ -33,7 +39,10 @@ pub enum SyscallError {
     EINVAL = 22,
     EMFILE = 24,
     ENOTTY = 25,
+    ETXTBSY = 26,
     ESPIPE = 29,
+    ERANGE = 34,
+    ENAMETOOLONG = 36,
     ENOSYS = 38,
     ELOOP = 40,
     ETIMEDOUT = 110,
@@ -70,6 +79,9 @@ impl From<crate::fs::vfs::types::VfsError> for SyscallError {
             VfsError::IsDirectory => SyscallError::EISDIR,
             VfsError::Interrupted => SyscallError::EINTR,
             VfsError::TooManySymlinks => SyscallError::ELOOP,
+            VfsError::BadAddress => SyscallError::EFAULT,
+            VfsError::TextBusy => SyscallError::ETXTBSY,
+            VfsError::Busy => SyscallError::EBUSY,
             VfsError::DriverError(d) => match d {
                 crate::device::DriverError::Timeout => SyscallError::ETIMEDOUT,
                 crate::device::DriverError::NoDevice => SyscallError::ENODEV,
@@ -81,6 +93,20 @@ impl From<crate::fs::vfs::types::VfsError> for SyscallError {
     }
 }
 
+impl From<crate::proc::loader::ExecError> for SyscallError {
+    fn from(err: crate::proc::loader::ExecError) -> Self {
+        use crate::proc::loader::ExecError;
+        match err {
+            ExecError::BadFormat(_) => SyscallError::ENOEXEC,
+            ExecError::AccessDenied => SyscallError::EACCES,
+            ExecError::ArgListTooLong => SyscallError::E2BIG,
+            ExecError::TooManyInterpreters => SyscallError::ELOOP,
+            ExecError::NoMemory(_) => SyscallError::ENOMEM,
+            ExecError::Vfs(err) => err.into(),
+        }
+    }
+}
+
 /// System Call Result Type (Idiomatic Rust Error Propagation)
 pub type SyscallResult = Result<usize, SyscallError>;
 
@@ -126,47 +152,24 @@ macro_rules! define_syscall_table {
                 },
             )*
         ];
+
+        // `dispatch` binary searches the table, so reject it at build time unless sorted.
+        const _: () = {
+            let nums: &[u64] = &[$($num),*];
+            let mut i = 1;
+            while i < nums.len() {
+                assert!(nums[i - 1] < nums[i], "SYSCALL_TABLE is not sorted by number");
+                i += 1;
+            }
+        };
     };
 }
 
 /// Maximum virtual address allowed for user space pointers (Ring 3 canonical boundary).
 pub const USER_SPACE_MAX_ADDR: u64 = 0x0000_7FFF_FFFF_FFFF;
 
-/// Validate if a user pointer and range lies strictly within user-space memory.
-pub fn is_user_ptr_valid(ptr: u64, len: usize) -> bool {
-    if ptr == 0 {
-        return false;
-    }
-    let end = match ptr.checked_add(len as u64) {
-        Some(e) => e,
-        None => return false,
-    };
-    end <= USER_SPACE_MAX_ADDR
-}
-
-/// Helper to safely copy a null-terminated string from user space memory into kernel `String`.
-pub unsafe fn read_user_string(
-    ptr: *const u8,
-    max_len: usize,
-) -> Result<alloc::string::String, SyscallError> {
-    if !is_user_ptr_valid(ptr as u64, 1) {
-        return Err(SyscallError::EFAULT);
-    }
-    let mut vec = alloc::vec::Vec::new();
-    for i in 0..max_len {
-        let addr = ptr as u64 + i as u64;
-        if !is_user_ptr_valid(addr, 1) {
-            return Err(SyscallError::EFAULT);
-        }
-        // SAFETY: User space memory range validated.
-        let byte = unsafe { core::ptr::read_volatile((addr) as *const u8) };
-        if byte == 0 {
-            break;
-        }
-        vec.push(byte);
-    }
-    alloc::string::String::from_utf8(vec).map_err(|_| SyscallError::EINVAL)
-}
+/// Maximum length of a path passed to a system call, including the terminating NUL.
+pub const PATH_MAX: usize = 4096;
 
 /// System Call Dispatcher utilizing Asterinas-style Binary Search on architecture-specific table
 pub fn dispatch(frame: &mut SyscallFrame) -> u64 {
@@ -1,7 +1,10 @@
-use super::{SyscallError, SyscallResult, is_user_ptr_valid, read_user_string};
+use super::uaccess::{UserPtr, read_user_string};
+use super::{PATH_MAX, SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
 use crate::mm::vmm::paging::PageTable;
 use crate::proc::ProcessId;
+use crate::proc::process::rlimit::{RLIM_NLIMITS, RLimit64};
+use crate::sched::group::SchedGroup;
 
 /// `sys_yield` (SYS_YIELD = 24)
 /// Yield the CPU to another runnable thread.
@@ -116,18 +119,32 @@ pub fn sys_getegid(_frame: &mut SyscallFrame) -> SyscallResult {
 
 /// `sys_setsid` (SYS_SETSID = 112)
 /// Creates a new session if the calling process is not a process group leader.
+///
+/// The new session gets its own autogroup, so that it shares CPU time with other
+/// sessions as a whole.
 pub fn sys_setsid(_frame: &mut SyscallFrame) -> SyscallResult {
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
-    let mut proc = proc_arc.lock();
-    proc.pgid = proc.pid;
-    Ok(proc.pid.as_u64() as usize)
+    let (pid, threads) = {
+        let mut proc = proc_arc.lock();
+        proc.pgid = proc.pid;
+        (proc.pid, proc.threads.values().cloned().collect::<alloc::vec::Vec<_>>())
+    };
+
+    let group = SchedGroup::new(None);
+    crate::arch::without_interrupts(|| {
+        let mut sched = crate::sched::SCHEDULER.lock();
+        for thread in &threads {
+            sched.set_group(thread, Some(group.clone()));
+        }
+    });
+    Ok(pid.as_u64() as usize)
 }
 
 /// `sys_getgroups` (SYS_GETGROUPS = 115)
 /// Get list of supplementary group IDs.
 pub fn sys_getgroups(frame: &mut SyscallFrame) -> SyscallResult {
     let size = frame.arg1() as i32;
-    let list_ptr = frame.arg2() as *mut u32;
+    let list_ptr = UserPtr::<u32>::new(frame.arg2());
 
     if size < 0 {
         return Err(SyscallError::EINVAL);
@@ -135,107 +152,127 @@ pub fn sys_getgroups(frame: &mut SyscallFrame) -> SyscallResult {
     if size == 0 {
         return Ok(1);
     }
-    if !is_user_ptr_valid(list_ptr as u64, core::mem::size_of::<u32>()) {
-        return Err(SyscallError::EFAULT);
-    }
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let proc = proc_arc.lock();
     let gid = proc.gid;
     drop(proc);
 
-    // SAFETY: Validated user memory pointer bounds.
-    unsafe {
-        core::ptr::write_volatile(list_ptr, gid);
-    }
+    list_ptr.write(gid)?;
     Ok(1)
 }
 
-/// Linux 64-bit resource limit structure.
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct RLimit64 {
-    pub rlim_cur: u64,
-    pub rlim_max: u64,
+/// `sys_personality` (SYS_PERSONALITY = 135)
+/// Set the process execution domain; `0xffffffff` only queries it.
+/// Returns the previous personality.
+pub fn sys_personality(frame: &mut SyscallFrame) -> SyscallResult {
+    let persona = frame.arg1() as u32;
+
+    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let mut proc = proc_arc.lock();
+    let old = proc.personality;
+    if persona != 0xffff_ffff {
+        proc.personality = persona;
+    }
+    Ok(old as usize)
+}
+
+/// Apply a `setrlimit`/`prlimit64` request from a caller with effective UID `euid`.
+///
+/// The soft limit may not exceed the hard limit, and only root may raise the hard limit.
+fn set_rlimit(
+    proc: &mut crate::proc::Process,
+    resource: usize,
+    new: RLimit64,
+    euid: u32,
+) -> Result<(), SyscallError> {
+    if new.rlim_cur > new.rlim_max {
+        return Err(SyscallError::EINVAL);
+    }
+    if new.rlim_max > proc.rlimits[resource].rlim_max && euid != 0 {
+        return Err(SyscallError::EPERM);
+    }
+    proc.rlimits[resource] = new;
+    Ok(())
 }
 
-const RLIM_INFINITY: u64 = !0u64;
-
-fn get_default_rlimit(resource: i32) -> RLimit64 {
-    match resource {
-        3 /* RLIMIT_STACK */ => RLimit64 {
-            rlim_cur: 8 * 1024 * 1024,
-            rlim_max: 64 * 1024 * 1024,
-        },
-        7 /* RLIMIT_NOFILE */ => RLimit64 {
-            rlim_cur: 1024,
-            rlim_max: 4096,
-        },
-        6 /* RLIMIT_NPROC */ => RLimit64 {
-            rlim_cur: 4096,
-            rlim_max: 4096,
-        },
-        _ => RLimit64 {
-            rlim_cur: RLIM_INFINITY,
-            rlim_max: RLIM_INFINITY,
-        },
+/// Validate a resource number passed to the rlimit system calls.
+fn rlimit_resource(resource: u64) -> Result<usize, SyscallError> {
+    let resource = resource as u32 as usize;
+    if resource >= RLIM_NLIMITS {
+        return Err(SyscallError::EINVAL);
     }
+    Ok(resource)
 }
 
 /// `sys_getrlimit` (SYS_GETRLIMIT = 97)
 /// Get resource limits.
 pub fn sys_getrlimit(frame: &mut SyscallFrame) -> SyscallResult {
-    let resource = frame.arg1() as i32;
-    let rlim_ptr = frame.arg2() as *mut RLimit64;
+    let resource = rlimit_resource(frame.arg1())?;
+    let rlim_ptr = UserPtr::<RLimit64>::new(frame.arg2());
 
-    if !is_user_ptr_valid(rlim_ptr as u64, core::mem::size_of::<RLimit64>()) {
-        return Err(SyscallError::EFAULT);
-    }
-
-    let limit = get_default_rlimit(resource);
-    // SAFETY: Validated user memory pointer bounds.
-    unsafe {
-        core::ptr::write_volatile(rlim_ptr, limit);
-    }
+    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let limit = proc_arc.lock().rlimits[resource];
+    rlim_ptr.write(limit)?;
     Ok(0)
 }
 
 /// `sys_setrlimit` (SYS_SETRLIMIT = 160)
 /// Set resource limits.
 pub fn sys_setrlimit(frame: &mut SyscallFrame) -> SyscallResult {
-    let _resource = frame.arg1() as i32;
-    let rlim_ptr = frame.arg2() as *const RLimit64;
+    let resource = rlimit_resource(frame.arg1())?;
+    let rlim_ptr = UserPtr::<RLimit64>::new(frame.arg2());
+    let new_limit = rlim_ptr.read()?;
 
-    if !is_user_ptr_valid(rlim_ptr as u64, core::mem::size_of::<RLimit64>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let mut proc = proc_arc.lock();
+    let euid = proc.euid;
+    set_rlimit(&mut proc, resource, new_limit, euid)?;
     Ok(0)
 }
 
 /// `sys_prlimit64` (SYS_PRLIMIT64 = 302)
 /// Get/set resource limits of an arbitrary process.
+///
+/// Other processes may only be inspected or changed by root or by a caller with the
+/// same user and group IDs.
 pub fn sys_prlimit64(frame: &mut SyscallFrame) -> SyscallResult {
-    let _pid = frame.arg1() as i32;
-    let resource = frame.arg2() as i32;
-    let new_limit_ptr = frame.arg3() as *const RLimit64;
-    let old_limit_ptr = frame.arg4() as *mut RLimit64;
-
-    if !new_limit_ptr.is_null()
-        && !is_user_ptr_valid(new_limit_ptr as u64, core::mem::size_of::<RLimit64>())
-    {
-        return Err(SyscallError::EFAULT);
-    }
-
-    if !old_limit_ptr.is_null() {
-        if !is_user_ptr_valid(old_limit_ptr as u64, core::mem::size_of::<RLimit64>()) {
-            return Err(SyscallError::EFAULT);
+    let pid = frame.arg1() as i32;
+    let resource = rlimit_resource(frame.arg2())?;
+    let new_limit_ptr = UserPtr::<RLimit64>::new(frame.arg3());
+    let old_limit_ptr = UserPtr::<RLimit64>::new(frame.arg4());
+    let new_limit = new_limit_ptr.read_if_nonnull()?;
+
+    let self_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let (self_pid, uid, gid, euid) = {
+        let proc = self_arc.lock();
+        (proc.pid, proc.uid, proc.gid, proc.euid)
+    };
+    let target_arc = if pid == 0 || pid as u64 == self_pid.as_u64() {
+        self_arc
+    } else if pid < 0 {
+        return Err(SyscallError::ESRCH);
+    } else {
+        let target = crate::proc::find_process(ProcessId(pid as u64)).ok_or(SyscallError::ESRCH)?;
+        let permitted = {
+            let t = target.lock();
+            euid == 0 || (t.uid == uid && t.euid == uid && t.gid == gid && t.egid == gid)
+        };
+        if !permitted {
+            return Err(SyscallError::EPERM);
         }
-        let limit = get_default_rlimit(resource);
-        // SAFETY: Validated user memory pointer bounds.
-        unsafe {
-            core::ptr::write_volatile(old_limit_ptr, limit);
+        target
+    };
+
+    let old_limit = {
+        let mut target = target_arc.lock();
+        let old_limit = target.rlimits[resource];
+        if let Some(new_limit) = new_limit {
+            set_rlimit(&mut target, resource, new_limit, euid)?;
         }
-    }
+        old_limit
+    };
+    old_limit_ptr.write_if_nonnull(old_limit)?;
 
     Ok(0)
 }
@@ -259,18 +296,70 @@ pub fn sys_vfork(frame: &mut SyscallFrame) -> SyscallResult {
 /// `sys_execve` (SYS_EXECVE = 59)
 /// Execute program file.
 pub fn sys_execve(frame: &mut SyscallFrame) -> SyscallResult {
-    let path_ptr = frame.arg1() as *const u8;
-    let argv_ptr = frame.arg2() as *const *const u8;
-    let envp_ptr = frame.arg3() as *const *const u8;
+    let path_ptr = frame.arg1();
+    let argv_ptr = UserPtr::<u64>::new(frame.arg2());
+    let envp_ptr = UserPtr::<u64>::new(frame.arg3());
+
+    // Copy everything out of the old image before locking the process, since touching
+    // user memory may fault and the fault handler takes the process lock.
+    let path = read_user_string(path_ptr, PATH_MAX)?;
+    let cmdline = crate::proc::process::cmdline::CommandLine::from_user(argv_ptr, envp_ptr)?;
+    let dentry = crate::fs::resolve_path(&path)?;
+
+    exec_dentry(frame, dentry, &path, cmdline)
+}
+
+/// `execveat` flag: execute the file `dfd` refers to when the path is empty.
+pub const AT_EMPTY_PATH: i32 = 0x1000;
+
+/// `sys_execveat` (SYS_EXECVEAT = 322)
+/// Execute program file relative to a directory file descriptor.
+pub fn sys_execveat(frame: &mut SyscallFrame) -> SyscallResult {
+    let dfd = frame.arg1() as i32;
+    let path_ptr = frame.arg2();
+    let argv_ptr = UserPtr::<u64>::new(frame.arg3());
+    let envp_ptr = UserPtr::<u64>::new(frame.arg4());
+    let flags = frame.arg5() as i32;
 
-    let path = unsafe { read_user_string(path_ptr, 256)? };
+    if flags & !AT_EMPTY_PATH != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let path = read_user_string(path_ptr, PATH_MAX)?;
+    let cmdline = crate::proc::process::cmdline::CommandLine::from_user(argv_ptr, envp_ptr)?;
+
+    // Scripts started from a descriptor see it as /dev/fd/N, like on Linux.
+    let (dentry, file_name) = if path.is_empty() {
+        if flags & AT_EMPTY_PATH == 0 {
+            return Err(SyscallError::ENOENT);
+        }
+        let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+        let file = proc_arc.lock().fd_table.get(dfd)?;
+        (file.dentry.clone(), alloc::format!("/dev/fd/{}", dfd))
+    } else {
+        let full_path = super::fs::resolve_at_path(dfd, &path)?;
+        let file_name = if path.starts_with('/') || dfd == super::fs::AT_FDCWD {
+            path
+        } else {
+            alloc::format!("/dev/fd/{}/{}", dfd, path)
+        };
+        (crate::fs::resolve_path(&full_path)?, file_name)
+    };
 
+    exec_dentry(frame, dentry, &file_name, cmdline)
+}
+
+/// Replace the current process image with `dentry` and return to its entry point.
+fn exec_dentry(
+    frame: &mut SyscallFrame,
+    dentry: alloc::sync::Arc<crate::fs::Dentry>,
+    file_name: &str,
+    cmdline: crate::proc::process::cmdline::CommandLine,
+) -> SyscallResult {
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
     let mut proc = proc_arc.lock();
 
-    let (entry_point, stack_top) = proc
-        .execute(&path, 0, argv_ptr, envp_ptr)
-        .map_err(|_| SyscallError::ENOENT)?;
+    let (entry_point, stack_top) = proc.execute_file(dentry, file_name, cmdline)?;
 
     let new_cr3 = proc.address_space.lock().page_table().root().as_u64();
 
@@ -317,9 +406,9 @@ pub struct RUsage {
 /// Wait for process state change.
 pub fn sys_wait4(frame: &mut SyscallFrame) -> SyscallResult {
     let pid_raw = frame.arg1() as i32;
-    let wstatus = frame.arg2() as *mut i32;
+    let wstatus = UserPtr::<i32>::new(frame.arg2());
     let options = frame.arg3() as i32;
-    let rusage_ptr = frame.arg4() as *mut RUsage;
+    let rusage_ptr = UserPtr::<RUsage>::new(frame.arg4());
 
     let wnohang = (options & 1) != 0;
     let wuntraced = (options & 2) != 0;
@@ -343,20 +432,8 @@ pub fn sys_wait4(frame: &mut SyscallFrame) -> SyscallResult {
         }
     };
 
-    if !wstatus.is_null() && is_user_ptr_valid(wstatus as u64, core::mem::size_of::<i32>()) {
-        // SAFETY: User pointer validated within Ring 3 address bounds.
-        unsafe {
-            core::ptr::write_volatile(wstatus, status);
-        }
-    }
-
-    if !rusage_ptr.is_null() && is_user_ptr_valid(rusage_ptr as u64, core::mem::size_of::<RUsage>())
-    {
-        // SAFETY: User pointer validated within Ring 3 address bounds.
-        unsafe {
-            core::ptr::write_volatile(rusage_ptr, RUsage::default());
-        }
-    }
+    wstatus.write_if_nonnull(status)?;
+    rusage_ptr.write_if_nonnull(RUsage::default())?;
 
     Ok(child_pid.as_u64() as usize)
 }
@@ -383,7 +460,7 @@ pub fn sys_exit_group(frame: &mut SyscallFrame) -> SyscallResult {
 /// then yields the CPU via `schedule(false)`. If no other runnable thread exists,
 /// it falls into the idle loop. Either path prevents `iretq` from firing into a
 /// dead user-space context.
-fn do_exit(code: i32) -> ! {
+pub(crate) fn do_exit(code: i32) -> ! {
     let ppid_opt = if let Some(proc_arc) = crate::proc::current_process() {
         let mut proc = proc_arc.lock();
         proc.exit(code);
@@ -0,0 +1,508 @@
+use super::time::TimeSpec;
+use super::uaccess::{UserPtr, UserSlice, copy_from_user, copy_to_user};
+use super::{SyscallError, SyscallResult};
+use crate::arch::syscall::syscall::SyscallFrame;
+use crate::proc::process::rlimit::{RLIMIT_NICE, RLIMIT_RTPRIO};
+use crate::proc::thread::thread::{DEFAULT_THREAD_SLICE_NS, SchedParams};
+use crate::proc::{Process, ProcessId, Thread};
+use crate::sched::fair::{MAX_SLICE_NS, MIN_SLICE_NS};
+use crate::sched::policy::{SCHED_RESET_ON_FORK, SchedPolicy};
+use crate::sched::{CPU_MASK_ALL, Nice, SCHEDULER};
+use crate::sync::spinlock::Spinlock;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Size of the affinity masks exchanged with user space, in bytes.
+///
+/// Like Linux, the kernel deals in whole `unsigned long`s of the CPU set.
+const CPU_SET_BYTES: usize = 8;
+
+/// Resolve the `pid` argument of the scheduling calls, 0 meaning the caller.
+///
+/// Thread IDs are not visible to user space, so `pid` names a process and the calls act
+/// on all of its threads.
+fn target_process(pid: i32) -> Result<Arc<Spinlock<Process>>, SyscallError> {
+    match pid {
+        0 => crate::proc::current_process().ok_or(SyscallError::ESRCH),
+        pid if pid < 0 => Err(SyscallError::EINVAL),
+        pid => crate::proc::find_process(ProcessId(pid as u64)).ok_or(SyscallError::ESRCH),
+    }
+}
+
+/// Whether the caller may change the scheduling parameters of `target`: root may change
+/// any process, others only processes of their own user.
+fn may_reschedule(target: &Arc<Spinlock<Process>>) -> Result<bool, SyscallError> {
+    let self_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    if Arc::ptr_eq(&self_arc, target) {
+        return Ok(true);
+    }
+    let euid = self_arc.lock().euid;
+    let t = target.lock();
+    Ok(euid == 0 || euid == t.uid || euid == t.euid)
+}
+
+fn threads_of(proc: &Arc<Spinlock<Process>>) -> Vec<Arc<Spinlock<Thread>>> {
+    proc.lock().threads.values().cloned().collect()
+}
+
+/// `struct sched_param` of the Linux ABI.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// Scheduling parameters and reset-on-fork flag of the main thread of `proc`.
+fn current_params(proc: &Arc<Spinlock<Process>>) -> (SchedParams, bool) {
+    threads_of(proc).first().map_or((SchedParams::default(), false), |t| {
+        let t = t.lock();
+        let params = SchedParams {
+            policy: t.policy,
+            rt_priority: t.rt_priority,
+            nice: t.nice,
+            slice_ns: t.slice_ns,
+        };
+        (params, t.reset_on_fork)
+    })
+}
+
+/// Whether the caller may give a thread the nice value `nice`, if that is below the
+/// thread's `current` one: root always may, others down to the floor set by
+/// `RLIMIT_NICE` (given as `20 - nice`).
+fn may_set_nice(nice: Nice, current: Nice) -> Result<bool, SyscallError> {
+    if nice >= current {
+        return Ok(true);
+    }
+    let self_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let proc = self_arc.lock();
+    Ok(proc.euid == 0 || (20 - nice.value() as i64) as u64 <= proc.rlimits[RLIMIT_NICE].rlim_cur)
+}
+
+/// Set the nice value and slice of every thread of `proc`.
+fn apply_fair_params(proc: &Arc<Spinlock<Process>>, nice: Nice, slice_ns: Option<u64>) {
+    let threads = threads_of(proc);
+    crate::arch::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        for thread in &threads {
+            let slice_ns = slice_ns.unwrap_or_else(|| thread.lock().slice_ns);
+            sched.reweight(thread, nice, slice_ns);
+        }
+    });
+}
+
+/// Validate and apply a policy change to every thread of the process `pid`.
+///
+/// Real-time policies need root or a priority within the caller's `RLIMIT_RTPRIO`.
+fn apply_policy(
+    pid: i32,
+    policy: SchedPolicy,
+    priority: i32,
+    reset_on_fork: bool,
+) -> Result<(), SyscallError> {
+    let (min, max) = policy.priority_range();
+    if priority < min as i32 || priority > max as i32 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let target = target_process(pid)?;
+    if !may_reschedule(&target)? {
+        return Err(SyscallError::EPERM);
+    }
+    if policy.is_realtime() {
+        let self_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+        let (euid, rtprio_limit) = {
+            let proc = self_arc.lock();
+            (proc.euid, proc.rlimits[RLIMIT_RTPRIO].rlim_cur)
+        };
+        if euid != 0 && priority as u64 > rtprio_limit {
+            return Err(SyscallError::EPERM);
+        }
+    }
+
+    let threads = threads_of(&target);
+    crate::arch::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        for thread in &threads {
+            sched.set_policy(thread, policy, priority as u8, reset_on_fork);
+        }
+    });
+    Ok(())
+}
+
+/// `sys_sched_setparam` (SYS_SCHED_SETPARAM = 142)
+/// Set the real-time priority of a process, keeping its policy.
+pub fn sys_sched_setparam(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let param = UserPtr::<SchedParam>::new(frame.arg2()).read()?;
+
+    let (params, reset_on_fork) = current_params(&target_process(pid)?);
+    apply_policy(pid, params.policy, param.sched_priority, reset_on_fork)?;
+    Ok(0)
+}
+
+/// `sys_sched_getparam` (SYS_SCHED_GETPARAM = 143)
+/// Get the real-time priority of a process.
+pub fn sys_sched_getparam(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let param_ptr = UserPtr::<SchedParam>::new(frame.arg2());
+
+    let (params, _) = current_params(&target_process(pid)?);
+    param_ptr.write(SchedParam {
+        sched_priority: params.rt_priority as i32,
+    })?;
+    Ok(0)
+}
+
+/// `sys_sched_setscheduler` (SYS_SCHED_SETSCHEDULER = 144)
+/// Set the scheduling policy and real-time priority of a process.
+///
+/// `SCHED_RESET_ON_FORK` may be or'ed into the policy.
+pub fn sys_sched_setscheduler(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let raw_policy = frame.arg2() as u32;
+    let param = UserPtr::<SchedParam>::new(frame.arg3()).read()?;
+
+    let reset_on_fork = raw_policy & SCHED_RESET_ON_FORK != 0;
+    let policy = SchedPolicy::from_raw(raw_policy & !SCHED_RESET_ON_FORK)
+        .ok_or(SyscallError::EINVAL)?;
+    apply_policy(pid, policy, param.sched_priority, reset_on_fork)?;
+    Ok(0)
+}
+
+/// `sys_sched_getscheduler` (SYS_SCHED_GETSCHEDULER = 145)
+/// Get the scheduling policy of a process, with `SCHED_RESET_ON_FORK` if it is set.
+pub fn sys_sched_getscheduler(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+
+    let (params, reset_on_fork) = current_params(&target_process(pid)?);
+    let flag = if reset_on_fork { SCHED_RESET_ON_FORK } else { 0 };
+    Ok((params.policy.as_raw() | flag) as usize)
+}
+
+/// `sys_sched_get_priority_max` (SYS_SCHED_GET_PRIORITY_MAX = 146)
+/// Get the highest priority valid for a policy.
+pub fn sys_sched_get_priority_max(frame: &mut SyscallFrame) -> SyscallResult {
+    let policy = SchedPolicy::from_raw(frame.arg1() as u32).ok_or(SyscallError::EINVAL)?;
+    Ok(policy.priority_range().1 as usize)
+}
+
+/// `sys_sched_get_priority_min` (SYS_SCHED_GET_PRIORITY_MIN = 147)
+/// Get the lowest priority valid for a policy.
+pub fn sys_sched_get_priority_min(frame: &mut SyscallFrame) -> SyscallResult {
+    let policy = SchedPolicy::from_raw(frame.arg1() as u32).ok_or(SyscallError::EINVAL)?;
+    Ok(policy.priority_range().0 as usize)
+}
+
+/// `sys_sched_rr_get_interval` (SYS_SCHED_RR_GET_INTERVAL = 148)
+/// Get the SCHED_RR quantum of a process; 0 if it is not scheduled round-robin.
+pub fn sys_sched_rr_get_interval(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let tp_ptr = UserPtr::<TimeSpec>::new(frame.arg2());
+
+    let (params, _) = current_params(&target_process(pid)?);
+    let slice_ns = if params.policy == SchedPolicy::RoundRobin {
+        crate::sched::rt::rr_timeslice_ns()
+    } else {
+        0
+    };
+    tp_ptr.write(TimeSpec {
+        tv_sec: (slice_ns / 1_000_000_000) as i64,
+        tv_nsec: (slice_ns % 1_000_000_000) as i64,
+    })?;
+    Ok(0)
+}
+
+pub const PRIO_PROCESS: i32 = 0;
+pub const PRIO_PGRP: i32 = 1;
+pub const PRIO_USER: i32 = 2;
+
+/// Processes selected by the `which`/`who` pair of `getpriority`/`setpriority`; `who`
+/// 0 means the caller's process, process group or user.
+fn priority_targets(which: i32, who: i32) -> Result<Vec<Arc<Spinlock<Process>>>, SyscallError> {
+    let self_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    let (self_pgid, self_uid) = {
+        let proc = self_arc.lock();
+        (proc.pgid, proc.uid)
+    };
+    let targets = match which {
+        PRIO_PROCESS => alloc::vec![target_process(who).map_err(|_| SyscallError::ESRCH)?],
+        PRIO_PGRP => {
+            let pgid = if who == 0 { self_pgid } else { ProcessId(who as u64) };
+            crate::proc::find_processes_by_pgid(pgid)
+        }
+        PRIO_USER => {
+            let uid = if who == 0 { self_uid } else { who as u32 };
+            crate::proc::all_processes()
+                .into_iter()
+                .filter(|p| p.lock().uid == uid)
+                .collect()
+        }
+        _ => return Err(SyscallError::EINVAL),
+    };
+    if targets.is_empty() {
+        return Err(SyscallError::ESRCH);
+    }
+    Ok(targets)
+}
+
+/// `sys_getpriority` (SYS_GETPRIORITY = 140)
+/// Get the highest priority among the selected processes.
+///
+/// As with the raw Linux system call, the result is `20 - nice`, in `[1, 40]`.
+pub fn sys_getpriority(frame: &mut SyscallFrame) -> SyscallResult {
+    let which = frame.arg1() as i32;
+    let who = frame.arg2() as i32;
+
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|proc| current_params(proc).0.nice)
+        .min()
+        .unwrap_or_default();
+    Ok((20 - nice.value() as i64) as usize)
+}
+
+/// `sys_setpriority` (SYS_SETPRIORITY = 141)
+/// Set the nice value of the selected processes, clamped to `[-20, 19]`.
+pub fn sys_setpriority(frame: &mut SyscallFrame) -> SyscallResult {
+    let which = frame.arg1() as i32;
+    let who = frame.arg2() as i32;
+    let nice = Nice::from_raw_clamped((frame.arg3() as i32).clamp(-128, 127) as i8);
+
+    let mut result = Ok(0);
+    for proc in priority_targets(which, who)? {
+        if !may_reschedule(&proc)? {
+            result = Err(SyscallError::EPERM);
+            continue;
+        }
+        if !may_set_nice(nice, current_params(&proc).0.nice)? {
+            result = Err(SyscallError::EACCES);
+            continue;
+        }
+        apply_fair_params(&proc, nice, None);
+    }
+    result
+}
+
+/// `struct sched_attr` of the Linux ABI.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+    pub sched_util_min: u32,
+    pub sched_util_max: u32,
+}
+
+/// Size of the first version of `struct sched_attr`, without utilization clamps.
+const SCHED_ATTR_SIZE_VER0: usize = 48;
+
+/// Largest `struct sched_attr` accepted from user space.
+const SCHED_ATTR_SIZE_MAX: usize = 4096;
+
+pub const SCHED_FLAG_RESET_ON_FORK: u64 = 0x01;
+pub const SCHED_FLAG_KEEP_POLICY: u64 = 0x08;
+pub const SCHED_FLAG_KEEP_PARAMS: u64 = 0x10;
+
+/// Read a `struct sched_attr` of the size given in its first field.
+///
+/// Newer, larger versions are accepted as long as the fields unknown here are zero;
+/// otherwise the size the kernel supports is written back and `E2BIG` returned.
+fn read_sched_attr(addr: u64) -> Result<SchedAttr, SyscallError> {
+    let size_ptr = UserPtr::<u32>::new(addr);
+    let size = match size_ptr.read()? as usize {
+        0 => SCHED_ATTR_SIZE_VER0,
+        size => size,
+    };
+    let too_big = || -> Result<SchedAttr, SyscallError> {
+        size_ptr.write(size_of::<SchedAttr>() as u32)?;
+        Err(SyscallError::E2BIG)
+    };
+    if !(SCHED_ATTR_SIZE_VER0..=SCHED_ATTR_SIZE_MAX).contains(&size) {
+        return too_big();
+    }
+
+    let known = size.min(size_of::<SchedAttr>());
+    if size > known {
+        let rest = UserSlice::new(addr + known as u64, size - known)?.read_to_vec()?;
+        if rest.iter().any(|&b| b != 0) {
+            return too_big();
+        }
+    }
+
+    let mut attr = SchedAttr::default();
+    // SAFETY: `SchedAttr` is plain old data and the view covers its first `known` bytes.
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(&mut attr as *mut SchedAttr as *mut u8, known)
+    };
+    copy_from_user(bytes, addr)?;
+    Ok(attr)
+}
+
+/// `sys_sched_setattr` (SYS_SCHED_SETATTR = 314)
+/// Set the scheduling policy and parameters of a process.
+///
+/// For the fair policies, `sched_nice` sets the nice value and `sched_runtime` the
+/// requested slice, clamped to [`MIN_SLICE_NS`, `MAX_SLICE_NS`]; 0 restores the default.
+/// SCHED_DEADLINE is not supported.
+pub fn sys_sched_setattr(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let attr_ptr = frame.arg2();
+    let flags = frame.arg3();
+
+    if flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let attr = read_sched_attr(attr_ptr)?;
+    let known_flags = SCHED_FLAG_RESET_ON_FORK | SCHED_FLAG_KEEP_POLICY | SCHED_FLAG_KEEP_PARAMS;
+    if attr.sched_flags & !known_flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let target = target_process(pid)?;
+    let (current, _) = current_params(&target);
+    let policy = if attr.sched_flags & SCHED_FLAG_KEEP_POLICY != 0 {
+        current.policy
+    } else {
+        SchedPolicy::from_raw(attr.sched_policy).ok_or(SyscallError::EINVAL)?
+    };
+    let keep_params = attr.sched_flags & SCHED_FLAG_KEEP_PARAMS != 0;
+    let reset_on_fork = attr.sched_flags & SCHED_FLAG_RESET_ON_FORK != 0;
+
+    let priority = if keep_params {
+        current.rt_priority as i32
+    } else {
+        attr.sched_priority.min(i32::MAX as u32) as i32
+    };
+    let (nice, slice_ns) = if keep_params || policy.is_realtime() {
+        (current.nice, current.slice_ns)
+    } else {
+        let nice = Nice::from_raw_clamped(attr.sched_nice.clamp(-128, 127) as i8);
+        let slice_ns = match attr.sched_runtime {
+            0 => DEFAULT_THREAD_SLICE_NS,
+            runtime => runtime.clamp(MIN_SLICE_NS, MAX_SLICE_NS),
+        };
+        (nice, slice_ns)
+    };
+    if !may_set_nice(nice, current.nice)? {
+        return Err(SyscallError::EPERM);
+    }
+
+    apply_policy(pid, policy, priority, reset_on_fork)?;
+    apply_fair_params(&target, nice, Some(slice_ns));
+    Ok(0)
+}
+
+/// `sys_sched_getattr` (SYS_SCHED_GETATTR = 315)
+/// Get the scheduling policy and parameters of a process.
+///
+/// Writes at most `size` bytes and sets the `size` field to the number written.
+pub fn sys_sched_getattr(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let attr_ptr = frame.arg2();
+    let size = frame.arg3() as usize;
+    let flags = frame.arg4();
+
+    if flags != 0 || !(SCHED_ATTR_SIZE_VER0..=SCHED_ATTR_SIZE_MAX).contains(&size) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let (params, reset_on_fork) = current_params(&target_process(pid)?);
+    let len = size.min(size_of::<SchedAttr>());
+    let attr = SchedAttr {
+        size: len as u32,
+        sched_policy: params.policy.as_raw(),
+        sched_flags: if reset_on_fork { SCHED_FLAG_RESET_ON_FORK } else { 0 },
+        sched_nice: params.nice.value() as i32,
+        sched_priority: params.rt_priority as u32,
+        sched_runtime: if params.policy.is_realtime() { 0 } else { params.slice_ns },
+        sched_util_max: 1024,
+        ..SchedAttr::default()
+    };
+    // SAFETY: Viewing the first `len` bytes of a plain old data struct.
+    let bytes = unsafe { core::slice::from_raw_parts(&attr as *const SchedAttr as *const u8, len) };
+    copy_to_user(attr_ptr, bytes)?;
+    Ok(0)
+}
+
+/// `sys_sched_setaffinity` (SYS_SCHED_SETAFFINITY = 203)
+/// Set the CPUs a process may run on.
+///
+/// CPUs beyond the supported ones are ignored; a mask without any online CPU is
+/// rejected.
+pub fn sys_sched_setaffinity(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let len = frame.arg2() as usize;
+    let mask_ptr = frame.arg3();
+
+    let mut bytes = [0u8; CPU_SET_BYTES];
+    let copied = len.min(CPU_SET_BYTES);
+    copy_from_user(&mut bytes[..copied], mask_ptr)?;
+    let mask = (u64::from_le_bytes(bytes) & CPU_MASK_ALL as u64) as u32;
+
+    let target = target_process(pid)?;
+    if !may_reschedule(&target)? {
+        return Err(SyscallError::EPERM);
+    }
+    let threads = threads_of(&target);
+
+    let cpu_id = crate::arch::cpu_id();
+    let must_move = crate::arch::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        if mask & sched.online_mask() == 0 {
+            return Err(SyscallError::EINVAL);
+        }
+        for thread in &threads {
+            sched.set_affinity(thread, mask);
+        }
+        let current = sched.current_threads[cpu_id as usize].clone();
+        Ok(mask & (1 << cpu_id) == 0
+            && current.is_some_and(|c| threads.iter().any(|t| Arc::ptr_eq(t, &c))))
+    })?;
+
+    // The caller is running on a CPU it just gave up; move it now.
+    if must_move {
+        crate::sched::preempt();
+    }
+    Ok(0)
+}
+
+/// `sys_sched_getaffinity` (SYS_SCHED_GETAFFINITY = 204)
+/// Get the CPUs a process may run on.
+///
+/// Returns the number of bytes written to the mask buffer.
+pub fn sys_sched_getaffinity(frame: &mut SyscallFrame) -> SyscallResult {
+    let pid = frame.arg1() as i32;
+    let len = frame.arg2() as usize;
+    let mask_ptr = frame.arg3();
+
+    if len < CPU_SET_BYTES || !len.is_multiple_of(CPU_SET_BYTES) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let target = target_process(pid)?;
+    let mask = threads_of(&target)
+        .first()
+        .map_or(CPU_MASK_ALL, |t| t.lock().cpus_allowed);
+    let online = crate::arch::without_interrupts(|| SCHEDULER.lock().online_mask());
+
+    copy_to_user(mask_ptr, &u64::from(mask & online).to_le_bytes())?;
+    Ok(CPU_SET_BYTES)
+}
+
+/// `sys_getcpu` (SYS_GETCPU = 309)
+/// Get the CPU and NUMA node the caller is running on.
+pub fn sys_getcpu(frame: &mut SyscallFrame) -> SyscallResult {
+    let cpu_ptr = UserPtr::<u32>::new(frame.arg1());
+    let node_ptr = UserPtr::<u32>::new(frame.arg2());
+
+    cpu_ptr.write_if_nonnull(crate::arch::cpu_id())?;
+    node_ptr.write_if_nonnull(0)?;
+    Ok(0)
+}
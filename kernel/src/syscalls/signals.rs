@@ -1,4 +1,5 @@
-use super::{SyscallError, SyscallResult, is_user_ptr_valid};
+use super::uaccess::UserPtr;
+use super::{SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
 use crate::ipc::signal::{SigAction, SigSet, is_uncatchable};
 
@@ -45,8 +46,8 @@ pub fn sys_kill(frame: &mut SyscallFrame) -> SyscallResult {
 /// Examine and change a signal action.
 pub fn sys_rt_sigaction(frame: &mut SyscallFrame) -> SyscallResult {
     let sig = frame.arg1() as u8;
-    let act = frame.arg2() as *const SigAction;
-    let oact = frame.arg3() as *mut SigAction;
+    let act = UserPtr::<SigAction>::new(frame.arg2());
+    let oact = UserPtr::<SigAction>::new(frame.arg3());
 
     if sig == 0 || sig > 64 {
         return Err(SyscallError::EINVAL);
@@ -55,33 +56,15 @@ pub fn sys_rt_sigaction(frame: &mut SyscallFrame) -> SyscallResult {
         return Err(SyscallError::EINVAL);
     }
 
-    if !act.is_null() && !is_user_ptr_valid(act as u64, core::mem::size_of::<SigAction>()) {
-        return Err(SyscallError::EFAULT);
-    }
-    if !oact.is_null() && !is_user_ptr_valid(oact as u64, core::mem::size_of::<SigAction>()) {
-        return Err(SyscallError::EFAULT);
-    }
-
-    let new_action = if !act.is_null() {
-        // SAFETY: User pointer validated within Ring 3 address space bounds.
-        Some(unsafe { core::ptr::read_volatile(act) })
-    } else {
-        None
-    };
+    let new_action = act.read_if_nonnull()?;
 
     let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
-    let mut proc = proc_arc.lock();
-    let old_action = proc
+    let old_action = proc_arc
+        .lock()
         .sigaction(sig, new_action)
         .map_err(|_| SyscallError::EINVAL)?;
 
-    if !oact.is_null() {
-        // SAFETY: User pointer validated within Ring 3 address space bounds.
-        unsafe {
-            core::ptr::write_volatile(oact, old_action);
-        }
-    }
-
+    oact.write_if_nonnull(old_action)?;
     Ok(0)
 }
 
@@ -89,36 +72,18 @@ pub fn sys_rt_sigaction(frame: &mut SyscallFrame) -> SyscallResult {
 /// Examine and change blocked signals.
 pub fn sys_rt_sigprocmask(frame: &mut SyscallFrame) -> SyscallResult {
     let how = frame.arg1() as i32;
-    let set_ptr = frame.arg2() as *const SigSet;
-    let oset_ptr = frame.arg3() as *mut SigSet;
+    let set_ptr = UserPtr::<SigSet>::new(frame.arg2());
+    let oset_ptr = UserPtr::<SigSet>::new(frame.arg3());
 
-    if !set_ptr.is_null() && !is_user_ptr_valid(set_ptr as u64, core::mem::size_of::<SigSet>()) {
-        return Err(SyscallError::EFAULT);
-    }
-    if !oset_ptr.is_null() && !is_user_ptr_valid(oset_ptr as u64, core::mem::size_of::<SigSet>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let set = set_ptr.read_if_nonnull()?.unwrap_or(0);
 
     let thread_arc = crate::proc::current_thread().ok_or(SyscallError::ESRCH)?;
-    let mut thread = thread_arc.lock();
-
-    let set = if !set_ptr.is_null() {
-        // SAFETY: User pointer validated within Ring 3 address space bounds.
-        unsafe { core::ptr::read_unaligned(set_ptr) }
-    } else {
-        0
-    };
-
-    let old_mask = thread
+    let old_mask = thread_arc
+        .lock()
         .update_sigmask(how, set)
         .map_err(|_| SyscallError::EINVAL)?;
 
-    if !oset_ptr.is_null() {
-        // SAFETY: User pointer validated within Ring 3 address space bounds.
-        unsafe {
-            core::ptr::write_volatile(oset_ptr, old_mask);
-        }
-    }
+    oset_ptr.write_if_nonnull(old_mask)?;
     Ok(0)
 }
 
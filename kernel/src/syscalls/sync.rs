@@ -3,7 +3,8 @@
 //! Provides the POSIX `sys_futex` system call (Syscall #202 on x86_64) for userspace
 //! fast synchronization primitives (mutexes, condition variables, semaphores, barriers).
 
-use super::{is_user_ptr_valid, SyscallError, SyscallResult};
+use super::uaccess::UserPtr;
+use super::{SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
 use crate::mm::PageTable;
 use crate::proc::thread::ThreadState;
@@ -25,12 +26,12 @@ pub struct TimeSpec {
 
 /// Helper to validate a user futex address (must be valid Ring 3 pointer and 4-byte aligned).
 #[inline]
-fn validate_futex_ptr(ptr: *const u32) -> Result<(), SyscallError> {
-    let addr = ptr as u64;
+fn validate_futex_ptr(ptr: UserPtr<u32>) -> Result<(), SyscallError> {
+    let addr = ptr.addr();
     if addr == 0 || (addr % 4) != 0 {
         return Err(SyscallError::EINVAL);
     }
-    if !is_user_ptr_valid(addr, core::mem::size_of::<u32>()) {
+    if addr >= super::USER_SPACE_MAX_ADDR {
         return Err(SyscallError::EFAULT);
     }
     Ok(())
@@ -38,11 +39,11 @@ fn validate_futex_ptr(ptr: *const u32) -> Result<(), SyscallError> {
 
 /// Helper to resolve a `FutexKey` from a user-space virtual address.
 fn resolve_futex_key(
-    uaddr: *const u32,
+    uaddr: UserPtr<u32>,
     is_private: bool,
     proc: &crate::proc::Process,
 ) -> FutexKey {
-    let vaddr = uaddr as u64;
+    let vaddr = uaddr.addr();
     if is_private {
         FutexKey::Private {
             pid: proc.pid.as_u64(),
@@ -65,18 +66,10 @@ fn resolve_futex_key(
 }
 
 /// Helper to safely parse a user-space `timespec` structure.
-fn parse_user_timespec(timeout_ptr: *const TimeSpec) -> Result<Option<TimeSpec>, SyscallError> {
-    if timeout_ptr.is_null() {
+fn parse_user_timespec(timeout_ptr: UserPtr<TimeSpec>) -> Result<Option<TimeSpec>, SyscallError> {
+    let Some(ts) = timeout_ptr.read_if_nonnull()? else {
         return Ok(None);
-    }
-
-    let addr = timeout_ptr as u64;
-    if !is_user_ptr_valid(addr, core::mem::size_of::<TimeSpec>()) {
-        return Err(SyscallError::EFAULT);
-    }
-
-    // SAFETY: Validated user memory within Ring 3 boundary.
-    let ts = unsafe { core::ptr::read_volatile(timeout_ptr) };
+    };
     if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= 1_000_000_000 {
         return Err(SyscallError::EINVAL);
     }
@@ -96,11 +89,11 @@ fn parse_user_timespec(timeout_ptr: *const TimeSpec) -> Result<Option<TimeSpec>,
 /// - `arg5` (uaddr2): Target pointer for requeue operations.
 /// - `arg6` (val3): Expected value (for cmp_requeue) or bitset mask (for bitset wait/wake).
 pub fn sys_futex(frame: &mut SyscallFrame) -> SyscallResult {
-    let uaddr = frame.arg1() as *const u32;
+    let uaddr = UserPtr::<u32>::new(frame.arg1());
     let futex_op = frame.arg2() as u32;
     let val = frame.arg3() as u32;
     let timeout_or_val2 = frame.arg4();
-    let uaddr2 = frame.arg5() as *const u32;
+    let uaddr2 = UserPtr::<u32>::new(frame.arg5());
     let val3 = frame.arg6() as u32;
 
     validate_futex_ptr(uaddr)?;
@@ -117,47 +110,46 @@ pub fn sys_futex(frame: &mut SyscallFrame) -> SyscallResult {
     match cmd {
         FUTEX_WAIT => {
             let thread_arc = crate::proc::current_thread().ok_or(SyscallError::ESRCH)?;
-            let timeout_ptr = timeout_or_val2 as *const TimeSpec;
+            let timeout_ptr = UserPtr::<TimeSpec>::new(timeout_or_val2);
             let timeout = parse_user_timespec(timeout_ptr)?;
 
-            let current_ns = crate::arch::timer::hpet::elapsed_ns();
+            // FUTEX_WAIT timeouts are relative, whichever clock is asked for
+            let current_ns = crate::time::now_ns();
             let deadline_ns = timeout.map(|ts| {
                 let duration_ns = (ts.tv_sec as u64)
                     .saturating_mul(1_000_000_000)
                     .saturating_add(ts.tv_nsec as u64);
-                if is_realtime {
-                    duration_ns
-                } else {
-                    current_ns.saturating_add(duration_ns)
-                }
+                current_ns.saturating_add(duration_ns)
             });
 
             // Enqueue thread in futex wait queue under lock
             {
                 let mut mgr = FUTEX_MANAGER.lock();
-                // SAFETY: `uaddr` is verified and valid for 4-byte read.
-                unsafe {
-                    mgr.wait_prepare(
-                        key,
-                        thread_arc.clone(),
-                        uaddr,
-                        val,
-                        FUTEX_BITSET_MATCH_ANY,
-                        deadline_ns,
-                    )?;
-                }
+                mgr.wait_prepare(
+                    key,
+                    thread_arc.clone(),
+                    uaddr,
+                    val,
+                    FUTEX_BITSET_MATCH_ANY,
+                    deadline_ns,
+                )?;
             }
 
-            // Put current thread to sleep and switch context
+            // Put current thread to sleep and switch context, to be woken at the deadline
             {
                 let mut t = thread_arc.lock();
                 t.state = ThreadState::Sleeping;
             }
+            let timer =
+                deadline_ns.map(|deadline| crate::time::timer::add(deadline, thread_arc.clone()));
             crate::sched::schedule(false);
+            if let Some(timer) = timer {
+                crate::time::timer::cancel(timer);
+            }
 
             // Once unblocked, check if woken by timeout
             let tid = thread_arc.lock().tid;
-            let now_ns = crate::arch::timer::hpet::elapsed_ns();
+            let now_ns = crate::time::now_ns();
             if let Some(deadline) = deadline_ns {
                 if now_ns >= deadline {
                     let mut mgr = FUTEX_MANAGER.lock();
@@ -197,8 +189,7 @@ pub fn sys_futex(frame: &mut SyscallFrame) -> SyscallResult {
             let key2 = resolve_futex_key(uaddr2, is_private, &proc);
             drop(proc);
 
-            // SAFETY: `uaddr` validated with `validate_futex_ptr`.
-            let current_val = unsafe { core::ptr::read_volatile(uaddr) };
+            let current_val = uaddr.read()?;
             if current_val != val3 {
                 return Err(SyscallError::EAGAIN);
             }
@@ -219,35 +210,43 @@ pub fn sys_futex(frame: &mut SyscallFrame) -> SyscallResult {
             }
 
             let thread_arc = crate::proc::current_thread().ok_or(SyscallError::ESRCH)?;
-            let timeout_ptr = timeout_or_val2 as *const TimeSpec;
+            let timeout_ptr = UserPtr::<TimeSpec>::new(timeout_or_val2);
             let timeout = parse_user_timespec(timeout_ptr)?;
 
-            // In Linux, FUTEX_WAIT_BITSET timeouts are absolute timestamps
+            // In Linux, FUTEX_WAIT_BITSET timeouts are absolute timestamps, on the realtime
+            // clock if asked for; timers run on the monotonic clock
             let deadline_ns = timeout.map(|ts| {
-                (ts.tv_sec as u64)
+                let abs_ns = (ts.tv_sec as u64)
                     .saturating_mul(1_000_000_000)
-                    .saturating_add(ts.tv_nsec as u64)
+                    .saturating_add(ts.tv_nsec as u64);
+                if is_realtime {
+                    crate::time::timekeeping::realtime_to_monotonic(abs_ns)
+                } else {
+                    abs_ns
+                }
             });
 
             // Enqueue thread in futex wait queue under lock
             {
                 let mut mgr = FUTEX_MANAGER.lock();
-                // SAFETY: `uaddr` is verified and valid for 4-byte read.
-                unsafe {
-                    mgr.wait_prepare(key, thread_arc.clone(), uaddr, val, bitset, deadline_ns)?;
-                }
+                mgr.wait_prepare(key, thread_arc.clone(), uaddr, val, bitset, deadline_ns)?;
             }
 
-            // Put current thread to sleep and switch context
+            // Put current thread to sleep and switch context, to be woken at the deadline
             {
                 let mut t = thread_arc.lock();
                 t.state = ThreadState::Sleeping;
             }
+            let timer =
+                deadline_ns.map(|deadline| crate::time::timer::add(deadline, thread_arc.clone()));
             crate::sched::schedule(false);
+            if let Some(timer) = timer {
+                crate::time::timer::cancel(timer);
+            }
 
             // Once unblocked, check if woken by timeout
             let tid = thread_arc.lock().tid;
-            let now_ns = crate::arch::timer::hpet::elapsed_ns();
+            let now_ns = crate::time::now_ns();
             if let Some(deadline) = deadline_ns {
                 if now_ns >= deadline {
                     let mut mgr = FUTEX_MANAGER.lock();
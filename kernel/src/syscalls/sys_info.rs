@@ -1,4 +1,5 @@
-use super::{SyscallError, SyscallResult, is_user_ptr_valid};
+use super::SyscallResult;
+use super::uaccess::UserPtr;
 use crate::arch::syscall::syscall::SyscallFrame;
 
 /// x86_64 Linux ABI compatible utsname structure layout.
@@ -32,26 +33,65 @@ fn set_bytes(dst: &mut [u8; 65], src: &[u8]) {
     dst[len] = 0;
 }
 
+/// Host name reported by `uname` and in core file names.
+pub const HOSTNAME: &str = "petra";
+
 /// `sys_uname` (SYS_UNAME = 63)
 /// Get name and information about current kernel.
 pub fn sys_uname(frame: &mut SyscallFrame) -> SyscallResult {
-    let buf = frame.arg1() as *mut UtsName;
-    if !is_user_ptr_valid(buf as u64, core::mem::size_of::<UtsName>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let buf = UserPtr::<UtsName>::new(frame.arg1());
 
     let mut uts = UtsName::default();
     set_bytes(&mut uts.sysname, b"PetraOS");
-    set_bytes(&mut uts.nodename, b"petra");
+    set_bytes(&mut uts.nodename, HOSTNAME.as_bytes());
     set_bytes(&mut uts.release, b"0.1.0");
     set_bytes(&mut uts.version, b"PetraOS Kernel v0.1.0 no_std");
     set_bytes(&mut uts.machine, b"x86_64");
     set_bytes(&mut uts.domainname, b"localdomain");
 
-    // SAFETY: Writing UtsName struct to user pointer after bounds validation.
-    unsafe {
-        core::ptr::write_volatile(buf, uts);
-    }
+    buf.write(uts)?;
+    Ok(0)
+}
+
+/// x86_64 Linux ABI compatible `struct sysinfo`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SysInfo {
+    /// Seconds since boot.
+    pub uptime: i64,
+    /// 1, 5 and 15 minute load averages, scaled by 65536.
+    pub loads: [u64; 3],
+    pub totalram: u64,
+    pub freeram: u64,
+    pub sharedram: u64,
+    pub bufferram: u64,
+    pub totalswap: u64,
+    pub freeswap: u64,
+    pub procs: u16,
+    pub pad: u16,
+    pub totalhigh: u64,
+    pub freehigh: u64,
+    /// Size in bytes of the unit the memory fields count.
+    pub mem_unit: u32,
+    pub _f: [u8; 4],
+}
+
+/// `sys_sysinfo` (SYS_SYSINFO = 99)
+/// Get overall system statistics: uptime, load averages, memory and thread counts.
+pub fn sys_sysinfo(frame: &mut SyscallFrame) -> SyscallResult {
+    use crate::sched::loadavg::{self, FSHIFT};
+    use crate::time::timekeeping::NSEC_PER_SEC;
 
+    let buf = UserPtr::<SysInfo>::new(frame.arg1());
+    let info = SysInfo {
+        uptime: crate::time::timekeeping::boottime_ns().div_ceil(NSEC_PER_SEC) as i64,
+        loads: loadavg::averages().map(|load| load << (16 - FSHIFT)),
+        totalram: crate::mm::PMM.total_pages() as u64 * 4096,
+        freeram: crate::mm::PMM.free_pages_count() as u64 * 4096,
+        procs: crate::proc::nr_threads().min(u16::MAX as usize) as u16,
+        mem_unit: 1,
+        ..Default::default()
+    };
+    buf.write(info)?;
     Ok(0)
 }
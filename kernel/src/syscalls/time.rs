@@ -1,5 +1,7 @@
-use super::{SyscallError, SyscallResult, is_user_ptr_valid};
+use super::uaccess::UserPtr;
+use super::{SyscallError, SyscallResult};
 use crate::arch::syscall::syscall::SyscallFrame;
+use crate::time::timekeeping::{self, NSEC_PER_SEC};
 
 /// POSIX timeval structure for `sys_gettimeofday`
 #[repr(C)]
@@ -30,55 +32,81 @@ pub struct Tms {
 /// `sys_gettimeofday` (SYS_GETTIMEOFDAY = 96)
 /// Returns system wall-clock time in seconds and microseconds since Unix epoch.
 pub fn sys_gettimeofday(frame: &mut SyscallFrame) -> SyscallResult {
-    let tv_ptr = frame.arg1() as *mut TimeVal;
-    let tz_ptr = frame.arg2() as *mut TimeZone;
+    let tv_ptr = UserPtr::<TimeVal>::new(frame.arg1());
+    let tz_ptr = UserPtr::<TimeZone>::new(frame.arg2());
 
     if !tv_ptr.is_null() {
-        if !is_user_ptr_valid(tv_ptr as u64, core::mem::size_of::<TimeVal>()) {
-            return Err(SyscallError::EFAULT);
-        }
-        let (sec, usec) = crate::drivers::time::cmos_rtc::get_wall_time();
+        let now_ns = timekeeping::realtime_ns();
         let tv = TimeVal {
-            tv_sec: sec as i64,
-            tv_usec: usec as i64,
+            tv_sec: (now_ns / NSEC_PER_SEC) as i64,
+            tv_usec: (now_ns % NSEC_PER_SEC / 1000) as i64,
         };
-        // SAFETY: tv_ptr verified with is_user_ptr_valid above.
-        unsafe {
-            core::ptr::write_volatile(tv_ptr, tv);
-        }
+        tv_ptr.write(tv)?;
     }
 
+    tz_ptr.write_if_nonnull(TimeZone {
+        tz_minuteswest: 0,
+        tz_dsttime: 0,
+    })?;
+
+    Ok(0)
+}
+
+/// `sys_settimeofday` (SYS_SETTIMEOFDAY = 164)
+/// Sets the system wall-clock time. The timezone is accepted but ignored, as the kernel
+/// keeps time in UTC only.
+pub fn sys_settimeofday(frame: &mut SyscallFrame) -> SyscallResult {
+    let tv_ptr = UserPtr::<TimeVal>::new(frame.arg1());
+    let tz_ptr = UserPtr::<TimeZone>::new(frame.arg2());
+
+    let tv = if tv_ptr.is_null() { None } else { Some(tv_ptr.read()?) };
     if !tz_ptr.is_null() {
-        if !is_user_ptr_valid(tz_ptr as u64, core::mem::size_of::<TimeZone>()) {
-            return Err(SyscallError::EFAULT);
-        }
-        let tz = TimeZone {
-            tz_minuteswest: 0,
-            tz_dsttime: 0,
-        };
-        // SAFETY: tz_ptr verified with is_user_ptr_valid above.
-        unsafe {
-            core::ptr::write_volatile(tz_ptr, tz);
-        }
+        tz_ptr.read()?;
     }
+    check_privileged()?;
 
+    if let Some(tv) = tv {
+        if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+            return Err(SyscallError::EINVAL);
+        }
+        timekeeping::settime(
+            (tv.tv_sec as u64)
+                .saturating_mul(NSEC_PER_SEC)
+                .saturating_add(tv.tv_usec as u64 * 1000),
+        );
+    }
     Ok(0)
 }
 
+/// Fail with `EPERM` unless the calling process may set the system clock.
+fn check_privileged() -> Result<(), SyscallError> {
+    let proc_arc = crate::proc::current_process().ok_or(SyscallError::ESRCH)?;
+    if proc_arc.lock().euid != 0 {
+        return Err(SyscallError::EPERM);
+    }
+    Ok(())
+}
+
+/// `sys_time` (SYS_TIME = 201)
+/// Returns wall-clock seconds since the Unix epoch, also storing them at `tloc` if non-null.
+pub fn sys_time(frame: &mut SyscallFrame) -> SyscallResult {
+    let tloc = UserPtr::<i64>::new(frame.arg1());
+
+    let sec = timekeeping::realtime_ns() / NSEC_PER_SEC;
+    tloc.write_if_nonnull(sec as i64)?;
+    Ok(sec as usize)
+}
+
 /// `sys_times` (SYS_TIMES = 100)
 /// Returns elapsed system clock ticks since system boot, and fills process CPU timing.
 pub fn sys_times(frame: &mut SyscallFrame) -> SyscallResult {
-    let buf_ptr = frame.arg1() as *mut Tms;
+    let buf_ptr = UserPtr::<Tms>::new(frame.arg1());
 
     // Standard POSIX clock ticks per second (CLK_TCK = 100)
-    let elapsed_ns = crate::arch::timer::hpet::elapsed_ns();
+    let elapsed_ns = crate::time::now_ns();
     let total_ticks = (elapsed_ns / 10_000_000) as i64; // 10ms per tick (100Hz)
 
     if !buf_ptr.is_null() {
-        if !is_user_ptr_valid(buf_ptr as u64, core::mem::size_of::<Tms>()) {
-            return Err(SyscallError::EFAULT);
-        }
-
         // Retrieve current process CPU times if available
         let mut utime = total_ticks;
         let mut stime = 0i64;
@@ -103,10 +131,7 @@ pub fn sys_times(frame: &mut SyscallFrame) -> SyscallResult {
             tms_cstime: 0,
         };
 
-        // SAFETY: buf_ptr verified with is_user_ptr_valid above.
-        unsafe {
-            core::ptr::write_volatile(buf_ptr, tms);
-        }
+        buf_ptr.write(tms)?;
     }
 
     Ok(total_ticks as usize)
@@ -129,61 +154,138 @@ pub struct TimeSpec {
     pub tv_nsec: i64,
 }
 
+impl TimeSpec {
+    fn from_ns(ns: u64) -> Self {
+        TimeSpec {
+            tv_sec: (ns / NSEC_PER_SEC) as i64,
+            tv_nsec: (ns % NSEC_PER_SEC) as i64,
+        }
+    }
+}
+
+/// Read the clock `clock_id`, in nanoseconds.
+fn clock_ns(clock_id: i32) -> Result<u64, SyscallError> {
+    Ok(match clock_id {
+        CLOCK_REALTIME => timekeeping::realtime_ns(),
+        CLOCK_REALTIME_COARSE => timekeeping::realtime_coarse_ns(),
+        CLOCK_MONOTONIC => timekeeping::monotonic_ns(),
+        CLOCK_MONOTONIC_COARSE => timekeeping::monotonic_coarse_ns(),
+        CLOCK_MONOTONIC_RAW => timekeeping::raw_ns(),
+        CLOCK_BOOTTIME => timekeeping::boottime_ns(),
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => crate::time::now_ns(),
+        _ => return Err(SyscallError::EINVAL),
+    })
+}
+
 /// `sys_clock_gettime` (SYS_CLOCK_GETTIME = 228)
 /// Retrieve time of the specified clock.
 pub fn sys_clock_gettime(frame: &mut SyscallFrame) -> SyscallResult {
     let clock_id = frame.arg1() as i32;
-    let tp_ptr = frame.arg2() as *mut TimeSpec;
+    let tp_ptr = UserPtr::<TimeSpec>::new(frame.arg2());
+
+    let ts = TimeSpec::from_ns(clock_ns(clock_id)?);
+    tp_ptr.write(ts)?;
+    Ok(0)
+}
+
+/// `sys_clock_settime` (SYS_CLOCK_SETTIME = 227)
+/// Set the time of the specified clock. Only `CLOCK_REALTIME` can be set.
+pub fn sys_clock_settime(frame: &mut SyscallFrame) -> SyscallResult {
+    let clock_id = frame.arg1() as i32;
+    let tp_ptr = UserPtr::<TimeSpec>::new(frame.arg2());
 
-    if !is_user_ptr_valid(tp_ptr as u64, core::mem::size_of::<TimeSpec>()) {
-        return Err(SyscallError::EFAULT);
+    let ts = tp_ptr.read()?;
+    if clock_id != CLOCK_REALTIME {
+        return Err(SyscallError::EINVAL);
+    }
+    check_privileged()?;
+    if ts.tv_sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&ts.tv_nsec) {
+        return Err(SyscallError::EINVAL);
     }
 
-    let ts = match clock_id {
-        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => {
-            let (sec, usec) = crate::drivers::time::cmos_rtc::get_wall_time();
-            TimeSpec {
-                tv_sec: sec as i64,
-                tv_nsec: (usec as i64) * 1000,
-            }
-        }
-        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
-            let elapsed_ns = crate::arch::timer::hpet::elapsed_ns();
-            TimeSpec {
-                tv_sec: (elapsed_ns / 1_000_000_000) as i64,
-                tv_nsec: (elapsed_ns % 1_000_000_000) as i64,
-            }
-        }
-        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
-            let elapsed_ns = crate::arch::timer::hpet::elapsed_ns();
-            TimeSpec {
-                tv_sec: (elapsed_ns / 1_000_000_000) as i64,
-                tv_nsec: (elapsed_ns % 1_000_000_000) as i64,
-            }
-        }
+    timekeeping::settime(
+        (ts.tv_sec as u64)
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(ts.tv_nsec as u64),
+    );
+    Ok(0)
+}
+
+/// `sys_clock_getres` (SYS_CLOCK_GETRES = 229)
+/// Retrieve the resolution of the specified clock.
+pub fn sys_clock_getres(frame: &mut SyscallFrame) -> SyscallResult {
+    let clock_id = frame.arg1() as i32;
+    let res_ptr = UserPtr::<TimeSpec>::new(frame.arg2());
+
+    let res_ns = match clock_id {
+        CLOCK_REALTIME_COARSE | CLOCK_MONOTONIC_COARSE => crate::time::tick::TICK_NS,
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_BOOTTIME
+        | CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => 1,
         _ => return Err(SyscallError::EINVAL),
     };
+    res_ptr.write_if_nonnull(TimeSpec::from_ns(res_ns))?;
+    Ok(0)
+}
 
-    // SAFETY: Validated user memory pointer bounds.
-    unsafe {
-        core::ptr::write_volatile(tp_ptr, ts);
-    }
+/// POSIX-extension timex structure for `sys_adjtimex`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timex {
+    pub modes: u32,
+    pub offset: i64,
+    pub freq: i64,
+    pub maxerror: i64,
+    pub esterror: i64,
+    pub status: i32,
+    pub constant: i64,
+    pub precision: i64,
+    pub tolerance: i64,
+    pub time: TimeVal,
+    pub tick: i64,
+    pub ppsfreq: i64,
+    pub jitter: i64,
+    pub shift: i32,
+    pub stabil: i64,
+    pub jitcnt: i64,
+    pub calcnt: i64,
+    pub errcnt: i64,
+    pub stbcnt: i64,
+    pub tai: i32,
+    _reserved: [i32; 11],
+}
 
-    Ok(0)
+/// Read and adjust the clock discipline through the user `Timex` at `buf`.
+fn do_adjtimex(buf: u64) -> SyscallResult {
+    let tx_ptr = UserPtr::<Timex>::new(buf);
+    let mut tx = tx_ptr.read()?;
+    let privileged = check_privileged().is_ok();
+    let state = timekeeping::adjtimex(&mut tx, privileged)?;
+    tx_ptr.write(tx)?;
+    Ok(state as usize)
+}
+
+/// `sys_adjtimex` (SYS_ADJTIMEX = 159)
+/// Read or tune the system clock: slew offsets, correct its frequency or step it.
+pub fn sys_adjtimex(frame: &mut SyscallFrame) -> SyscallResult {
+    do_adjtimex(frame.arg1())
+}
+
+/// `sys_clock_adjtime` (SYS_CLOCK_ADJTIME = 305)
+/// `adjtimex` for the specified clock. Only `CLOCK_REALTIME` can be adjusted.
+pub fn sys_clock_adjtime(frame: &mut SyscallFrame) -> SyscallResult {
+    if frame.arg1() as i32 != CLOCK_REALTIME {
+        return Err(SyscallError::EINVAL);
+    }
+    do_adjtimex(frame.arg2())
 }
 
 /// `sys_nanosleep` (SYS_NANOSLEEP = 35)
 /// High-resolution sleep.
 pub fn sys_nanosleep(frame: &mut SyscallFrame) -> SyscallResult {
-    let req_ptr = frame.arg1() as *const TimeSpec;
-    let rem_ptr = frame.arg2() as *mut TimeSpec;
-
-    if !is_user_ptr_valid(req_ptr as u64, core::mem::size_of::<TimeSpec>()) {
-        return Err(SyscallError::EFAULT);
-    }
+    let req_ptr = UserPtr::<TimeSpec>::new(frame.arg1());
+    let rem_ptr = UserPtr::<TimeSpec>::new(frame.arg2());
 
-    // SAFETY: Validated user memory pointer bounds.
-    let req = unsafe { core::ptr::read_unaligned(req_ptr) };
+    let req = req_ptr.read()?;
     if req.tv_sec < 0 || req.tv_nsec < 0 || req.tv_nsec >= 1_000_000_000 {
         return Err(SyscallError::EINVAL);
     }
@@ -192,17 +294,9 @@ pub fn sys_nanosleep(frame: &mut SyscallFrame) -> SyscallResult {
         .saturating_mul(1_000_000_000)
         .saturating_add(req.tv_nsec as u64);
 
-    let start_ns = crate::arch::timer::hpet::elapsed_ns();
-    while crate::arch::timer::hpet::elapsed_ns().saturating_sub(start_ns) < target_ns {
-        crate::proc::thread::Thread::yield_cpu();
-    }
+    crate::time::timer::sleep_until(crate::time::now_ns().saturating_add(target_ns));
 
-    if !rem_ptr.is_null() && is_user_ptr_valid(rem_ptr as u64, core::mem::size_of::<TimeSpec>()) {
-        // SAFETY: Validated user memory pointer bounds.
-        unsafe {
-            core::ptr::write_volatile(rem_ptr, TimeSpec::default());
-        }
-    }
+    rem_ptr.write_if_nonnull(TimeSpec::default())?;
 
     Ok(0)
 }
@@ -0,0 +1,229 @@
+//! Checked access to user-space memory.
+//!
+//! All kernel reads and writes of user memory go through these helpers. Ranges are
+//! checked against `USER_SPACE_MAX_ADDR` up front, and the copies themselves run in the
+//! architecture's fault-tolerant routines, so a pointer to unmapped memory yields
+//! `EFAULT` rather than a kernel page fault.
+
+use super::{SyscallError, USER_SPACE_MAX_ADDR};
+use crate::fs::vfs::types::VfsError;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{MaybeUninit, size_of};
+
+/// A user pointer was out of range or referred to unmapped memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault;
+
+impl From<Fault> for SyscallError {
+    fn from(_: Fault) -> Self {
+        SyscallError::EFAULT
+    }
+}
+
+impl From<Fault> for VfsError {
+    fn from(_: Fault) -> Self {
+        VfsError::BadAddress
+    }
+}
+
+/// Returns true if `[addr, addr + len)` lies entirely in user space.
+fn range_ok(addr: u64, len: usize) -> bool {
+    addr.checked_add(len as u64).is_some_and(|end| end <= USER_SPACE_MAX_ADDR)
+}
+
+/// Copy `dst.len()` bytes from the user address `src` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Fault> {
+    if dst.is_empty() {
+        return Ok(());
+    }
+    if src == 0 || !range_ok(src, dst.len()) {
+        return Err(Fault);
+    }
+    // SAFETY: `dst` is a valid kernel buffer and the user range was checked above.
+    let left =
+        unsafe { crate::arch::uaccess::copy_user(dst.as_mut_ptr(), src as *const u8, dst.len()) };
+    if left == 0 { Ok(()) } else { Err(Fault) }
+}
+
+/// Copy `src` to the user address `dst`.
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Fault> {
+    if src.is_empty() {
+        return Ok(());
+    }
+    if dst == 0 || !range_ok(dst, src.len()) {
+        return Err(Fault);
+    }
+    // SAFETY: `src` is a valid kernel buffer and the user range was checked above.
+    let left =
+        unsafe { crate::arch::uaccess::copy_user(dst as *mut u8, src.as_ptr(), src.len()) };
+    if left == 0 { Ok(()) } else { Err(Fault) }
+}
+
+/// Copy a NUL-terminated string from the user address `src` into `dst`.
+///
+/// Returns the string length without the terminator, or `dst.len()` if no terminator was
+/// found within `dst.len()` bytes.
+pub fn strncpy_from_user(dst: &mut [u8], src: u64) -> Result<usize, Fault> {
+    if src == 0 || src >= USER_SPACE_MAX_ADDR {
+        return Err(Fault);
+    }
+    // Never read past the end of user space.
+    let max = dst.len().min((USER_SPACE_MAX_ADDR - src) as usize);
+    // SAFETY: `src .. src + max` lies in user space; faults are handled by the fixup table.
+    let len = unsafe { crate::arch::uaccess::strncpy_from_user(&mut dst[..max], src as *const u8) }
+        .ok_or(Fault)?;
+    if len == max && max < dst.len() {
+        // The string runs into the end of user space.
+        return Err(Fault);
+    }
+    Ok(len)
+}
+
+/// Copy a NUL-terminated string of fewer than `max_len` bytes out of user space.
+///
+/// Returns `None` if no terminator is found within `max_len` bytes.
+pub fn strndup_user(ptr: u64, max_len: usize) -> Result<Option<Vec<u8>>, Fault> {
+    // Copy in growing chunks so that short strings do not pay for a `max_len` buffer.
+    let mut buf = Vec::new();
+    let mut chunk = 256;
+    while buf.len() < max_len {
+        let start = buf.len();
+        let want = chunk.min(max_len - start);
+        buf.resize(start + want, 0);
+        let len = strncpy_from_user(&mut buf[start..], ptr + start as u64)?;
+        if len < want {
+            buf.truncate(start + len);
+            return Ok(Some(buf));
+        }
+        chunk *= 2;
+    }
+    Ok(None)
+}
+
+/// Read a NUL-terminated UTF-8 string of fewer than `max_len` bytes from user space.
+///
+/// Fails with `ENAMETOOLONG` if no terminator is found within `max_len` bytes.
+pub fn read_user_string(ptr: u64, max_len: usize) -> Result<String, SyscallError> {
+    let bytes = strndup_user(ptr, max_len)?.ok_or(SyscallError::ENAMETOOLONG)?;
+    String::from_utf8(bytes).map_err(|_| SyscallError::EINVAL)
+}
+
+/// A typed pointer into user space.
+///
+/// `T` must be plain old data: every bit pattern read from user memory must be a valid `T`.
+pub struct UserPtr<T> {
+    addr: u64,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub const fn new(addr: u64) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    pub const fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// Pointer to the `count`-th element after this one.
+    pub fn add(&self, count: usize) -> Self {
+        Self::new(self.addr.wrapping_add((count * size_of::<T>()) as u64))
+    }
+
+    /// Read a `T` from user memory.
+    pub fn read(&self) -> Result<T, Fault> {
+        let mut value = MaybeUninit::<T>::uninit();
+        // SAFETY: The byte view covers exactly the storage of `value`.
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        copy_from_user(bytes, self.addr)?;
+        // SAFETY: Fully initialized by the copy; `T` is plain old data.
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Write `value` to user memory.
+    pub fn write(&self, value: T) -> Result<(), Fault> {
+        // SAFETY: Viewing a `Copy` value as its raw bytes.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>())
+        };
+        copy_to_user(self.addr, bytes)
+    }
+
+    /// Write `value` unless the pointer is null, which callers use for optional out-parameters.
+    pub fn write_if_nonnull(&self, value: T) -> Result<(), Fault> {
+        if self.is_null() { Ok(()) } else { self.write(value) }
+    }
+
+    /// Read a `T` unless the pointer is null.
+    pub fn read_if_nonnull(&self) -> Result<Option<T>, Fault> {
+        if self.is_null() { Ok(None) } else { self.read().map(Some) }
+    }
+}
+
+/// A byte range in user space, such as a `read()`/`write()` buffer.
+#[derive(Clone, Copy)]
+pub struct UserSlice {
+    addr: u64,
+    len: usize,
+}
+
+impl UserSlice {
+    /// Create a slice after checking that the range lies in user space.
+    pub fn new(addr: u64, len: usize) -> Result<Self, Fault> {
+        if len != 0 && (addr == 0 || !range_ok(addr, len)) {
+            return Err(Fault);
+        }
+        Ok(Self { addr, len })
+    }
+
+    pub const fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the whole slice into a new kernel buffer.
+    pub fn read_to_vec(&self) -> Result<Vec<u8>, Fault> {
+        let mut buf = alloc::vec![0u8; self.len];
+        copy_from_user(&mut buf, self.addr)?;
+        Ok(buf)
+    }
+
+    /// Copy `src` to the start of the slice, failing if it does not fit.
+    pub fn write(&self, src: &[u8]) -> Result<(), Fault> {
+        self.write_at(0, src)
+    }
+
+    /// Copy `src` to the slice at byte `offset`, failing if it does not fit.
+    pub fn write_at(&self, offset: usize, src: &[u8]) -> Result<(), Fault> {
+        match offset.checked_add(src.len()) {
+            Some(end) if end <= self.len => copy_to_user(self.addr + offset as u64, src),
+            _ => Err(Fault),
+        }
+    }
+}
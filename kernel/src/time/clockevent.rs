@@ -0,0 +1,70 @@
+//! Clock event devices: timers that raise one interrupt on a CPU at a programmed time.
+//!
+//! Architecture code registers the devices each CPU can use; the one with the highest
+//! rating is kept and programmed by the tick code for the CPU's next event. All devices
+//! run in one-shot mode, so a CPU with nothing to do takes no timer interrupts at all.
+
+use crate::sched::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
+
+/// A timer that interrupts the CPU it is programmed on once, after a given delay.
+pub trait ClockEventDevice: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Preference among the devices usable on a CPU; the highest rated one is used.
+    fn rating(&self) -> u32;
+
+    /// Shortest delay the device can be programmed with.
+    fn min_delta_ns(&self) -> u64;
+
+    /// Longest delay the device can be programmed with.
+    fn max_delta_ns(&self) -> u64;
+
+    /// Prepare the device for one-shot events on the calling CPU.
+    fn enable(&self);
+
+    /// Raise one timer interrupt on the calling CPU `delta_ns` from now, replacing any
+    /// event programmed before.
+    fn set_next_event(&self, delta_ns: u64);
+
+    /// Cancel the programmed event, if any.
+    fn shutdown(&self);
+}
+
+/// Device in use on each CPU.
+static DEVICES: [Spinlock<Option<&'static dyn ClockEventDevice>>; MAX_CPUS] =
+    [const { Spinlock::new(None) }; MAX_CPUS];
+
+/// Offer `device` to the calling CPU `cpu`, which switches to it if it is rated higher
+/// than the device it uses now.
+pub fn register(cpu: u32, device: &'static dyn ClockEventDevice) {
+    let Some(slot) = DEVICES.get(cpu as usize) else {
+        return;
+    };
+    let mut slot = slot.lock();
+    if slot.is_some_and(|current| current.rating() >= device.rating()) {
+        return;
+    }
+    if let Some(current) = slot.take() {
+        current.shutdown();
+    }
+    device.enable();
+    *slot = Some(device);
+    log::info!("clockevent: CPU {} uses {}.", cpu, device.name());
+}
+
+/// Program the next event of the calling CPU `cpu` for `delta_ns` from now, within the
+/// limits of its device; a delay beyond them fires early, and the caller reprograms.
+pub fn program(cpu: u32, delta_ns: u64) {
+    if let Some(device) = DEVICES.get(cpu as usize).and_then(|slot| *slot.lock()) {
+        let delta_ns = delta_ns.clamp(device.min_delta_ns(), device.max_delta_ns());
+        device.set_next_event(delta_ns);
+    }
+}
+
+/// Cancel the next event of the calling CPU `cpu`.
+pub fn shutdown(cpu: u32) {
+    if let Some(device) = DEVICES.get(cpu as usize).and_then(|slot| *slot.lock()) {
+        device.shutdown();
+    }
+}
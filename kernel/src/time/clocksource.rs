@@ -0,0 +1,37 @@
+//! Clock sources: free-running counters that timekeeping reads the time from.
+//!
+//! Architecture code registers the counters it has with [`crate::time::timekeeping`],
+//! which reads the highest rated one.
+
+/// Fixed-point shift of [`ClockSource::mult`].
+pub const MULT_SHIFT: u32 = 32;
+
+/// A counter that increases at a constant rate and is readable from every CPU.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Preference among the registered sources; the highest rated one is used.
+    fn rating(&self) -> u32;
+
+    /// Current counter value.
+    fn read(&self) -> u64;
+
+    /// Bits of the counter that are implemented; it wraps to zero past this mask.
+    fn mask(&self) -> u64 {
+        u64::MAX
+    }
+
+    /// Nanoseconds per count, shifted left by [`MULT_SHIFT`].
+    fn mult(&self) -> u64;
+
+    /// How the vDSO reads this counter, or 0 if user space cannot.
+    fn vdso_mode(&self) -> u32 {
+        0
+    }
+}
+
+/// Convert `cycles` counted at `mult` into nanoseconds.
+#[inline]
+pub fn cycles_to_ns(cycles: u64, mult: u64) -> u64 {
+    ((cycles as u128 * mult as u128) >> MULT_SHIFT) as u64
+}
@@ -0,0 +1,13 @@
+//! Time: clock sources and timekeeping, clock event devices, the scheduler tick and
+//! wakeup timers.
+
+pub mod clockevent;
+pub mod clocksource;
+pub mod tick;
+pub mod timekeeping;
+pub mod timer;
+
+/// Nanoseconds elapsed since boot on the monotonic clock.
+pub fn now_ns() -> u64 {
+    timekeeping::monotonic_ns()
+}
@@ -0,0 +1,178 @@
+//! The scheduler tick, and stopping it when it is not needed.
+//!
+//! The tick charges the running thread for its CPU time and lets the scheduler switch
+//! threads. It is driven by one-shot clock events: after every event and every context
+//! switch the CPU programs its clock event device for whatever comes first, its next
+//! tick or its earliest timer. The tick is only needed while threads share the CPU, so
+//! it is stopped while the CPU idles and deferred for up to [`MAX_DEFER_NS`] while a
+//! single fair thread has the CPU to itself. Time that passes meanwhile is charged in
+//! one go when the CPU next switches threads or ticks.
+
+use super::{clockevent, timekeeping, timer};
+use crate::sched::{MAX_CPUS, SCHEDULER, cputime, loadavg};
+use crate::sync::spinlock::Spinlock;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Tick interval while threads share a CPU (100 Hz).
+pub const TICK_NS: u64 = 10_000_000;
+
+/// Longest a CPU running a single thread goes without a tick.
+pub const MAX_DEFER_NS: u64 = 1_000_000_000;
+
+/// How a CPU needs its tick for what it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickMode {
+    /// Threads compete for the CPU: tick every [`TICK_NS`].
+    Periodic,
+    /// A single fair thread runs with nothing queued: tick every [`MAX_DEFER_NS`].
+    Single,
+    /// The CPU idles: no tick at all, only timers, and the updates timekeeping needs.
+    Idle,
+}
+
+struct TickState {
+    /// When the running thread was last charged for its time.
+    last_tick_ns: u64,
+    /// When the clock event device is programmed to fire, `u64::MAX` for never.
+    next_event_ns: u64,
+    mode: TickMode,
+}
+
+/// Tick state per CPU, only touched by the CPU itself with interrupts disabled.
+static STATES: [Spinlock<TickState>; MAX_CPUS] = [const {
+    Spinlock::new(TickState {
+        last_tick_ns: 0,
+        next_event_ns: u64::MAX,
+        mode: TickMode::Periodic,
+    })
+}; MAX_CPUS];
+
+/// Bit `n` is set while CPU `n` runs with its tick stopped or deferred.
+static STOPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Whether `cpu` runs without its periodic tick, and has to be kicked to notice that
+/// threads were queued on it.
+pub fn is_stopped(cpu: u32) -> bool {
+    cpu < u32::BITS && STOPPED.load(Ordering::Relaxed) & (1 << cpu) != 0
+}
+
+/// Start the periodic tick on the calling CPU `cpu` once it has a clock event device.
+pub fn start(cpu: u32) {
+    let Some(state) = STATES.get(cpu as usize) else {
+        return;
+    };
+    let now = super::now_ns();
+    crate::arch::without_interrupts(|| {
+        let mut state = state.lock();
+        state.last_tick_ns = now;
+        state.mode = TickMode::Periodic;
+        state.next_event_ns = u64::MAX;
+        program_next(cpu, &mut state, now);
+    });
+}
+
+/// Switch the calling CPU `cpu` to the tick `mode` and program its next event.
+///
+/// Called with interrupts disabled whenever what the CPU runs may have changed.
+pub fn reprogram(cpu: u32, mode: TickMode) {
+    let Some(state) = STATES.get(cpu as usize) else {
+        return;
+    };
+    let mut state = state.lock();
+    if state.mode != mode {
+        state.mode = mode;
+        match mode {
+            TickMode::Periodic => STOPPED.fetch_and(!(1 << cpu), Ordering::Relaxed),
+            _ => STOPPED.fetch_or(1 << cpu, Ordering::Relaxed),
+        };
+    }
+    program_next(cpu, &mut state, super::now_ns());
+}
+
+/// Time elapsed on the calling CPU `cpu` since its running thread was last charged,
+/// which is charged now.
+///
+/// Called with interrupts disabled, before a CPU whose tick is stopped switches threads.
+pub fn take_elapsed(cpu: u32) -> u64 {
+    let Some(state) = STATES.get(cpu as usize) else {
+        return 0;
+    };
+    let now = super::now_ns();
+    let mut state = state.lock();
+    let elapsed = now.saturating_sub(state.last_tick_ns);
+    state.last_tick_ns = now;
+    elapsed
+}
+
+/// A timer due at `deadline_ns` became the earliest on the calling CPU `cpu`.
+pub(super) fn timer_added(cpu: u32, deadline_ns: u64) {
+    let Some(state) = STATES.get(cpu as usize) else {
+        return;
+    };
+    let mut state = state.lock();
+    if deadline_ns < state.next_event_ns {
+        program_next(cpu, &mut state, super::now_ns());
+    }
+}
+
+/// Handle a clock event on the calling CPU `cpu`: wake the threads of due timers and
+/// run the scheduler tick if it is due. `user_mode` tells whether the event interrupted
+/// user code. Returns whether the CPU should reschedule.
+pub fn handle_interrupt(cpu: u32, user_mode: bool) -> bool {
+    let Some(state) = STATES.get(cpu as usize) else {
+        return false;
+    };
+    crate::sync::rcu::sched_clock_irq(cpu);
+    let now = super::now_ns();
+    state.lock().next_event_ns = u64::MAX;
+    timer::expire(cpu, now);
+
+    let elapsed = {
+        let mut state = state.lock();
+        let elapsed = now.saturating_sub(state.last_tick_ns);
+        (elapsed >= TICK_NS).then(|| {
+            state.last_tick_ns = now;
+            elapsed
+        })
+    };
+    if elapsed.is_some() {
+        timekeeping::update();
+    }
+
+    let mut sched = SCHEDULER.lock();
+    if let Some(elapsed) = elapsed {
+        cputime::account(&sched, cpu, elapsed, Some(user_mode));
+        loadavg::update(&sched, now);
+    }
+    let resched = elapsed.is_some_and(|elapsed| sched.tick(cpu, elapsed));
+    let mode = sched.tick_mode(cpu);
+    drop(sched);
+
+    reprogram(cpu, mode);
+    resched
+}
+
+/// Program the next event of `cpu` for its next tick or its earliest timer.
+fn program_next(cpu: u32, state: &mut TickState, now: u64) {
+    let tick = match state.mode {
+        TickMode::Periodic => Some(state.last_tick_ns + TICK_NS),
+        TickMode::Single => Some(state.last_tick_ns + MAX_DEFER_NS),
+        TickMode::Idle => timekeeping::max_idle_ns().map(|max| state.last_tick_ns + max),
+    };
+    let next = match (tick, timer::next_deadline(cpu)) {
+        (Some(tick), Some(timer)) => Some(tick.min(timer)),
+        (tick, timer) => tick.or(timer),
+    };
+
+    match next {
+        Some(next) if next == state.next_event_ns => {}
+        Some(next) => {
+            state.next_event_ns = next;
+            clockevent::program(cpu, next.saturating_sub(now));
+        }
+        None => {
+            state.next_event_ns = u64::MAX;
+            clockevent::shutdown(cpu);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Timekeeping: the system clocks, kept from the best clock source.
+//!
+//! `CLOCK_MONOTONIC_RAW` converts the clock source at its nominal rate. `CLOCK_MONOTONIC`
+//! runs at the rate set through `adjtimex`, plus up to [`SLEW_PPM`] faster or slower while
+//! an offset is slewed out. `CLOCK_REALTIME` is `CLOCK_MONOTONIC` plus an offset that
+//! setting the clock moves. `CLOCK_BOOTTIME` equals `CLOCK_MONOTONIC`, as the system does
+//! not suspend. The coarse clocks return the time of the last update, which the scheduler
+//! tick makes.
+//!
+//! Reading the time folds nothing: the clocks are computed from the state of the last
+//! update, so they are exact between updates. Updates only have to come often enough for
+//! a narrow counter not to wrap in between, and before the rate changes.
+
+use super::clocksource::{ClockSource, cycles_to_ns};
+use crate::arch::vdso::{self, CLOCK_MODE_NONE, VdsoClock};
+use crate::sync::spinlock::Spinlock;
+use crate::syscalls::SyscallError;
+use crate::syscalls::time::{TimeVal, Timex};
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Largest frequency correction `adjtimex` accepts, in ppm shifted left by 16.
+const MAX_FREQ: i64 = 500 << 16;
+
+/// Rate at which an offset is slewed out, in ppm.
+const SLEW_PPM: u64 = 500;
+
+/// Largest offset `ADJ_OFFSET` accepts, in nanoseconds.
+const MAX_PHASE_NS: i64 = 500_000_000;
+
+/// Largest error estimate, in microseconds.
+const MAX_ERROR_US: i64 = 16_000_000;
+
+/// Nominal length of a clock tick as seen by `adjtimex`, in microseconds (`USER_HZ` 100).
+const TICK_US: i64 = 10_000;
+
+// adjtimex mode bits.
+const ADJ_OFFSET: u32 = 0x0001;
+const ADJ_FREQUENCY: u32 = 0x0002;
+const ADJ_MAXERROR: u32 = 0x0004;
+const ADJ_ESTERROR: u32 = 0x0008;
+const ADJ_STATUS: u32 = 0x0010;
+const ADJ_TIMECONST: u32 = 0x0020;
+const ADJ_TAI: u32 = 0x0080;
+const ADJ_SETOFFSET: u32 = 0x0100;
+const ADJ_MICRO: u32 = 0x1000;
+const ADJ_NANO: u32 = 0x2000;
+const ADJ_TICK: u32 = 0x4000;
+const ADJ_OFFSET_SINGLESHOT: u32 = 0x8001;
+const ADJ_OFFSET_SS_READ: u32 = 0xa001;
+
+// Clock status bits.
+const STA_PLL: i32 = 0x0001;
+const STA_UNSYNC: i32 = 0x0040;
+const STA_NANO: i32 = 0x2000;
+/// Status bits that only the kernel sets: the PPS signal state, `STA_CLOCKERR`,
+/// `STA_NANO`, `STA_MODE` and `STA_CLK`.
+const STA_RONLY: i32 = 0xff00;
+
+/// Clock states returned by `adjtimex`.
+const TIME_OK: i32 = 0;
+const TIME_ERROR: i32 = 5;
+
+/// Discipline state set through `adjtimex`.
+struct Ntp {
+    /// Frequency correction, in ppm shifted left by 16.
+    freq: i64,
+    /// Length of a clock tick, in microseconds.
+    tick_us: i64,
+    status: i32,
+    maxerror: i64,
+    esterror: i64,
+    constant: i64,
+    tai: i32,
+}
+
+struct Timekeeper {
+    source: Option<&'static dyn ClockSource>,
+    mask: u64,
+    /// Counter value at the last update.
+    cycle_last: u64,
+    /// Nanoseconds per count at the nominal and at the corrected rate.
+    raw_mult: u64,
+    mult: u64,
+    /// `CLOCK_MONOTONIC` and `CLOCK_MONOTONIC_RAW` at the last update.
+    mono_ns: u64,
+    raw_ns: u64,
+    /// `CLOCK_REALTIME` minus `CLOCK_MONOTONIC`.
+    wall_offset_ns: i64,
+    /// Offset still to be slewed into the clock.
+    slew_ns: i64,
+    ntp: Ntp,
+}
+
+static TIMEKEEPER: Spinlock<Timekeeper> = Spinlock::new(Timekeeper {
+    source: None,
+    mask: u64::MAX,
+    cycle_last: 0,
+    raw_mult: 0,
+    mult: 0,
+    mono_ns: 0,
+    raw_ns: 0,
+    wall_offset_ns: 0,
+    slew_ns: 0,
+    ntp: Ntp {
+        freq: 0,
+        tick_us: TICK_US,
+        status: STA_UNSYNC,
+        maxerror: MAX_ERROR_US,
+        esterror: MAX_ERROR_US,
+        constant: 2,
+        tai: 0,
+    },
+});
+
+impl Timekeeper {
+    /// Counts from the last update to the counter value `cycles`.
+    fn delta(&self, cycles: u64) -> u64 {
+        cycles.wrapping_sub(self.cycle_last) & self.mask
+    }
+
+    /// Part of the pending offset slewed out over `raw_delta_ns`.
+    fn slewed(&self, raw_delta_ns: u64) -> i64 {
+        let max = (raw_delta_ns as u128 * SLEW_PPM as u128 / 1_000_000).min(i64::MAX as u128);
+        self.slew_ns.clamp(-(max as i64), max as i64)
+    }
+
+    /// `CLOCK_MONOTONIC` and `CLOCK_MONOTONIC_RAW` now.
+    fn read(&self) -> (u64, u64) {
+        let Some(source) = self.source else {
+            return (self.mono_ns, self.raw_ns);
+        };
+        let delta = self.delta(source.read());
+        let raw_delta = cycles_to_ns(delta, self.raw_mult);
+        let mono = self.mono_ns + cycles_to_ns(delta, self.mult);
+        (mono.saturating_add_signed(self.slewed(raw_delta)), self.raw_ns + raw_delta)
+    }
+
+    fn realtime_ns(&self, mono_ns: u64) -> u64 {
+        mono_ns.saturating_add_signed(self.wall_offset_ns)
+    }
+
+    /// Fold the time since the last update into the clocks and publish them to the vDSO.
+    fn advance(&mut self) {
+        let Some(source) = self.source else {
+            return;
+        };
+        let cycles = source.read();
+        let delta = self.delta(cycles);
+        let raw_delta = cycles_to_ns(delta, self.raw_mult);
+        let slewed = self.slewed(raw_delta);
+        let mono_delta = cycles_to_ns(delta, self.mult);
+        self.mono_ns = (self.mono_ns + mono_delta).saturating_add_signed(slewed);
+        self.raw_ns += raw_delta;
+        self.slew_ns -= slewed;
+        self.cycle_last = cycles;
+        self.publish();
+    }
+
+    /// Apply the frequency correction and tick length to the rate of `CLOCK_MONOTONIC`.
+    fn update_mult(&mut self) {
+        let ppm = self.ntp.freq + (((self.ntp.tick_us - TICK_US) * 100) << 16);
+        let correction = self.raw_mult as i128 * ppm as i128 / (1_000_000 << 16);
+        self.mult = (self.raw_mult as i128 + correction) as u64;
+    }
+
+    fn publish(&self) {
+        // The vDSO cannot slew, so it leaves the time to the system calls meanwhile.
+        let mode = match self.source {
+            Some(source) if self.slew_ns == 0 => source.vdso_mode(),
+            _ => CLOCK_MODE_NONE,
+        };
+        vdso::update_clock(&VdsoClock {
+            mode,
+            cycle_last: self.cycle_last,
+            mult: self.mult,
+            base_ns: self.mono_ns,
+            wall_offset_ns: self.wall_offset_ns as u64,
+        });
+    }
+
+    /// Set `CLOCK_REALTIME` to `realtime_ns`, dropping any offset still being slewed.
+    fn set_realtime(&mut self, realtime_ns: u64) {
+        self.advance();
+        let (mono_ns, _) = self.read();
+        self.wall_offset_ns = (realtime_ns as i128 - mono_ns as i128) as i64;
+        self.slew_ns = 0;
+        self.publish();
+    }
+}
+
+fn with_timekeeper<R>(f: impl FnOnce(&mut Timekeeper) -> R) -> R {
+    crate::arch::without_interrupts(|| f(&mut TIMEKEEPER.lock()))
+}
+
+/// Offer `source` for timekeeping, which switches to it if it is rated higher than the
+/// source in use. The clocks continue from where the old source left them.
+pub fn register(source: &'static dyn ClockSource) {
+    let switched = with_timekeeper(|tk| {
+        let first = match tk.source {
+            Some(current) if current.rating() >= source.rating() => return false,
+            Some(_) => {
+                tk.advance();
+                false
+            }
+            None => true,
+        };
+        tk.source = Some(source);
+        tk.mask = source.mask();
+        tk.cycle_last = source.read() & tk.mask;
+        tk.raw_mult = source.mult();
+        tk.update_mult();
+        if first {
+            // Count from when the counter started, which is close enough to boot.
+            tk.mono_ns = cycles_to_ns(tk.cycle_last, tk.raw_mult);
+            tk.raw_ns = tk.mono_ns;
+        }
+        tk.publish();
+        true
+    });
+    if switched {
+        log::info!("timekeeping: using clock source {}.", source.name());
+    }
+}
+
+/// Fold the elapsed time into the clocks. Called on the scheduler tick.
+pub fn update() {
+    with_timekeeper(|tk| tk.advance());
+}
+
+/// Publish the clocks to the vDSO once it has been set up.
+pub fn sync_vdso() {
+    with_timekeeper(|tk| tk.publish());
+}
+
+/// Longest the clocks may go without an update before the clock source wraps, if it can.
+pub fn max_idle_ns() -> Option<u64> {
+    with_timekeeper(|tk| (tk.mask != u64::MAX).then(|| cycles_to_ns(tk.mask / 2, tk.raw_mult)))
+}
+
+/// `CLOCK_MONOTONIC`, in nanoseconds since boot.
+pub fn monotonic_ns() -> u64 {
+    with_timekeeper(|tk| tk.read().0)
+}
+
+/// `CLOCK_MONOTONIC_RAW`, in nanoseconds since boot.
+pub fn raw_ns() -> u64 {
+    with_timekeeper(|tk| tk.read().1)
+}
+
+/// `CLOCK_BOOTTIME`, in nanoseconds since boot.
+pub fn boottime_ns() -> u64 {
+    monotonic_ns()
+}
+
+/// `CLOCK_REALTIME`, in nanoseconds since the Unix epoch.
+pub fn realtime_ns() -> u64 {
+    with_timekeeper(|tk| tk.realtime_ns(tk.read().0))
+}
+
+/// `CLOCK_MONOTONIC_COARSE`: `CLOCK_MONOTONIC` at the last update.
+pub fn monotonic_coarse_ns() -> u64 {
+    with_timekeeper(|tk| tk.mono_ns)
+}
+
+/// `CLOCK_REALTIME_COARSE`: `CLOCK_REALTIME` at the last update.
+pub fn realtime_coarse_ns() -> u64 {
+    with_timekeeper(|tk| tk.realtime_ns(tk.mono_ns))
+}
+
+/// Convert an absolute `CLOCK_REALTIME` time into `CLOCK_MONOTONIC`.
+pub fn realtime_to_monotonic(realtime_ns: u64) -> u64 {
+    with_timekeeper(|tk| realtime_ns.saturating_add_signed(-tk.wall_offset_ns))
+}
+
+/// Initialize `CLOCK_REALTIME` from the hardware clock, `epoch_sec` seconds since the
+/// Unix epoch.
+pub fn init_realtime(epoch_sec: u64) {
+    with_timekeeper(|tk| tk.set_realtime(epoch_sec.saturating_mul(NSEC_PER_SEC)));
+}
+
+/// Set `CLOCK_REALTIME` to `realtime_ns` and write the new time to the hardware clock.
+///
+/// The clock counts as unsynchronized afterwards, until `adjtimex` says otherwise.
+pub fn settime(realtime_ns: u64) {
+    with_timekeeper(|tk| {
+        tk.set_realtime(realtime_ns);
+        tk.ntp.status |= STA_UNSYNC;
+        tk.ntp.maxerror = MAX_ERROR_US;
+        tk.ntp.esterror = MAX_ERROR_US;
+    });
+    sync_rtc();
+}
+
+/// Write `CLOCK_REALTIME` to the battery-backed hardware clock.
+pub fn sync_rtc() {
+    crate::drivers::time::cmos_rtc::write_time(realtime_ns() / NSEC_PER_SEC);
+}
+
+/// Read and adjust the clock discipline, as `adjtimex` does. Adjusting needs `privileged`.
+///
+/// Offsets are slewed out at [`SLEW_PPM`]: `ADJ_OFFSET` replaces the pending offset while
+/// `STA_PLL` is set, `ADJ_OFFSET_SINGLESHOT` does so like `adjtime` and returns the old
+/// one. Returns the clock state.
+pub fn adjtimex(tx: &mut Timex, privileged: bool) -> Result<i32, SyscallError> {
+    let modes = tx.modes;
+    if modes & ADJ_OFFSET_SINGLESHOT == ADJ_OFFSET_SINGLESHOT
+        && modes != ADJ_OFFSET_SINGLESHOT
+        && modes != ADJ_OFFSET_SS_READ
+    {
+        return Err(SyscallError::EINVAL);
+    }
+    if modes != 0 && modes != ADJ_OFFSET_SS_READ && !privileged {
+        return Err(SyscallError::EPERM);
+    }
+    if modes & ADJ_TICK != 0 && !(TICK_US * 9 / 10..=TICK_US * 11 / 10).contains(&tx.tick) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let step_ns = if modes & ADJ_SETOFFSET != 0 {
+        let (sec, frac) = (tx.time.tv_sec, tx.time.tv_usec);
+        let frac_ns = if modes & ADJ_NANO != 0 { frac } else { frac.saturating_mul(1000) };
+        if !(0..NSEC_PER_SEC as i64).contains(&frac_ns) {
+            return Err(SyscallError::EINVAL);
+        }
+        Some(sec.saturating_mul(NSEC_PER_SEC as i64).saturating_add(frac_ns))
+    } else {
+        None
+    };
+
+    let state = with_timekeeper(|tk| {
+        let old_slew_ns = tk.slew_ns;
+        if let Some(step_ns) = step_ns {
+            let (mono_ns, _) = tk.read();
+            let realtime = tk.realtime_ns(mono_ns).saturating_add_signed(step_ns);
+            tk.set_realtime(realtime);
+        }
+
+        if modes == ADJ_OFFSET_SINGLESHOT {
+            tk.advance();
+            tk.slew_ns = tx.offset.saturating_mul(1000);
+            tk.publish();
+        } else if modes != ADJ_OFFSET_SS_READ {
+            if modes & ADJ_NANO != 0 {
+                tk.ntp.status |= STA_NANO;
+            }
+            if modes & ADJ_MICRO != 0 {
+                tk.ntp.status &= !STA_NANO;
+            }
+            if modes & ADJ_STATUS != 0 {
+                tk.ntp.status = (tk.ntp.status & STA_RONLY) | (tx.status & !STA_RONLY);
+            }
+            if modes & ADJ_MAXERROR != 0 {
+                tk.ntp.maxerror = tx.maxerror.clamp(0, MAX_ERROR_US);
+            }
+            if modes & ADJ_ESTERROR != 0 {
+                tk.ntp.esterror = tx.esterror.clamp(0, MAX_ERROR_US);
+            }
+            if modes & ADJ_TIMECONST != 0 {
+                tk.ntp.constant = tx.constant.clamp(0, 10);
+            }
+            if modes & ADJ_TAI != 0 && tx.constant >= 0 {
+                tk.ntp.tai = tx.constant as i32;
+            }
+            if modes & (ADJ_FREQUENCY | ADJ_TICK) != 0 {
+                tk.advance();
+                if modes & ADJ_FREQUENCY != 0 {
+                    tk.ntp.freq = tx.freq.clamp(-MAX_FREQ, MAX_FREQ);
+                }
+                if modes & ADJ_TICK != 0 {
+                    tk.ntp.tick_us = tx.tick;
+                }
+                tk.update_mult();
+                tk.publish();
+            }
+            if modes & ADJ_OFFSET != 0 && tk.ntp.status & STA_PLL != 0 {
+                let offset_ns = if tk.ntp.status & STA_NANO != 0 {
+                    tx.offset
+                } else {
+                    tx.offset.saturating_mul(1000)
+                };
+                tk.advance();
+                tk.slew_ns = offset_ns.clamp(-MAX_PHASE_NS, MAX_PHASE_NS);
+                tk.publish();
+            }
+        }
+
+        // Report the state after the changes; adjtime-style calls get the old offset.
+        let nano = tk.ntp.status & STA_NANO != 0;
+        let slew_ns = if modes & ADJ_OFFSET_SINGLESHOT == ADJ_OFFSET_SINGLESHOT {
+            old_slew_ns
+        } else {
+            tk.slew_ns
+        };
+        tx.offset = if nano && modes & ADJ_OFFSET_SINGLESHOT != ADJ_OFFSET_SINGLESHOT {
+            slew_ns
+        } else {
+            slew_ns / 1000
+        };
+        tx.freq = tk.ntp.freq;
+        tx.maxerror = tk.ntp.maxerror;
+        tx.esterror = tk.ntp.esterror;
+        tx.status = tk.ntp.status;
+        tx.constant = tk.ntp.constant;
+        tx.precision = 1;
+        tx.tolerance = MAX_FREQ;
+        tx.tick = tk.ntp.tick_us;
+        tx.tai = tk.ntp.tai;
+        let realtime = tk.realtime_ns(tk.read().0);
+        let frac_ns = (realtime % NSEC_PER_SEC) as i64;
+        tx.time = TimeVal {
+            tv_sec: (realtime / NSEC_PER_SEC) as i64,
+            tv_usec: if nano { frac_ns } else { frac_ns / 1000 },
+        };
+        if tk.ntp.status & STA_UNSYNC != 0 {
+            TIME_ERROR
+        } else {
+            TIME_OK
+        }
+    });
+
+    if step_ns.is_some() {
+        sync_rtc();
+    }
+    Ok(state)
+}
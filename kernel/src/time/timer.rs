@@ -0,0 +1,99 @@
+//! Per-CPU queues of wakeup timers.
+//!
+//! A thread that sleeps until some time arms a timer for it. Timers stay on the CPU that
+//! armed them, ordered by deadline, and the tick code programs that CPU's clock event
+//! device for the earliest one, so a timer fires when it is due rather than on the next
+//! scheduler tick.
+
+use crate::proc::thread::{Thread, ThreadState};
+use crate::sched::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Delay before retrying a wakeup whose thread was locked by the interrupted code.
+const RETRY_NS: u64 = 20_000;
+
+/// Deadline and sequence number, keeping timers with the same deadline apart.
+type TimerKey = (u64, u64);
+
+/// Threads to wake per CPU, by deadline.
+static TIMERS: [Spinlock<BTreeMap<TimerKey, Arc<Spinlock<Thread>>>>; MAX_CPUS] =
+    [const { Spinlock::new(BTreeMap::new()) }; MAX_CPUS];
+
+static NEXT_TIMER_SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// An armed timer, to [`cancel`] it with.
+pub struct TimerHandle {
+    cpu: usize,
+    key: TimerKey,
+}
+
+/// Wake `thread` at `deadline_ns` on the monotonic clock, if it still sleeps then.
+pub fn add(deadline_ns: u64, thread: Arc<Spinlock<Thread>>) -> TimerHandle {
+    crate::arch::without_interrupts(|| {
+        // CPUs beyond MAX_CPUS never run threads; keep the index in range regardless.
+        let cpu = (crate::arch::cpu_id() as usize).min(MAX_CPUS - 1);
+        let key = (deadline_ns, NEXT_TIMER_SEQ.fetch_add(1, Ordering::Relaxed));
+        let earliest = {
+            let mut timers = TIMERS[cpu].lock();
+            timers.insert(key, thread);
+            timers.first_key_value().is_some_and(|(first, _)| *first == key)
+        };
+        if earliest {
+            super::tick::timer_added(cpu as u32, deadline_ns);
+        }
+        TimerHandle { cpu, key }
+    })
+}
+
+/// Disarm a timer. Returns whether it had not fired yet.
+pub fn cancel(handle: TimerHandle) -> bool {
+    crate::arch::without_interrupts(|| TIMERS[handle.cpu].lock().remove(&handle.key).is_some())
+}
+
+/// Deadline of the earliest timer armed on `cpu`.
+pub fn next_deadline(cpu: u32) -> Option<u64> {
+    let timers = TIMERS.get(cpu as usize)?.lock();
+    timers.first_key_value().map(|(&(deadline, _), _)| deadline)
+}
+
+/// Wake the threads of all timers on `cpu` that are due at `now_ns`.
+///
+/// Called from the timer interrupt. A thread the interrupted code holds locked cannot
+/// be woken from here without deadlocking, so its timer is retried shortly after.
+pub fn expire(cpu: u32, now_ns: u64) {
+    let Some(queue) = TIMERS.get(cpu as usize) else {
+        return;
+    };
+    let expired = {
+        let mut timers = queue.lock();
+        let pending = timers.split_off(&(now_ns.saturating_add(1), 0));
+        core::mem::replace(&mut *timers, pending)
+    };
+
+    let mut retry = Vec::new();
+    for ((_, seq), thread) in expired {
+        if let Err(thread) = Thread::try_unblock(thread) {
+            retry.push(((now_ns + RETRY_NS, seq), thread));
+        }
+    }
+    if !retry.is_empty() {
+        queue.lock().extend(retry);
+    }
+}
+
+/// Sleep on the calling thread until `deadline_ns` on the monotonic clock.
+pub fn sleep_until(deadline_ns: u64) {
+    let Some(thread) = crate::proc::current_thread() else {
+        return;
+    };
+    while super::now_ns() < deadline_ns {
+        thread.lock().state = ThreadState::Sleeping;
+        let handle = add(deadline_ns, thread.clone());
+        crate::sched::schedule(false);
+        cancel(handle);
+    }
+}
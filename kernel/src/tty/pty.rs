@@ -12,6 +12,7 @@ use crate::fs::vfs::dentry::Dentry;
 use crate::fs::vfs::mount::MOUNT_TABLE;
 use crate::fs::vfs::types::{FileOps, Inode, InodeOps, InodeType, Stat, VfsError};
 use crate::sync::spinlock::Spinlock;
+use crate::syscalls::uaccess::UserPtr;
 use crate::tty::termios::{
     FIONREAD, LineDiscipline, TCFLSH, TCGETS, TCSBRK, TCSETS, TCSETSF, TCSETSW, TCXONC, TIOCGPGRP,
     TIOCGPTN, TIOCGWINSZ, TIOCNOTTY, TIOCSCTTY, TIOCSPGRP, TIOCSPTLCK, TIOCSWINSZ, Termios, WinSize,
@@ -83,8 +84,8 @@ pub static PTY_MANAGER: PtyManager = PtyManager::new();
 
 /// Helper to register a dynamic `/dev/pts/N` inode in devfs.
 fn register_pts_node(id: u32, pair: Arc<PtyPair>) -> Result<(), VfsError> {
-    let mt = MOUNT_TABLE.read();
-    if let Some((mount, _)) = mt.lookup("/dev") {
+    let dev_mount = MOUNT_TABLE.read().lookup("/dev");
+    if let Some((mount, _)) = dev_mount {
         let pts_name = format!("{}", id);
         let pts_ino = mount.superblock.alloc_ino();
         let pts_inode = Arc::new(Inode {
@@ -92,7 +93,6 @@ fn register_pts_node(id: u32, pair: Arc<PtyPair>) -> Result<(), VfsError> {
             inode_type: InodeType::CharDevice,
             ops: Arc::new(PtsInode { pair }),
         });
-        drop(mt);
         crate::fs::devfs::register_pts_node(&pts_name, pts_inode);
     }
     Ok(())
@@ -172,47 +172,21 @@ impl FileOps for PtyMasterFileOps {
     fn ioctl(&self, cmd: u64, arg: usize) -> Result<usize, VfsError> {
         match cmd {
             TIOCGPTN => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut u32) = self.pair.id;
-                }
+                UserPtr::<u32>::new(arg as u64).write(self.pair.id)?;
                 Ok(0)
             }
             TIOCSPTLCK => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                let lock_val = unsafe { *(arg as *const i32) };
+                let lock_val = UserPtr::<i32>::new(arg as u64).read()?;
                 self.pair.locked.store(lock_val != 0, Ordering::SeqCst);
                 Ok(0)
             }
             TIOCGWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<WinSize>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let ws = self.pair.slave_ldisc.lock().winsize;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut WinSize) = ws;
-                }
+                UserPtr::<WinSize>::new(arg as u64).write(ws)?;
                 Ok(0)
             }
             TIOCSWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<WinSize>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                let ws = unsafe { *(arg as *const WinSize) };
+                let ws = UserPtr::<WinSize>::new(arg as u64).read()?;
                 let mut ldisc = self.pair.slave_ldisc.lock();
                 ldisc.winsize = ws;
                 if ldisc.foreground_pgid > 0 {
@@ -224,14 +198,8 @@ impl FileOps for PtyMasterFileOps {
                 Ok(0)
             }
             FIONREAD => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let len = self.pair.master_buffer.lock().len() as i32;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut i32) = len;
-                }
+                UserPtr::<i32>::new(arg as u64).write(len)?;
                 Ok(0)
             }
             _ => Err(VfsError::NotSupported),
@@ -315,54 +283,22 @@ impl FileOps for PtySlaveFileOps {
     fn ioctl(&self, cmd: u64, arg: usize) -> Result<usize, VfsError> {
         match cmd {
             TCGETS => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<Termios>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let t = self.pair.slave_ldisc.lock().termios;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut Termios) = t;
-                }
+                UserPtr::<Termios>::new(arg as u64).write(t)?;
                 Ok(0)
             }
             TCSETS | TCSETSW | TCSETSF => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<Termios>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                let t = unsafe { *(arg as *const Termios) };
+                let t = UserPtr::<Termios>::new(arg as u64).read()?;
                 self.pair.slave_ldisc.lock().termios = t;
                 Ok(0)
             }
             TIOCGWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<WinSize>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let ws = self.pair.slave_ldisc.lock().winsize;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut WinSize) = ws;
-                }
+                UserPtr::<WinSize>::new(arg as u64).write(ws)?;
                 Ok(0)
             }
             TIOCSWINSZ => {
-                if !crate::syscalls::is_user_ptr_valid(
-                    arg as u64,
-                    core::mem::size_of::<WinSize>(),
-                ) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                let ws = unsafe { *(arg as *const WinSize) };
+                let ws = UserPtr::<WinSize>::new(arg as u64).read()?;
                 let mut ldisc = self.pair.slave_ldisc.lock();
                 ldisc.winsize = ws;
                 if ldisc.foreground_pgid > 0 {
@@ -374,22 +310,12 @@ impl FileOps for PtySlaveFileOps {
                 Ok(0)
             }
             TIOCGPGRP => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let pgid = self.pair.slave_ldisc.lock().foreground_pgid;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut i32) = pgid;
-                }
+                UserPtr::<i32>::new(arg as u64).write(pgid)?;
                 Ok(0)
             }
             TIOCSPGRP => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
-                // SAFETY: Pointer is user space verified.
-                let pgid = unsafe { *(arg as *const i32) };
+                let pgid = UserPtr::<i32>::new(arg as u64).read()?;
                 self.pair.slave_ldisc.lock().foreground_pgid = pgid;
                 Ok(0)
             }
@@ -402,14 +328,8 @@ impl FileOps for PtySlaveFileOps {
                 Ok(0)
             }
             FIONREAD => {
-                if !crate::syscalls::is_user_ptr_valid(arg as u64, 4) {
-                    return Err(VfsError::InvalidInput);
-                }
                 let len = self.pair.slave_ldisc.lock().available_read_bytes() as i32;
-                // SAFETY: Pointer is user space verified.
-                unsafe {
-                    *(arg as *mut i32) = len;
-                }
+                UserPtr::<i32>::new(arg as u64).write(len)?;
                 Ok(0)
             }
             _ => Err(VfsError::NotSupported),
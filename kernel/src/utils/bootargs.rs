@@ -0,0 +1,25 @@
+//! Kernel Command Line
+//!
+//! Options passed to the kernel through the bootloader's `cmdline` setting. Options are
+//! whitespace separated and are either bare flags (`norandmaps`) or `key=value` pairs.
+
+/// The raw kernel command line, or an empty string if the bootloader passed none.
+pub fn cmdline() -> &'static str {
+    crate::limine::KERNEL_FILE_REQUEST
+        .get_response()
+        .and_then(|resp| resp.file().string().to_str().ok())
+        .unwrap_or("")
+}
+
+/// Returns true if the bare flag `name` appears on the command line.
+pub fn has_flag(name: &str) -> bool {
+    cmdline().split_whitespace().any(|opt| opt == name)
+}
+
+/// Returns the value of the first `name=value` option on the command line.
+pub fn get(name: &str) -> Option<&'static str> {
+    cmdline().split_whitespace().find_map(|opt| {
+        let (key, value) = opt.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
@@ -1,5 +1,6 @@
 //! General Kernel Utilities
 
+pub mod bootargs;
 pub mod cpio;
 
 pub use cpio::{
@@ -0,0 +1,13 @@
+//! Deferred work: softirqs and tasklets run when interrupt handlers return, and
+//! workqueues run by kernel worker threads.
+
+pub mod softirq;
+pub mod tasklet;
+pub mod workqueue;
+
+pub use softirq::{irq_enter, irq_exit, open_softirq, raise_softirq};
+pub use tasklet::{tasklet_hi_schedule, tasklet_schedule, Tasklet};
+pub use workqueue::{
+    alloc_workqueue, flush_work, queue_delayed_work, queue_work, queue_work_on,
+    schedule_delayed_work, schedule_work, system_unbound_wq, system_wq, Work, Workqueue,
+};
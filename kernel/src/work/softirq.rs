@@ -0,0 +1,176 @@
+//! Softirqs: work raised by interrupt handlers and run when they return.
+//!
+//! A handler raises a softirq on its CPU with [`raise_softirq`]; [`irq_exit`] runs the
+//! raised softirqs once the handler is done, with interrupts enabled again. Softirqs that
+//! keep being raised are left to the per-CPU `ksoftirqd` thread after a few rounds, as are
+//! softirqs raised outside of interrupt handlers, so they cannot starve threads.
+//!
+//! Softirq handlers run in interrupt context: they must not sleep, and may only take
+//! locks that are otherwise taken with interrupts disabled.
+
+use crate::proc::kthread::{kthread_create, kthread_should_stop};
+use crate::proc::thread::{Thread, ThreadState};
+use crate::sched::{MAX_CPUS, cputime};
+use crate::sync::spinlock::Spinlock;
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// High priority tasklets.
+pub const HI_SOFTIRQ: usize = 0;
+/// Tasklets.
+pub const TASKLET_SOFTIRQ: usize = 1;
+/// RCU callbacks, see `crate::sync::rcu`.
+pub const RCU_SOFTIRQ: usize = 2;
+
+pub const NR_SOFTIRQS: usize = 3;
+
+/// Rounds of raised softirqs run on interrupt exit before the rest goes to `ksoftirqd`.
+const MAX_SOFTIRQ_RESTART: u32 = 10;
+
+/// Handler of each softirq, indexed by softirq number.
+type Handlers = [Option<fn()>; NR_SOFTIRQS];
+
+/// Handler of each softirq, set once with [`open_softirq`].
+static HANDLERS: Spinlock<Handlers> = Spinlock::new([None; NR_SOFTIRQS]);
+
+/// Softirqs raised on each CPU, bit `n` for softirq `n`.
+static PENDING: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Interrupt handlers each CPU is nested in.
+static IRQ_DEPTH: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Set while a CPU runs softirqs, so nested interrupts leave them to the outer run.
+static IN_SOFTIRQ: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// `ksoftirqd` of each CPU.
+static KSOFTIRQD: [Spinlock<Option<Arc<Spinlock<Thread>>>>; MAX_CPUS] =
+    [const { Spinlock::new(None) }; MAX_CPUS];
+
+/// Run `handler` for softirq `nr`.
+pub fn open_softirq(nr: usize, handler: fn()) {
+    crate::arch::without_interrupts(|| HANDLERS.lock()[nr] = Some(handler));
+}
+
+/// Raise softirq `nr` on the calling CPU.
+pub fn raise_softirq(nr: usize) {
+    crate::arch::without_interrupts(|| {
+        let cpu = crate::arch::cpu_id() as usize;
+        if cpu >= MAX_CPUS {
+            return;
+        }
+        PENDING[cpu].fetch_or(1 << nr, Ordering::Relaxed);
+        // Outside of interrupt handlers, nothing runs it on the way out.
+        if !in_interrupt(cpu) {
+            wake_ksoftirqd(cpu);
+        }
+    });
+}
+
+/// Whether `cpu` runs an interrupt handler or softirqs.
+pub fn in_interrupt(cpu: usize) -> bool {
+    IRQ_DEPTH.get(cpu).is_some_and(|depth| depth.load(Ordering::Relaxed) != 0)
+        || IN_SOFTIRQ.get(cpu).is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// Called on entry to a device or timer interrupt handler on `cpu`.
+pub fn irq_enter(cpu: u32) {
+    if let Some(depth) = IRQ_DEPTH.get(cpu as usize) {
+        depth.fetch_add(1, Ordering::Relaxed);
+    }
+    cputime::irq_enter(cpu);
+}
+
+/// Called when the interrupt handler entered with [`irq_enter`] on `cpu` is done and has
+/// signalled the end of the interrupt: runs the softirqs it raised.
+///
+/// Returns whether the handler interrupted a thread, rather than another handler or
+/// softirqs, so that it may switch threads.
+pub fn irq_exit(cpu: u32) -> bool {
+    cputime::irq_exit(cpu);
+    let cpu = cpu as usize;
+    let Some(depth) = IRQ_DEPTH.get(cpu) else {
+        return true;
+    };
+    if depth.fetch_sub(1, Ordering::Relaxed) != 1 || IN_SOFTIRQ[cpu].load(Ordering::Relaxed) {
+        return false;
+    }
+    if PENDING[cpu].load(Ordering::Relaxed) != 0 {
+        do_softirq(cpu);
+    }
+    true
+}
+
+/// Run the softirqs raised on the calling CPU `cpu`. Called with interrupts disabled.
+fn do_softirq(cpu: usize) {
+    let start = crate::time::now_ns();
+    IN_SOFTIRQ[cpu].store(true, Ordering::Relaxed);
+    for _ in 0..MAX_SOFTIRQ_RESTART {
+        let pending = PENDING[cpu].swap(0, Ordering::Relaxed);
+        if pending == 0 {
+            break;
+        }
+        let handlers = *HANDLERS.lock();
+        crate::arch::enable_interrupts();
+        for (nr, handler) in handlers.iter().enumerate() {
+            if pending & (1 << nr) != 0
+                && let Some(handler) = handler
+            {
+                handler();
+            }
+        }
+        crate::arch::disable_interrupts();
+    }
+    IN_SOFTIRQ[cpu].store(false, Ordering::Relaxed);
+    cputime::softirq_time(cpu as u32, crate::time::now_ns().saturating_sub(start));
+
+    if PENDING[cpu].load(Ordering::Relaxed) != 0 {
+        wake_ksoftirqd(cpu);
+    }
+}
+
+fn wake_ksoftirqd(cpu: usize) {
+    let thread = KSOFTIRQD[cpu].lock().clone();
+    if let Some(thread) = thread {
+        Thread::wake(thread);
+    }
+}
+
+/// Body of `ksoftirqd/N`: run softirqs raised on its CPU until none are left, then sleep.
+fn ksoftirqd(cpu: usize) -> i32 {
+    let Some(thread) = crate::proc::current_thread() else {
+        return 0;
+    };
+    while !kthread_should_stop() {
+        let ran = crate::arch::without_interrupts(|| {
+            if PENDING[cpu].load(Ordering::Relaxed) == 0 {
+                thread.lock().state = ThreadState::Sleeping;
+                return false;
+            }
+            do_softirq(cpu);
+            true
+        });
+        // Yield between rounds, sleep once none are left.
+        crate::sched::schedule(ran);
+    }
+    0
+}
+
+/// Start `ksoftirqd` on every online CPU and register the tasklet softirqs.
+fn init() -> Result<(), &'static str> {
+    open_softirq(HI_SOFTIRQ, super::tasklet::hi_action);
+    open_softirq(TASKLET_SOFTIRQ, super::tasklet::action);
+
+    let possible = crate::sched::possible_mask();
+    for cpu in (0..MAX_CPUS).filter(|&cpu| possible & (1 << cpu) != 0) {
+        let kthread = kthread_create(&format!("ksoftirqd/{}", cpu), move || ksoftirqd(cpu));
+        kthread.bind(cpu as u32);
+        crate::arch::without_interrupts(|| {
+            *KSOFTIRQD[cpu].lock() = Some(kthread.thread().clone());
+        });
+        kthread.wake();
+    }
+    Ok(())
+}
+
+crate::early_initcall!(init);
@@ -0,0 +1,88 @@
+//! Tasklets: deferred functions run from a softirq.
+//!
+//! A tasklet scheduled from an interrupt handler runs once on the same CPU when the
+//! handler returns, however often it was scheduled until then. A tasklet never runs on
+//! two CPUs at once; one scheduled while it runs elsewhere runs again afterwards.
+
+use super::softirq::{self, HI_SOFTIRQ, TASKLET_SOFTIRQ};
+use crate::sched::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Queued to run.
+const STATE_SCHED: u8 = 1 << 0;
+/// Running on some CPU.
+const STATE_RUN: u8 = 1 << 1;
+
+/// A function to run from softirq context, declared as a `static`.
+pub struct Tasklet {
+    func: fn(),
+    state: AtomicU8,
+}
+
+impl Tasklet {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            state: AtomicU8::new(0),
+        }
+    }
+}
+
+type TaskletList = Spinlock<VecDeque<&'static Tasklet>>;
+
+/// Tasklets queued on each CPU, taken with interrupts disabled.
+static TASKLETS: [TaskletList; MAX_CPUS] = [const { Spinlock::new(VecDeque::new()) }; MAX_CPUS];
+static HI_TASKLETS: [TaskletList; MAX_CPUS] =
+    [const { Spinlock::new(VecDeque::new()) }; MAX_CPUS];
+
+fn schedule_on(lists: &[TaskletList; MAX_CPUS], nr: usize, tasklet: &'static Tasklet) {
+    if tasklet.state.fetch_or(STATE_SCHED, Ordering::AcqRel) & STATE_SCHED != 0 {
+        return;
+    }
+    crate::arch::without_interrupts(|| {
+        let cpu = (crate::arch::cpu_id() as usize).min(MAX_CPUS - 1);
+        lists[cpu].lock().push_back(tasklet);
+        softirq::raise_softirq(nr);
+    });
+}
+
+/// Run `tasklet` on the calling CPU, unless it is queued already.
+pub fn tasklet_schedule(tasklet: &'static Tasklet) {
+    schedule_on(&TASKLETS, TASKLET_SOFTIRQ, tasklet);
+}
+
+/// Run `tasklet` on the calling CPU ahead of the other tasklets.
+pub fn tasklet_hi_schedule(tasklet: &'static Tasklet) {
+    schedule_on(&HI_TASKLETS, HI_SOFTIRQ, tasklet);
+}
+
+/// Run the tasklets queued on the calling CPU.
+fn run(lists: &[TaskletList; MAX_CPUS], nr: usize) {
+    let cpu = (crate::arch::cpu_id() as usize).min(MAX_CPUS - 1);
+    let queued = crate::arch::without_interrupts(|| core::mem::take(&mut *lists[cpu].lock()));
+    for tasklet in queued {
+        if tasklet.state.fetch_or(STATE_RUN, Ordering::Acquire) & STATE_RUN != 0 {
+            // Running on another CPU: try again on the next round.
+            crate::arch::without_interrupts(|| {
+                lists[cpu].lock().push_back(tasklet);
+                softirq::raise_softirq(nr);
+            });
+            continue;
+        }
+        tasklet.state.fetch_and(!STATE_SCHED, Ordering::AcqRel);
+        (tasklet.func)();
+        tasklet.state.fetch_and(!STATE_RUN, Ordering::Release);
+    }
+}
+
+/// Handler of `TASKLET_SOFTIRQ`.
+pub(super) fn action() {
+    run(&TASKLETS, TASKLET_SOFTIRQ);
+}
+
+/// Handler of `HI_SOFTIRQ`.
+pub(super) fn hi_action() {
+    run(&HI_TASKLETS, HI_SOFTIRQ);
+}
@@ -0,0 +1,340 @@
+//! Workqueues: deferred functions run by kernel worker threads.
+//!
+//! Work queued on a workqueue runs in a worker thread, where it may sleep, rather than in
+//! the context that queued it, which may be an interrupt handler. A per-CPU workqueue has
+//! a worker bound to each CPU and runs work on the CPU that queued it; an unbound one has
+//! a pool of workers, one per CPU, that run work wherever the scheduler places them.
+//!
+//! A work item is queued at most once at a time and never runs concurrently with itself:
+//! queued again while it runs, it runs once more after it finishes. Work that sleeps
+//! holds up the work queued behind it on a per-CPU workqueue, so long running work
+//! belongs on an unbound one.
+
+use crate::proc::kthread::kthread_create;
+use crate::proc::thread::{Thread, ThreadState};
+use crate::sched::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A function to run from a workqueue, declared as a `static`.
+pub struct Work {
+    func: fn(),
+    /// Taken with interrupts disabled.
+    state: Spinlock<WorkState>,
+}
+
+struct WorkState {
+    /// Queued, or waiting for its delay, and not started yet.
+    pending: bool,
+    running: bool,
+    /// Pool to queue the work on once it stops running, if it was queued meanwhile.
+    requeue: Option<Arc<WorkerPool>>,
+    /// Threads waiting in `flush_work`.
+    flushers: Vec<Arc<Spinlock<Thread>>>,
+}
+
+impl Work {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            state: Spinlock::new(WorkState {
+                pending: false,
+                running: false,
+                requeue: None,
+                flushers: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Workers and the work queued for them.
+struct WorkerPool {
+    /// Taken with interrupts disabled.
+    inner: Spinlock<PoolInner>,
+}
+
+struct PoolInner {
+    worklist: VecDeque<&'static Work>,
+    /// Delayed work by due time and sequence number.
+    delayed: BTreeMap<(u64, u64), &'static Work>,
+    /// Workers sleeping until there is work.
+    idle: Vec<Arc<Spinlock<Thread>>>,
+}
+
+static NEXT_DELAYED_SEQ: AtomicU64 = AtomicU64::new(0);
+
+impl WorkerPool {
+    fn new() -> Self {
+        Self {
+            inner: Spinlock::new(PoolInner {
+                worklist: VecDeque::new(),
+                delayed: BTreeMap::new(),
+                idle: Vec::new(),
+            }),
+        }
+    }
+
+    /// Queue `work` and wake a worker for it. Called with interrupts disabled.
+    fn insert(&self, work: &'static Work) {
+        let mut inner = self.inner.lock();
+        inner.worklist.push_back(work);
+        if let Some(worker) = inner.idle.pop() {
+            Thread::wake(worker);
+        }
+    }
+
+    /// Queue `work` once `deadline_ns` has passed. Called with interrupts disabled.
+    fn insert_delayed(&self, work: &'static Work, deadline_ns: u64) {
+        let mut inner = self.inner.lock();
+        let key = (deadline_ns, NEXT_DELAYED_SEQ.fetch_add(1, Ordering::Relaxed));
+        inner.delayed.insert(key, work);
+        // A sleeping worker has to arm its timer for the new earliest deadline.
+        if inner.delayed.first_key_value().is_some_and(|(first, _)| *first == key)
+            && let Some(worker) = inner.idle.pop()
+        {
+            Thread::wake(worker);
+        }
+    }
+
+    /// Body of a worker thread: run queued work, and sleep while there is none.
+    fn worker(self: Arc<Self>) -> i32 {
+        let Some(thread) = crate::proc::current_thread() else {
+            return 0;
+        };
+        loop {
+            let next = crate::arch::without_interrupts(|| {
+                let mut inner = self.inner.lock();
+                let now = crate::time::now_ns();
+                while let Some(entry) = inner.delayed.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+                    let work = entry.remove();
+                    inner.worklist.push_back(work);
+                }
+                if let Some(work) = inner.worklist.pop_front() {
+                    return Ok(work);
+                }
+                thread.lock().state = ThreadState::Sleeping;
+                inner.idle.push(thread.clone());
+                Err(inner.delayed.first_key_value().map(|(&(deadline, _), _)| deadline))
+            });
+
+            match next {
+                Ok(work) => self.process(work),
+                Err(deadline) => {
+                    let timer = deadline.map(|ns| crate::time::timer::add(ns, thread.clone()));
+                    crate::sched::schedule(false);
+                    if let Some(timer) = timer {
+                        crate::time::timer::cancel(timer);
+                    }
+                    crate::arch::without_interrupts(|| {
+                        self.inner.lock().idle.retain(|idle| !Arc::ptr_eq(idle, &thread));
+                    });
+                }
+            }
+        }
+    }
+
+    /// Run `work` taken off the worklist, unless it is still running elsewhere.
+    fn process(self: &Arc<Self>, work: &'static Work) {
+        let run = crate::arch::without_interrupts(|| {
+            let mut state = work.state.lock();
+            if state.running {
+                // Runs again once the other worker is done with it.
+                state.requeue = Some(self.clone());
+                return false;
+            }
+            state.pending = false;
+            state.running = true;
+            true
+        });
+        if !run {
+            return;
+        }
+
+        (work.func)();
+
+        crate::arch::without_interrupts(|| {
+            let mut state = work.state.lock();
+            state.running = false;
+            if let Some(pool) = state.requeue.take() {
+                drop(state);
+                pool.insert(work);
+            } else if !state.pending {
+                for flusher in core::mem::take(&mut state.flushers) {
+                    Thread::wake(flusher);
+                }
+            }
+        });
+    }
+}
+
+/// A queue of work with its own worker threads.
+pub struct Workqueue {
+    name: &'static str,
+    pools: Pools,
+}
+
+enum Pools {
+    /// A pool bound to each online CPU.
+    PerCpu([Option<Arc<WorkerPool>>; MAX_CPUS]),
+    Unbound(Arc<WorkerPool>),
+}
+
+/// Create a workqueue whose workers are named after `name`. Workqueues are never freed.
+///
+/// With `unbound`, work runs on any CPU instead of the one that queued it.
+pub fn alloc_workqueue(name: &'static str, unbound: bool) -> &'static Workqueue {
+    let possible = crate::sched::possible_mask();
+    let cpus = (0..MAX_CPUS).filter(|&cpu| possible & (1 << cpu) != 0);
+    let pools = if unbound {
+        let pool = Arc::new(WorkerPool::new());
+        for (i, _) in cpus.enumerate() {
+            let worker = pool.clone();
+            kthread_create(&format!("{}/u{}", name, i), move || worker.worker()).wake();
+        }
+        Pools::Unbound(pool)
+    } else {
+        let mut pools = [const { None }; MAX_CPUS];
+        for cpu in cpus {
+            let pool = Arc::new(WorkerPool::new());
+            let worker = pool.clone();
+            let kthread = kthread_create(&format!("{}/{}", name, cpu), move || worker.worker());
+            kthread.bind(cpu as u32);
+            kthread.wake();
+            pools[cpu] = Some(pool);
+        }
+        Pools::PerCpu(pools)
+    };
+    log::info!("workqueue: created {} ({})", name, if unbound { "unbound" } else { "per-CPU" });
+    Box::leak(Box::new(Workqueue { name, pools }))
+}
+
+impl Workqueue {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Pool running work queued from `cpu`.
+    fn pool(&self, cpu: usize) -> Option<&Arc<WorkerPool>> {
+        match &self.pools {
+            Pools::PerCpu(pools) => {
+                pools.get(cpu).and_then(Option::as_ref).or_else(|| pools.iter().flatten().next())
+            }
+            Pools::Unbound(pool) => Some(pool),
+        }
+    }
+
+    /// Queue `work` on the pool of `cpu`, now or after `delay_ns`.
+    fn queue(&self, cpu: usize, work: &'static Work, delay_ns: u64) -> bool {
+        let Some(pool) = self.pool(cpu) else {
+            return false;
+        };
+        crate::arch::without_interrupts(|| {
+            let mut state = work.state.lock();
+            if state.pending {
+                return false;
+            }
+            state.pending = true;
+            if delay_ns > 0 {
+                drop(state);
+                let deadline = crate::time::now_ns().saturating_add(delay_ns);
+                pool.insert_delayed(work, deadline);
+            } else if state.running {
+                state.requeue = Some(pool.clone());
+            } else {
+                drop(state);
+                pool.insert(work);
+            }
+            true
+        })
+    }
+}
+
+/// Queue `work` on `wq`, on the calling CPU for a per-CPU workqueue.
+///
+/// Returns `false` if it was already queued. May be called from interrupt handlers.
+pub fn queue_work(wq: &Workqueue, work: &'static Work) -> bool {
+    queue_work_on(crate::arch::cpu_id(), wq, work)
+}
+
+/// Queue `work` on `wq`, on `cpu` for a per-CPU workqueue.
+pub fn queue_work_on(cpu: u32, wq: &Workqueue, work: &'static Work) -> bool {
+    wq.queue(cpu as usize, work, 0)
+}
+
+/// Queue `work` on `wq` once `delay_ns` nanoseconds have passed.
+///
+/// Returns `false` if it was already queued. May be called from interrupt handlers.
+pub fn queue_delayed_work(wq: &Workqueue, work: &'static Work, delay_ns: u64) -> bool {
+    wq.queue(crate::arch::cpu_id() as usize, work, delay_ns)
+}
+
+/// Wait until `work` is neither queued nor running, including any delay it was queued
+/// with. Returns whether it had to wait. Must not be called from the work itself.
+pub fn flush_work(work: &'static Work) -> bool {
+    let Some(thread) = crate::proc::current_thread() else {
+        return false;
+    };
+    let mut waited = false;
+    loop {
+        let idle = crate::arch::without_interrupts(|| {
+            let mut state = work.state.lock();
+            if !state.pending && !state.running {
+                return true;
+            }
+            thread.lock().state = ThreadState::Sleeping;
+            state.flushers.push(thread.clone());
+            false
+        });
+        if idle {
+            return waited;
+        }
+        waited = true;
+        crate::sched::schedule(false);
+    }
+}
+
+/// The system workqueues, set up by `init`.
+static SYSTEM_WQ: Spinlock<Option<&'static Workqueue>> = Spinlock::new(None);
+static SYSTEM_UNBOUND_WQ: Spinlock<Option<&'static Workqueue>> = Spinlock::new(None);
+
+/// The shared per-CPU workqueue, for short work.
+pub fn system_wq() -> Option<&'static Workqueue> {
+    crate::arch::without_interrupts(|| *SYSTEM_WQ.lock())
+}
+
+/// The shared unbound workqueue, for work that may take long or sleep.
+pub fn system_unbound_wq() -> Option<&'static Workqueue> {
+    crate::arch::without_interrupts(|| *SYSTEM_UNBOUND_WQ.lock())
+}
+
+/// Queue `work` on the system workqueue. Returns `false` if it was already queued, or if
+/// the workqueues are not set up yet.
+pub fn schedule_work(work: &'static Work) -> bool {
+    system_wq().is_some_and(|wq| queue_work(wq, work))
+}
+
+/// Queue `work` on the system workqueue once `delay_ns` nanoseconds have passed.
+pub fn schedule_delayed_work(work: &'static Work, delay_ns: u64) -> bool {
+    system_wq().is_some_and(|wq| queue_delayed_work(wq, work, delay_ns))
+}
+
+/// Create the system workqueues.
+fn init() -> Result<(), &'static str> {
+    let events = alloc_workqueue("events", false);
+    let unbound = alloc_workqueue("events_unbound", true);
+    crate::arch::without_interrupts(|| {
+        *SYSTEM_WQ.lock() = Some(events);
+        *SYSTEM_UNBOUND_WQ.lock() = Some(unbound);
+    });
+    Ok(())
+}
+
+crate::early_initcall!(init);